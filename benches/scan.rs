@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+#![feature(used_with_arg)]
+
+extern crate alloc;
+extern crate bare_test;
+
+#[bare_test::tests]
+mod bench {
+    use bare_test::println;
+    use pcie::{
+        fixture::{mixed, nvme, scan_cost, Fixture},
+        MockFunction,
+    };
+
+    fn endpoint() -> MockFunction {
+        nvme()
+            .with_bar32(0, 0x4000, false)
+            .with_bar32(1, 0x1000, false)
+    }
+
+    fn bridge() -> MockFunction {
+        MockFunction::bridge(0x1b36, 0x000c)
+    }
+
+    /// `count` endpoints side by side on the root bus
+    fn flat(count: u8) -> Fixture {
+        (0..count).fold(Fixture::new(), |f, device| {
+            f.endpoint(device, 0, endpoint())
+        })
+    }
+
+    /// `depth` bridges in a chain, an endpoint below each
+    fn chain(depth: u8) -> Fixture {
+        (0..depth).fold(Fixture::new().endpoint(0, 0, endpoint()), |below, _| {
+            Fixture::new()
+                .bridge(0, 0, bridge(), below)
+                .endpoint(1, 0, endpoint())
+        })
+    }
+
+    #[test]
+    fn bench_scan_cost() {
+        let fixtures = [
+            ("flat 1", flat(1)),
+            ("flat 32", flat(32)),
+            ("chain 4", chain(4)),
+            ("mixed", mixed()),
+        ];
+        println!("fixture   functions  absent  reads/fn on demand  reads/fn snapshot  writes");
+        for (name, fixture) in fixtures {
+            let baseline = scan_cost(&fixture, false);
+            let snapshot = scan_cost(&fixture, true);
+            assert_eq!(baseline.functions, snapshot.functions);
+            assert_eq!(baseline.absent_reads, snapshot.absent_reads);
+            assert_eq!(baseline.writes, snapshot.writes);
+            println!(
+                "{name:<9} {:>9}  {:>6}  {:>18}  {:>17}  {:>6}",
+                baseline.functions,
+                baseline.absent_reads,
+                baseline.reads_per_function(),
+                snapshot.reads_per_function(),
+                baseline.writes,
+            );
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Root port side of AER: find which function an error message came from
+//! and hand it to the handler its driver registered.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use bit_field::BitField;
+
+use crate::{err::Error, Bdf, HwEvent, NodeId, PciAddress, RootComplex};
+
+/// Root Error Status bits
+const ERR_COR_RECEIVED: usize = 0;
+const MULTIPLE_ERR_COR: usize = 1;
+const ERR_UNCOR_RECEIVED: usize = 2;
+const MULTIPLE_ERR_UNCOR: usize = 3;
+const FIRST_UNCOR_FATAL: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AerSeverity {
+    Correctable,
+    NonFatal,
+    Fatal,
+}
+
+/// Error message received by a root port, see
+/// [`RootComplex::handle_root_error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AerEvent {
+    pub port: PciAddress,
+    /// Requester of the message, from Error Source Identification
+    pub source: PciAddress,
+    /// `source` in the stored topology, `None` before a scan or if it
+    /// wasn't found
+    pub node: Option<NodeId>,
+    pub severity: AerSeverity,
+    /// AER status of `source` as read when dispatching, 0 without AER
+    pub status: u32,
+    /// More messages of the same kind arrived, only the first source is
+    /// recorded
+    pub multiple: bool,
+}
+
+pub(crate) type AerHandler = Box<dyn FnMut(&AerEvent) + Send>;
+
+impl RootComplex {
+    /// Call `handler` for errors `device` reports, e.g. registered when its
+    /// driver probes it. Replaces a handler registered before.
+    pub fn on_aer_error(
+        &mut self,
+        device: PciAddress,
+        handler: impl FnMut(&AerEvent) + Send + 'static,
+    ) {
+        self.remove_aer_handler(device);
+        self.aer_handlers.push((device, Box::new(handler)));
+    }
+
+    pub fn remove_aer_handler(&mut self, device: PciAddress) {
+        self.aer_handlers.retain(|(d, _)| *d != device);
+    }
+
+    /// Service the AER interrupt of root port `port`: read Root Error Status
+    /// and Error Source Identification, map each source to its topology
+    /// node and call its handler, then clear the AER status of the source
+    /// and the root status handled.
+    ///
+    /// Returns the dispatched events, also those without a handler.
+    pub fn handle_root_error(&mut self, port: PciAddress) -> Result<Vec<AerEvent>, Error> {
+        let base = self
+            .function(port)
+            .ok_or(Error::InvalidArgument("no device at port address"))?;
+        let aer = base
+            .aer()
+            .ok_or(Error::NotSupported("root port without AER"))?;
+        let status = aer.root_error_status();
+        let (cor, uncor) = aer.error_source_ids();
+
+        let mut pending = Vec::new();
+        if status.get_bit(ERR_COR_RECEIVED) {
+            pending.push((
+                cor,
+                AerSeverity::Correctable,
+                status.get_bit(MULTIPLE_ERR_COR),
+            ));
+        }
+        if status.get_bit(ERR_UNCOR_RECEIVED) {
+            let severity = if status.get_bit(FIRST_UNCOR_FATAL) {
+                AerSeverity::Fatal
+            } else {
+                AerSeverity::NonFatal
+            };
+            pending.push((uncor, severity, status.get_bit(MULTIPLE_ERR_UNCOR)));
+        }
+
+        let mut events = Vec::with_capacity(pending.len());
+        for (requester, severity, multiple) in pending {
+            let source = PciAddress::new(
+                port.segment(),
+                (requester >> 8) as u8,
+                requester.get_bits(3..8) as u8,
+                requester.get_bits(0..3) as u8,
+            );
+            let event = AerEvent {
+                port,
+                source,
+                node: self.topology().and_then(|t| t.find(source)),
+                severity,
+                status: self.source_status(source, severity, 0),
+                multiple,
+            };
+            match self.aer_handlers.iter_mut().find(|(d, _)| *d == source) {
+                Some((_, handler)) => handler(&event),
+                None => match &self.rate_limit {
+                    Some(limit) => {
+                        let kind = match severity {
+                            AerSeverity::Correctable => HwEvent::CorrectableError,
+                            _ => HwEvent::UncorrectableError,
+                        };
+                        limit.report(kind, source);
+                    }
+                    None => warn!("{}: {severity:?} AER error, no handler", Bdf(source)),
+                },
+            }
+            self.source_status(source, severity, event.status);
+            events.push(event);
+        }
+        // After the sources, so a new message latches again
+        aer.clear_root_error_status(status.get_bits(0..7));
+        Ok(events)
+    }
+
+    /// AER status of `source` for `severity`, then clear the `clear` bits
+    /// of it.
+    fn source_status(&mut self, source: PciAddress, severity: AerSeverity, clear: u32) -> u32 {
+        let Some(base) = self.function(source) else {
+            return 0;
+        };
+        let Some(aer) = base.aer() else {
+            return 0;
+        };
+        let status = match severity {
+            AerSeverity::Correctable => aer.correctable_status(),
+            _ => aer.uncorrectable_status(),
+        };
+        match severity {
+            _ if clear == 0 => {}
+            AerSeverity::Correctable => aer.clear_correctable_status(clear),
+            _ => aer.clear_uncorrectable_status(clear),
+        }
+        status
+    }
+}
@@ -0,0 +1,126 @@
+//! Config write auditing.
+//!
+//! Wrap a chip with [`Audited`] to funnel every config write, including the
+//! ones `pci_types` issues internally (BAR sizing, command updates), through
+//! a single [`AuditPolicy`].
+
+use bit_field::BitField;
+use rdif_pcie::{DriverGeneric, Interface, KError};
+
+use crate::{Bdf, PciAddress};
+
+/// Register group a config write targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigRegion {
+    /// IDs, class, cache line size, interrupt line etc.
+    Header,
+    Command,
+    /// BARs and the expansion ROM BAR
+    Bar,
+    BusNumber,
+    /// Bridge I/O, memory and prefetchable windows
+    BridgeWindow,
+    Capability,
+    ExtendedCapability,
+}
+
+impl ConfigRegion {
+    /// Region of the dword at `offset` in a header of `header_type`, the
+    /// register at 0x0e with the multi-function bit.
+    pub fn classify(header_type: u8, offset: u16) -> Self {
+        match (header_type & 0x7f, offset & !0x3) {
+            (_, 0x04) => Self::Command,
+            (_, 0x100..) => Self::ExtendedCapability,
+            // PCI-to-PCI bridge
+            (0x01, 0x10 | 0x14 | 0x38) => Self::Bar,
+            (0x01, 0x18) => Self::BusNumber,
+            (0x01, 0x1c..=0x30) => Self::BridgeWindow,
+            // CardBus bridge: socket registers BAR, capabilities pointer at
+            // 0x14, the header runs up to 0x48
+            (0x02, 0x10) => Self::Bar,
+            (0x02, 0x18) => Self::BusNumber,
+            (0x02, 0x1c..=0x38) => Self::BridgeWindow,
+            (0x02, 0x40 | 0x44) => Self::Header,
+            (_, 0x40..) => Self::Capability,
+            (0x00, 0x10..=0x24 | 0x30) => Self::Bar,
+            _ => Self::Header,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Deny,
+}
+
+pub trait AuditPolicy: Send + 'static {
+    fn check(
+        &mut self,
+        address: PciAddress,
+        offset: u16,
+        value: u32,
+        region: ConfigRegion,
+    ) -> Verdict;
+}
+
+impl<F> AuditPolicy for F
+where
+    F: FnMut(PciAddress, u16, u32, ConfigRegion) -> Verdict + Send + 'static,
+{
+    fn check(
+        &mut self,
+        address: PciAddress,
+        offset: u16,
+        value: u32,
+        region: ConfigRegion,
+    ) -> Verdict {
+        self(address, offset, value, region)
+    }
+}
+
+/// Chip wrapper applying `P` to every config write, denied writes are dropped.
+pub struct Audited<C, P> {
+    inner: C,
+    policy: P,
+}
+
+impl<C: Interface, P: AuditPolicy> Audited<C, P> {
+    pub fn new(inner: C, policy: P) -> Self {
+        Self { inner, policy }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Interface, P: AuditPolicy> DriverGeneric for Audited<C, P> {
+    fn open(&mut self) -> Result<(), KError> {
+        self.inner.open()
+    }
+
+    fn close(&mut self) -> Result<(), KError> {
+        self.inner.close()
+    }
+}
+
+impl<C: Interface, P: AuditPolicy> Interface for Audited<C, P> {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        self.inner.read(address, offset)
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let header_type = self.inner.read(address, 0x0c).get_bits(16..24) as u8;
+        let region = ConfigRegion::classify(header_type, offset);
+        match self.policy.check(address.into(), offset, value, region) {
+            Verdict::Allow => self.inner.write(address, offset, value),
+            Verdict::Deny => {
+                warn!(
+                    "{}: denied config write {offset:#x} <- {value:#x} ({region:?})",
+                    Bdf(address.into())
+                )
+            }
+        }
+    }
+}
@@ -1 +1,155 @@
 pub use rdif_pcie::SimpleBarAllocator;
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::BarKind;
+
+/// Allocations made of several BARs, on top of [`SimpleBarAllocator`].
+pub trait BarAllocatorExt {
+    /// `count` blocks of `size` back to back, the first one aligned to
+    /// `align`, e.g. the VF BARs of an SR-IOV PF. Returns the start of the
+    /// first block, `None` for I/O or when there is no space.
+    fn alloc_contiguous(
+        &mut self,
+        size: u64,
+        count: u64,
+        align: u64,
+        kind: BarKind,
+        prefetchable: bool,
+    ) -> Option<u64>;
+}
+
+impl BarAllocatorExt for SimpleBarAllocator {
+    /// The allocator hands out naturally aligned power of two blocks, the
+    /// request is rounded up to one and the tail past `size * count` stays
+    /// unused.
+    fn alloc_contiguous(
+        &mut self,
+        size: u64,
+        count: u64,
+        align: u64,
+        kind: BarKind,
+        prefetchable: bool,
+    ) -> Option<u64> {
+        if size == 0 || count == 0 || !align.is_power_of_two() {
+            return None;
+        }
+        let block = size
+            .checked_mul(count)?
+            .max(align)
+            .checked_next_power_of_two()?;
+        match kind {
+            BarKind::Mem32 => self
+                .alloc_memory32_with_pref(u32::try_from(block).ok()?, prefetchable)
+                .map(|v| v as u64),
+            BarKind::Mem64 => self.alloc_memory64_with_pref(block, prefetchable),
+            BarKind::Io => None,
+        }
+    }
+}
+
+/// Where BARs and bridge windows are taken from: the host windows of the
+/// controller, or the windows of the bridge above.
+pub(crate) trait BarSpace {
+    fn alloc_memory32(&mut self, size: u32, prefetchable: bool) -> Option<u32>;
+
+    fn alloc_memory64(&mut self, size: u64, prefetchable: bool) -> Option<u64>;
+
+    fn alloc_io(&mut self, _size: u32) -> Option<u32> {
+        None
+    }
+
+    /// Give back a block handed out before. The host windows are handed
+    /// out front to back and can't take anything back.
+    fn free(&mut self, _block: Range<u64>) {}
+}
+
+impl BarSpace for SimpleBarAllocator {
+    fn alloc_memory32(&mut self, size: u32, prefetchable: bool) -> Option<u32> {
+        self.alloc_memory32_with_pref(size, prefetchable)
+    }
+
+    fn alloc_memory64(&mut self, size: u64, prefetchable: bool) -> Option<u64> {
+        self.alloc_memory64_with_pref(size, prefetchable)
+    }
+}
+
+/// Naturally aligned power of two blocks of one window, split and merged
+/// buddy style. Requests fit in any order as long as their sizes, rounded
+/// up to powers of two, add up to at most the window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WindowAllocator {
+    window: Range<u64>,
+    free: Vec<Range<u64>>,
+}
+
+impl WindowAllocator {
+    pub fn new(window: Range<u64>) -> Self {
+        // Largest aligned blocks covering the window
+        let mut free = Vec::new();
+        let mut start = window.start;
+        while start < window.end {
+            let align = if start == 0 {
+                u64::MAX
+            } else {
+                1 << start.trailing_zeros()
+            };
+            let mut size = align.min(window.end - start);
+            size = 1 << (63 - size.leading_zeros());
+            free.push(start..start + size);
+            start += size;
+        }
+        Self { window, free }
+    }
+
+    pub fn window(&self) -> Range<u64> {
+        self.window.clone()
+    }
+
+    pub fn contains(&self, block: &Range<u64>) -> bool {
+        self.window.start <= block.start && block.end <= self.window.end
+    }
+
+    pub fn alloc(&mut self, size: u64) -> Option<u64> {
+        let size = size.max(1).checked_next_power_of_two()?;
+        let (i, _) = self
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.end - b.start >= size)
+            .min_by_key(|(_, b)| b.end - b.start)?;
+        let mut block = self.free.swap_remove(i);
+        while block.end - block.start > size {
+            let half = (block.end - block.start) / 2;
+            self.free.push(block.start + half..block.end);
+            block.end = block.start + half;
+        }
+        Some(block.start)
+    }
+
+    /// Give back a block returned by [`Self::alloc`] for `size`.
+    pub fn free(&mut self, start: u64, size: u64) {
+        let Some(size) = size.max(1).checked_next_power_of_two() else {
+            return;
+        };
+        let mut block = start..start + size;
+        loop {
+            let len = block.end - block.start;
+            let buddy = block.start ^ len;
+            match self
+                .free
+                .iter()
+                .position(|b| b.start == buddy && b.end - b.start == len)
+            {
+                Some(i) => {
+                    self.free.swap_remove(i);
+                    let start = block.start.min(buddy);
+                    block = start..start + 2 * len;
+                }
+                None => break,
+            }
+        }
+        self.free.push(block);
+    }
+}
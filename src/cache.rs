@@ -0,0 +1,131 @@
+//! Read-only identification registers of functions, kept so hot paths
+//! don't go back to config space for them.
+
+use alloc::vec::Vec;
+use core::{fmt::Display, ops::RangeInclusive};
+
+use pci_types::ConfigRegionAccess;
+
+use crate::{Bdf, DeviceType, HeaderType, PciAddress, PcieController};
+
+/// Vendor, device, class and header type of one function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigIdentity {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub revision_id: u8,
+    pub base_class: u8,
+    pub sub_class: u8,
+    pub interface: u8,
+    pub header_type: HeaderType,
+}
+
+impl ConfigIdentity {
+    /// `None` if no function answers at `address`.
+    pub fn read(controller: &mut PcieController, address: PciAddress) -> Option<Self> {
+        let raw = address.into();
+        let access = controller.config_access(raw);
+        let id = unsafe { access.read(raw, 0x00) };
+        if id as u16 == 0xffff {
+            return None;
+        }
+        let class = unsafe { access.read(raw, 0x08) };
+        let header = unsafe { access.read(raw, 0x0c) };
+        Some(Self {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            revision_id: class as u8,
+            base_class: (class >> 24) as u8,
+            sub_class: (class >> 16) as u8,
+            interface: (class >> 8) as u8,
+            header_type: HeaderType::from((header >> 16) as u8),
+        })
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        DeviceType::from((self.base_class, self.sub_class))
+    }
+}
+
+/// Same line as `Display` of [`crate::Endpoint`], without touching the
+/// function.
+impl Display for ConfigIdentity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let class_name = format!("{:?}", self.device_type());
+        write!(
+            f,
+            "{} {:<24} {:04x}:{:04x} (rev {:02x}, prog-if {:02x})",
+            Bdf(self.address),
+            class_name,
+            self.vendor_id,
+            self.device_id,
+            self.revision_id,
+            self.interface,
+        )
+    }
+}
+
+/// [`ConfigIdentity`] per function, filled on first use.
+///
+/// Nothing here notices a function going away or being replaced, whoever
+/// rescans or resets has to invalidate. [`crate::RootComplex::with_config_cache`]
+/// does so for its own rescans and resets.
+#[derive(Debug, Clone, Default)]
+pub struct CachedConfig {
+    entries: Vec<ConfigIdentity>,
+}
+
+impl CachedConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cached entry of `address`, config space is not read.
+    pub fn get(&self, address: PciAddress) -> Option<&ConfigIdentity> {
+        self.entries.iter().find(|e| e.address == address)
+    }
+
+    /// Cached entry of `address`, read from `controller` on a miss. Absent
+    /// functions are not remembered.
+    pub fn load(
+        &mut self,
+        controller: &mut PcieController,
+        address: PciAddress,
+    ) -> Option<ConfigIdentity> {
+        if let Some(hit) = self.get(address) {
+            return Some(*hit);
+        }
+        let identity = ConfigIdentity::read(controller, address)?;
+        self.entries.push(identity);
+        Some(identity)
+    }
+
+    pub fn invalidate(&mut self, address: PciAddress) {
+        self.entries.retain(|e| e.address != address);
+    }
+
+    /// Drop every function on `buses` of `segment`, e.g. below a bridge
+    /// that was rescanned or reset.
+    pub fn invalidate_buses(&mut self, segment: u16, buses: RangeInclusive<u8>) {
+        self.entries
+            .retain(|e| e.address.segment() != segment || !buses.contains(&e.address.bus()));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ConfigIdentity> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
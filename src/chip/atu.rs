@@ -0,0 +1,187 @@
+use alloc::vec::Vec;
+
+use crate::{err::Error, PciMem32, PciMem64, PcieController};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowKind {
+    Io,
+    Memory32,
+    Memory64,
+}
+
+/// A host bridge window translating `cpu_address` to `pci_address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostWindow {
+    pub kind: WindowKind,
+    pub cpu_address: u64,
+    pub pci_address: u64,
+    pub size: u64,
+    pub prefetchable: bool,
+}
+
+/// Controllers with programmable outbound address translation (e.g. the
+/// DesignWare iATU).
+pub trait OutboundAtu {
+    fn num_outbound(&self) -> usize;
+
+    fn program_outbound(&mut self, index: usize, window: &HostWindow) -> Result<(), Error>;
+
+    fn disable_outbound(&mut self, index: usize);
+}
+
+/// An inbound window translating device DMA at `pci_address` to system
+/// memory at `cpu_address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InboundWindow {
+    pub cpu_address: u64,
+    pub pci_address: u64,
+    pub size: u64,
+}
+
+/// Controllers with programmable inbound address translation, needed before
+/// DMA-capable drivers run on controllers without a default 1:1 inbound map.
+pub trait InboundAtu {
+    fn num_inbound(&self) -> usize;
+
+    /// Granularity of inbound regions, both addresses and size.
+    fn inbound_align(&self) -> u64 {
+        0x1000
+    }
+
+    fn program_inbound(&mut self, index: usize, window: &InboundWindow) -> Result<(), Error>;
+
+    fn disable_inbound(&mut self, index: usize);
+}
+
+/// Validate and program `windows` into the inbound regions, disabling the rest.
+pub fn program_inbound(
+    atu: &mut (impl InboundAtu + ?Sized),
+    windows: &[InboundWindow],
+) -> Result<(), Error> {
+    if windows.len() > atu.num_inbound() {
+        return Err(Error::InvalidArgument("more windows than inbound regions"));
+    }
+    let align = atu.inbound_align();
+    for window in windows {
+        if window.size == 0 {
+            return Err(Error::InvalidArgument("empty inbound window"));
+        }
+        if !window.cpu_address.is_multiple_of(align)
+            || !window.pci_address.is_multiple_of(align)
+            || !window.size.is_multiple_of(align)
+        {
+            return Err(Error::InvalidArgument("inbound window not aligned"));
+        }
+        if window.pci_address.checked_add(window.size).is_none()
+            || window.cpu_address.checked_add(window.size).is_none()
+        {
+            return Err(Error::InvalidArgument("inbound window overflows"));
+        }
+    }
+    for (index, window) in windows.iter().enumerate() {
+        atu.program_inbound(index, window)?;
+    }
+    for index in windows.len()..atu.num_inbound() {
+        atu.disable_inbound(index);
+    }
+    Ok(())
+}
+
+/// Inbound windows from the raw (big-endian) devicetree `dma-ranges`
+/// property of a host bridge: 3 cells of PCI address,
+/// `parent_address_cells` of CPU address and 2 of size per entry.
+///
+/// Declares which system RAM devices can DMA to, hand the result to
+/// [`program_inbound`] and [`crate::WindowTranslator`].
+pub fn parse_dma_ranges(
+    raw: &[u8],
+    parent_address_cells: usize,
+) -> Result<Vec<InboundWindow>, Error> {
+    if !(1..=2).contains(&parent_address_cells) {
+        return Err(Error::InvalidArgument("unsupported #address-cells"));
+    }
+    let entry_cells = 3 + parent_address_cells + 2;
+    if !raw.len().is_multiple_of(entry_cells * 4) {
+        return Err(Error::InvalidArgument(
+            "dma-ranges length is not whole entries",
+        ));
+    }
+    let (chunks, _) = raw.as_chunks::<4>();
+    let cells: Vec<u32> = chunks.iter().map(|c| u32::from_be_bytes(*c)).collect();
+    let join = |cells: &[u32]| cells.iter().fold(0u64, |acc, c| (acc << 32) | *c as u64);
+
+    let mut windows = Vec::new();
+    for entry in cells.chunks_exact(entry_cells) {
+        // phys.hi space code: 0b10 32-bit, 0b11 64-bit memory
+        if (entry[0] >> 24) & 0b10 == 0 {
+            return Err(Error::InvalidArgument(
+                "dma-ranges entry is not memory space",
+            ));
+        }
+        windows.push(InboundWindow {
+            pci_address: join(&entry[1..3]),
+            cpu_address: join(&entry[3..3 + parent_address_cells]),
+            size: join(&entry[3 + parent_address_cells..]),
+        });
+    }
+    Ok(windows)
+}
+
+/// Host bridge windows, the single source for both the outbound translation
+/// and the BAR allocator so the two can't disagree.
+#[derive(Debug, Clone, Default)]
+pub struct HostWindows {
+    windows: alloc::vec::Vec<HostWindow>,
+}
+
+impl HostWindows {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, window: HostWindow) -> &mut Self {
+        self.windows.push(window);
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &HostWindow> {
+        self.windows.iter()
+    }
+
+    /// Program one outbound region per window and disable the rest.
+    pub fn program_outbound(&self, atu: &mut (impl OutboundAtu + ?Sized)) -> Result<(), Error> {
+        if self.windows.len() > atu.num_outbound() {
+            return Err(Error::InvalidArgument("more windows than outbound regions"));
+        }
+        for (index, window) in self.windows.iter().enumerate() {
+            atu.program_outbound(index, window)?;
+        }
+        for index in self.windows.len()..atu.num_outbound() {
+            atu.disable_outbound(index);
+        }
+        Ok(())
+    }
+
+    /// Hand the memory windows, as PCI addresses, to the BAR allocator.
+    pub fn configure_allocator(&self, controller: &mut PcieController) {
+        for window in &self.windows {
+            match window.kind {
+                WindowKind::Memory32 => controller.set_mem32(
+                    PciMem32 {
+                        address: window.pci_address as _,
+                        size: window.size as _,
+                    },
+                    window.prefetchable,
+                ),
+                WindowKind::Memory64 => controller.set_mem64(
+                    PciMem64 {
+                        address: window.pci_address as _,
+                        size: window.size as _,
+                    },
+                    window.prefetchable,
+                ),
+                WindowKind::Io => {}
+            }
+        }
+    }
+}
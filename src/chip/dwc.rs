@@ -0,0 +1,490 @@
+use alloc::boxed::Box;
+use core::ptr::NonNull;
+
+use rdif_pcie::{DriverGeneric, Interface};
+
+use super::{
+    HostWindow, InboundAtu, InboundWindow, LinkCounter, LinkMeasurement, LinkMonitor, OutboundAtu,
+    WindowKind,
+};
+use crate::{err::Error, Delay, ErrorInjector, InjectedError, PciAddress};
+
+// iATU viewport registers in DBI, used when the iATU is not unrolled
+const ATU_VIEWPORT: usize = 0x900;
+const ATU_VIEWPORT_REGS: usize = 0x904;
+const ATU_VIEWPORT_INBOUND: u32 = 1 << 31;
+
+// Per region registers, relative to the region (unrolled) or 0x904 (viewport)
+const ATU_REGION_CTRL1: usize = 0x00;
+const ATU_REGION_CTRL2: usize = 0x04;
+const ATU_LOWER_BASE: usize = 0x08;
+const ATU_UPPER_BASE: usize = 0x0c;
+const ATU_LIMIT: usize = 0x10;
+const ATU_LOWER_TARGET: usize = 0x14;
+const ATU_UPPER_TARGET: usize = 0x18;
+const ATU_UPPER_LIMIT: usize = 0x20;
+
+const ATU_UNROLL_INBOUND: usize = 0x100;
+
+const ATU_TYPE_MEM: u32 = 0x0;
+const ATU_TYPE_IO: u32 = 0x2;
+const ATU_TYPE_CFG0: u32 = 0x4;
+const ATU_TYPE_CFG1: u32 = 0x5;
+const ATU_INCREASE_REGION_SIZE: u32 = 1 << 13;
+const ATU_ENABLE: u32 = 1 << 31;
+
+/// Polls of the region enable, 1ms apart
+const ATU_ENABLE_RETRIES: usize = 5;
+
+// RAS DES vendor specific extended capability, registers relative to it
+const EXT_CAP_VSEC: u32 = 0x000b;
+const VSEC_ID_RAS_DES: u32 = 0x2;
+const EVENT_COUNTER_CONTROL: usize = 0x08;
+const EVENT_COUNTER_DATA: usize = 0x0c;
+const TIME_BASED_CONTROL: usize = 0x10;
+const TIME_BASED_DATA: usize = 0x14;
+const TIME_BASED_DATA_HIGH: usize = 0x18;
+
+const EVENT_COUNTER_ALL_CLEAR: u32 = 0x3;
+const EVENT_COUNTER_ALL_ON: u32 = 0x7 << 2;
+const EVENT_COUNTER_ALL_OFF: u32 = 0x5 << 2;
+const TIME_BASED_START: u32 = 1;
+
+// RAS DES error injection, one enable bit and EINJn register per group
+const ERR_INJ_ENABLE: usize = 0x30;
+const ERR_INJ0: usize = 0x34;
+const EINJ_TYPE_SHIFT: u32 = 8;
+const EINJ_GROUP_CRC: usize = 0;
+const EINJ_TX_LCRC: u32 = 0;
+const EINJ_TX_16B_CRC_DLLP: u32 = 1;
+const EINJ_TX_ECRC: u32 = 3;
+
+// AER status bits the injections above end up in, at the link partner
+const AER_BAD_TLP: u32 = 1 << 6;
+const AER_BAD_DLLP: u32 = 1 << 7;
+const AER_ECRC: u32 = 1 << 19;
+
+/// DesignWare PCIe root complex (RK3568, i.MX8 and many other ARM SoCs).
+///
+/// The root port is reached through DBI, buses below it through a type 0/1
+/// outbound iATU region retargeted when the target changes. That region is
+/// the last one, the others are left to [`OutboundAtu`].
+pub struct DwPcieHost {
+    dbi: NonNull<u8>,
+    /// Unrolled iATU base, `None` for the viewport in DBI
+    atu: Option<NonNull<u8>>,
+    cfg: NonNull<u8>,
+    cfg_cpu_address: u64,
+    cfg_size: u64,
+    root_bus: u8,
+    num_outbound: usize,
+    num_inbound: usize,
+    /// Target currently programmed in the config region
+    cfg_target: Option<(u32, u32)>,
+    measurement: Option<LinkMeasurement>,
+    delay: Box<dyn Delay + Send>,
+}
+
+unsafe impl Send for DwPcieHost {}
+
+impl DwPcieHost {
+    /// `cfg` maps the config window at `cfg_cpu_address`, at least 4KiB.
+    /// `delay` waits for iATU regions to become enabled.
+    pub fn new(
+        dbi: NonNull<u8>,
+        cfg: NonNull<u8>,
+        cfg_cpu_address: u64,
+        cfg_size: u64,
+        delay: impl Delay + Send + 'static,
+    ) -> Self {
+        Self {
+            dbi,
+            atu: None,
+            cfg,
+            cfg_cpu_address,
+            cfg_size,
+            root_bus: 0,
+            num_outbound: 2,
+            num_inbound: 2,
+            cfg_target: None,
+            measurement: None,
+            delay: Box::new(delay),
+        }
+    }
+
+    /// iATU registers at `atu` instead of the DBI viewport (DWC 4.80+).
+    pub fn with_unrolled_atu(mut self, atu: NonNull<u8>) -> Self {
+        self.atu = Some(atu);
+        self
+    }
+
+    /// Number of iATU regions implemented, from the SoC manual.
+    pub fn with_regions(mut self, outbound: usize, inbound: usize) -> Self {
+        assert!(outbound > 0, "config access needs an outbound region");
+        self.num_outbound = outbound;
+        self.num_inbound = inbound;
+        self
+    }
+
+    pub fn with_root_bus(mut self, bus: u8) -> Self {
+        self.root_bus = bus;
+        self
+    }
+
+    pub(crate) fn root_bus(&self) -> u8 {
+        self.root_bus
+    }
+
+    fn dbi_read(&self, offset: usize) -> u32 {
+        u32::from_le(unsafe { self.dbi.add(offset).cast::<u32>().read_volatile() })
+    }
+
+    fn dbi_write(&self, offset: usize, value: u32) {
+        unsafe {
+            self.dbi
+                .add(offset)
+                .cast::<u32>()
+                .write_volatile(value.to_le())
+        }
+    }
+
+    fn atu_reg(&self, index: usize, inbound: bool, reg: usize) -> NonNull<u32> {
+        let ptr = match self.atu {
+            Some(atu) => {
+                let dir = if inbound { ATU_UNROLL_INBOUND } else { 0 };
+                unsafe { atu.add((index << 9) + dir + reg) }
+            }
+            None => {
+                let dir = if inbound { ATU_VIEWPORT_INBOUND } else { 0 };
+                self.dbi_write(ATU_VIEWPORT, dir | index as u32);
+                unsafe { self.dbi.add(ATU_VIEWPORT_REGS + reg) }
+            }
+        };
+        ptr.cast()
+    }
+
+    fn atu_write(&self, index: usize, inbound: bool, reg: usize, value: u32) {
+        unsafe {
+            self.atu_reg(index, inbound, reg)
+                .write_volatile(value.to_le())
+        }
+    }
+
+    fn atu_read(&self, index: usize, inbound: bool, reg: usize) -> u32 {
+        u32::from_le(unsafe { self.atu_reg(index, inbound, reg).read_volatile() })
+    }
+
+    fn program_region(
+        &mut self,
+        index: usize,
+        inbound: bool,
+        ty: u32,
+        base: u64,
+        target: u64,
+        size: u64,
+    ) -> Result<(), Error> {
+        if size == 0 {
+            return Err(Error::InvalidArgument("empty iATU region"));
+        }
+        let limit = base
+            .checked_add(size - 1)
+            .ok_or(Error::InvalidArgument("iATU region overflows"))?;
+        let mut ctrl1 = ty;
+        if limit >> 32 != base >> 32 {
+            ctrl1 |= ATU_INCREASE_REGION_SIZE;
+        }
+        self.atu_write(index, inbound, ATU_LOWER_BASE, base as u32);
+        self.atu_write(index, inbound, ATU_UPPER_BASE, (base >> 32) as u32);
+        self.atu_write(index, inbound, ATU_LIMIT, limit as u32);
+        if ctrl1 & ATU_INCREASE_REGION_SIZE != 0 {
+            self.atu_write(index, inbound, ATU_UPPER_LIMIT, (limit >> 32) as u32);
+        }
+        self.atu_write(index, inbound, ATU_LOWER_TARGET, target as u32);
+        self.atu_write(index, inbound, ATU_UPPER_TARGET, (target >> 32) as u32);
+        self.atu_write(index, inbound, ATU_REGION_CTRL1, ctrl1);
+        self.atu_write(index, inbound, ATU_REGION_CTRL2, ATU_ENABLE);
+
+        // The enable takes effect asynchronously, make sure it did before
+        // the first access through the region.
+        for _ in 0..ATU_ENABLE_RETRIES {
+            if self.atu_read(index, inbound, ATU_REGION_CTRL2) & ATU_ENABLE != 0 {
+                return Ok(());
+            }
+            self.delay.delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Offset of the RAS DES capability in DBI
+    fn ras_des(&self) -> Option<usize> {
+        let mut offset = 0x100;
+        // Bounded in case of a looping list
+        for _ in 0..(0x1000 - 0x100) / 4 {
+            let header = self.dbi_read(offset);
+            if header == 0 || header == u32::MAX {
+                return None;
+            }
+            if header & 0xffff == EXT_CAP_VSEC
+                && self.dbi_read(offset + 4) & 0xffff == VSEC_ID_RAS_DES
+            {
+                return Some(offset);
+            }
+            offset = (header >> 20) as usize & !0x3;
+            if offset < 0x100 {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn cfg_region(&self) -> usize {
+        self.num_outbound - 1
+    }
+
+    /// Config pointer for `address`, `None` for functions that can't exist.
+    fn map(&mut self, address: PciAddress, offset: u16) -> Option<NonNull<u32>> {
+        let bus = address.bus();
+        if bus == self.root_bus {
+            // Only the root port itself lives on the root bus
+            if address.device() != 0 || address.function() != 0 {
+                return None;
+            }
+            return Some(unsafe { self.dbi.add(offset as usize).cast() });
+        }
+        if bus < self.root_bus {
+            return None;
+        }
+        // A link has a single device downstream of the root port
+        let ty = if bus == self.root_bus + 1 {
+            if address.device() != 0 {
+                return None;
+            }
+            ATU_TYPE_CFG0
+        } else {
+            ATU_TYPE_CFG1
+        };
+
+        let target = (bus as u32) << 24
+            | (address.device() as u32) << 19
+            | (address.function() as u32) << 16;
+        if self.cfg_target != Some((ty, target)) {
+            self.program_region(
+                self.cfg_region(),
+                false,
+                ty,
+                self.cfg_cpu_address,
+                target as u64,
+                self.cfg_size,
+            )
+            .inspect_err(|_| warn!("DWC: config iATU region not enabled"))
+            .ok()?;
+            self.cfg_target = Some((ty, target));
+        }
+        Some(unsafe { self.cfg.add(offset as usize).cast() })
+    }
+}
+
+impl DriverGeneric for DwPcieHost {
+    fn open(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+}
+
+impl Interface for DwPcieHost {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        let address = PciAddress::from(address);
+        match self.map(address, offset) {
+            Some(ptr) => u32::from_le(unsafe { ptr.as_ptr().read_volatile() }),
+            None => u32::MAX,
+        }
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let address = PciAddress::from(address);
+        if let Some(ptr) = self.map(address, offset) {
+            unsafe { ptr.as_ptr().write_volatile(value.to_le()) }
+        }
+    }
+}
+
+impl OutboundAtu for DwPcieHost {
+    /// The last region is kept for config access.
+    fn num_outbound(&self) -> usize {
+        self.num_outbound - 1
+    }
+
+    fn program_outbound(&mut self, index: usize, window: &HostWindow) -> Result<(), Error> {
+        if index >= self.num_outbound() {
+            return Err(Error::InvalidArgument("outbound region out of range"));
+        }
+        let ty = match window.kind {
+            WindowKind::Io => ATU_TYPE_IO,
+            WindowKind::Memory32 | WindowKind::Memory64 => ATU_TYPE_MEM,
+        };
+        self.program_region(
+            index,
+            false,
+            ty,
+            window.cpu_address,
+            window.pci_address,
+            window.size,
+        )
+    }
+
+    fn disable_outbound(&mut self, index: usize) {
+        self.atu_write(index, false, ATU_REGION_CTRL2, 0);
+    }
+}
+
+impl InboundAtu for DwPcieHost {
+    fn num_inbound(&self) -> usize {
+        self.num_inbound
+    }
+
+    fn inbound_align(&self) -> u64 {
+        0x1_0000
+    }
+
+    fn program_inbound(&mut self, index: usize, window: &InboundWindow) -> Result<(), Error> {
+        if index >= self.num_inbound {
+            return Err(Error::InvalidArgument("inbound region out of range"));
+        }
+        self.program_region(
+            index,
+            true,
+            ATU_TYPE_MEM,
+            window.pci_address,
+            window.cpu_address,
+            window.size,
+        )
+    }
+
+    fn disable_inbound(&mut self, index: usize) {
+        self.atu_write(index, true, ATU_REGION_CTRL2, 0);
+    }
+}
+
+/// Group and event number of the RAS DES event counter
+fn event_select(counter: LinkCounter) -> u32 {
+    let (group, event) = match counter {
+        LinkCounter::ReceiverError => (1, 0x06),
+        LinkCounter::BadTlp => (2, 0x00),
+        LinkCounter::LcrcError => (2, 0x01),
+        LinkCounter::BadDllp => (2, 0x02),
+        LinkCounter::ReplayRollover => (2, 0x03),
+        LinkCounter::ReplayTimeout => (2, 0x04),
+        LinkCounter::RxNak => (2, 0x05),
+        LinkCounter::TxNak => (2, 0x06),
+        LinkCounter::RetriedTlp => (2, 0x07),
+        LinkCounter::PoisonedTlp => (3, 0x01),
+        LinkCounter::EcrcError => (3, 0x02),
+        LinkCounter::UnsupportedRequest => (3, 0x03),
+        LinkCounter::CompletionTimeout => (3, 0x05),
+    };
+    group << 24 | event << 16
+}
+
+fn report_select(measurement: LinkMeasurement) -> u32 {
+    match measurement {
+        LinkMeasurement::L0Time => 0x03,
+        LinkMeasurement::L1Time => 0x04,
+        LinkMeasurement::RecoveryTime => 0x07,
+        LinkMeasurement::TxPayload => 0x20,
+        LinkMeasurement::RxPayload => 0x21,
+    }
+}
+
+impl LinkMonitor for DwPcieHost {
+    fn start_counters(&mut self) -> Result<(), Error> {
+        let ras = self
+            .ras_des()
+            .ok_or(Error::NotSupported("RAS DES capability"))?;
+        self.dbi_write(ras + EVENT_COUNTER_CONTROL, EVENT_COUNTER_ALL_CLEAR);
+        self.dbi_write(ras + EVENT_COUNTER_CONTROL, EVENT_COUNTER_ALL_ON);
+        Ok(())
+    }
+
+    fn stop_counters(&mut self) {
+        if let Some(ras) = self.ras_des() {
+            self.dbi_write(ras + EVENT_COUNTER_CONTROL, EVENT_COUNTER_ALL_OFF);
+        }
+    }
+
+    fn counter(&mut self, counter: LinkCounter) -> Option<u32> {
+        let ras = self.ras_des()?;
+        // Lane select left at 0
+        self.dbi_write(ras + EVENT_COUNTER_CONTROL, event_select(counter));
+        Some(self.dbi_read(ras + EVENT_COUNTER_DATA))
+    }
+
+    fn start_measurement(&mut self, measurement: LinkMeasurement) -> Result<(), Error> {
+        let ras = self
+            .ras_des()
+            .ok_or(Error::NotSupported("RAS DES capability"))?;
+        // Restarting the timer clears the data, duration 0 runs until stopped
+        self.dbi_write(ras + TIME_BASED_CONTROL, 0);
+        self.dbi_write(
+            ras + TIME_BASED_CONTROL,
+            report_select(measurement) << 24 | TIME_BASED_START,
+        );
+        self.measurement = Some(measurement);
+        Ok(())
+    }
+
+    fn measurement(&mut self) -> Option<u64> {
+        let measurement = self.measurement?;
+        let ras = self.ras_des()?;
+        let value = loop {
+            let high = self.dbi_read(ras + TIME_BASED_DATA_HIGH);
+            let low = self.dbi_read(ras + TIME_BASED_DATA);
+            if self.dbi_read(ras + TIME_BASED_DATA_HIGH) == high {
+                break (high as u64) << 32 | low as u64;
+            }
+        };
+        Some(match measurement {
+            // Counted in 16 byte units
+            LinkMeasurement::TxPayload | LinkMeasurement::RxPayload => value * 16,
+            _ => value,
+        })
+    }
+
+    fn stop_measurement(&mut self) {
+        if let Some(ras) = self.ras_des() {
+            self.dbi_write(ras + TIME_BASED_CONTROL, 0);
+        }
+        self.measurement = None;
+    }
+}
+
+/// Injects through RAS DES on the transmit side of the root port, so the
+/// device at the other end of the link reports the error. Only Bad TLP and
+/// Bad DLLP (correctable) and ECRC (uncorrectable) can be raised.
+impl ErrorInjector for DwPcieHost {
+    fn inject(&mut self, address: PciAddress, error: InjectedError) -> Result<(), Error> {
+        if address.bus() != self.root_bus || address.device() != 0 || address.function() != 0 {
+            return Err(Error::InvalidArgument(
+                "error injection is on the root port",
+            ));
+        }
+        let kind = match error {
+            InjectedError::Correctable(AER_BAD_TLP) => EINJ_TX_LCRC,
+            InjectedError::Correctable(AER_BAD_DLLP) => EINJ_TX_16B_CRC_DLLP,
+            InjectedError::Uncorrectable(AER_ECRC) => EINJ_TX_ECRC,
+            _ => return Err(Error::NotSupported("error type for RAS DES injection")),
+        };
+        let ras = self
+            .ras_des()
+            .ok_or(Error::NotSupported("RAS DES capability"))?;
+        // A single error, the enable bit clears once it has been sent
+        self.dbi_write(
+            ras + ERR_INJ0 + EINJ_GROUP_CRC * 4,
+            kind << EINJ_TYPE_SHIFT | 1,
+        );
+        self.dbi_write(ras + ERR_INJ_ENABLE, 1 << EINJ_GROUP_CRC);
+        Ok(())
+    }
+}
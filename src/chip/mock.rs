@@ -0,0 +1,373 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bit_field::BitField;
+use rdif_pcie::{DriverGeneric, Interface, KError};
+
+use crate::{
+    err::Error, ErrorInjector, ExtCapWalker, InjectedError, PciAddress, CAP_ID_PCIE, EXT_CAP_ID_AER,
+};
+
+const CONFIG_DWORDS: usize = 0x1000 / 4;
+
+/// In-memory config space, used to test enumeration without hardware.
+#[derive(Default, Clone)]
+pub struct MockController {
+    functions: BTreeMap<(u8, u8, u8), MockFunction>,
+    count: Arc<AccessCount>,
+}
+
+/// Config accesses a [`MockController`] and its clones have served.
+#[derive(Debug, Default)]
+pub struct AccessCount {
+    reads: AtomicUsize,
+    absent_reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl AccessCount {
+    pub fn reads(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    /// Reads of addresses without a function, part of [`Self::reads`]
+    pub fn absent_reads(&self) -> usize {
+        self.absent_reads.load(Ordering::Relaxed)
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.reads.store(0, Ordering::Relaxed);
+        self.absent_reads.store(0, Ordering::Relaxed);
+        self.writes.store(0, Ordering::Relaxed);
+    }
+}
+
+impl MockController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_function(mut self, bus: u8, device: u8, function: u8, f: MockFunction) -> Self {
+        self.functions.insert((bus, device, function), f);
+        self
+    }
+
+    /// Counter kept across handing the controller to a [`crate::RootComplex`].
+    pub fn access_count(&self) -> Arc<AccessCount> {
+        self.count.clone()
+    }
+
+    pub fn function(&self, bus: u8, device: u8, function: u8) -> Option<&MockFunction> {
+        self.functions.get(&(bus, device, function))
+    }
+
+    fn key(address: PciAddress) -> (u8, u8, u8) {
+        (address.bus(), address.device(), address.function())
+    }
+}
+
+impl ErrorInjector for MockController {
+    /// Latch `error` in the AER status and Device Status registers of the
+    /// function, as hardware would when detecting it.
+    fn inject(&mut self, address: PciAddress, error: InjectedError) -> Result<(), Error> {
+        let f = self
+            .functions
+            .get_mut(&Self::key(address))
+            .ok_or(Error::InvalidArgument("no such mock function"))?;
+        let aer = f
+            .extended_capability(EXT_CAP_ID_AER)
+            .ok_or(Error::NotSupported("mock function has no AER capability"))?;
+
+        let (offset, bits, detected) = match error {
+            InjectedError::Correctable(bits) => (aer + 0x10, bits, 0),
+            InjectedError::Uncorrectable(bits) => {
+                let fatal = f.read(aer + 0x0c) & bits != 0;
+                (aer + 0x04, bits, if fatal { 2 } else { 1 })
+            }
+        };
+        f.latch(offset, bits);
+        if let Some(pcie) = f.capability(0x10) {
+            // Correctable / Non-Fatal / Fatal Error Detected
+            f.latch(pcie + 0x08, 1 << (16 + detected));
+        }
+        Ok(())
+    }
+}
+
+impl DriverGeneric for MockController {
+    fn open(&mut self) -> Result<(), KError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), KError> {
+        Ok(())
+    }
+}
+
+impl Interface for MockController {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        let address = PciAddress::from(address);
+        self.count.reads.fetch_add(1, Ordering::Relaxed);
+        match self.functions.get(&Self::key(address)) {
+            Some(f) => f.read(offset),
+            None => {
+                self.count.absent_reads.fetch_add(1, Ordering::Relaxed);
+                u32::MAX
+            }
+        }
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let address = PciAddress::from(address);
+        self.count.writes.fetch_add(1, Ordering::Relaxed);
+        if let Some(f) = self.functions.get_mut(&Self::key(address)) {
+            f.write(offset, value);
+        }
+    }
+}
+
+/// Config space of one mocked function.
+///
+/// Every dword has a write mask, bits outside of it are read-only. BAR sizing
+/// works by giving BARs a mask of `!(size - 1)`.
+#[derive(Clone)]
+pub struct MockFunction {
+    regs: Vec<u32>,
+    masks: Vec<u32>,
+    rw1c: Vec<u32>,
+    last_cap: Option<u16>,
+    next_cap: u16,
+    last_ext: Option<u16>,
+    next_ext: u16,
+    tables: Vec<Table>,
+    /// (offset, bits) latched by any write to the dword at offset
+    write_latches: Vec<(u16, u32)>,
+}
+
+/// Dword at `data` indexed by the low byte of the one at `select`
+#[derive(Clone)]
+struct Table {
+    select: u16,
+    data: u16,
+    entries: Vec<u32>,
+}
+
+impl MockFunction {
+    /// `class` is `[base_class, sub_class, interface]`
+    pub fn endpoint(vendor_id: u16, device_id: u16, class: [u8; 3]) -> Self {
+        Self::new(vendor_id, device_id, class, 0x00).with_register(0x3c, 0, 0xff)
+    }
+
+    pub fn bridge(vendor_id: u16, device_id: u16) -> Self {
+        Self::new(vendor_id, device_id, [0x06, 0x04, 0x00], 0x01)
+            .with_register(0x18, 0, 0x00ff_ffff)
+            .with_register(0x1c, 0, 0x0000_f0f0)
+            .with_register(0x20, 0, 0xfff0_fff0)
+            .with_register(0x24, 0, 0xfff0_fff0)
+            .with_register(0x28, 0, u32::MAX)
+            .with_register(0x2c, 0, u32::MAX)
+            .with_register(0x30, 0, u32::MAX)
+            .with_register(0x3c, 0, 0xffff_00ff)
+    }
+
+    fn new(vendor_id: u16, device_id: u16, class: [u8; 3], header_type: u8) -> Self {
+        let mut s = Self {
+            regs: vec![0; CONFIG_DWORDS],
+            masks: vec![0; CONFIG_DWORDS],
+            rw1c: vec![0; CONFIG_DWORDS],
+            last_cap: None,
+            next_cap: 0x40,
+            last_ext: None,
+            next_ext: 0x100,
+            tables: Vec::new(),
+            write_latches: Vec::new(),
+        };
+        s.regs[0] = (device_id as u32) << 16 | vendor_id as u32;
+        s.regs[2] = (class[0] as u32) << 24 | (class[1] as u32) << 16 | (class[2] as u32) << 8;
+        s.regs[3] = (header_type as u32) << 16;
+        // Command register, cache line size and latency timer
+        s.masks[1] = 0x0000_07ff;
+        s.masks[3] = 0x0000_ffff;
+        s
+    }
+
+    pub fn multifunction(mut self) -> Self {
+        self.regs[3].set_bit(23, true);
+        self
+    }
+
+    pub fn with_register(mut self, offset: u16, value: u32, write_mask: u32) -> Self {
+        let i = (offset / 4) as usize;
+        self.regs[i] = value;
+        self.masks[i] = write_mask;
+        self
+    }
+
+    pub fn with_bar32(self, index: usize, size: u32, prefetchable: bool) -> Self {
+        let flags = if prefetchable { 0x8 } else { 0 };
+        self.with_register(Self::bar_offset(index), flags, !(size - 1) & !0xf)
+    }
+
+    pub fn with_bar64(self, index: usize, size: u64, prefetchable: bool) -> Self {
+        let flags = if prefetchable { 0xc } else { 0x4 };
+        let mask = !(size - 1) & !0xf;
+        let offset = Self::bar_offset(index);
+        self.with_register(offset, flags, mask as u32)
+            .with_register(offset + 4, 0, (mask >> 32) as u32)
+    }
+
+    pub fn with_io_bar(self, index: usize, size: u32) -> Self {
+        self.with_register(Self::bar_offset(index), 0x1, !(size - 1) & !0x3)
+    }
+
+    /// Append a capability, the low 16 bits of `regs[0]` are replaced by the
+    /// ID and next pointer. Everything but the header is writable.
+    pub fn with_capability(mut self, id: u8, regs: &[u32]) -> Self {
+        let offset = self.next_cap;
+        let end = offset + (regs.len() as u16) * 4;
+        assert!(end <= 0x100, "capabilities overflow the config header");
+
+        match self.last_cap {
+            Some(last) => {
+                self.regs[last as usize / 4].set_bits(8..16, offset as u32);
+            }
+            None => self.regs[0x34 / 4] = offset as u32,
+        }
+        // Capabilities List
+        self.regs[1].set_bit(16 + 4, true);
+
+        for (i, value) in regs.iter().enumerate() {
+            self.regs[offset as usize / 4 + i] = *value;
+            self.masks[offset as usize / 4 + i] = u32::MAX;
+        }
+        let head = offset as usize / 4;
+        self.regs[head].set_bits(0..16, id as u32);
+        self.masks[head] = 0xffff_0000;
+
+        self.last_cap = Some(offset);
+        self.next_cap = end;
+        self
+    }
+
+    /// Append an extended capability, `regs` starts after the header dword.
+    /// Extended config space is only read on functions with a PCI Express
+    /// capability, see [`Self::with_pcie`].
+    pub fn with_extended_capability(mut self, id: u16, version: u8, regs: &[u32]) -> Self {
+        let offset = self.next_ext;
+        let end = offset + (regs.len() as u16 + 1) * 4;
+        assert!(
+            end as usize <= CONFIG_DWORDS * 4,
+            "extended capabilities overflow"
+        );
+
+        if let Some(last) = self.last_ext {
+            self.regs[last as usize / 4].set_bits(20..32, offset as u32);
+        }
+        let head = offset as usize / 4;
+        self.regs[head] = (version as u32) << 16 | id as u32;
+        for (i, value) in regs.iter().enumerate() {
+            self.regs[head + 1 + i] = *value;
+            self.masks[head + 1 + i] = u32::MAX;
+        }
+
+        self.last_ext = Some(offset);
+        self.next_ext = end;
+        self
+    }
+
+    /// Append a PCI Express capability (version 2) of `port_type`, the
+    /// Device/Port Type field encoding.
+    pub fn with_pcie(self, port_type: u8) -> Self {
+        let mut regs = [0; 15];
+        regs[0] = 0x2 << 16 | (port_type as u32 & 0xf) << 20;
+        self.with_capability(CAP_ID_PCIE, &regs)
+    }
+
+    /// Append an AER capability with RW1C status registers
+    pub fn with_aer(self) -> Self {
+        let s = self.with_extended_capability(EXT_CAP_ID_AER, 2, &[0; 13]);
+        let aer = s.last_ext.unwrap_or_default();
+        s.with_rw1c(aer + 0x04, u32::MAX)
+            .with_rw1c(aer + 0x10, u32::MAX)
+    }
+
+    /// Serve `entries` at `data` by the low byte written to `select`, as
+    /// Power Budgeting Data Select does. Past the end `data` reads 0.
+    pub fn with_table(mut self, select: u16, data: u16, entries: &[u32]) -> Self {
+        self.tables.push(Table {
+            select,
+            data,
+            entries: entries.to_vec(),
+        });
+        self
+    }
+
+    /// Bits in `mask` are write-1-to-clear
+    pub fn with_rw1c(mut self, offset: u16, mask: u32) -> Self {
+        let i = (offset / 4) as usize;
+        self.masks[i] &= !mask;
+        self.rw1c[i] |= mask;
+        self
+    }
+
+    /// Latch `bits` after every write to the dword at `offset`, like a port
+    /// setting Command Completed
+    pub fn with_write_latch(mut self, offset: u16, bits: u32) -> Self {
+        self.write_latches.push((offset & !0x3, bits));
+        self
+    }
+
+    pub fn capability(&self, id: u8) -> Option<u16> {
+        let mut offset = self.read(0x34) as u16 & 0xfc;
+        while offset >= 0x40 {
+            let data = self.read(offset);
+            if data.get_bits(0..8) as u8 == id {
+                return Some(offset);
+            }
+            offset = data.get_bits(8..16) as u16 & 0xfc;
+        }
+        None
+    }
+
+    pub fn extended_capability(&self, id: u16) -> Option<u16> {
+        ExtCapWalker::new(|offset| self.read(offset), 0x100)
+            .find(|cap| cap.id == id)
+            .map(|cap| cap.offset)
+    }
+
+    /// Set `bits` as the hardware would, ignoring write masks
+    pub fn latch(&mut self, offset: u16, bits: u32) {
+        self.regs[offset as usize / 4] |= bits;
+    }
+
+    pub fn read(&self, offset: u16) -> u32 {
+        if let Some(table) = self.tables.iter().find(|t| t.data == offset & !0x3) {
+            let index = self.read(table.select) as u8 as usize;
+            return table.entries.get(index).copied().unwrap_or(0);
+        }
+        self.regs
+            .get(offset as usize / 4)
+            .copied()
+            .unwrap_or(u32::MAX)
+    }
+
+    fn write(&mut self, offset: u16, value: u32) {
+        let i = offset as usize / 4;
+        if i < CONFIG_DWORDS {
+            self.regs[i] = (self.regs[i] & !self.masks[i]) | (value & self.masks[i]);
+            self.regs[i] &= !(value & self.rw1c[i]);
+        }
+        for (_, bits) in self.write_latches.iter().filter(|l| l.0 == offset & !0x3) {
+            self.regs[i] |= bits;
+        }
+    }
+
+    fn bar_offset(index: usize) -> u16 {
+        assert!(index < 6, "BAR index out of range");
+        0x10 + index as u16 * 4
+    }
+}
@@ -1,29 +1,97 @@
-use core::ptr::NonNull;
+use alloc::{boxed::Box, vec::Vec};
+use core::{ops::RangeInclusive, ptr::NonNull};
 
 pub use rdif_pcie::PcieController;
 use rdif_pcie::{DriverGeneric, Interface};
 
-use crate::PciAddress;
+use crate::{err::Error, PciAddress};
+
+mod atu;
+mod dwc;
+#[cfg(feature = "mock")]
+mod mock;
+mod monitor;
+mod phy;
+mod port;
+#[cfg(target_arch = "x86_64")]
+mod port_io;
+mod qemu;
+mod quirk;
+mod rk3588;
+mod tegra;
+
+pub use atu::*;
+pub use dwc::DwPcieHost;
+#[cfg(feature = "mock")]
+pub use mock::{AccessCount, MockController, MockFunction};
+pub use monitor::{LinkCounter, LinkMeasurement, LinkMonitor};
+pub use phy::PciePhy;
+pub use port::PortControl;
+#[cfg(target_arch = "x86_64")]
+pub use port_io::{PortIo, PortIoCam};
+pub use qemu::QemuVirt;
+pub(crate) use quirk::SharedQuirk;
+pub use quirk::{AccessPolicy, ConfigQuirk};
+pub use rk3588::Rk3588Pcie;
+pub use tegra::TegraPcie;
+
+/// Config space of one bus in ECAM
+const ECAM_BUS_SIZE: usize = 1 << 20;
 
 pub struct PcieGeneric {
-    mmio_base: NonNull<u8>,
+    regions: Vec<EcamRegion>,
+}
+
+/// ECAM aperture for a group of buses, `base` maps `buses.start()`.
+#[derive(Debug, Clone)]
+pub struct EcamRegion {
+    pub buses: RangeInclusive<u8>,
+    pub base: NonNull<u8>,
 }
 
 unsafe impl Send for PcieGeneric {}
 
 impl PcieGeneric {
     pub fn new(mmio_base: NonNull<u8>) -> Self {
-        Self { mmio_base }
+        Self::with_regions([EcamRegion {
+            buses: 0..=255,
+            base: mmio_base,
+        }])
     }
 
-    fn mmio_addr(&self, mmio_base: NonNull<u8>, address: PciAddress, offset: u16) -> NonNull<u32> {
-        let address = (address.bus() as u32) << 20
+    /// [`Self::new`] for a window of `size` bytes, covering as many buses
+    /// as fit. `mmio_base` must be aligned to a bus and hold at least one.
+    pub fn try_new(mmio_base: NonNull<u8>, size: usize) -> Result<Self, Error> {
+        let base = mmio_base.as_ptr() as usize;
+        if !base.is_multiple_of(ECAM_BUS_SIZE) || size < ECAM_BUS_SIZE {
+            return Err(Error::InvalidEcamWindow { base, size });
+        }
+        let last_bus = ((size / ECAM_BUS_SIZE).min(256) - 1) as u8;
+        Ok(Self::with_regions([EcamRegion {
+            buses: 0..=last_bus,
+            base: mmio_base,
+        }]))
+    }
+
+    /// For host bridges whose ECAM is split across several `reg` windows.
+    pub fn with_regions(regions: impl IntoIterator<Item = EcamRegion>) -> Self {
+        Self {
+            regions: regions.into_iter().collect(),
+        }
+    }
+
+    fn mmio_addr(&self, address: PciAddress, offset: u16) -> Option<NonNull<u32>> {
+        let region = self
+            .regions
+            .iter()
+            .find(|r| r.buses.contains(&address.bus()))?;
+        let address = ((address.bus() - region.buses.start()) as u32) << 20
             | (address.device() as u32) << 15
             | (address.function() as u32) << 12
             | offset as u32;
         unsafe {
-            let ptr: NonNull<u32> = mmio_base.cast().add((address >> 2) as usize);
-            ptr
+            let ptr: NonNull<u32> = region.base.cast().add((address >> 2) as usize);
+            Some(ptr)
         }
     }
 }
@@ -38,14 +106,44 @@ impl DriverGeneric for PcieGeneric {
     }
 }
 
+// ECAM is little-endian regardless of the host byte order.
+// Buses outside every region read as all-ones like an absent device.
 impl Interface for PcieGeneric {
-    fn read(&mut self, address: PciAddress, offset: u16) -> u32 {
-        let ptr = self.mmio_addr(self.mmio_base, address, offset);
-        unsafe { ptr.as_ptr().read_volatile() }
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        let address = PciAddress::from(address);
+        match self.mmio_addr(address, offset) {
+            Some(ptr) => u32::from_le(unsafe { ptr.as_ptr().read_volatile() }),
+            None => u32::MAX,
+        }
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let address = PciAddress::from(address);
+        if let Some(ptr) = self.mmio_addr(address, offset) {
+            unsafe { ptr.as_ptr().write_volatile(value.to_le()) }
+        }
+    }
+}
+
+/// Type-erased controller, see [`crate::RootComplex::new_dyn`].
+pub(crate) struct DynController(pub Box<dyn Interface>);
+
+impl DriverGeneric for DynController {
+    fn open(&mut self) -> Result<(), rdif_pcie::KError> {
+        self.0.open()
+    }
+
+    fn close(&mut self) -> Result<(), rdif_pcie::KError> {
+        self.0.close()
+    }
+}
+
+impl Interface for DynController {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        self.0.read(address, offset)
     }
 
-    fn write(&mut self, address: PciAddress, offset: u16, value: u32) {
-        let ptr = self.mmio_addr(self.mmio_base, address, offset);
-        unsafe { ptr.as_ptr().write_volatile(value) }
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        self.0.write(address, offset, value)
     }
 }
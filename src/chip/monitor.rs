@@ -0,0 +1,55 @@
+use crate::err::Error;
+
+/// Link error event counted by the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkCounter {
+    /// Physical layer receiver errors, lane 0 only
+    ReceiverError,
+    BadTlp,
+    BadDllp,
+    LcrcError,
+    ReplayTimeout,
+    ReplayRollover,
+    RxNak,
+    TxNak,
+    RetriedTlp,
+    PoisonedTlp,
+    EcrcError,
+    UnsupportedRequest,
+    CompletionTimeout,
+}
+
+/// What a time based measurement accumulates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMeasurement {
+    /// Core clock cycles spent in L0
+    L0Time,
+    /// Core clock cycles spent in L1
+    L1Time,
+    /// Core clock cycles spent in Recovery
+    RecoveryTime,
+    /// Transmitted TLP payload in bytes
+    TxPayload,
+    /// Received TLP payload in bytes
+    RxPayload,
+}
+
+/// Root ports exposing link statistics (e.g. the DesignWare RAS DES
+/// counters), for performance monitoring tools.
+pub trait LinkMonitor {
+    /// Clear and enable all error counters.
+    fn start_counters(&mut self) -> Result<(), Error>;
+
+    fn stop_counters(&mut self);
+
+    /// `None` if the event can't be counted.
+    fn counter(&mut self, counter: LinkCounter) -> Option<u32>;
+
+    /// Start accumulating `measurement` from zero, replacing the running one.
+    fn start_measurement(&mut self, measurement: LinkMeasurement) -> Result<(), Error>;
+
+    /// Value accumulated so far by the running measurement.
+    fn measurement(&mut self) -> Option<u64>;
+
+    fn stop_measurement(&mut self);
+}
@@ -0,0 +1,31 @@
+use alloc::boxed::Box;
+
+use crate::err::Error;
+
+/// PHY of a controller that needs it brought up before link training, e.g.
+/// the RK3588 PCIe 3.0 PHY. Calls come in order: [`Self::init`],
+/// [`Self::power_on`], then eventually [`Self::power_off`].
+pub trait PciePhy {
+    /// Clocks, resets and lane configuration (bifurcation), with PERST#
+    /// still asserted.
+    fn init(&mut self) -> Result<(), Error>;
+
+    /// Release the PHY reset and wait for its PLL to lock.
+    fn power_on(&mut self) -> Result<(), Error>;
+
+    fn power_off(&mut self) {}
+}
+
+impl<T: PciePhy + ?Sized> PciePhy for Box<T> {
+    fn init(&mut self) -> Result<(), Error> {
+        (**self).init()
+    }
+
+    fn power_on(&mut self) -> Result<(), Error> {
+        (**self).power_on()
+    }
+
+    fn power_off(&mut self) {
+        (**self).power_off()
+    }
+}
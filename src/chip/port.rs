@@ -0,0 +1,33 @@
+use alloc::boxed::Box;
+
+use crate::PciAddress;
+
+/// Board hooks for the sideband signals of root ports, for boards where
+/// PERST# and the reference clock are GPIOs or clock-controller outputs
+/// rather than part of the host bridge. Handed to
+/// [`crate::RootComplex::with_port_control`], which calls them from
+/// [`crate::RootComplex::bring_up_port`] and
+/// [`crate::RootComplex::reset_port`].
+pub trait PortControl {
+    /// Put the slot below `port` in fundamental reset.
+    fn assert_perst(&mut self, port: PciAddress);
+
+    fn deassert_perst(&mut self, port: PciAddress);
+
+    /// Start REFCLK to the slot below `port`, called with PERST# asserted.
+    fn enable_refclk(&mut self, _port: PciAddress) {}
+}
+
+impl<T: PortControl + ?Sized> PortControl for Box<T> {
+    fn assert_perst(&mut self, port: PciAddress) {
+        (**self).assert_perst(port)
+    }
+
+    fn deassert_perst(&mut self, port: PciAddress) {
+        (**self).deassert_perst(port)
+    }
+
+    fn enable_refclk(&mut self, port: PciAddress) {
+        (**self).enable_refclk(port)
+    }
+}
@@ -0,0 +1,81 @@
+use rdif_pcie::{DriverGeneric, Interface};
+
+use crate::PciAddress;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// 32 bit port I/O, supplied by the platform so the crate stays no_std.
+pub trait PortIo: Send + 'static {
+    fn inl(&mut self, port: u16) -> u32;
+
+    fn outl(&mut self, port: u16, value: u32);
+}
+
+impl<R, W> PortIo for (R, W)
+where
+    R: FnMut(u16) -> u32 + Send + 'static,
+    W: FnMut(u16, u32) + Send + 'static,
+{
+    fn inl(&mut self, port: u16) -> u32 {
+        (self.0)(port)
+    }
+
+    fn outl(&mut self, port: u16, value: u32) {
+        (self.1)(port, value)
+    }
+}
+
+/// Legacy configuration mechanism #1 (0xCF8/0xCFC) for PCs without ECAM.
+///
+/// Only segment 0 and the first 256 bytes of config space are reachable,
+/// everything else reads as all-ones and writes are dropped.
+pub struct PortIoCam<P> {
+    io: P,
+}
+
+impl<P: PortIo> PortIoCam<P> {
+    pub fn new(io: P) -> Self {
+        Self { io }
+    }
+
+    fn select(&mut self, address: PciAddress, offset: u16) -> bool {
+        if address.segment() != 0 || offset >= 0x100 {
+            return false;
+        }
+        let value = 1 << 31
+            | (address.bus() as u32) << 16
+            | (address.device() as u32) << 11
+            | (address.function() as u32) << 8
+            | (offset as u32 & 0xfc);
+        self.io.outl(CONFIG_ADDRESS, value);
+        true
+    }
+}
+
+impl<P: PortIo> DriverGeneric for PortIoCam<P> {
+    fn open(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+}
+
+impl<P: PortIo> Interface for PortIoCam<P> {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        let address = PciAddress::from(address);
+        if !self.select(address, offset) {
+            return u32::MAX;
+        }
+        self.io.inl(CONFIG_DATA)
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let address = PciAddress::from(address);
+        if self.select(address, offset) {
+            self.io.outl(CONFIG_DATA, value);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use core::ops::RangeInclusive;
+
+use super::{HostWindow, HostWindows, WindowKind};
+
+// aarch64 `virt` memory map, hw/arm/virt.c
+const ECAM: (u64, u64) = (0x3f00_0000, 0x0100_0000);
+const HIGH_ECAM: (u64, u64) = (0x40_1000_0000, 0x1000_0000);
+const MMIO: (u64, u64) = (0x1000_0000, 0x2eff_0000);
+const HIGH_MMIO: (u64, u64) = (0x80_0000_0000, 0x80_0000_0000);
+
+/// Host bridge layout of the QEMU aarch64 `virt` machine.
+///
+/// With `highmem` (the default of recent machine types) the ECAM moves
+/// above 4GiB and covers 256 buses instead of 16, and a 64-bit MMIO window
+/// is added next to the 32-bit one. Prefer the values from the device tree
+/// when there is one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QemuVirt {
+    pub highmem: bool,
+}
+
+impl QemuVirt {
+    /// `(address, size)` of the ECAM in use, map it for [`super::PcieGeneric`].
+    pub const fn ecam(&self) -> (u64, u64) {
+        if self.highmem {
+            HIGH_ECAM
+        } else {
+            ECAM
+        }
+    }
+
+    /// Buses the ECAM covers.
+    pub const fn buses(&self) -> RangeInclusive<u8> {
+        if self.highmem {
+            0..=255
+        } else {
+            0..=15
+        }
+    }
+
+    /// The 32-bit window and, with highmem, the 64-bit one. Both are
+    /// identity mapped and not prefetchable.
+    pub fn windows(&self) -> HostWindows {
+        let mut windows = HostWindows::new();
+        windows.push(HostWindow {
+            kind: WindowKind::Memory32,
+            cpu_address: MMIO.0,
+            pci_address: MMIO.0,
+            size: MMIO.1,
+            prefetchable: false,
+        });
+        if self.highmem {
+            windows.push(HostWindow {
+                kind: WindowKind::Memory64,
+                cpu_address: HIGH_MMIO.0,
+                pci_address: HIGH_MMIO.0,
+                size: HIGH_MMIO.1,
+                prefetchable: false,
+            });
+        }
+        windows
+    }
+}
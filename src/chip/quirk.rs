@@ -0,0 +1,33 @@
+use alloc::sync::Arc;
+
+use crate::PciAddress;
+
+/// Whether enumeration may touch a config address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPolicy {
+    Allow,
+    /// Never access, treated as an absent function
+    Skip,
+}
+
+/// Config addresses a chip can't safely touch, e.g. root complexes that hang
+/// when device 0 is probed on non-zero functions.
+///
+/// `Controller` is defined upstream, so chips provide this alongside it and
+/// it is handed to [`crate::PciIterator::with_quirk`]. It is consulted for
+/// every access of the functions found, reads of skipped dwords return all
+/// ones and writes to them are dropped.
+pub trait ConfigQuirk {
+    fn quirk(&self, address: PciAddress, offset: u16) -> AccessPolicy;
+}
+
+pub(crate) type SharedQuirk = Arc<dyn ConfigQuirk + Send + Sync>;
+
+impl<F> ConfigQuirk for F
+where
+    F: Fn(PciAddress, u16) -> AccessPolicy,
+{
+    fn quirk(&self, address: PciAddress, offset: u16) -> AccessPolicy {
+        self(address, offset)
+    }
+}
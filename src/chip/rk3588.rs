@@ -0,0 +1,174 @@
+use alloc::boxed::Box;
+use core::ptr::NonNull;
+
+use rdif_pcie::{DriverGeneric, Interface};
+
+use super::{DwPcieHost, PciePhy};
+use crate::{err::Error, Delay};
+
+// PCIE_CLIENT registers in the APB region, the upper half of a write masks
+// the bits it changes
+const CLIENT_GENERAL_CONTROL: usize = 0x000;
+const CLIENT_LTSSM_STATUS: usize = 0x300;
+
+const DEVICE_TYPE_RC: u32 = hiword(0xf0, 0x40);
+const ENABLE_LTSSM: u32 = hiword(0x0c, 0x0c);
+const DISABLE_LTSSM: u32 = hiword(0x0c, 0x08);
+
+const SMLH_LINKUP: u32 = 1 << 16;
+const RDLH_LINKUP: u32 = 1 << 17;
+const LTSSM_STATE_MASK: u32 = 0x3f;
+const LTSSM_L0: u32 = 0x11;
+
+/// Tpvperl, power and reference clock stable before PERST# is released
+const PERST_DELAY_MS: u32 = 100;
+const LINK_POLL_MS: u32 = 90;
+const LINK_POLL_RETRIES: usize = 10;
+/// Devices may answer config requests with RRS this long after link up
+const RRS_READY_MS: u32 = 100;
+
+const fn hiword(mask: u32, value: u32) -> u32 {
+    (mask << 16) | value
+}
+
+/// RK3588 PCIe 3.0 (and 2.0) root complex, a DesignWare core behind the
+/// Rockchip client registers.
+///
+/// Firmware usually leaves the controller off, [`Self::start_link`] brings
+/// it up from cold boot. `host` should use the unrolled iATU at
+/// DBI + 0x300000 ([`DwPcieHost::with_unrolled_atu`]).
+pub struct Rk3588Pcie {
+    host: DwPcieHost,
+    apb: NonNull<u8>,
+    phy: Option<Box<dyn PciePhy + Send>>,
+    perst: Option<Box<dyn FnMut(bool) + Send>>,
+    delay: Box<dyn Delay + Send>,
+    root_bus: u8,
+}
+
+unsafe impl Send for Rk3588Pcie {}
+
+impl Rk3588Pcie {
+    /// `delay` waits out the spec timings of the bring-up.
+    pub fn new(host: DwPcieHost, apb: NonNull<u8>, delay: impl Delay + Send + 'static) -> Self {
+        Self {
+            root_bus: host.root_bus(),
+            host,
+            apb,
+            phy: None,
+            perst: None,
+            delay: Box::new(delay),
+        }
+    }
+
+    pub fn with_phy(mut self, phy: impl PciePhy + Send + 'static) -> Self {
+        self.phy = Some(Box::new(phy));
+        self
+    }
+
+    /// Drive PERST# through `f`, called with `true` to assert the reset.
+    /// Without it PERST# is left to the board.
+    pub fn with_perst(mut self, f: impl FnMut(bool) + Send + 'static) -> Self {
+        self.perst = Some(Box::new(f));
+        self
+    }
+
+    /// For the iATU and RAS DES registers, e.g. [`crate::HostWindows::program_outbound`].
+    pub fn host_mut(&mut self) -> &mut DwPcieHost {
+        &mut self.host
+    }
+
+    /// Hold the device in reset, bring up the PHY, switch the controller to
+    /// root complex mode and start link training, then release PERST# and
+    /// poll until the link reaches L0.
+    pub fn start_link(&mut self) -> Result<(), Error> {
+        self.set_perst(true);
+        self.apb_write(CLIENT_GENERAL_CONTROL, DISABLE_LTSSM);
+        if let Some(phy) = &mut self.phy {
+            phy.init()?;
+            phy.power_on()?;
+        }
+        self.apb_write(CLIENT_GENERAL_CONTROL, DEVICE_TYPE_RC);
+        self.apb_write(CLIENT_GENERAL_CONTROL, ENABLE_LTSSM);
+
+        self.delay.delay_ms(PERST_DELAY_MS);
+        self.set_perst(false);
+
+        for _ in 0..LINK_POLL_RETRIES {
+            if self.link_up() {
+                self.delay.delay_ms(RRS_READY_MS);
+                return Ok(());
+            }
+            self.delay.delay_ms(LINK_POLL_MS);
+        }
+        warn!("RK3588: link down, LTSSM state {:#x}", self.ltssm_state());
+        Err(Error::LinkDown)
+    }
+
+    /// Reset the device and power the PHY off.
+    pub fn stop_link(&mut self) {
+        self.set_perst(true);
+        self.apb_write(CLIENT_GENERAL_CONTROL, DISABLE_LTSSM);
+        if let Some(phy) = &mut self.phy {
+            phy.power_off();
+        }
+    }
+
+    pub fn link_up(&self) -> bool {
+        let status = self.apb_read(CLIENT_LTSSM_STATUS);
+        status & (SMLH_LINKUP | RDLH_LINKUP) == SMLH_LINKUP | RDLH_LINKUP
+            && status & LTSSM_STATE_MASK == LTSSM_L0
+    }
+
+    /// Raw LTSSM state, for diagnosing a link that doesn't train.
+    pub fn ltssm_state(&self) -> u8 {
+        (self.apb_read(CLIENT_LTSSM_STATUS) & LTSSM_STATE_MASK) as u8
+    }
+
+    fn set_perst(&mut self, asserted: bool) {
+        if let Some(perst) = &mut self.perst {
+            perst(asserted);
+        }
+    }
+
+    fn apb_read(&self, offset: usize) -> u32 {
+        u32::from_le(unsafe { self.apb.add(offset).cast::<u32>().read_volatile() })
+    }
+
+    fn apb_write(&self, offset: usize, value: u32) {
+        unsafe {
+            self.apb
+                .add(offset)
+                .cast::<u32>()
+                .write_volatile(value.to_le())
+        }
+    }
+}
+
+impl DriverGeneric for Rk3588Pcie {
+    fn open(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+}
+
+// Requests below the root port abort while the link is down, those read as
+// absent devices instead.
+impl Interface for Rk3588Pcie {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        if address.bus() != self.root_bus && !self.link_up() {
+            return u32::MAX;
+        }
+        self.host.read(address, offset)
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        if address.bus() != self.root_bus && !self.link_up() {
+            return;
+        }
+        self.host.write(address, offset, value)
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+use rdif_pcie::{DriverGeneric, Interface};
+
+use crate::PciAddress;
+
+// AFI registers
+const AFI_AXI_BAR0_SZ: usize = 0x00;
+const AFI_AXI_BAR0_START: usize = 0x18;
+const AFI_FPCI_BAR0: usize = 0x30;
+
+/// FPCI address of type 1 extended config space, 4KiB pages are selected
+/// in units of 256 bytes
+const FPCI_CONFIG_BASE: u32 = 0xfe10_0000;
+const CONFIG_WINDOW_SIZE: u32 = 0x1000;
+
+// Root port registers
+const RP_LINK_CONTROL_STATUS: usize = 0x90;
+const RP_LINK_CONTROL_STATUS_DL_LINK_ACTIVE: u32 = 1 << 29;
+
+/// NVIDIA Tegra (Tegra20 to Tegra210) PCIe controller, the AFI bridge with
+/// up to three root ports. Tegra194 and later are DesignWare cores, see
+/// [`super::DwPcieHost`].
+///
+/// Root port `n` is device `n + 1` on bus 0 and decodes its config space
+/// from its own register block. Buses below are reached through a 4KiB
+/// window the AFI moves over the FPCI config space.
+pub struct TegraPcie {
+    afi: NonNull<u8>,
+    cfg: NonNull<u8>,
+    ports: Vec<(u8, NonNull<u8>)>,
+    /// Page currently selected in the config window
+    fpci_target: Option<u32>,
+}
+
+unsafe impl Send for TegraPcie {}
+
+impl TegraPcie {
+    /// `cfg` maps at least 4KiB at `cfg_cpu_address`, the AXI address the
+    /// AFI translates to config requests.
+    pub fn new(afi: NonNull<u8>, cfg: NonNull<u8>, cfg_cpu_address: u32) -> Self {
+        let pcie = Self {
+            afi,
+            cfg,
+            ports: Vec::new(),
+            fpci_target: None,
+        };
+        pcie.afi_write(AFI_AXI_BAR0_START, cfg_cpu_address);
+        // In 4KiB units
+        pcie.afi_write(AFI_AXI_BAR0_SZ, CONFIG_WINDOW_SIZE >> 12);
+        pcie
+    }
+
+    /// Root port `index` (0 based, as in the `pci@N` device tree nodes) with
+    /// its registers at `base`.
+    pub fn with_port(mut self, index: u8, base: NonNull<u8>) -> Self {
+        self.ports.retain(|(i, _)| *i != index);
+        self.ports.push((index, base));
+        self
+    }
+
+    /// Whether the data link layer of root port `index` is up, `None` for
+    /// ports not added.
+    pub fn port_link_up(&self, index: u8) -> Option<bool> {
+        let (_, base) = self.ports.iter().find(|(i, _)| *i == index)?;
+        let status = u32::from_le(unsafe {
+            base.add(RP_LINK_CONTROL_STATUS)
+                .cast::<u32>()
+                .read_volatile()
+        });
+        Some(status & RP_LINK_CONTROL_STATUS_DL_LINK_ACTIVE != 0)
+    }
+
+    fn afi_write(&self, offset: usize, value: u32) {
+        unsafe {
+            self.afi
+                .add(offset)
+                .cast::<u32>()
+                .write_volatile(value.to_le())
+        }
+    }
+
+    /// Config pointer for `address`, `None` for functions that can't exist.
+    fn map(&mut self, address: PciAddress, offset: u16) -> Option<NonNull<u32>> {
+        let offset = offset as u32 & !0x3;
+        if address.bus() == 0 {
+            if address.function() != 0 {
+                return None;
+            }
+            let index = address.device().checked_sub(1)?;
+            let (_, base) = self.ports.iter().find(|(i, _)| *i == index)?;
+            return Some(unsafe { base.add(offset as usize).cast() });
+        }
+
+        let fpci = ((offset & 0xf00) << 16)
+            | (address.bus() as u32) << 16
+            | (address.device() as u32) << 11
+            | (address.function() as u32) << 8
+            | (offset & 0xff);
+        let target = FPCI_CONFIG_BASE + ((fpci & !(CONFIG_WINDOW_SIZE - 1)) >> 8);
+        if self.fpci_target != Some(target) {
+            self.afi_write(AFI_FPCI_BAR0, target);
+            self.fpci_target = Some(target);
+        }
+        Some(unsafe {
+            self.cfg
+                .add((fpci & (CONFIG_WINDOW_SIZE - 1)) as usize)
+                .cast()
+        })
+    }
+}
+
+impl DriverGeneric for TegraPcie {
+    fn open(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), rdif_pcie::KError> {
+        Ok(())
+    }
+}
+
+impl Interface for TegraPcie {
+    fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+        let address = PciAddress::from(address);
+        match self.map(address, offset) {
+            Some(ptr) => u32::from_le(unsafe { ptr.as_ptr().read_volatile() }),
+            None => u32::MAX,
+        }
+    }
+
+    fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+        let address = PciAddress::from(address);
+        if let Some(ptr) = self.map(address, offset) {
+            unsafe { ptr.as_ptr().write_volatile(value.to_le()) }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! `log` style macros over defmt. The message is still built with
+//! `core::fmt` and sent as one string, call sites stay unchanged.
+#![allow(unused_macros)]
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        ::defmt::error!("{}", ::defmt::Display2Format(&format_args!($($arg)*)))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        ::defmt::warn!("{}", ::defmt::Display2Format(&format_args!($($arg)*)))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        ::defmt::info!("{}", ::defmt::Display2Format(&format_args!($($arg)*)))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::defmt::debug!("{}", ::defmt::Display2Format(&format_args!($($arg)*)))
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        ::defmt::trace!("{}", ::defmt::Display2Format(&format_args!($($arg)*)))
+    };
+}
@@ -0,0 +1,11 @@
+/// Sleep or busy-wait provided by the kernel, for operations that poll the
+/// hardware.
+pub trait Delay {
+    fn delay_ms(&mut self, ms: u32);
+}
+
+impl<F: FnMut(u32)> Delay for F {
+    fn delay_ms(&mut self, ms: u32) {
+        self(ms)
+    }
+}
@@ -1,9 +1,90 @@
 use alloc::string::String;
 
+use crate::PciAddress;
+
 #[derive(Debug)]
 pub enum Error {
     Unknown,
     ParseFail(String),
+    /// No bus number left below `bus_max` for the secondary bus of `bridge`
+    BusNumberExhausted {
+        bridge: PciAddress,
+        bus_max: u8,
+    },
+    InvalidArgument(&'static str),
+    NotSupported(&'static str),
+    /// The BAR allocator has no window left for a BAR of `address`
+    NoBarSpace {
+        address: PciAddress,
+    },
+    /// Assigning enumeration without a BAR allocator configured
+    NoBarAllocator {
+        address: PciAddress,
+    },
+    /// No free MSI-X vector or host interrupt message left for `address`
+    NoVectorSpace {
+        address: PciAddress,
+    },
+    /// The link did not train after the device was released from reset
+    LinkDown,
+    /// The device did not finish an operation in time
+    Timeout,
+    /// `address` misbehaved while being probed and was skipped, with the
+    /// buses below it if it is a bridge
+    DeviceFault {
+        address: PciAddress,
+        fault: DeviceFault,
+    },
+    /// ECAM window not aligned to a bus (1MiB) or smaller than one bus
+    InvalidEcamWindow {
+        base: usize,
+        size: usize,
+    },
+    /// No host bridge window covers `address`
+    NotTranslatable {
+        address: u64,
+    },
+}
+
+/// How a function misbehaved during enumeration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceFault {
+    /// Register `offset` read as all-ones although the Vendor ID did not,
+    /// e.g. a completer that stopped responding or surprise removal
+    AllOnes { offset: u16 },
+    /// Probing took `elapsed_ms`, more than the budget of
+    /// [`crate::PciIterator::with_probe_budget`]
+    Slow { elapsed_ms: u64 },
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::BusNumberExhausted { bridge, bus_max } => defmt::write!(
+                f,
+                "BusNumberExhausted {{ bridge: {}, bus_max: {=u8} }}",
+                crate::Bdf(*bridge),
+                bus_max
+            ),
+            Self::NoBarSpace { address } => {
+                defmt::write!(f, "NoBarSpace {{ address: {} }}", crate::Bdf(*address))
+            }
+            Self::NoBarAllocator { address } => {
+                defmt::write!(f, "NoBarAllocator {{ address: {} }}", crate::Bdf(*address))
+            }
+            Self::NoVectorSpace { address } => {
+                defmt::write!(f, "NoVectorSpace {{ address: {} }}", crate::Bdf(*address))
+            }
+            Self::DeviceFault { address, fault } => defmt::write!(
+                f,
+                "DeviceFault {{ address: {}, fault: {} }}",
+                crate::Bdf(*address),
+                defmt::Debug2Format(fault)
+            ),
+            other => defmt::write!(f, "{}", defmt::Debug2Format(other)),
+        }
+    }
 }
 
 pub type Result<T = ()> = core::result::Result<T, Error>;
@@ -0,0 +1,93 @@
+use core::{fmt::Display, ops::Range};
+
+use crate::{err::Error, Bdf, PciAddress};
+
+/// Something the enumerator did or ran into, see
+/// [`crate::PciIterator::with_events`].
+#[derive(Debug, Clone, Copy)]
+pub enum ScanEvent<'e> {
+    DeviceFound {
+        address: PciAddress,
+        vendor_id: u16,
+        device_id: u16,
+    },
+    BridgeFound {
+        address: PciAddress,
+        secondary_bus: u8,
+    },
+    BarAssigned {
+        address: PciAddress,
+        /// BAR register, the low half for 64-bit BARs
+        index: u8,
+        range: &'e Range<u64>,
+    },
+    /// A function was not probed because a quirk or the
+    /// [`crate::ResourcePolicy`] said so
+    Skipped { address: PciAddress },
+    /// BAR outside the windows of [`crate::BusAddressTranslator`]
+    BarNotRoutable {
+        address: PciAddress,
+        range: &'e Range<u64>,
+    },
+    /// 64-bit BARs were kept below 4GiB because `bridge` above has a 32-bit
+    /// prefetchable window
+    Below4GFallback {
+        address: PciAddress,
+        bridge: PciAddress,
+    },
+    /// Also recorded in [`crate::PciIterator::errors`]
+    Error {
+        address: PciAddress,
+        error: &'e Error,
+    },
+}
+
+impl ScanEvent<'_> {
+    pub fn address(&self) -> PciAddress {
+        match *self {
+            Self::DeviceFound { address, .. }
+            | Self::BridgeFound { address, .. }
+            | Self::BarAssigned { address, .. }
+            | Self::Skipped { address }
+            | Self::BarNotRoutable { address, .. }
+            | Self::Below4GFallback { address, .. }
+            | Self::Error { address, .. } => address,
+        }
+    }
+
+    /// Whether the event reports a problem, these are logged as warnings
+    /// when no callback is installed.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            Self::BarNotRoutable { .. } | Self::Below4GFallback { .. } | Self::Error { .. }
+        )
+    }
+}
+
+impl Display for ScanEvent<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: ", Bdf(self.address()))?;
+        match self {
+            Self::DeviceFound {
+                vendor_id,
+                device_id,
+                ..
+            } => write!(f, "found {vendor_id:04x}:{device_id:04x}"),
+            Self::BridgeFound { secondary_bus, .. } => {
+                write!(f, "bridge to bus {secondary_bus:02x}")
+            }
+            Self::BarAssigned { index, range, .. } => write!(f, "BAR{index} {range:#x?}"),
+            Self::Skipped { .. } => write!(f, "skipped"),
+            Self::BarNotRoutable { range, .. } => {
+                write!(f, "BAR {range:#x?} is not routable")
+            }
+            Self::Below4GFallback { bridge, .. } => write!(
+                f,
+                "64-bit BARs kept below 4GiB, {} has no 64-bit prefetchable window",
+                Bdf(*bridge)
+            ),
+            Self::Error { error, .. } => write!(f, "{error:?}"),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Declarative fake hierarchies for regression tests.
+//!
+//! A [`Fixture`] describes a tree of mocked functions and places each one on
+//! the bus the enumerator will assign to it, [`golden`] renders a scanned
+//! [`PciTopology`] one node per line for comparison with [`assert_golden`]:
+//!
+//! ```text
+//! 0000:00:00.0 1b36:000c bridge 01-02
+//! 0000:01:00.0 1b36:0010 bar0 0x10000000+0x4000
+//! ```
+//!
+//! [`scan_cost`] counts the config accesses an enumeration of a fixture
+//! takes, the figure to watch when touching the probe path. Probes of empty
+//! slots are counted apart, they depend on the bus layout only.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+use crate::{
+    enumerate_by_controller, Bdf, MockController, MockFunction, NodeKind, PciMem32, PciTopology,
+    PcieController,
+};
+
+/// Hierarchy below one bus, functions in any order.
+#[derive(Clone, Default)]
+pub struct Fixture {
+    slots: Vec<Slot>,
+}
+
+#[derive(Clone)]
+struct Slot {
+    device: u8,
+    function: u8,
+    config: MockFunction,
+    below: Option<Fixture>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn endpoint(mut self, device: u8, function: u8, config: MockFunction) -> Self {
+        self.slots.push(Slot {
+            device,
+            function,
+            config,
+            below: None,
+        });
+        self
+    }
+
+    /// `config` should come from [`MockFunction::bridge`].
+    pub fn bridge(
+        mut self,
+        device: u8,
+        function: u8,
+        config: MockFunction,
+        below: Fixture,
+    ) -> Self {
+        self.slots.push(Slot {
+            device,
+            function,
+            config,
+            below: Some(below),
+        });
+        self
+    }
+
+    /// Controller with every function on its bus, numbered depth first as a
+    /// fresh enumeration does. Function 0 of devices with several functions
+    /// is marked multi-function.
+    pub fn controller(&self) -> MockController {
+        let mut controller = MockController::new();
+        let mut next_bus = 0;
+        self.place(&mut controller, 0, &mut next_bus);
+        controller
+    }
+
+    fn place(&self, controller: &mut MockController, bus: u8, next_bus: &mut u8) {
+        let mut slots: Vec<&Slot> = self.slots.iter().collect();
+        slots.sort_by_key(|s| (s.device, s.function));
+        for slot in slots {
+            let mut config = slot.config.clone();
+            let siblings = self.slots.iter().filter(|s| s.device == slot.device);
+            if slot.function == 0 && siblings.count() > 1 {
+                config = config.multifunction();
+            }
+            *controller =
+                core::mem::take(controller).with_function(bus, slot.device, slot.function, config);
+            if let Some(below) = &slot.below {
+                *next_bus += 1;
+                below.place(controller, *next_bus, next_bus);
+            }
+        }
+    }
+}
+
+/// QEMU's NVMe controller (1b36:0010) without BARs, the endpoint most
+/// fixtures are made of.
+pub fn nvme() -> MockFunction {
+    MockFunction::endpoint(0x1b36, 0x0010, [0x01, 0x08, 0x02])
+}
+
+/// Eight endpoints behind a bridge and a multi-function device beside it,
+/// each with two 32-bit BARs. The [`scan_cost`] figures are pinned on it.
+pub fn mixed() -> Fixture {
+    let endpoint = || {
+        nvme()
+            .with_bar32(0, 0x4000, false)
+            .with_bar32(1, 0x1000, false)
+    };
+    let below = (0..8).fold(Fixture::new(), |f, device| {
+        f.endpoint(device, 0, endpoint())
+    });
+    Fixture::new()
+        .bridge(0, 0, MockFunction::bridge(0x1b36, 0x000c), below)
+        .endpoint(1, 0, endpoint())
+        .endpoint(1, 1, endpoint())
+}
+
+/// Config accesses of one enumeration, see [`scan_cost`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanCost {
+    /// Endpoints found
+    pub functions: usize,
+    pub reads: usize,
+    /// Reads of slots without a function, part of `reads`
+    pub absent_reads: usize,
+    pub writes: usize,
+}
+
+impl ScanCost {
+    /// Reads spent on functions found, probes of empty slots left out.
+    pub fn reads_per_function(&self) -> usize {
+        (self.reads - self.absent_reads) / self.functions.max(1)
+    }
+}
+
+/// Enumerate `fixture` once, assigning BARs from 256MiB below 4GiB, and
+/// count its config accesses. Without `header_snapshot` every header
+/// register is read when it is needed, the baseline to compare against.
+/// The snapshot only saves header registers read more than once; BAR sizing
+/// and probes of empty slots cost the same either way.
+pub fn scan_cost(fixture: &Fixture, header_snapshot: bool) -> ScanCost {
+    let mock = fixture.controller();
+    let count = mock.access_count();
+    let mut controller = PcieController::new(mock);
+    controller.set_mem32(
+        PciMem32 {
+            address: 0x1000_0000,
+            size: 0x1000_0000,
+        },
+        false,
+    );
+    let functions = enumerate_by_controller(&mut controller, None)
+        .with_header_snapshot(header_snapshot)
+        .count();
+    ScanCost {
+        functions,
+        reads: count.reads(),
+        absent_reads: count.absent_reads(),
+        writes: count.writes(),
+    }
+}
+
+/// One line per node in scan order: address, IDs, then bus numbers of
+/// bridges or the memory BARs of endpoints.
+pub fn golden(topology: &PciTopology) -> String {
+    let mut out = String::new();
+    for (_, node) in topology.nodes() {
+        let _ = write!(
+            out,
+            "{} {:04x}:{:04x}",
+            Bdf(node.address),
+            node.vendor_id,
+            node.device_id
+        );
+        match &node.kind {
+            NodeKind::Bridge { bus, .. } => {
+                let _ = write!(out, " bridge {:02x}-{:02x}", bus.secondary, bus.subordinate);
+            }
+            NodeKind::Endpoint { bars } => {
+                for bar in bars {
+                    let _ = write!(
+                        out,
+                        " bar{} {:#x}+{:#x}",
+                        bar.index,
+                        bar.range.start,
+                        bar.range.end - bar.range.start
+                    );
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Panic at the first line where `topology` differs from `expected`.
+/// Leading and trailing whitespace of lines and blank lines are ignored.
+pub fn assert_golden(topology: &PciTopology, expected: &str) {
+    let actual = golden(topology);
+    let lines = |s: &str| -> Vec<String> {
+        s.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let (actual_lines, expected_lines) = (lines(&actual), lines(expected));
+    for i in 0..actual_lines.len().max(expected_lines.len()) {
+        let a = actual_lines.get(i).map_or("<none>", String::as_str);
+        let e = expected_lines.get(i).map_or("<none>", String::as_str);
+        assert!(
+            a == e,
+            "topology differs at line {}:\n  expected: {e}\n  actual:   {a}\nfull topology:\n{actual}",
+            i + 1
+        );
+    }
+}
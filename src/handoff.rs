@@ -0,0 +1,275 @@
+//! Configuration left by one kernel for the next, e.g. across kexec, so the
+//! second stage can take over bus numbers and BARs instead of enumerating.
+//!
+//! The blob is little endian: a `PCIH` header with version, segment and
+//! record count, then one fixed-size record per function in scan order.
+
+use alloc::vec::Vec;
+
+use crate::{
+    err::Error, BarResource, CommandRegister, HeaderType, NodeKind, PciAddress, PciHeaderBase,
+    PciNode, PciTopology, PcieController, RootComplex,
+};
+
+const MAGIC: &[u8; 4] = b"PCIH";
+const VERSION: u16 = 2;
+const HEADER_LEN: usize = 12;
+const RECORD_LEN: usize = 60;
+/// 0x10..=0x30: BARs and ROM of endpoints; BARs, bus numbers and windows
+/// of bridges
+const REGS: usize = 9;
+
+const FLAG_BRIDGE: u8 = 1 << 0;
+const FLAG_PCIE: u8 = 1 << 1;
+
+/// Saved state of one function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    address: PciAddress,
+    flags: u8,
+    id: u32,
+    command: u16,
+    bridge_control: u16,
+    device_control: u16,
+    device_control2: u16,
+    regs: [u32; REGS],
+    /// log2 of the size of each endpoint BAR, 0 for none or the high half
+    bar_sizes: [u8; 6],
+}
+
+impl Record {
+    fn read(controller: &mut PcieController, address: PciAddress) -> Option<Self> {
+        let base = PciHeaderBase::new(controller, address)?;
+        let bridge = base.header_type() == HeaderType::PciPciBridge;
+        let pcie = base.pcie_capability().map(|cap| cap.offset());
+        let mut flags = 0;
+        if bridge {
+            flags |= FLAG_BRIDGE;
+        }
+        if pcie.is_some() {
+            flags |= FLAG_PCIE;
+        }
+        Some(Self {
+            address,
+            flags,
+            id: base.read(0x00),
+            command: base.read(0x04) as u16,
+            bridge_control: if bridge {
+                (base.read(0x3c) >> 16) as u16
+            } else {
+                0
+            },
+            device_control: pcie.map_or(0, |cap| base.read(cap + 0x08) as u16),
+            device_control2: pcie.map_or(0, |cap| base.read(cap + 0x28) as u16),
+            regs: core::array::from_fn(|i| base.read(0x10 + i as u16 * 4)),
+            bar_sizes: [0; 6],
+        })
+    }
+
+    /// Node with the saved BAR sizes, for reading the topology without
+    /// sizing BARs that may be in use. `None` for bridges.
+    fn known_node(&self) -> Option<PciNode> {
+        if self.flags & FLAG_BRIDGE != 0 {
+            return None;
+        }
+        let bars = (0..6)
+            .filter(|&i| self.bar_sizes[i] != 0)
+            .map(|i| {
+                let low = self.regs[i];
+                let high = match (low & 0x6 == 0x4, self.regs.get(i + 1)) {
+                    (true, Some(&high)) => high as u64,
+                    _ => 0,
+                };
+                let start = high << 32 | (low & !0xf) as u64;
+                BarResource {
+                    index: i as u8,
+                    range: start..start + (1 << self.bar_sizes[i]),
+                    prefetchable: low & 0x8 != 0,
+                }
+            })
+            .collect();
+        Some(PciNode {
+            address: self.address,
+            vendor_id: self.id as u16,
+            device_id: (self.id >> 16) as u16,
+            base_class: 0,
+            sub_class: 0,
+            port_type: None,
+            kind: NodeKind::Endpoint { bars },
+            parent: None,
+            children: Vec::new(),
+        })
+    }
+
+    /// Whether the live function `self` still looks like `saved`. Bus
+    /// mastering may have been turned off by the drivers going down.
+    fn matches(&self, saved: &Self) -> bool {
+        let decode = (CommandRegister::IO_ENABLE | CommandRegister::MEMORY_ENABLE).bits();
+        let regs = self
+            .regs
+            .iter()
+            .zip(&saved.regs)
+            .enumerate()
+            .all(|(i, (a, b))| {
+                // Secondary Status shares the dword with the I/O window
+                let mask = if i == 3 && self.flags & FLAG_BRIDGE != 0 {
+                    0xffff
+                } else {
+                    u32::MAX
+                };
+                a & mask == b & mask
+            });
+        // Initiate FLR / Bridge Configuration Retry Enable reads back as 0
+        let device_control = !(1 << 15);
+        self.id == saved.id
+            && self.flags == saved.flags
+            && self.command & decode == saved.command & decode
+            && self.bridge_control == saved.bridge_control
+            && self.device_control & device_control == saved.device_control & device_control
+            && self.device_control2 == saved.device_control2
+            && regs
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[
+            self.address.bus(),
+            self.address.device(),
+            self.address.function(),
+            self.flags,
+        ]);
+        out.extend_from_slice(&self.id.to_le_bytes());
+        for half in [
+            self.command,
+            self.bridge_control,
+            self.device_control,
+            self.device_control2,
+        ] {
+            out.extend_from_slice(&half.to_le_bytes());
+        }
+        for reg in self.regs {
+            out.extend_from_slice(&reg.to_le_bytes());
+        }
+        out.extend_from_slice(&self.bar_sizes);
+        out.extend_from_slice(&[0; 2]);
+    }
+
+    fn decode(segment: u16, data: &[u8]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        Self {
+            address: PciAddress::new(segment, data[0], data[1], data[2]),
+            flags: data[3],
+            id: u32_at(4),
+            command: u16_at(8),
+            bridge_control: u16_at(10),
+            device_control: u16_at(12),
+            device_control2: u16_at(14),
+            regs: core::array::from_fn(|i| u32_at(16 + i * 4)),
+            bar_sizes: data[52..58].try_into().unwrap(),
+        }
+    }
+}
+
+fn decode(blob: &[u8]) -> Result<(u16, Vec<Record>), Error> {
+    let header = blob
+        .get(..HEADER_LEN)
+        .ok_or(Error::InvalidArgument("truncated handoff state"))?;
+    if &header[..4] != MAGIC {
+        return Err(Error::InvalidArgument("not a handoff state"));
+    }
+    if u16::from_le_bytes([header[4], header[5]]) != VERSION {
+        return Err(Error::NotSupported("handoff state version"));
+    }
+    let segment = u16::from_le_bytes([header[6], header[7]]);
+    let count = u16::from_le_bytes([header[8], header[9]]) as usize;
+    if blob.len() != HEADER_LEN + count * RECORD_LEN {
+        return Err(Error::InvalidArgument("truncated handoff state"));
+    }
+    let records = blob[HEADER_LEN..]
+        .as_chunks::<RECORD_LEN>()
+        .0
+        .iter()
+        .map(|r| Record::decode(segment, r))
+        .collect();
+    Ok((segment, records))
+}
+
+impl RootComplex {
+    /// Bus numbers, BARs and their sizes, bridge windows and enabled
+    /// features (Command, Bridge Control, PCIe Device Control 1/2) of every
+    /// function of the stored topology, for [`Self::import_state`] in the
+    /// next kernel.
+    pub fn export_state(&mut self) -> Result<Vec<u8>, Error> {
+        let topology = self
+            .topology()
+            .ok_or(Error::InvalidArgument("nothing scanned to export"))?;
+        let nodes: Vec<_> = topology
+            .nodes()
+            .map(|(_, n)| {
+                let mut sizes = [0u8; 6];
+                if let NodeKind::Endpoint { bars } = &n.kind {
+                    for bar in bars {
+                        let size = bar.range.end - bar.range.start;
+                        sizes[bar.index as usize] = size.trailing_zeros() as u8;
+                    }
+                }
+                (n.address, sizes)
+            })
+            .collect();
+        let count = u16::try_from(nodes.len())
+            .map_err(|_| Error::NotSupported("too many functions to export"))?;
+
+        let mut blob = Vec::with_capacity(HEADER_LEN + nodes.len() * RECORD_LEN);
+        blob.extend_from_slice(MAGIC);
+        blob.extend_from_slice(&VERSION.to_le_bytes());
+        blob.extend_from_slice(&self.segment().to_le_bytes());
+        blob.extend_from_slice(&count.to_le_bytes());
+        blob.extend_from_slice(&[0; 2]);
+        for (address, bar_sizes) in nodes {
+            let record = Record::read(self.controller(), address)
+                .ok_or(Error::InvalidArgument("function gone since the scan"))?;
+            Record {
+                bar_sizes,
+                ..record
+            }
+            .encode(&mut blob);
+        }
+        Ok(blob)
+    }
+
+    /// Take over the configuration saved by [`Self::export_state`] instead
+    /// of enumerating. Every saved function must still be there with the
+    /// same IDs, bus numbers, BARs, windows and features, and no function
+    /// may have been added, nothing is adopted otherwise. BARs are not sized
+    /// again. On success the topology is stored as by [`Self::scan`], later
+    /// enumerations walk it read-only and the adopted BARs and windows are
+    /// kept clear of when [`Self::rescan_bus`] assigns new endpoints.
+    ///
+    /// Returns the number of functions adopted.
+    pub fn import_state(&mut self, blob: &[u8]) -> Result<usize, Error> {
+        let (segment, records) = decode(blob)?;
+        if segment != self.segment() {
+            return Err(Error::InvalidArgument("handoff state of another segment"));
+        }
+        for saved in &records {
+            let live = Record::read(self.controller(), saved.address);
+            if !live.is_some_and(|live| live.matches(saved)) {
+                return Err(Error::InvalidArgument(
+                    "handoff state does not match the hardware",
+                ));
+            }
+        }
+        let known =
+            PciTopology::from_nodes(records.iter().filter_map(Record::known_node).collect());
+        let bus = self.first_bus();
+        let topology = PciTopology::read(self.controller(), segment, bus, false, Some(&known));
+        let saved = |address| records.iter().any(|r| r.address == address);
+        if !topology.nodes().all(|(_, n)| saved(n.address)) {
+            return Err(Error::InvalidArgument(
+                "function added since the handoff state was saved",
+            ));
+        }
+        self.adopt(topology);
+        Ok(records.len())
+    }
+}
@@ -0,0 +1,252 @@
+//! Flat, versioned device list for handing enumeration results to another
+//! address space (e.g. userland servers over IPC) without config reads.
+//!
+//! Encoded little-endian with fixed size records:
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 16    | [`InventoryHeader`] |
+//! | `count * entry_size` | [`InventoryEntry`] records |
+//!
+//! Readers accept any `entry_size` at least [`InventoryEntry::SIZE`] so
+//! later versions can append fields to a record.
+
+use alloc::vec::Vec;
+
+use crate::{err::Error, BarKind, Endpoint, PciAddress};
+
+pub const INVENTORY_MAGIC: u32 = u32::from_le_bytes(*b"PCIV");
+pub const INVENTORY_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InventoryHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub entry_size: u16,
+    pub count: u32,
+    pub reserved: u32,
+}
+
+impl InventoryHeader {
+    pub const SIZE: usize = 16;
+}
+
+/// Memory BAR summary, `size == 0` for unimplemented BARs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InventoryBar {
+    pub address: u64,
+    pub size: u64,
+    pub flags: u32,
+}
+
+impl InventoryBar {
+    pub const FLAG_PREFETCHABLE: u32 = 1 << 0;
+    pub const FLAG_64BIT: u32 = 1 << 1;
+    pub const SIZE: usize = 20;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InventoryEntry {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub revision_id: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub base_class: u8,
+    pub sub_class: u8,
+    pub interface: u8,
+    /// 0 = none, 1 = INTA ... 4 = INTD
+    pub intx_pin: u8,
+    pub intx_line: u8,
+    /// Supported MSI vectors, 0 without MSI
+    pub msi_vectors: u8,
+    /// MSI-X table size, 0 without MSI-X
+    pub msix_vectors: u16,
+    /// By BAR register, a 64-bit BAR in the slot of its low half
+    pub bars: [InventoryBar; 6],
+}
+
+impl InventoryEntry {
+    pub const SIZE: usize = 18 + 6 * InventoryBar::SIZE;
+
+    pub fn new(ep: &Endpoint) -> Self {
+        let address = ep.address();
+        let class = ep.revision_and_class();
+        let irq = ep.interrupt_summary();
+        let mut bars = [InventoryBar::default(); 6];
+        for info in ep.bar_info().filter(|b| b.kind != BarKind::Io) {
+            let mut flags = 0;
+            if info.kind == BarKind::Mem64 {
+                flags |= InventoryBar::FLAG_64BIT;
+            }
+            if info.prefetchable {
+                flags |= InventoryBar::FLAG_PREFETCHABLE;
+            }
+            bars[info.index as usize] = InventoryBar {
+                address: info.assigned.unwrap_or(0),
+                size: info.size,
+                flags,
+            };
+        }
+        Self {
+            segment: address.segment(),
+            bus: address.bus(),
+            device: address.device(),
+            function: address.function(),
+            revision_id: class.revision_id,
+            vendor_id: ep.vendor_id(),
+            device_id: ep.device_id(),
+            base_class: class.base_class,
+            sub_class: class.sub_class,
+            interface: class.interface,
+            intx_pin: irq.intx_pin.unwrap_or(0),
+            intx_line: ep.interrupt_line(),
+            msi_vectors: irq.msi.map_or(0, |m| m.vectors),
+            msix_vectors: irq.msix.map_or(0, |m| m.table_size),
+            bars,
+        }
+    }
+
+    pub fn address(&self) -> PciAddress {
+        PciAddress::new(self.segment, self.bus, self.device, self.function)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.segment.to_le_bytes());
+        out.extend_from_slice(&[self.bus, self.device, self.function, self.revision_id]);
+        out.extend_from_slice(&self.vendor_id.to_le_bytes());
+        out.extend_from_slice(&self.device_id.to_le_bytes());
+        out.extend_from_slice(&[
+            self.base_class,
+            self.sub_class,
+            self.interface,
+            self.intx_pin,
+            self.intx_line,
+            self.msi_vectors,
+        ]);
+        out.extend_from_slice(&self.msix_vectors.to_le_bytes());
+        for bar in &self.bars {
+            out.extend_from_slice(&bar.address.to_le_bytes());
+            out.extend_from_slice(&bar.size.to_le_bytes());
+            out.extend_from_slice(&bar.flags.to_le_bytes());
+        }
+    }
+
+    fn decode(b: &[u8]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+        let mut bars = [InventoryBar::default(); 6];
+        for (i, bar) in bars.iter_mut().enumerate() {
+            let at = 18 + i * InventoryBar::SIZE;
+            *bar = InventoryBar {
+                address: u64_at(at),
+                size: u64_at(at + 8),
+                flags: u32_at(at + 16),
+            };
+        }
+        Self {
+            segment: u16_at(0),
+            bus: b[2],
+            device: b[3],
+            function: b[4],
+            revision_id: b[5],
+            vendor_id: u16_at(6),
+            device_id: u16_at(8),
+            base_class: b[10],
+            sub_class: b[11],
+            interface: b[12],
+            intx_pin: b[13],
+            intx_line: b[14],
+            msi_vectors: b[15],
+            msix_vectors: u16_at(16),
+            bars,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceInventory {
+    entries: Vec<InventoryEntry>,
+}
+
+impl DeviceInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, entry: InventoryEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[InventoryEntry] {
+        &self.entries
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(InventoryHeader::SIZE + self.entries.len() * InventoryEntry::SIZE);
+        out.extend_from_slice(&INVENTORY_MAGIC.to_le_bytes());
+        out.extend_from_slice(&INVENTORY_VERSION.to_le_bytes());
+        out.extend_from_slice(&(InventoryEntry::SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        for entry in &self.entries {
+            entry.encode(&mut out);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let header = bytes
+            .get(..InventoryHeader::SIZE)
+            .ok_or(Error::InvalidArgument("inventory truncated"))?;
+        let header = InventoryHeader {
+            magic: u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            version: u16::from_le_bytes([header[4], header[5]]),
+            entry_size: u16::from_le_bytes([header[6], header[7]]),
+            count: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+            reserved: u32::from_le_bytes(header[12..16].try_into().unwrap()),
+        };
+        if header.magic != INVENTORY_MAGIC {
+            return Err(Error::InvalidArgument("not a device inventory"));
+        }
+        if header.version == 0 || header.version > INVENTORY_VERSION {
+            return Err(Error::NotSupported("device inventory version"));
+        }
+        let entry_size = header.entry_size as usize;
+        if entry_size < InventoryEntry::SIZE {
+            return Err(Error::InvalidArgument("inventory entry too small"));
+        }
+        let body = &bytes[InventoryHeader::SIZE..];
+        if body.len() / entry_size < header.count as usize {
+            return Err(Error::InvalidArgument("inventory truncated"));
+        }
+        let entries = body
+            .chunks_exact(entry_size)
+            .take(header.count as usize)
+            .map(InventoryEntry::decode)
+            .collect();
+        Ok(Self { entries })
+    }
+}
+
+impl FromIterator<InventoryEntry> for DeviceInventory {
+    fn from_iter<T: IntoIterator<Item = InventoryEntry>>(iter: T) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a Endpoint> for DeviceInventory {
+    fn from_iter<T: IntoIterator<Item = &'a Endpoint>>(iter: T) -> Self {
+        iter.into_iter().map(InventoryEntry::new).collect()
+    }
+}
@@ -0,0 +1,72 @@
+//! Per-endpoint input for interrupt remapping tables (VT-d IRTE, AMD-Vi
+//! DTE, ITS device tables), keyed by requester ID.
+
+use alloc::vec::Vec;
+
+use crate::{Endpoint, PciAddress, PciHeaderBase, PciTopology, PcieController, RootComplex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct IrqRemapEntry {
+    pub segment: u16,
+    /// `bus << 8 | device << 3 | function`, the source ID of MSI writes
+    pub requester_id: u16,
+    /// Supported MSI vectors, 0 without MSI
+    pub msi_vectors: u8,
+    /// MSI-X table size, 0 without MSI-X
+    pub msix_vectors: u16,
+    /// 1 = INTA ... 4 = INTD, 0 without INTx
+    pub intx_pin: u8,
+    /// `intx_pin` after swizzling up to the root bus
+    pub intx_root_pin: u8,
+    /// Root bus device the interrupt map (device tree, _PRT) is looked up
+    /// with, the endpoint itself on the root bus
+    pub intx_root_device: u8,
+}
+
+pub fn requester_id(address: PciAddress) -> u16 {
+    (address.bus() as u16) << 8 | (address.device() as u16) << 3 | address.function() as u16
+}
+
+/// One entry per endpoint of `topology`, in scan order. MSI capabilities are
+/// read from the live config space.
+pub fn irq_remap_table(
+    controller: &mut PcieController,
+    topology: &PciTopology,
+) -> Vec<IrqRemapEntry> {
+    let mut table = Vec::new();
+    for node in topology.endpoints() {
+        let address = node.address;
+        let Some(base) = PciHeaderBase::new(controller, address) else {
+            continue;
+        };
+        let irq = Endpoint::new(base).interrupt_summary();
+        let pin = irq.intx_pin.unwrap_or(0);
+        let root_device = topology
+            .upstream_path(address)
+            .and_then(|path| path.first().copied())
+            .unwrap_or(address)
+            .device();
+        table.push(IrqRemapEntry {
+            segment: address.segment(),
+            requester_id: requester_id(address),
+            msi_vectors: irq.msi.map_or(0, |msi| msi.vectors),
+            msix_vectors: irq.msix.map_or(0, |msix| msix.table_size),
+            intx_pin: pin,
+            intx_root_pin: topology.intx_swizzle(address, pin).unwrap_or(0),
+            intx_root_device: root_device,
+        });
+    }
+    table
+}
+
+impl RootComplex {
+    /// [`irq_remap_table`] of the topology kept by the last scan, empty
+    /// before any.
+    pub fn irq_remap_table(&mut self) -> Vec<IrqRemapEntry> {
+        let Some(topology) = self.topology().cloned() else {
+            return Vec::new();
+        };
+        irq_remap_table(self.controller(), &topology)
+    }
+}
@@ -0,0 +1,192 @@
+//! Config access from interrupt handlers, e.g. to mask an MSI vector,
+//! serialized with the read-modify-write cycles of task context.
+
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+use pci_types::ConfigRegionAccess;
+
+use crate::{
+    err::Error, PciAddress, PciHeaderBase, PcieController, RootComplex, CAP_ID_MSI, CAP_ID_MSIX,
+};
+
+/// Spin lock that keeps interrupts on the local CPU disabled while held,
+/// provided by the embedder, e.g. over its `spin_lock_irqsave`.
+pub trait IrqLock: Send + Sync {
+    /// Disable local interrupts and take the lock, returns what
+    /// [`Self::unlock`] restores.
+    fn lock(&self) -> usize;
+
+    fn unlock(&self, saved: usize);
+}
+
+pub(crate) struct IrqLockGuard<'a> {
+    lock: &'a dyn IrqLock,
+    saved: usize,
+}
+
+impl<'a> IrqLockGuard<'a> {
+    pub(crate) fn new(lock: &'a dyn IrqLock) -> Self {
+        let saved = lock.lock();
+        Self { lock, saved }
+    }
+}
+
+impl Drop for IrqLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.unlock(self.saved);
+    }
+}
+
+/// Config access handed to [`RootComplex::with_irq_safe_access`].
+pub struct IrqConfig<'a> {
+    controller: &'a mut PcieController,
+}
+
+impl IrqConfig<'_> {
+    pub fn read(&mut self, address: PciAddress, offset: u16) -> u32 {
+        let access = self.controller.config_access(address.into());
+        unsafe { access.read(address.into(), offset & !0x3) }
+    }
+
+    pub fn write(&mut self, address: PciAddress, offset: u16, value: u32) {
+        let access = self.controller.config_access(address.into());
+        unsafe { access.write(address.into(), offset & !0x3, value) }
+    }
+
+    /// Set or clear the per-vector mask bit of MSI `vector`.
+    pub fn set_msi_masked(
+        &mut self,
+        address: PciAddress,
+        vector: u8,
+        masked: bool,
+    ) -> Result<(), Error> {
+        set_msi_masked(&self.function(address)?, vector, masked)
+    }
+
+    /// Set or clear the MSI-X Function Mask, masking every vector at once
+    /// without touching the table.
+    pub fn set_msix_function_masked(
+        &mut self,
+        address: PciAddress,
+        masked: bool,
+    ) -> Result<(), Error> {
+        set_msix_function_masked(&self.function(address)?, masked)
+    }
+
+    fn function(&mut self, address: PciAddress) -> Result<PciHeaderBase, Error> {
+        PciHeaderBase::new(self.controller, address)
+            .ok_or(Error::InvalidArgument("no device at address"))
+    }
+}
+
+/// Shared config access to one function for interrupt handlers, from
+/// [`RootComplex::irq_handle`]. Every call holds the lock of
+/// [`RootComplex::with_irq_lock`], which the read-modify-write cycles of
+/// functions found through the [`RootComplex`] take as well.
+#[derive(Clone)]
+pub struct IrqConfigHandle {
+    lock: Arc<dyn IrqLock>,
+    function: Arc<PciHeaderBase>,
+}
+
+impl IrqConfigHandle {
+    pub fn address(&self) -> PciAddress {
+        self.function.address()
+    }
+
+    pub fn read(&self, offset: u16) -> u32 {
+        let _guard = IrqLockGuard::new(&*self.lock);
+        self.function.read(offset & !0x3)
+    }
+
+    pub fn write(&self, offset: u16, value: u32) {
+        let _guard = IrqLockGuard::new(&*self.lock);
+        self.function.write(offset & !0x3, value);
+    }
+
+    /// See [`IrqConfig::set_msi_masked`].
+    pub fn set_msi_masked(&self, vector: u8, masked: bool) -> Result<(), Error> {
+        let _guard = IrqLockGuard::new(&*self.lock);
+        set_msi_masked(&self.function, vector, masked)
+    }
+
+    /// See [`IrqConfig::set_msix_function_masked`].
+    pub fn set_msix_function_masked(&self, masked: bool) -> Result<(), Error> {
+        let _guard = IrqLockGuard::new(&*self.lock);
+        set_msix_function_masked(&self.function, masked)
+    }
+}
+
+/// The IRQ lock is held by the caller.
+fn set_msi_masked(base: &PciHeaderBase, vector: u8, masked: bool) -> Result<(), Error> {
+    let offset = base
+        .find_capability(CAP_ID_MSI)
+        .ok_or(Error::NotSupported("no MSI capability"))?;
+    let control = base.read(offset).get_bits(16..32);
+    if !control.get_bit(8) {
+        return Err(Error::NotSupported("no MSI per-vector masking"));
+    }
+    if vector as u32 >= 1 << control.get_bits(1..4).min(5) {
+        return Err(Error::InvalidArgument("MSI vector out of range"));
+    }
+    // Mask Bits follow the 32 or 64-bit message address and the data
+    let mask = offset + if control.get_bit(7) { 0x10 } else { 0x0c };
+    let mut bits = base.read(mask);
+    bits.set_bit(vector as usize, masked);
+    base.write(mask, bits);
+    Ok(())
+}
+
+/// The IRQ lock is held by the caller.
+fn set_msix_function_masked(base: &PciHeaderBase, masked: bool) -> Result<(), Error> {
+    let offset = base
+        .find_capability(CAP_ID_MSIX)
+        .ok_or(Error::NotSupported("no MSI-X capability"))?;
+    let mut data = base.read(offset);
+    data.set_bit(30, masked);
+    base.write(offset, data);
+    Ok(())
+}
+
+impl RootComplex {
+    /// Lock taken by [`Self::with_irq_safe_access`], [`IrqConfigHandle`]
+    /// and by the read-modify-write cycles of config accessors and of
+    /// functions found through this root complex, e.g.
+    /// [`PciHeaderBase::update_command`] or MSI/MSI-X enable.
+    pub fn with_irq_lock(mut self, lock: impl IrqLock + 'static) -> Self {
+        self.irq_lock = Some(Arc::new(lock));
+        self
+    }
+
+    /// Run `f` with local interrupts disabled and the lock given to
+    /// [`Self::with_irq_lock`] held. Fails without such a lock.
+    pub fn with_irq_safe_access<R>(
+        &mut self,
+        f: impl FnOnce(&mut IrqConfig<'_>) -> R,
+    ) -> Result<R, Error> {
+        let lock = self
+            .irq_lock
+            .clone()
+            .ok_or(Error::NotSupported("no IRQ lock configured"))?;
+        let _guard = IrqLockGuard::new(&*lock);
+        Ok(f(&mut IrqConfig {
+            controller: self.controller(),
+        }))
+    }
+
+    /// Handle to the function at `address` that interrupt handlers can keep
+    /// and use through `&self`. Fails without [`Self::with_irq_lock`].
+    pub fn irq_handle(&mut self, address: PciAddress) -> Result<IrqConfigHandle, Error> {
+        let lock = self
+            .irq_lock
+            .clone()
+            .ok_or(Error::NotSupported("no IRQ lock configured"))?;
+        let function = PciHeaderBase::new(self.controller(), address)
+            .ok_or(Error::InvalidArgument("no device at address"))?;
+        Ok(IrqConfigHandle {
+            lock,
+            function: Arc::new(function),
+        })
+    }
+}
@@ -1,22 +1,81 @@
+//! Enumeration is compiled once: chips are erased behind [`PcieController`]
+//! and every config access of a function goes through one concrete handle
+//! around its `ConfigAccess`, so supporting another chip type doesn't
+//! duplicate the scan and BAR code.
 #![no_std]
 
 #[macro_use]
 extern crate alloc;
 
+#[cfg(not(feature = "defmt"))]
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "defmt")]
+#[macro_use]
+mod defmt_log;
+
+mod aer_dispatch;
+#[cfg(feature = "audit")]
+pub mod audit;
 mod bar_alloc;
+mod cache;
 mod chip;
+mod delay;
 pub mod err;
+mod event;
+#[cfg(feature = "fixture")]
+pub mod fixture;
+mod handoff;
+mod inventory;
+mod irq_remap;
+mod irq_safe;
+#[cfg(feature = "lint")]
+pub mod lint;
+mod lock;
+mod mmio;
+mod parallel;
+pub mod passthrough;
+mod policy;
+mod ratelimit;
+mod rcrb;
 mod root;
+#[cfg(feature = "sysfs")]
+pub mod sysfs;
+mod topology;
+mod translate;
 mod types;
 
-pub use chip::PcieGeneric;
+pub use chip::{
+    parse_dma_ranges, program_inbound, AccessPolicy, ConfigQuirk, DwPcieHost, EcamRegion,
+    HostWindow, HostWindows, InboundAtu, InboundWindow, LinkCounter, LinkMeasurement, LinkMonitor,
+    OutboundAtu, PcieGeneric, PciePhy, PortControl, QemuVirt, Rk3588Pcie, TegraPcie, WindowKind,
+};
+#[cfg(feature = "mock")]
+pub use chip::{AccessCount, MockController, MockFunction};
+#[cfg(target_arch = "x86_64")]
+pub use chip::{PortIo, PortIoCam};
 pub use rdif_pcie::Interface as Controller;
 pub use rdif_pcie::{PciMem32, PciMem64, PcieController};
 
+pub use aer_dispatch::{AerEvent, AerSeverity};
 pub use bar_alloc::*;
+pub use cache::{CachedConfig, ConfigIdentity};
+pub use delay::Delay;
+pub use event::ScanEvent;
+pub use inventory::*;
+pub use irq_remap::{irq_remap_table, requester_id, IrqRemapEntry};
+pub use irq_safe::{IrqConfig, IrqConfigHandle, IrqLock};
+pub use mmio::MmioMapper;
+pub use parallel::{scan_parallel, ScanExecutor, ScanJob, SequentialExecutor};
+pub use policy::{DeviceOverride, ResourcePolicy};
+pub use ratelimit::{EventLimiter, HwEvent};
+pub use rcrb::{Rcrb, RcrbCapability};
+pub use topology::{BarResource, NodeId, NodeKind, PciNode, PciTopology, RescanEvent};
+pub use translate::{BusAddressTranslator, IdentityTranslator, WindowTranslator};
 pub use types::*;
 
-pub use root::enumerate_by_controller;
+pub use root::{
+    enumerate_by_controller, required_window_sizes, EndpointIterExt, PciIterator, ResumeToken,
+    RootComplex, VgaPolicy, WindowSizes,
+};
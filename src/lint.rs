@@ -0,0 +1,131 @@
+//! Compliance checks over a scanned hierarchy.
+//!
+//! [`lint`] flags configurations that are against the spec or will bite a
+//! driver later. Findings are advisory, nothing is changed.
+
+use alloc::vec::Vec;
+
+use crate::{
+    CommandRegister, DevicePortType, NodeId, NodeKind, PciAddress, PciHeaderBase, PciTopology,
+    PcieController, RootComplex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finding {
+    /// Memory decoding is on while memory BAR `index` is still at 0
+    MemoryEnabledWithUnassignedBar { address: PciAddress, index: u8 },
+    /// The window is disabled (base above limit) but BARs below need it
+    InvertedWindow {
+        bridge: PciAddress,
+        prefetchable: bool,
+    },
+    /// Max Payload Size differs across a link
+    MpsMismatch {
+        upstream: PciAddress,
+        downstream: PciAddress,
+        upstream_mps: u16,
+        downstream_mps: u16,
+    },
+    /// Switch downstream port without ACS, functions below can't be
+    /// isolated from each other by an IOMMU
+    MissingAcs { port: PciAddress },
+}
+
+/// Check every node of `topology`, reading the live config space.
+pub fn lint(controller: &mut PcieController, topology: &PciTopology) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (id, node) in topology.nodes() {
+        let Some(base) = PciHeaderBase::new(controller, node.address) else {
+            continue;
+        };
+        match &node.kind {
+            NodeKind::Endpoint { bars } => {
+                let memory_enabled = base.command().contains(CommandRegister::MEMORY_ENABLE);
+                for bar in bars.iter().filter(|b| b.range.start == 0) {
+                    if memory_enabled {
+                        findings.push(Finding::MemoryEnabledWithUnassignedBar {
+                            address: node.address,
+                            index: bar.index,
+                        });
+                    }
+                }
+            }
+            NodeKind::Bridge {
+                memory,
+                prefetchable,
+                ..
+            } => {
+                let (needs_memory, needs_prefetchable) = window_needs(topology, id);
+                if needs_memory && memory.is_none() {
+                    findings.push(Finding::InvertedWindow {
+                        bridge: node.address,
+                        prefetchable: false,
+                    });
+                }
+                // Prefetchable BARs may also sit in the memory window
+                if needs_prefetchable && prefetchable.is_none() && memory.is_none() {
+                    findings.push(Finding::InvertedWindow {
+                        bridge: node.address,
+                        prefetchable: true,
+                    });
+                }
+            }
+        }
+
+        let cap = base.pcie_capability();
+        if node.port_type == Some(DevicePortType::DownstreamPort) && base.acs().is_none() {
+            findings.push(Finding::MissingAcs { port: node.address });
+        }
+        let (Some(cap), Some(parent)) = (cap, node.parent) else {
+            continue;
+        };
+        let downstream_mps = cap.max_payload_size();
+        let upstream = topology.node(parent).address;
+        let Some(upstream_base) = PciHeaderBase::new(controller, upstream) else {
+            continue;
+        };
+        if let Some(upstream_cap) = upstream_base.pcie_capability() {
+            let upstream_mps = upstream_cap.max_payload_size();
+            if upstream_mps != downstream_mps {
+                findings.push(Finding::MpsMismatch {
+                    upstream,
+                    downstream: node.address,
+                    upstream_mps,
+                    downstream_mps,
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Whether assigned BARs below `bridge` need its memory and prefetchable
+/// windows.
+fn window_needs(topology: &PciTopology, bridge: NodeId) -> (bool, bool) {
+    let mut needs = (false, false);
+    let mut pending = topology.node(bridge).children.clone();
+    while let Some(id) = pending.pop() {
+        let node = topology.node(id);
+        pending.extend_from_slice(&node.children);
+        if let NodeKind::Endpoint { bars } = &node.kind {
+            for bar in bars.iter().filter(|b| b.range.start != 0) {
+                if bar.prefetchable {
+                    needs.1 = true;
+                } else {
+                    needs.0 = true;
+                }
+            }
+        }
+    }
+    needs
+}
+
+impl RootComplex {
+    /// [`lint`] the topology kept by the last scan, empty before any.
+    pub fn lint(&mut self) -> Vec<Finding> {
+        let Some(topology) = self.topology().cloned() else {
+            return Vec::new();
+        };
+        lint(self.controller(), &topology)
+    }
+}
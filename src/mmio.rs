@@ -0,0 +1,16 @@
+use core::ptr::NonNull;
+
+/// Maps device memory (BAR regions) into the CPU address space.
+pub trait MmioMapper {
+    /// Map `size` bytes at PCI address `address` as device memory.
+    fn map(&self, address: u64, size: usize) -> NonNull<u8>;
+}
+
+impl<F> MmioMapper for F
+where
+    F: Fn(u64, usize) -> NonNull<u8>,
+{
+    fn map(&self, address: u64, size: usize) -> NonNull<u8> {
+        self(address, size)
+    }
+}
@@ -0,0 +1,49 @@
+//! Enumerating several host bridges at once, one job per [`RootComplex`].
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{PciTopology, RootComplex};
+
+/// Scan job handed to a [`ScanExecutor`]
+pub type ScanJob<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Runs jobs concurrently, e.g. one per CPU, provided by the kernel.
+///
+/// `run` must not return before every job has finished, the jobs borrow
+/// the caller's stack.
+pub trait ScanExecutor {
+    fn run<'a>(&self, jobs: Vec<ScanJob<'a>>);
+}
+
+/// Runs the jobs one after another on the calling CPU.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequentialExecutor;
+
+impl ScanExecutor for SequentialExecutor {
+    fn run<'a>(&self, jobs: Vec<ScanJob<'a>>) {
+        jobs.into_iter().for_each(|job| job());
+    }
+}
+
+/// [`RootComplex::scan`] every root through `executor` and merge the
+/// results in the order of `roots`. Roots sharing buses of a segment can't
+/// assign them at the same time, give each its own segment with
+/// [`RootComplex::with_segment`].
+pub fn scan_parallel(
+    roots: &mut [RootComplex],
+    executor: &(impl ScanExecutor + ?Sized),
+) -> PciTopology {
+    let mut results: Vec<Option<PciTopology>> = roots.iter().map(|_| None).collect();
+    let jobs = roots
+        .iter_mut()
+        .zip(results.iter_mut())
+        .map(|(root, result)| Box::new(move || *result = Some(root.scan())) as ScanJob<'_>)
+        .collect();
+    executor.run(jobs);
+
+    let mut merged = PciTopology::default();
+    for topology in results.into_iter().flatten() {
+        merged.merge(topology);
+    }
+    merged
+}
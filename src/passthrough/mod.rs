@@ -0,0 +1,7 @@
+//! Helpers for assigning devices to guests.
+
+mod msix;
+mod shadow;
+
+pub use msix::*;
+pub use shadow::ShadowConfig;
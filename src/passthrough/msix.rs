@@ -0,0 +1,273 @@
+use core::ops::Range;
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    err::Error, BusAddressTranslator, MsiAllocator, MsiMessage, MsixCapability, MsixTable,
+    PciAddress,
+};
+
+const PAGE_SIZE: u64 = 0x1000;
+const ENTRY_SIZE: u64 = 16;
+
+/// Guest MSI-X entry update the VMM has to route: interrupts arriving on
+/// `host` must be injected into the guest as `guest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsixRemap {
+    pub vector: u16,
+    pub guest: MsiMessage,
+    pub host: MsiMessage,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VirtualEntry {
+    guest: MsiMessage,
+    masked: bool,
+    host: Option<MsiMessage>,
+}
+
+/// Guest view of a BAR holding the table or the PBA
+#[derive(Debug, Clone, Copy)]
+struct GuestBar {
+    index: u8,
+    is_64: bool,
+    address: u64,
+}
+
+impl GuestBar {
+    fn new(cap: &MsixCapability<'_>, index: u8) -> Self {
+        let offset = 0x10 + index as u16 * 4;
+        let low = cap.header().read(offset);
+        let is_64 = low & 0x6 == 0x4 && index < 5;
+        let mut address = (low & !0xf) as u64;
+        if is_64 {
+            address |= (cap.header().read(offset + 4) as u64) << 32;
+        }
+        Self {
+            index,
+            is_64,
+            address,
+        }
+    }
+
+    fn register(&self) -> u16 {
+        0x10 + self.index as u16 * 4
+    }
+}
+
+/// Emulated MSI-X table of a passed-through device.
+///
+/// The guest never reaches the hardware table: accesses to the pages holding
+/// the table or the PBA must be trapped and forwarded to [`Self::read`] and
+/// [`Self::write`]. Hardware entries only ever hold messages handed out by the
+/// [`MsiAllocator`].
+///
+/// The trapped pages move with the BARs holding them, guest writes to those
+/// BAR registers must go through [`Self::write_bar_register`] so
+/// [`Self::locate_guest`] keeps finding them.
+pub struct VirtualMsix {
+    device: PciAddress,
+    table: MsixTable,
+    table_bar: u8,
+    table_offset: u64,
+    pba_bar: u8,
+    pba_offset: u64,
+    guest_bars: Vec<GuestBar>,
+    entries: Vec<VirtualEntry>,
+    translator: Option<Arc<dyn BusAddressTranslator>>,
+}
+
+impl VirtualMsix {
+    /// Masks every hardware entry, `table` must be the mapped table of `cap`.
+    pub fn new(device: PciAddress, cap: &MsixCapability<'_>, table: MsixTable) -> Self {
+        let len = table.len();
+        for vector in 0..len {
+            table.mask(vector);
+        }
+        let mut guest_bars = vec![GuestBar::new(cap, cap.table_bar())];
+        if cap.pba_bar() != cap.table_bar() {
+            guest_bars.push(GuestBar::new(cap, cap.pba_bar()));
+        }
+        Self {
+            device,
+            table,
+            table_bar: cap.table_bar(),
+            table_offset: cap.table_offset() as u64,
+            pba_bar: cap.pba_bar(),
+            pba_offset: cap.pba_offset() as u64,
+            guest_bars,
+            entries: vec![
+                VirtualEntry {
+                    guest: MsiMessage {
+                        address: 0,
+                        data: 0
+                    },
+                    masked: true,
+                    host: None,
+                };
+                len as usize
+            ],
+            translator: None,
+        }
+    }
+
+    /// Program host doorbells as the bus addresses given by `translator`
+    /// instead of as is.
+    pub fn with_translator(mut self, translator: Arc<dyn BusAddressTranslator>) -> Self {
+        self.translator = Some(translator);
+        self
+    }
+
+    /// BAR relative ranges the VMM must not map into the guest
+    pub fn trapped_ranges(&self) -> [(u8, Range<u64>); 2] {
+        let table_len = self.entries.len() as u64 * ENTRY_SIZE;
+        let pba_len = (self.entries.len() as u64).div_ceil(64) * 8;
+        [
+            (self.table_bar, page_range(self.table_offset, table_len)),
+            (self.pba_bar, page_range(self.pba_offset, pba_len)),
+        ]
+    }
+
+    pub fn traps(&self, bar: u8, offset: u64) -> bool {
+        self.trapped_ranges()
+            .iter()
+            .any(|(b, range)| *b == bar && range.contains(&offset))
+    }
+
+    /// Config offsets of the BAR registers holding the table and the PBA,
+    /// with the upper halves of 64-bit BARs.
+    pub fn bar_registers(&self) -> impl Iterator<Item = u16> + '_ {
+        self.guest_bars.iter().flat_map(|bar| {
+            let upper = bar.is_64.then_some(bar.register() + 4);
+            core::iter::once(bar.register()).chain(upper)
+        })
+    }
+
+    /// Guest write of config dword `offset`, after the VMM applied the BAR
+    /// size mask. Returns `false` if it is not one of [`Self::bar_registers`].
+    pub fn write_bar_register(&mut self, offset: u16, value: u32) -> bool {
+        for bar in &mut self.guest_bars {
+            if offset == bar.register() {
+                bar.address = (bar.address & !0xffff_ffff) | (value & !0xf) as u64;
+                return true;
+            }
+            if bar.is_64 && offset == bar.register() + 4 {
+                bar.address = (bar.address & 0xffff_ffff) | (value as u64) << 32;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// (BAR, offset) of a guest physical access to a trapped page, at the
+    /// BAR addresses the guest programmed.
+    pub fn locate_guest(&self, address: u64) -> Option<(u8, u64)> {
+        self.guest_bars.iter().find_map(|bar| {
+            let offset = address.checked_sub(bar.address)?;
+            self.traps(bar.index, offset).then_some((bar.index, offset))
+        })
+    }
+
+    /// Guest read of a trapped dword. Pending bits are not emulated.
+    pub fn read(&self, bar: u8, offset: u64) -> u32 {
+        let Some((vector, dword)) = self.locate(bar, offset) else {
+            return 0;
+        };
+        let entry = &self.entries[vector as usize];
+        match dword {
+            0 => entry.guest.address as u32,
+            1 => (entry.guest.address >> 32) as u32,
+            2 => entry.guest.data,
+            _ => entry.masked as u32,
+        }
+    }
+
+    /// Guest write of a trapped dword. Returns the routing the VMM has to
+    /// install when an entry becomes, or stays, unmasked.
+    ///
+    /// Fails with [`Error::NoVectorSpace`] when the allocator has no message
+    /// left and with [`Error::NotTranslatable`] when the host doorbell is not
+    /// reachable from PCI, the entry stays masked in hardware.
+    pub fn write(
+        &mut self,
+        bar: u8,
+        offset: u64,
+        value: u32,
+        allocator: &mut (impl MsiAllocator + ?Sized),
+    ) -> Result<Option<MsixRemap>, Error> {
+        let Some((vector, dword)) = self.locate(bar, offset) else {
+            return Ok(None);
+        };
+        let entry = &mut self.entries[vector as usize];
+        match dword {
+            0 => entry.guest.address = (entry.guest.address & !0xffff_ffff) | value as u64,
+            1 => entry.guest.address = (entry.guest.address & 0xffff_ffff) | (value as u64) << 32,
+            2 => entry.guest.data = value,
+            _ => entry.masked = value & 1 != 0,
+        }
+
+        if entry.masked {
+            self.table.mask(vector);
+            return Ok(None);
+        }
+
+        let host = match entry.host {
+            Some(host) => host,
+            None => {
+                let host = allocator.alloc(self.device).ok_or(Error::NoVectorSpace {
+                    address: self.device,
+                })?;
+                let doorbell = match &self.translator {
+                    Some(t) => t.cpu_to_bus(host.address),
+                    None => Some(host.address),
+                };
+                let Some(doorbell) = doorbell else {
+                    allocator.free(self.device, host);
+                    return Err(Error::NotTranslatable {
+                        address: host.address,
+                    });
+                };
+                entry.host = Some(host);
+                self.table.mask(vector);
+                self.table.set_message(vector, doorbell, host.data);
+                host
+            }
+        };
+        self.table.unmask(vector);
+        Ok(Some(MsixRemap {
+            vector,
+            guest: entry.guest,
+            host,
+        }))
+    }
+
+    /// Mask all entries and give the host messages back.
+    pub fn release(&mut self, allocator: &mut (impl MsiAllocator + ?Sized)) {
+        for (vector, entry) in self.entries.iter_mut().enumerate() {
+            self.table.mask(vector as u16);
+            entry.masked = true;
+            if let Some(host) = entry.host.take() {
+                allocator.free(self.device, host);
+            }
+        }
+    }
+
+    /// (vector, dword) of a table access, `None` for PBA or untrapped accesses
+    fn locate(&self, bar: u8, offset: u64) -> Option<(u16, u8)> {
+        if bar != self.table_bar || offset < self.table_offset {
+            return None;
+        }
+        let rel = offset - self.table_offset;
+        let vector = rel / ENTRY_SIZE;
+        if vector >= self.entries.len() as u64 {
+            return None;
+        }
+        Some((vector as u16, ((rel % ENTRY_SIZE) / 4) as u8))
+    }
+}
+
+fn page_range(offset: u64, len: u64) -> Range<u64> {
+    let start = offset & !(PAGE_SIZE - 1);
+    let end = (offset + len).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    start..end
+}
@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+
+use crate::{BarKind, Endpoint, HeaderType, PciAddress, PciHeaderBase};
+
+/// Command bits a guest may change: I/O, memory, bus master, parity error
+/// response, SERR# and interrupt disable
+const COMMAND_MASK: u32 = 0x0547;
+/// Error bits of Status and Secondary Status, RW1C
+const STATUS_RW1C: u32 = 0xf900;
+
+/// Copy of a function's config space with a write mask per dword, for VMMs
+/// emulating the config space of a passed-through device.
+///
+/// Only bits set in the write mask change on [`Self::write`], RW1C bits are
+/// cleared by writing 1. [`Self::capture`] sets up the standard header, the
+/// capabilities start out read-only, open them with [`Self::set_write_mask`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowConfig {
+    address: PciAddress,
+    data: Vec<u32>,
+    write_mask: Vec<u32>,
+    rw1c: Vec<u32>,
+}
+
+impl ShadowConfig {
+    /// Snapshot of `base`, of [`PciHeaderBase::config_space_size`].
+    pub fn capture(base: &PciHeaderBase) -> Self {
+        let len = base.config_space_size() as usize / 4;
+        let mut shadow = Self {
+            address: base.address(),
+            data: (0..len).map(|i| base.read(i as u16 * 4)).collect(),
+            write_mask: vec![0; len],
+            rw1c: vec![0; len],
+        };
+        shadow.set_write_mask(0x04, COMMAND_MASK);
+        shadow.set_rw1c(0x04, STATUS_RW1C << 16);
+        // Cache line size and latency timer
+        shadow.set_write_mask(0x0c, 0xffff);
+        // Interrupt line
+        shadow.set_write_mask(0x3c, 0xff);
+        if base.header_type() == HeaderType::PciPciBridge {
+            // Bus numbers and secondary latency timer
+            shadow.set_write_mask(0x18, 0xffff_ffff);
+            // I/O base and limit, Secondary Status
+            shadow.set_write_mask(0x1c, 0xf0f0);
+            shadow.set_rw1c(0x1c, STATUS_RW1C << 16);
+            // Memory and prefetchable windows with their upper halves, I/O
+            // upper halves
+            shadow.set_write_mask(0x20, 0xfff0_fff0);
+            shadow.set_write_mask(0x24, 0xfff0_fff0);
+            for offset in [0x28, 0x2c, 0x30] {
+                shadow.set_write_mask(offset, 0xffff_ffff);
+            }
+            // Interrupt line and Bridge Control
+            shadow.set_write_mask(0x3c, 0x0fff_00ff);
+        }
+        shadow
+    }
+
+    /// [`Self::capture`] with BARs writable as far as their size allows, so
+    /// the guest can size them. Sizes the BARs, quiesce the device first.
+    pub fn capture_endpoint(ep: &Endpoint) -> Self {
+        let mut shadow = Self::capture(ep);
+        for bar in ep.bar_info() {
+            let offset = 0x10 + bar.index as u16 * 4;
+            let mask = !(bar.size.max(1) - 1);
+            match bar.kind {
+                BarKind::Mem32 => shadow.set_write_mask(offset, mask as u32 & !0xf),
+                BarKind::Mem64 => {
+                    shadow.set_write_mask(offset, mask as u32 & !0xf);
+                    shadow.set_write_mask(offset + 4, (mask >> 32) as u32);
+                }
+                BarKind::Io => shadow.set_write_mask(offset, mask as u32 & !0x3),
+            }
+        }
+        shadow
+    }
+
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    /// Size in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len() * 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Dword containing `offset`, all-ones beyond the captured size.
+    pub fn read(&self, offset: u16) -> u32 {
+        self.data
+            .get(offset as usize / 4)
+            .copied()
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Guest write of the dword containing `offset`. Sub-dword writes must
+    /// be merged with [`Self::read`] by the caller, with RW1C bits of the
+    /// untouched bytes written as 0.
+    pub fn write(&mut self, offset: u16, value: u32) {
+        let i = offset as usize / 4;
+        let (Some(data), Some(mask), Some(rw1c)) = (
+            self.data.get_mut(i),
+            self.write_mask.get(i),
+            self.rw1c.get(i),
+        ) else {
+            return;
+        };
+        *data = (*data & !mask) | (value & mask);
+        *data &= !(value & rw1c);
+    }
+
+    pub fn write_mask(&self, offset: u16) -> u32 {
+        self.write_mask
+            .get(offset as usize / 4)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Bits of the dword containing `offset` the guest may change.
+    pub fn set_write_mask(&mut self, offset: u16, mask: u32) {
+        if let Some(m) = self.write_mask.get_mut(offset as usize / 4) {
+            *m = mask;
+        }
+    }
+
+    /// Bits of the dword containing `offset` cleared by writing 1.
+    pub fn set_rw1c(&mut self, offset: u16, bits: u32) {
+        if let Some(m) = self.rw1c.get_mut(offset as usize / 4) {
+            *m = bits;
+        }
+    }
+
+    /// Update the shadow from the device without touching the masks, e.g.
+    /// for status bits the hardware sets.
+    pub fn set_raw(&mut self, offset: u16, value: u32) {
+        if let Some(data) = self.data.get_mut(offset as usize / 4) {
+            *data = value;
+        }
+    }
+}
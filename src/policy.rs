@@ -0,0 +1,173 @@
+//! Per-device overrides of the enumeration, e.g. from a boot parameter:
+//!
+//! ```text
+//! 0000:01:00.0=skip;02:00.0=prefer64,no-msi;cls=64;lat=32
+//! ```
+//!
+//! `cls` and `lat` program Cache Line Size (bytes) and Latency Timer of
+//! every conventional PCI function.
+
+use alloc::{string::ToString, vec::Vec};
+use core::{ops::BitOr, str::FromStr};
+
+use crate::{err::Error, Bdf, InterruptPolicy, PciAddress};
+
+/// What to do differently for one function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DeviceOverride(u8);
+
+impl DeviceOverride {
+    /// Don't probe, like [`crate::AccessPolicy::Skip`]
+    pub const SKIP: Self = Self(1 << 0);
+    /// Place 64-bit BARs above 4GiB even if firmware put them below
+    pub const PREFER64: Self = Self(1 << 1);
+    /// Keep to INTx: MSI and MSI-X are turned off when enumerating, see
+    /// also [`ResourcePolicy::interrupt_policy`]
+    pub const NO_MSI: Self = Self(1 << 2);
+
+    const NAMES: [(Self, &'static str); 3] = [
+        (Self::SKIP, "skip"),
+        (Self::PREFER64, "prefer64"),
+        (Self::NO_MSI, "no-msi"),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitOr for DeviceOverride {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl FromStr for DeviceOverride {
+    type Err = Error;
+
+    /// Comma separated `skip`, `prefer64` and `no-msi`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',').try_fold(Self::empty(), |value, token| {
+            let (flag, _) = Self::NAMES
+                .iter()
+                .find(|(_, name)| *name == token.trim())
+                .ok_or_else(|| Error::ParseFail(token.to_string()))?;
+            Ok(value | *flag)
+        })
+    }
+}
+
+/// Overrides by address, handed to [`crate::PciIterator::with_policy`] or
+/// [`crate::RootComplex::with_policy`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourcePolicy {
+    overrides: Vec<(PciAddress, DeviceOverride)>,
+    cache_line_size: Option<u16>,
+    latency_timer: Option<u8>,
+}
+
+impl ResourcePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds to the overrides already given for `address`.
+    pub fn with(mut self, address: PciAddress, value: DeviceOverride) -> Self {
+        match self.overrides.iter_mut().find(|(a, _)| *a == address) {
+            Some((_, existing)) => existing.insert(value),
+            None => self.overrides.push((address, value)),
+        }
+        self
+    }
+
+    /// Cache Line Size in bytes for conventional PCI functions, e.g. the
+    /// CPU cache line. PCIe functions ignore it and are left alone.
+    pub fn with_cache_line_size(mut self, bytes: u16) -> Result<Self, Error> {
+        if !bytes.is_power_of_two() || !(4..=512).contains(&bytes) {
+            return Err(Error::InvalidArgument("cache line size"));
+        }
+        self.cache_line_size = Some(bytes);
+        Ok(self)
+    }
+
+    /// Latency Timer in PCI clocks for conventional PCI functions, and the
+    /// Secondary Latency Timer of PCIe-to-PCI bridges.
+    pub fn with_latency_timer(mut self, clocks: u8) -> Self {
+        self.latency_timer = Some(clocks);
+        self
+    }
+
+    pub fn cache_line_size(&self) -> Option<u16> {
+        self.cache_line_size
+    }
+
+    pub fn latency_timer(&self) -> Option<u8> {
+        self.latency_timer
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty() && self.cache_line_size.is_none() && self.latency_timer.is_none()
+    }
+
+    /// Empty for functions without overrides.
+    pub fn get(&self, address: PciAddress) -> DeviceOverride {
+        self.overrides
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|(_, v)| *v)
+            .unwrap_or_default()
+    }
+
+    /// `base` with MSI and MSI-X taken away for `no-msi` functions, for
+    /// [`crate::configure_interrupts`].
+    pub fn interrupt_policy(&self, address: PciAddress, base: InterruptPolicy) -> InterruptPolicy {
+        if self.get(address).contains(DeviceOverride::NO_MSI) {
+            InterruptPolicy {
+                allow_msi: false,
+                allow_msix: false,
+                ..base
+            }
+        } else {
+            base
+        }
+    }
+}
+
+impl FromStr for ResourcePolicy {
+    type Err = Error;
+
+    /// `bdf=override[,override]` entries separated by `;` or whitespace, see
+    /// the module docs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(|c: char| c == ';' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .try_fold(Self::new(), |policy, entry| {
+                let fail = || Error::ParseFail(entry.to_string());
+                let (key, value) = entry.split_once('=').ok_or_else(fail)?;
+                match key {
+                    "cls" => policy
+                        .with_cache_line_size(value.parse().map_err(|_| fail())?)
+                        .map_err(|_| fail()),
+                    "lat" => Ok(policy.with_latency_timer(value.parse().map_err(|_| fail())?)),
+                    bdf => {
+                        let Bdf(address) = bdf.parse()?;
+                        Ok(policy.with(address, value.parse()?))
+                    }
+                }
+            })
+    }
+}
@@ -0,0 +1,109 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::{Bdf, PciAddress, RootComplex};
+
+/// Recurring hardware conditions that may come in storms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwEvent {
+    CrsRetry,
+    LinkFlap,
+    CorrectableError,
+    UncorrectableError,
+}
+
+impl HwEvent {
+    const COUNT: usize = 4;
+}
+
+/// Allocation free, lock free rate limiter for [`HwEvent`]s.
+///
+/// At most `burst` events of each kind are reported per `window` ticks, the
+/// rest are counted and summarized with the first report of the next window.
+/// Safe to use from interrupt handlers.
+pub struct EventLimiter {
+    burst: u32,
+    window: usize,
+    window_start: [AtomicUsize; HwEvent::COUNT],
+    count: [AtomicU32; HwEvent::COUNT],
+    suppressed: [AtomicU32; HwEvent::COUNT],
+}
+
+impl EventLimiter {
+    /// `window` is in the same unit as the `now` passed to [`Self::record`].
+    pub const fn new(burst: u32, window: usize) -> Self {
+        Self {
+            burst,
+            window,
+            window_start: [const { AtomicUsize::new(0) }; HwEvent::COUNT],
+            count: [const { AtomicU32::new(0) }; HwEvent::COUNT],
+            suppressed: [const { AtomicU32::new(0) }; HwEvent::COUNT],
+        }
+    }
+
+    /// Returns `Some(suppressed)` if this occurrence should be reported, where
+    /// `suppressed` is the number of dropped occurrences since the last report.
+    pub fn record(&self, event: HwEvent, now: usize) -> Option<u32> {
+        let i = event as usize;
+        let start = self.window_start[i].load(Ordering::Relaxed);
+        if now.wrapping_sub(start) >= self.window {
+            self.window_start[i].store(now, Ordering::Relaxed);
+            self.count[i].store(1, Ordering::Relaxed);
+            return Some(self.suppressed[i].swap(0, Ordering::Relaxed));
+        }
+
+        let n = self.count[i].fetch_add(1, Ordering::Relaxed) + 1;
+        if n <= self.burst {
+            Some(0)
+        } else {
+            self.suppressed[i].fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// [`Self::record`] and log the event if it passes the limit.
+    pub fn report(&self, event: HwEvent, address: PciAddress, now: usize) {
+        match self.record(event, now) {
+            Some(0) => warn!("{}: {event:?}", Bdf(address)),
+            Some(suppressed) => warn!("{}: {event:?} ({suppressed} suppressed)", Bdf(address)),
+            None => {}
+        }
+    }
+
+    pub fn suppressed(&self, event: HwEvent) -> u32 {
+        self.suppressed[event as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// [`EventLimiter`] with the clock it is fed from, see
+/// [`RootComplex::with_event_limiter`].
+#[derive(Clone)]
+pub(crate) struct RateLimit {
+    limiter: &'static EventLimiter,
+    now: Arc<dyn Fn() -> usize + Send + Sync>,
+}
+
+impl RateLimit {
+    pub(crate) fn report(&self, event: HwEvent, address: PciAddress) {
+        self.limiter.report(event, address, (self.now)());
+    }
+}
+
+impl RootComplex {
+    /// Log recurring hardware conditions through `limiter`, `now` reading
+    /// the clock its window is measured in: CRS retries while enumerating,
+    /// AER errors without a handler and links lost below hot-plug ports,
+    /// see [`Self::handle_hotplug_interrupt`]. Without a limiter only the
+    /// AER errors are logged, each one.
+    pub fn with_event_limiter(
+        mut self,
+        limiter: &'static EventLimiter,
+        now: impl Fn() -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.rate_limit = Some(RateLimit {
+            limiter,
+            now: Arc::new(now),
+        });
+        self
+    }
+}
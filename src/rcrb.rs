@@ -0,0 +1,127 @@
+use core::ptr::NonNull;
+
+use crate::{ExtCapWalker, ExtendedCapability, ExtendedCapabilityHeader, PciAddress, RootComplex};
+
+/// Root Complex Register Block.
+///
+/// A 4KiB memory mapped block holding root complex level extended
+/// capabilities that don't live in any function's config space. Its base
+/// address comes from the platform (e.g. ACPI RCRB structures or RCRB Header
+/// capabilities).
+pub struct Rcrb {
+    base: NonNull<u8>,
+}
+
+unsafe impl Send for Rcrb {}
+
+impl Rcrb {
+    pub const SIZE: usize = 0x1000;
+
+    pub fn new(base: NonNull<u8>) -> Self {
+        Self { base }
+    }
+
+    /// All ones past the end of the block, like a read nothing answers.
+    pub fn read(&self, offset: u16) -> u32 {
+        self.reg(offset).map_or(u32::MAX, |reg| {
+            u32::from_le(unsafe { reg.as_ptr().read_volatile() })
+        })
+    }
+
+    /// Dropped past the end of the block.
+    pub fn write(&self, offset: u16, value: u32) {
+        if let Some(reg) = self.reg(offset) {
+            unsafe { reg.as_ptr().write_volatile(value.to_le()) }
+        }
+    }
+
+    /// Extended capabilities of the RCRB, the list starts at offset 0.
+    pub fn extended_capabilities(&self) -> impl Iterator<Item = ExtendedCapabilityHeader> + '_ {
+        ExtCapWalker::new(|offset| self.read(offset), 0)
+    }
+
+    pub fn find_extended_capability(&self, id: u16) -> Option<u16> {
+        self.extended_capabilities()
+            .find(|cap| cap.id == id)
+            .map(|cap| cap.offset)
+    }
+
+    /// Like [`Self::extended_capabilities`], with register access relative
+    /// to each capability.
+    pub fn capabilities(&self) -> impl Iterator<Item = RcrbCapability<'_>> + '_ {
+        self.extended_capabilities()
+            .map(|header| RcrbCapability { rcrb: self, header })
+    }
+
+    pub fn capability(&self, id: u16) -> Option<RcrbCapability<'_>> {
+        self.capabilities().find(|cap| cap.header.id == id)
+    }
+
+    fn reg(&self, offset: u16) -> Option<NonNull<u32>> {
+        ((offset as usize) < Self::SIZE)
+            .then(|| unsafe { self.base.cast::<u32>().add((offset >> 2) as usize) })
+    }
+}
+
+/// Extended capability inside an [`Rcrb`], e.g. AER of root complex
+/// integrated endpoints or an event collector.
+pub struct RcrbCapability<'a> {
+    rcrb: &'a Rcrb,
+    header: ExtendedCapabilityHeader,
+}
+
+impl RcrbCapability<'_> {
+    pub fn header(&self) -> ExtendedCapabilityHeader {
+        self.header
+    }
+
+    pub fn kind(&self) -> ExtendedCapability {
+        ExtendedCapability::from(self.header)
+    }
+
+    /// Register `reg` bytes into the capability, all ones if that is past
+    /// the end of the RCRB.
+    pub fn read(&self, reg: u16) -> u32 {
+        self.header
+            .offset
+            .checked_add(reg)
+            .map_or(u32::MAX, |offset| self.rcrb.read(offset))
+    }
+
+    /// Dropped if `reg` is past the end of the RCRB.
+    pub fn write(&self, reg: u16, value: u32) {
+        if let Some(offset) = self.header.offset.checked_add(reg) {
+            self.rcrb.write(offset, value)
+        }
+    }
+}
+
+impl RootComplex {
+    /// Make `rcrb` known, `owner` is the root port or event collector it
+    /// belongs to, `None` for the root complex itself.
+    pub fn register_rcrb(&mut self, owner: Option<PciAddress>, rcrb: Rcrb) {
+        self.rcrbs.push((owner, rcrb));
+    }
+
+    pub fn rcrbs(&self) -> impl Iterator<Item = (Option<PciAddress>, &Rcrb)> {
+        self.rcrbs.iter().map(|(owner, rcrb)| (*owner, rcrb))
+    }
+
+    /// RCRB registered for `owner`, see [`Self::register_rcrb`].
+    pub fn rcrb(&self, owner: Option<PciAddress>) -> Option<&Rcrb> {
+        self.rcrbs
+            .iter()
+            .find(|(o, _)| *o == owner)
+            .map(|(_, rcrb)| rcrb)
+    }
+
+    /// First capability `id` across the registered RCRBs, with its owner.
+    pub fn find_rcrb_capability(
+        &self,
+        id: u16,
+    ) -> Option<(Option<PciAddress>, RcrbCapability<'_>)> {
+        self.rcrbs
+            .iter()
+            .find_map(|(owner, rcrb)| Some((*owner, rcrb.capability(id)?)))
+    }
+}
@@ -1,38 +1,772 @@
-use alloc::vec::Vec;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 
-use crate::chip::PcieController;
-use crate::PciAddress;
-use crate::{Endpoint, PciConfigSpace, PciHeaderBase, PciPciBridge};
-use core::hint::spin_loop;
+use crate::aer_dispatch::AerHandler;
+use crate::cache::{CachedConfig, ConfigIdentity};
+use crate::chip::{
+    AccessPolicy, ConfigQuirk, DynController, PcieController, PcieGeneric, PortControl, SharedQuirk,
+};
+use crate::err::{DeviceFault, Error};
+use crate::event::ScanEvent;
+use crate::irq_safe::{IrqLock, IrqLockGuard};
+use crate::lock::SpinLock;
+use crate::ratelimit::{HwEvent, RateLimit};
+use crate::topology::{NodeKind, PciTopology, RescanEvent};
+use crate::{BarInfo, BarKind, BarSpace, SimpleBarAllocator, WindowAllocator};
+use crate::{Bdf, BridgeControl, CommandRegister, DeviceType, HeaderType, MsiMessage, PciAddress};
+use crate::{BridgeConfigWriter, BusNumber, Endpoint, PciConfigSpace, PciHeaderBase, PciPciBridge};
+use crate::{BusAddressTranslator, HostWindows, IdentityTranslator, WindowKind};
+use crate::{CardBusBridge, Unknown, CAP_ID_PCIE, EXT_CAP_ID_AER};
+use crate::{Controller, Delay, Mem64Placement, Rcrb};
+use crate::{DeviceOverride, DevicePortType, InterruptPolicy, ResourcePolicy};
+use bit_field::BitField;
+use core::{
+    hint::spin_loop,
+    ops::{Range, RangeInclusive},
+    ptr::NonNull,
+};
+use pci_types::ConfigRegionAccess;
+
+/// `(segment, buses)` claimed by enumerations assigning bus numbers, at
+/// most one at a time per bus range.
+static SCAN_LOCK: SpinLock<Vec<(u16, RangeInclusive<u8>)>> = SpinLock::new(Vec::new());
+
+/// Claim `buses` of `segment` for a writing enumeration, `false` if one
+/// already runs on an overlapping range.
+fn claim_buses(segment: u16, buses: RangeInclusive<u8>) -> bool {
+    let mut held = SCAN_LOCK.lock();
+    let overlaps = held
+        .iter()
+        .any(|(s, b)| *s == segment && b.start() <= buses.end() && buses.start() <= b.end());
+    if !overlaps {
+        held.push((segment, buses));
+    }
+    !overlaps
+}
+
+fn release_buses(segment: u16, buses: RangeInclusive<u8>) {
+    let mut held = SCAN_LOCK.lock();
+    if let Some(i) = held.iter().position(|c| *c == (segment, buses.clone())) {
+        held.swap_remove(i);
+    }
+}
 
 const MAX_DEVICE: u8 = 31;
 const MAX_FUNCTION: u8 = 7;
+const MEM32_END: u64 = 0x1_0000_0000;
+
+/// Host bridge owning its controller, entry point for config access and
+/// enumeration.
+pub struct RootComplex {
+    controller: PcieController,
+    segment: u16,
+    buses: Range<usize>,
+    scanned: bool,
+    topology: Option<PciTopology>,
+    translator: Arc<dyn BusAddressTranslator>,
+    policy: ResourcePolicy,
+    io_window: Option<Range<u32>>,
+    /// BARs and bridge windows adopted from a previous kernel, kept clear
+    /// of when assigning new endpoints
+    reserved: Vec<Range<u64>>,
+    pub(crate) rcrbs: Vec<(Option<PciAddress>, Rcrb)>,
+    port_control: Option<Box<dyn PortControl + Send>>,
+    config_cache: Option<CachedConfig>,
+    pub(crate) irq_lock: Option<Arc<dyn IrqLock>>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) aer_handlers: Vec<(PciAddress, AerHandler)>,
+}
+
+/// Tpvperl, power and REFCLK stable before PERST# is released
+const PERST_DELAY_MS: u32 = 100;
+/// Trst, minimum Secondary Bus Reset assertion
+const HOT_RESET_MS: u32 = 2;
+/// Wait after reset before the first config request below the port
+const RESET_RECOVERY_MS: u32 = 100;
+
+impl RootComplex {
+    pub fn new(controller: PcieController) -> Self {
+        Self {
+            controller,
+            segment: 0,
+            buses: 0..0x100,
+            scanned: false,
+            topology: None,
+            translator: Arc::new(IdentityTranslator),
+            policy: ResourcePolicy::new(),
+            io_window: None,
+            reserved: Vec::new(),
+            rcrbs: Vec::new(),
+            port_control: None,
+            config_cache: None,
+            irq_lock: None,
+            rate_limit: None,
+            aer_handlers: Vec::new(),
+        }
+    }
+
+    /// Plain ECAM host bridge at `mmio_base`, see [`PcieGeneric::try_new`].
+    pub fn new_generic(mmio_base: NonNull<u8>, size: usize) -> Result<Self, Error> {
+        Ok(Self::new(PcieController::new(PcieGeneric::try_new(
+            mmio_base, size,
+        )?)))
+    }
+
+    /// For controllers only known as trait objects, e.g. picked by a
+    /// driver registry at runtime.
+    pub fn new_dyn(controller: Box<dyn Controller>) -> Self {
+        Self::new(PcieController::new(DynController(controller)))
+    }
+
+    /// Drive PERST# and REFCLK of root ports through board code, see
+    /// [`Self::bring_up_port`] and [`Self::reset_port`].
+    pub fn with_port_control(mut self, control: impl PortControl + Send + 'static) -> Self {
+        self.port_control = Some(Box::new(control));
+        self
+    }
+
+    /// Power-on sequence of the slot below root port `port`: REFCLK on,
+    /// PERST# released after Tpvperl, then the recovery time before config
+    /// requests. Call before [`Self::enumerate`] or [`Self::rescan_below`].
+    /// Nothing to do without [`Self::with_port_control`].
+    pub fn bring_up_port(&mut self, port: PciAddress, delay: &mut (impl Delay + ?Sized)) {
+        let Some(control) = &mut self.port_control else {
+            return;
+        };
+        control.assert_perst(port);
+        control.enable_refclk(port);
+        delay.delay_ms(PERST_DELAY_MS);
+        control.deassert_perst(port);
+        delay.delay_ms(RESET_RECOVERY_MS);
+    }
+
+    /// Reset everything below bridge `port` and rescan it: fundamental reset
+    /// through PERST# for root ports with [`Self::with_port_control`],
+    /// Secondary Bus Reset otherwise.
+    pub fn reset_port(
+        &mut self,
+        port: PciAddress,
+        delay: &mut (impl Delay + ?Sized),
+    ) -> Result<Vec<RescanEvent>, Error> {
+        let base = self
+            .function(port)
+            .ok_or(Error::InvalidArgument("no device at bridge address"))?;
+        if !matches!(base.header_type(), HeaderType::PciPciBridge) {
+            return Err(Error::InvalidArgument("not a PCI-PCI bridge"));
+        }
+        let root_port = base
+            .pcie_capability()
+            .is_some_and(|cap| cap.device_port_type() == DevicePortType::RootPort);
+        if root_port && self.port_control.is_some() {
+            self.bring_up_port(port, delay);
+        } else {
+            let mut bridge = PciPciBridge::new(base);
+            bridge.update_bridge_control(|c| c | BridgeControl::SECONDARY_BUS_RESET);
+            delay.delay_ms(HOT_RESET_MS);
+            bridge.update_bridge_control(|mut c| {
+                c.remove(BridgeControl::SECONDARY_BUS_RESET);
+                c
+            });
+            delay.delay_ms(RESET_RECOVERY_MS);
+        }
+        self.rescan_below(port)
+    }
+
+    /// PCI domain of this host bridge, 0 by default. Host bridges scanned
+    /// side by side with [`crate::scan_parallel`] need distinct ones.
+    pub fn with_segment(mut self, segment: u16) -> Self {
+        self.segment = segment;
+        self
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    /// Buses the controller decodes, e.g. from the `bus-range` device tree
+    /// property, all 256 by default. Enumeration and lookups start at the
+    /// first one.
+    pub fn with_bus_range(mut self, buses: Range<usize>) -> Self {
+        self.buses = buses;
+        self
+    }
+
+    /// Keep vendor, device, class and header type of functions once read,
+    /// see [`Self::identity`]. Dropped for the buses a rescan or reset
+    /// touches.
+    pub fn with_config_cache(mut self) -> Self {
+        self.config_cache = Some(CachedConfig::new());
+        self
+    }
+
+    pub fn config_cache(&self) -> Option<&CachedConfig> {
+        self.config_cache.as_ref()
+    }
+
+    /// Identification registers of `address`, from the cache if enabled.
+    pub fn identity(&mut self, address: PciAddress) -> Option<ConfigIdentity> {
+        match self.config_cache.as_mut() {
+            Some(cache) => cache.load(&mut self.controller, address),
+            None => ConfigIdentity::read(&mut self.controller, address),
+        }
+    }
+
+    /// Apply per-device overrides when enumerating, see
+    /// [`PciIterator::with_policy`].
+    pub fn with_policy(mut self, policy: ResourcePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &ResourcePolicy {
+        &self.policy
+    }
+
+    /// `base` restricted by the overrides for `address`, see
+    /// [`ResourcePolicy::interrupt_policy`].
+    pub fn interrupt_policy(&self, address: PciAddress, base: InterruptPolicy) -> InterruptPolicy {
+        self.policy.interrupt_policy(address, base)
+    }
+
+    /// Use `translator` instead of identity mapping between CPU and bus
+    /// addresses.
+    pub fn with_translator(mut self, translator: impl BusAddressTranslator + 'static) -> Self {
+        self.translator = Arc::new(translator);
+        self
+    }
+
+    /// Hand the memory windows to the BAR allocator, see
+    /// [`HostWindows::configure_allocator`], and the I/O window to
+    /// enumeration, see [`PciIterator::with_io_window`]. Can be repeated,
+    /// e.g. for a 32-bit and a 64-bit aperture.
+    pub fn with_windows(mut self, windows: &HostWindows) -> Self {
+        windows.configure_allocator(&mut self.controller);
+        if let Some(io) = windows.iter().find(|w| w.kind == WindowKind::Io) {
+            let start = io.pci_address as u32;
+            self.io_window = Some(start..start.saturating_add(io.size as u32));
+        }
+        self
+    }
+
+    pub fn controller(&mut self) -> &mut PcieController {
+        &mut self.controller
+    }
+
+    /// Shared so e.g. [`crate::passthrough::VirtualMsix`] can use it too.
+    pub fn translator(&self) -> &Arc<dyn BusAddressTranslator> {
+        &self.translator
+    }
+
+    /// CPU physical address of memory BAR `index` of `ep`, for mapping it.
+    pub fn bar_cpu_address(&self, ep: &Endpoint, index: usize) -> Result<u64, Error> {
+        let bus = ep
+            .bar_address(index)
+            .ok_or(Error::InvalidArgument("not a memory BAR"))?;
+        self.translator
+            .bus_to_cpu(bus)
+            .ok_or(Error::NotTranslatable { address: bus })
+    }
+
+    /// `message` from the interrupt controller with the doorbell as the bus
+    /// address the device has to write, ready for the MSI/MSI-X registers.
+    pub fn msi_doorbell(&self, message: MsiMessage) -> Result<MsiMessage, Error> {
+        let address =
+            self.translator
+                .cpu_to_bus(message.address)
+                .ok_or(Error::NotTranslatable {
+                    address: message.address,
+                })?;
+        Ok(MsiMessage { address, ..message })
+    }
+
+    /// Only the first enumeration assigns bus numbers and BARs, later ones
+    /// walk the existing topology read-only. One that has to fall back to
+    /// read-only because another enumeration holds the buses doesn't count
+    /// as the first. `range` defaults to the buses of
+    /// [`Self::with_bus_range`].
+    pub fn enumerate(&mut self, range: Option<Range<usize>>) -> PciIterator<'_> {
+        let scanned = self.scanned;
+        if !scanned {
+            // Bus numbers are about to change
+            if let Some(cache) = self.config_cache.as_mut() {
+                cache.clear();
+            }
+        }
+        let range = range.unwrap_or(self.buses.clone());
+        let mut iter = enumerate_segment(&mut self.controller, self.segment, Some(range))
+            .with_translator(self.translator.clone())
+            .with_policy(self.policy.clone())
+            .with_irq_lock(self.irq_lock.clone())
+            .with_rate_limit(self.rate_limit.clone());
+        if let Some(io) = self.io_window.clone() {
+            iter = iter.with_io_window(io);
+        }
+        if scanned {
+            iter.read_only()
+        } else {
+            self.scanned = !iter.read_only;
+            iter
+        }
+    }
+
+    /// Enumerate (see [`Self::enumerate`]) and keep the resulting hierarchy,
+    /// bridges included, as a long-lived model.
+    pub fn scan(&mut self) -> PciTopology {
+        self.enumerate(None).for_each(drop);
+        let topology = PciTopology::read(
+            &mut self.controller,
+            self.segment,
+            self.buses.start as u8,
+            false,
+            self.topology.as_ref(),
+        );
+        self.topology = Some(topology.clone());
+        topology
+    }
+
+    /// Model kept by the last [`Self::scan`] or rescan.
+    pub fn topology(&self) -> Option<&PciTopology> {
+        self.topology.as_ref()
+    }
+
+    /// Treat the hierarchy as enumerated by someone else, e.g. a previous
+    /// kernel, and keep `topology` as its model. Its BARs and bridge windows
+    /// stay reserved for the functions owning them.
+    pub(crate) fn adopt(&mut self, topology: PciTopology) {
+        self.reserved = topology
+            .nodes()
+            .flat_map(|(_, node)| match &node.kind {
+                NodeKind::Endpoint { bars } => bars.iter().map(|b| b.range.clone()).collect(),
+                NodeKind::Bridge {
+                    memory,
+                    prefetchable,
+                    ..
+                } => memory
+                    .iter()
+                    .map(|w| w.start as u64..w.end as u64)
+                    .chain(prefetchable.clone())
+                    .collect::<Vec<_>>(),
+            })
+            .filter(|range| !range.is_empty())
+            .collect();
+        self.scanned = true;
+        self.topology = Some(topology);
+    }
+
+    /// First bus of [`Self::with_bus_range`]
+    pub(crate) fn first_bus(&self) -> u8 {
+        self.buses.start as u8
+    }
+
+    /// Re-probe `bus` and the buses below it, e.g. after a hot-plug event,
+    /// and update the stored topology. New endpoints get BARs if an
+    /// allocator is configured, upstream bridge windows are not grown for
+    /// them. New bridges are reported but bus numbers are only assigned by a
+    /// full enumeration.
+    ///
+    /// Functions on the rescanned buses have their BARs sized, quiesce them.
+    pub fn rescan_bus(&mut self, bus: u8) -> Vec<RescanEvent> {
+        let mut topology = self.topology.take().unwrap_or_default();
+        let parent = topology.bridge_to(bus);
+        let last = (self.buses.end - 1) as u8;
+        let below = match parent.map(|p| &topology.node(p).kind) {
+            Some(NodeKind::Bridge { bus: b, .. }) => Some(b.secondary..=b.subordinate),
+            _ if bus == self.first_bus() => Some(bus..=last),
+            _ => None,
+        };
+        if let Some(cache) = self.config_cache.as_mut() {
+            let buses = below.clone().unwrap_or(bus..=last);
+            cache.invalidate_buses(self.segment, buses);
+        }
+
+        // Below a port forwarding ARI the bus is one ARI device
+        let ari = parent
+            .and_then(|p| PciHeaderBase::new(&mut self.controller, topology.node(p).address))
+            .is_some_and(|base| forwards_ari(&PciPciBridge::new(base)));
+        let fresh = PciTopology::read(
+            &mut self.controller,
+            self.segment,
+            bus,
+            ari,
+            Some(&topology),
+        );
+        // A function with other IDs at a known address was replaced
+        let known: Vec<_> = topology
+            .nodes()
+            .map(|(_, n)| (n.address, n.vendor_id, n.device_id))
+            .collect();
+        for node in fresh.endpoints() {
+            if !known.contains(&(node.address, node.vendor_id, node.device_id)) {
+                self.assign_new_endpoint(node.address);
+            }
+        }
+        // Read again to pick up the assigned BARs
+        let fresh = PciTopology::read(
+            &mut self.controller,
+            self.segment,
+            bus,
+            ari,
+            Some(&topology),
+        );
+        // Not below a known bridge, as far down as the bridges found reach
+        let buses = below.unwrap_or_else(|| {
+            let subordinate = fresh
+                .nodes()
+                .filter_map(|(_, n)| match n.kind {
+                    NodeKind::Bridge { bus: b, .. } => Some(b.subordinate),
+                    NodeKind::Endpoint { .. } => None,
+                })
+                .fold(bus, u8::max);
+            bus..=subordinate
+        });
+        let events = topology.splice(buses, parent, fresh);
+        self.topology = Some(topology);
+        events
+    }
+
+    /// [`Self::rescan_bus`] for the secondary bus of `bridge`.
+    pub fn rescan_below(&mut self, bridge: PciAddress) -> Result<Vec<RescanEvent>, Error> {
+        let base = PciHeaderBase::new(&mut self.controller, bridge)
+            .ok_or(Error::InvalidArgument("no device at bridge address"))?;
+        if !matches!(base.header_type(), HeaderType::PciPciBridge) {
+            return Err(Error::InvalidArgument("not a PCI-PCI bridge"));
+        }
+        let secondary = PciPciBridge::new(base).secondary_bus_number();
+        if secondary <= bridge.bus() {
+            return Err(Error::InvalidArgument("bridge has no bus numbers assigned"));
+        }
+        Ok(self.rescan_bus(secondary))
+    }
+
+    /// Undo an enumeration done before, e.g. by the bootloader or the
+    /// kernel ahead of a warm reboot, so the next [`Self::enumerate`] starts
+    /// from scratch instead of colliding with stale numbers. Follows the bus
+    /// numbers found from the first bus of [`Self::with_bus_range`], turns
+    /// off decode and bus mastering, zeroes BARs and expansion ROM BARs, and
+    /// closes bridge windows and bus ranges from the deepest bridge up.
+    ///
+    /// Returns the number of functions reset. Drops the stored topology.
+    pub fn reset_config_state(&mut self) -> usize {
+        let mut visited = [false; 256];
+        let mut pending = vec![(self.first_bus(), false)];
+        let mut bridges = Vec::new();
+        let mut count = 0;
+        while let Some((bus, ari)) = pending.pop() {
+            if core::mem::replace(&mut visited[bus as usize], true) {
+                continue;
+            }
+            for function in probe_bus(&mut self.controller, self.segment, bus, ari, None) {
+                let (base, bars, rom): (&PciHeaderBase, _, _) = match &function {
+                    PciConfigSpace::Endpoint(ep) => (ep, 0x10..0x28, 0x30),
+                    PciConfigSpace::PciPciBridge(bridge) => {
+                        // Skip unassigned or looping bus numbers
+                        let secondary = bridge.secondary_bus_number();
+                        if secondary > bus {
+                            pending.push((secondary, forwards_ari(bridge)));
+                        }
+                        (bridge, 0x10..0x18, 0x38)
+                    }
+                    _ => continue,
+                };
+                quiesce(base);
+                for offset in bars.step_by(4).chain([rom]) {
+                    base.write(offset, 0);
+                }
+                count += 1;
+                if let PciConfigSpace::PciPciBridge(bridge) = function {
+                    bridges.push(bridge);
+                }
+            }
+        }
+        // Buses below a bridge are only reachable until it is cleared
+        for bridge in bridges.iter().rev() {
+            BridgeConfigWriter::new(bridge)
+                .bus_number(BusNumber {
+                    primary: 0,
+                    secondary: 0,
+                    subordinate: 0,
+                })
+                .io_window(None)
+                .memory_window(None)
+                .prefetchable_window(None)
+                .flush();
+        }
+        self.scanned = false;
+        self.topology = None;
+        self.reserved.clear();
+        if let Some(cache) = self.config_cache.as_mut() {
+            cache.clear();
+        }
+        count
+    }
+
+    /// `(pin, routed_pin_at_root)` for the legacy interrupt of `ep`, using
+    /// the stored topology. The root bus slot to look up in the interrupt
+    /// map (device tree, _PRT) is the first hop of
+    /// [`PciTopology::upstream_path`], or `ep` itself on the root bus.
+    ///
+    /// `None` without INTx pin or before [`Self::scan`].
+    pub fn intx_route(&self, ep: &Endpoint) -> Option<(u8, u8)> {
+        let pin = ep.interrupt_pin();
+        let routed = self.topology.as_ref()?.intx_swizzle(ep.address(), pin)?;
+        Some((pin, routed))
+    }
+
+    fn assign_new_endpoint(&mut self, address: PciAddress) {
+        let overrides = self.policy.get(address);
+        if overrides.contains(DeviceOverride::SKIP) {
+            return;
+        }
+        let Some(base) = self.function(address) else {
+            return;
+        };
+        let Some(allocator) = self.controller.bar_allocator.as_mut() else {
+            return;
+        };
+        // Bridge windows are not grown on rescan, keep to the firmware side
+        let placement = if overrides.contains(DeviceOverride::PREFER64) {
+            Mem64Placement::Prefer64
+        } else {
+            Mem64Placement::AsFound
+        };
+        let mut space = Unreserved {
+            space: allocator,
+            reserved: &self.reserved,
+        };
+        if let Err(e) = Endpoint::new(base).realloc_bar(&mut space, placement) {
+            warn!("{}: {e:?}, keeping BARs as found", Bdf(address));
+        }
+    }
+
+    /// Endpoints with class `base`/`sub`, e.g. `(0x01, 0x08)` for NVMe.
+    /// Like all lookups this walks the topology read-only, below bridges
+    /// only once [`Self::enumerate`] assigned their buses.
+    pub fn find_by_class(&mut self, base: u8, sub: u8) -> impl Iterator<Item = Endpoint> + '_ {
+        self.lookup().filter(move |ep| {
+            let class = ep.revision_and_class();
+            class.base_class == base && class.sub_class == sub
+        })
+    }
+
+    pub fn find_by_id(&mut self, vendor: u16, device: u16) -> impl Iterator<Item = Endpoint> + '_ {
+        self.lookup()
+            .filter(move |ep| ep.vendor_id() == vendor && ep.device_id() == device)
+    }
+
+    pub fn find_by_device_type(&mut self, ty: DeviceType) -> impl Iterator<Item = Endpoint> + '_ {
+        self.lookup().device_type(ty)
+    }
+
+    fn lookup(&mut self) -> PciIterator<'_> {
+        PciIterator::new(&mut self.controller, self.segment, self.buses.clone(), true)
+            .with_irq_lock(self.irq_lock.clone())
+    }
+
+    /// Function at `address`, its read-modify-write cycles taking the lock of
+    /// [`Self::with_irq_lock`].
+    pub(crate) fn function(&mut self, address: PciAddress) -> Option<PciHeaderBase> {
+        let mut base = PciHeaderBase::new(&mut self.controller, address)?;
+        base.set_irq_lock(self.irq_lock.clone());
+        Some(base)
+    }
+
+    pub fn read_config(&mut self, address: PciAddress, offset: u16) -> u32 {
+        let access = self.controller.config_access(address.into());
+        unsafe { access.read(address.into(), offset & !0x3) }
+    }
+
+    pub fn write_config(&mut self, address: PciAddress, offset: u16, value: u32) {
+        let access = self.controller.config_access(address.into());
+        unsafe { access.write(address.into(), offset & !0x3, value) }
+    }
+
+    pub fn read_config_u8(&mut self, address: PciAddress, offset: u16) -> u8 {
+        let shift = (offset & 0x3) * 8;
+        (self.read_config(address, offset) >> shift) as u8
+    }
+
+    /// `offset` must be 2 byte aligned.
+    pub fn read_config_u16(&mut self, address: PciAddress, offset: u16) -> u16 {
+        assert!(offset.is_multiple_of(2), "unaligned config access");
+        let shift = (offset & 0x3) * 8;
+        (self.read_config(address, offset) >> shift) as u16
+    }
+
+    pub fn write_config_u8(&mut self, address: PciAddress, offset: u16, value: u8) {
+        self.update_config(address, offset, 0xff, value as u32);
+    }
+
+    /// `offset` must be 2 byte aligned.
+    pub fn write_config_u16(&mut self, address: PciAddress, offset: u16, value: u16) {
+        assert!(offset.is_multiple_of(2), "unaligned config access");
+        self.update_config(address, offset, 0xffff, value as u32);
+    }
+
+    /// Read-modify-write of the dword holding `offset`, without writing back
+    /// ones to the RW1C status bits sharing it.
+    fn update_config(&mut self, address: PciAddress, offset: u16, mask: u32, value: u32) {
+        let shift = (offset & 0x3) * 8;
+        let dword = offset & !0x3;
+        let rw1c = self.rw1c_mask(address, dword);
+        // Keep IRQ paths of `with_irq_safe_access` out of the cycle
+        let lock = self.irq_lock.clone();
+        let _guard = lock.as_deref().map(IrqLockGuard::new);
+        let mut data = self.read_config(address, dword);
+        data &= !rw1c;
+        data = (data & !(mask << shift)) | (value & mask) << shift;
+        self.write_config(address, dword, data);
+    }
+
+    fn is_type1(&mut self, address: PciAddress) -> bool {
+        if self.config_cache.is_none() {
+            return self.read_config_u8(address, 0x0e) & 0x7f == 0x01;
+        }
+        self.identity(address)
+            .is_some_and(|id| id.header_type == HeaderType::PciPciBridge)
+    }
+
+    fn rw1c_mask(&mut self, address: PciAddress, dword: u16) -> u32 {
+        match dword {
+            // Status
+            0x04 => return 0xffff_0000,
+            // Secondary Status of type 1 headers
+            0x1c if self.is_type1(address) => return 0xffff_0000,
+            0..0x40 => return 0,
+            _ => {}
+        }
+        let Some(base) = self.function(address) else {
+            return 0;
+        };
+        if let Some(pcie) = base.find_capability(CAP_ID_PCIE) {
+            match dword.wrapping_sub(pcie) {
+                // Device, Link, Slot and Link 2 Status
+                0x08 | 0x10 | 0x18 | 0x30 => return 0xffff_0000,
+                // PME Status of Root Status
+                0x20 => return 1 << 16,
+                _ => {}
+            }
+        }
+        if dword < 0x100 {
+            return 0;
+        }
+        match base
+            .find_extended_capability(EXT_CAP_ID_AER)
+            .map(|aer| dword.wrapping_sub(aer))
+        {
+            // Uncorrectable and Correctable Error Status
+            Some(0x04 | 0x10) => u32::MAX,
+            // Root Error Status, the message number above is read-only
+            Some(0x30) => 0x7f,
+            _ => 0,
+        }
+    }
+}
 
+/// Enumerate and assign bus numbers and BARs. While another such
+/// enumeration is running this one falls back to read-only.
 pub fn enumerate_by_controller<'a>(
     controller: &'a mut PcieController,
     range: Option<core::ops::Range<usize>>,
-) -> impl Iterator<Item = Endpoint> + 'a {
-    let range = range.unwrap_or(0..0x100);
+) -> PciIterator<'a> {
+    enumerate_segment(controller, 0, range)
+}
 
-    PciIterator {
-        root: controller,
-        segment: 0,
-        bus_max: (range.end - 1) as _,
-        function: 0,
-        is_mulitple_function: false,
-        is_finish: false,
-        stack: alloc::vec![Bridge::root(range.start as _)],
+/// [`enumerate_by_controller`] under PCI domain `segment`, whose buses are
+/// claimed right away.
+fn enumerate_segment<'a>(
+    controller: &'a mut PcieController,
+    segment: u16,
+    range: Option<core::ops::Range<usize>>,
+) -> PciIterator<'a> {
+    let range = range.unwrap_or(0..0x100);
+    let read_only = !claim_buses(segment, range.start as u8..=(range.end - 1) as u8);
+    if read_only {
+        warn!("enumeration already running, scanning read-only");
     }
+    PciIterator::new(controller, segment, range, read_only)
 }
 
-pub(crate) struct PciIterator<'a> {
+pub struct PciIterator<'a> {
     root: &'a mut PcieController,
     segment: u16,
     stack: Vec<Bridge>,
+    root_bus: u8,
     bus_max: u8,
     function: u8,
     is_mulitple_function: bool,
+    /// Next Function Number of the ARI function probed last, 0 ends the
+    /// device
+    ari_next: u8,
     is_finish: bool,
+    errors: Vec<Error>,
+    quirk: Option<SharedQuirk>,
+    events: Option<EventSink<'a>>,
+    translator: Option<Arc<dyn BusAddressTranslator>>,
+    unassigned: Vec<PciAddress>,
+    /// Enable ACS on root and downstream ports
+    acs: bool,
+    vga: Option<VgaPolicy>,
+    vga_routed: bool,
+    policy: ResourcePolicy,
+    /// I/O space of the root bus, see [`Self::with_io_window`]
+    io_space: Option<WindowAllocator>,
+    probe_budget: Option<ProbeBudget<'a>>,
+    absent_retry: Option<AbsentRetry<'a>>,
+    /// Read each header in one pass while the function is probed
+    header_snapshot: bool,
+    /// Attached to every function found, see [`RootComplex::with_irq_lock`]
+    irq_lock: Option<Arc<dyn IrqLock>>,
+    /// CRS retries are reported to it, see [`RootComplex::with_event_limiter`]
+    rate_limit: Option<RateLimit>,
+    /// Don't write bus numbers, BARs or windows. Writing iterators hold
+    /// their buses in [`SCAN_LOCK`].
+    read_only: bool,
+}
+
+type EventSink<'a> = Box<dyn FnMut(ScanEvent<'_>) + 'a>;
+
+struct ProbeBudget<'a> {
+    budget_ms: u64,
+    now_ms: Box<dyn Fn() -> u64 + 'a>,
+}
+
+struct AbsentRetry<'a> {
+    attempts: u8,
+    delay_ms: u32,
+    delay: Box<dyn Delay + 'a>,
+}
+
+/// Received Master Abort in Secondary Status
+const SECONDARY_MASTER_ABORT: u16 = 1 << 13;
+
+/// Display controller the legacy VGA ranges (memory 0xa0000-0xbffff, I/O
+/// 0x3b0-0x3bb and 0x3c0-0x3df) are routed to, see [`PciIterator::with_vga`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VgaPolicy {
+    /// The first VGA compatible controller found
+    First,
+    Device(PciAddress),
+}
+
+/// Hand `event` to the callback, or log it if it is a warning. Takes the
+/// sink alone so events can borrow other iterator fields.
+fn emit(sink: &mut Option<EventSink<'_>>, event: ScanEvent<'_>) {
+    match sink {
+        Some(f) => f(event),
+        None if event.is_warning() => warn!("{event}"),
+        None => {}
+    }
+}
+
+impl Drop for PciIterator<'_> {
+    fn drop(&mut self) {
+        if !self.read_only {
+            // Stopped early, close the bridges still open
+            for bridge in &mut self.stack {
+                bridge.sync_bus_number();
+            }
+            release_buses(self.segment, self.root_bus..=self.bus_max);
+        }
+    }
 }
 
 impl<'a> Iterator for PciIterator<'a> {
@@ -47,7 +781,11 @@ impl<'a> Iterator for PciIterator<'a> {
                     }
                     PciConfigSpace::Endpoint(ep) => {
                         let item = ep;
+                        if !self.read_only {
+                            self.route_vga(&item);
+                        }
                         self.next(None);
+                        item.forget_snapshot();
                         return Some(item);
                     }
                     PciConfigSpace::CardBusBridge(_) | PciConfigSpace::Unknown(_) => {
@@ -64,50 +802,654 @@ impl<'a> Iterator for PciIterator<'a> {
 }
 
 impl<'a> PciIterator<'a> {
+    /// A writing iterator must hold `range` of `segment` in [`SCAN_LOCK`].
+    fn new(
+        controller: &'a mut PcieController,
+        segment: u16,
+        range: Range<usize>,
+        read_only: bool,
+    ) -> Self {
+        PciIterator {
+            root: controller,
+            segment,
+            root_bus: range.start as _,
+            bus_max: (range.end - 1) as _,
+            function: 0,
+            is_mulitple_function: false,
+            ari_next: 0,
+            is_finish: false,
+            stack: alloc::vec![Bridge::root(range.start as _)],
+            errors: Vec::new(),
+            quirk: None,
+            events: None,
+            translator: None,
+            unassigned: Vec::new(),
+            acs: false,
+            vga: None,
+            vga_routed: false,
+            policy: ResourcePolicy::new(),
+            io_space: None,
+            probe_budget: None,
+            absent_retry: None,
+            header_snapshot: true,
+            irq_lock: None,
+            rate_limit: None,
+            read_only,
+        }
+    }
+
+    /// Enumerate under PCI domain `segment` instead of 0, every yielded
+    /// address carries it.
+    pub fn with_segment(mut self, segment: u16) -> Self {
+        if !self.read_only && segment != self.segment {
+            let buses = self.root_bus..=self.bus_max;
+            release_buses(self.segment, buses.clone());
+            if !claim_buses(segment, buses) {
+                warn!("enumeration already running, scanning read-only");
+                self.read_only = true;
+            }
+        }
+        self.segment = segment;
+        self
+    }
+
+    /// Consult `quirk` before probing each function and on every config
+    /// access of the functions found.
+    pub fn with_quirk(mut self, quirk: impl ConfigQuirk + Send + Sync + 'static) -> Self {
+        self.quirk = Some(Arc::new(quirk));
+        self
+    }
+
+    /// Report what the scan finds and assigns to `f` instead of the log.
+    pub fn with_events(mut self, f: impl FnMut(ScanEvent<'_>) + 'a) -> Self {
+        self.events = Some(Box::new(f));
+        self
+    }
+
+    /// BARs the CPU can't reach through `translator` are reported as
+    /// [`ScanEvent::BarNotRoutable`] and get no bridge window.
+    pub fn with_translator(mut self, translator: Arc<dyn BusAddressTranslator>) -> Self {
+        self.translator = Some(translator);
+        self
+    }
+
+    /// Enable ACS isolation on every root and downstream port found, for
+    /// hypervisors forming IOMMU groups. Not applied when read-only.
+    pub fn with_acs(mut self) -> Self {
+        self.acs = true;
+        self
+    }
+
+    /// Route the legacy VGA ranges through the bridges above the display
+    /// controller picked by `policy`, with 16-bit I/O decode, and turn off
+    /// VGA forwarding left by firmware on all other bridges. Not applied
+    /// when read-only.
+    pub fn with_vga(mut self, policy: VgaPolicy) -> Self {
+        self.vga = Some(policy);
+        self
+    }
+
+    /// Skip functions marked `skip` as if a quirk did, allocate 64-bit BARs
+    /// of `prefer64` functions above 4GiB, turn off MSI and MSI-X of
+    /// `no-msi` functions and program the Cache Line Size and Latency Timer
+    /// of conventional PCI functions. Not applied when read-only, except for
+    /// skipping.
+    pub fn with_policy(mut self, policy: ResourcePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Assign I/O BARs and bridge I/O windows from `window`, in bus
+    /// addresses. Without it I/O BARs are left as found and I/O windows
+    /// closed.
+    pub fn with_io_window(mut self, window: Range<u32>) -> Self {
+        self.io_space = Some(WindowAllocator::new(window.start as u64..window.end as u64));
+        self
+    }
+
+    /// Record a [`DeviceFault::Slow`] for functions whose probe, BAR
+    /// assignment included, takes longer than `budget_ms` by the `now_ms`
+    /// clock, and skip them. Config reads can't be aborted, this only keeps
+    /// a crawling device from being used and its subtree from being walked.
+    pub fn with_probe_budget(mut self, budget_ms: u64, now_ms: impl Fn() -> u64 + 'a) -> Self {
+        self.probe_budget = Some(ProbeBudget {
+            budget_ms,
+            now_ms: Box::new(now_ms),
+        });
+        self
+    }
+
+    /// Don't take an all-ones Vendor ID below a bridge for an absent
+    /// device straight away: unless the bridge saw a master abort (an
+    /// Unsupported Request, nothing there), read it again up to `attempts`
+    /// times `delay_ms` apart, for devices behind links still training.
+    /// Below root and downstream ports only device 0 is retried, on the
+    /// root bus the Vendor ID is only read twice.
+    pub fn with_absent_retry(
+        mut self,
+        attempts: u8,
+        delay_ms: u32,
+        delay: impl Delay + 'a,
+    ) -> Self {
+        self.absent_retry = Some(AbsentRetry {
+            attempts,
+            delay_ms,
+            delay: Box::new(delay),
+        });
+        self
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    /// Stop here, enumeration continues from the returned token with
+    /// [`Self::resume`]. The quirk, event, translator, probe budget and
+    /// absent retry hooks are not kept, the scan lock, IRQ lock and event
+    /// limiter are.
+    pub fn pause(mut self) -> ResumeToken {
+        ResumeToken {
+            // Hand the scan lock over to the token
+            read_only: core::mem::replace(&mut self.read_only, true),
+            segment: self.segment,
+            root_bus: self.root_bus,
+            bus_max: self.bus_max,
+            function: self.function,
+            is_mulitple_function: self.is_mulitple_function,
+            is_finish: self.is_finish,
+            stack: self
+                .stack
+                .iter()
+                .enumerate()
+                .map(|(i, b)| BridgeSnapshot {
+                    // The root bridge at the bottom has no config space
+                    address: (i > 0).then(|| b.bridge.address()),
+                    secondary_bus: b.bridge.secondary_bus_number(),
+                    device: b.device,
+                    subordinate: b.subordinate,
+                    windows: b.windows.clone(),
+                    ari: b.ari,
+                })
+                .collect(),
+            errors: core::mem::take(&mut self.errors),
+            unassigned: core::mem::take(&mut self.unassigned),
+            acs: self.acs,
+            vga: self.vga,
+            vga_routed: self.vga_routed,
+            policy: core::mem::take(&mut self.policy),
+            io_space: self.io_space.take(),
+            irq_lock: self.irq_lock.take(),
+            rate_limit: self.rate_limit.take(),
+        }
+    }
+
+    /// Continue an enumeration paused with [`Self::pause`], the bridges on
+    /// the path are read back from `controller`.
+    pub fn resume(
+        controller: &'a mut PcieController,
+        mut token: ResumeToken,
+    ) -> Result<Self, Error> {
+        let mut stack = Vec::with_capacity(token.stack.len());
+        for snapshot in core::mem::take(&mut token.stack) {
+            let bridge = match snapshot.address {
+                Some(address) => PciHeaderBase::new(controller, address)
+                    .filter(|b| matches!(b.header_type(), HeaderType::PciPciBridge))
+                    .map(PciPciBridge::new)
+                    .ok_or(Error::InvalidArgument("stale resume token"))?,
+                None => PciPciBridge::root(snapshot.secondary_bus),
+            };
+            stack.push(Bridge {
+                bridge,
+                device: snapshot.device,
+                subordinate: snapshot.subordinate,
+                windows: snapshot.windows,
+                ari: snapshot.ari,
+            });
+        }
+        Ok(Self {
+            root: controller,
+            segment: token.segment,
+            stack,
+            root_bus: token.root_bus,
+            bus_max: token.bus_max,
+            function: token.function,
+            is_mulitple_function: token.is_mulitple_function,
+            ari_next: 0,
+            is_finish: token.is_finish,
+            errors: core::mem::take(&mut token.errors),
+            quirk: None,
+            events: None,
+            translator: None,
+            unassigned: core::mem::take(&mut token.unassigned),
+            acs: token.acs,
+            vga: token.vga,
+            vga_routed: token.vga_routed,
+            policy: core::mem::take(&mut token.policy),
+            io_space: token.io_space.take(),
+            probe_budget: None,
+            absent_retry: None,
+            header_snapshot: true,
+            irq_lock: token.irq_lock.take(),
+            rate_limit: token.rate_limit.take(),
+            read_only: core::mem::replace(&mut token.read_only, true),
+        })
+    }
+
+    /// Whether probing reads the first 64 bytes of each function's config
+    /// space in one pass, on by default. The snapshot only serves registers
+    /// hardware doesn't change by itself and is dropped when the function is
+    /// handed out.
+    #[cfg(feature = "fixture")]
+    pub(crate) fn with_header_snapshot(mut self, on: bool) -> Self {
+        self.header_snapshot = on;
+        self
+    }
+
+    pub(crate) fn with_irq_lock(mut self, lock: Option<Arc<dyn IrqLock>>) -> Self {
+        self.irq_lock = lock;
+        self
+    }
+
+    pub(crate) fn with_rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Walk the existing topology without writing bus numbers, BARs or
+    /// bridge windows.
+    pub fn read_only(mut self) -> Self {
+        if !self.read_only {
+            self.read_only = true;
+            release_buses(self.segment, self.root_bus..=self.bus_max);
+        }
+        self
+    }
+
+    fn allocates(&self) -> bool {
+        !self.read_only && self.root.bar_allocator.is_some()
+    }
+
+    /// Errors hit so far, subtrees that caused them were skipped.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Probe the current function, skipping it with a [`Error::DeviceFault`]
+    /// if it misbehaves.
     fn get_current_valid(&mut self) -> Option<PciConfigSpace> {
         let address = self.address();
-        let header_base = PciHeaderBase::new(self.root, address)?;
+        let start = self.probe_budget.as_ref().map(|b| (b.now_ms)());
+        let mut value = self.probe()?;
+        let bars = match &mut value {
+            PciConfigSpace::Endpoint(ep) if !self.read_only => self.assign_bars(ep),
+            _ => Vec::new(),
+        };
+        let fault = match (&self.probe_budget, start) {
+            (Some(budget), Some(start)) => {
+                let elapsed_ms = (budget.now_ms)().saturating_sub(start);
+                (elapsed_ms > budget.budget_ms).then_some(DeviceFault::Slow { elapsed_ms })
+            }
+            _ => None,
+        };
+        let Some(fault) = fault else {
+            self.report_found(&value, bars);
+            return Some(value);
+        };
+        match (&value, self.read_only) {
+            (PciConfigSpace::PciPciBridge(bridge), false) => {
+                // Stop the bridge from claiming the buses handed to it
+                BridgeConfigWriter::new(bridge)
+                    .bus_number(BusNumber {
+                        primary: 0,
+                        secondary: 0,
+                        subordinate: 0,
+                    })
+                    .flush();
+            }
+            (PciConfigSpace::Endpoint(ep), false) => {
+                // Off the bus, its BARs back to the windows
+                quiesce(ep);
+                self.release_bars(&bars);
+            }
+            _ => {}
+        }
+        self.fault(address, fault);
+        None
+    }
+
+    /// Events for a function that passed probing, with the BARs assigned
+    /// to it.
+    fn report_found(&mut self, value: &PciConfigSpace, bars: Vec<BarInfo>) {
+        match value {
+            PciConfigSpace::Endpoint(ep) => {
+                let address = ep.address();
+                emit(
+                    &mut self.events,
+                    ScanEvent::DeviceFound {
+                        address,
+                        vendor_id: ep.vendor_id(),
+                        device_id: ep.device_id(),
+                    },
+                );
+                for bar in bars {
+                    self.report_bar(address, bar);
+                }
+            }
+            PciConfigSpace::PciPciBridge(bridge) => emit(
+                &mut self.events,
+                ScanEvent::BridgeFound {
+                    address: bridge.address(),
+                    secondary_bus: bridge.secondary_bus_number(),
+                },
+            ),
+            PciConfigSpace::CardBusBridge(_) | PciConfigSpace::Unknown(_) => {}
+        }
+    }
+
+    /// Give BARs assigned by [`Self::assign_bars`] back to the windows of
+    /// the current bus.
+    fn release_bars(&mut self, bars: &[BarInfo]) {
+        let blocks = bars
+            .iter()
+            .filter_map(|bar| Some(bar.assigned?..bar.assigned? + bar.size));
+        match (self.root.bar_allocator.as_mut(), self.stack.len()) {
+            (Some(allocator), 1) => {
+                let mut host = HostSpace {
+                    allocator,
+                    io: self.io_space.as_mut(),
+                };
+                blocks.for_each(|block| host.free(block));
+            }
+            _ => {
+                if let Some(windows) = self.stack.last_mut().and_then(|b| b.windows.as_mut()) {
+                    blocks.for_each(|block| windows.free(block));
+                }
+            }
+        }
+    }
+
+    /// Second opinion on a function 0 that read as absent, see
+    /// [`Self::with_absent_retry`].
+    fn retry_absent(&mut self, address: PciAddress) -> Option<PciHeaderBase> {
+        if address.function() != 0 {
+            return None;
+        }
+        let retry = self.absent_retry.as_mut()?;
+        let parent = &self.stack.last()?.bridge;
+        let on_root_bus = self.stack.len() == 1;
+        // Only device 0 sits at the other end of a link
+        let below_link = !on_root_bus
+            && parent
+                .pcie_capability()
+                .is_some_and(|cap| cap.device_port_type().is_downstream_port());
+        if below_link && address.device() != 0 {
+            return None;
+        }
+        if !on_root_bus {
+            parent.clear_secondary_status(SECONDARY_MASTER_ABORT);
+        }
+        let attempts = if on_root_bus { 1 } else { retry.attempts };
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                if let Some(limit) = &self.rate_limit {
+                    limit.report(HwEvent::CrsRetry, address);
+                }
+                retry.delay.delay_ms(retry.delay_ms);
+            }
+            if let Some(base) = PciHeaderBase::probe(self.root, address, false, self.quirk.clone())
+            {
+                debug!("{} answered on retry {}", Bdf(address), attempt + 1);
+                return Some(base);
+            }
+            if !on_root_bus && parent.secondary_status().received_master_abort() {
+                parent.clear_secondary_status(SECONDARY_MASTER_ABORT);
+                return None;
+            }
+        }
+        None
+    }
+
+    fn fault(&mut self, address: PciAddress, fault: DeviceFault) {
+        let error = Error::DeviceFault { address, fault };
+        emit(
+            &mut self.events,
+            ScanEvent::Error {
+                address,
+                error: &error,
+            },
+        );
+        self.errors.push(error);
+    }
+
+    fn probe(&mut self) -> Option<PciConfigSpace> {
+        let address = self.address();
+        self.ari_next = 0;
+        let skip = self
+            .quirk
+            .as_ref()
+            .is_some_and(|q| q.quirk(address, 0) == AccessPolicy::Skip)
+            || self.policy.get(address).contains(DeviceOverride::SKIP);
+        if skip {
+            emit(&mut self.events, ScanEvent::Skipped { address });
+            return None;
+        }
+        let probed =
+            PciHeaderBase::probe(self.root, address, self.header_snapshot, self.quirk.clone());
+        let mut header_base = match probed {
+            Some(base) => base,
+            None => self.retry_absent(address)?,
+        };
+        header_base.set_irq_lock(self.irq_lock.clone());
+        if let Some(fault) = all_ones_fault(&header_base) {
+            self.fault(address, fault);
+            return None;
+        }
         self.is_mulitple_function = header_base.has_multiple_functions();
+        if self.stack.last().is_some_and(|b| b.ari) {
+            self.ari_next = header_base
+                .ari()
+                .map_or(0, |ari| ari.next_function_number());
+        }
 
         match header_base.header_type() {
-            pci_types::HeaderType::Endpoint => {
-                let bl = self.root.bar_allocator.as_mut();
-                let ep = Endpoint::new(header_base, bl);
+            HeaderType::Endpoint => {
+                if !self.read_only {
+                    self.program_legacy_timing(&header_base, false);
+                }
+                let ep = Endpoint::new(header_base);
+                if !self.read_only && self.policy.get(address).contains(DeviceOverride::NO_MSI) {
+                    ep.disable_msi();
+                }
                 Some(PciConfigSpace::Endpoint(ep))
             }
-            pci_types::HeaderType::PciPciBridge => {
-                let mut bridge = PciPciBridge::new(header_base);
+            HeaderType::PciPciBridge => {
+                if !self.read_only {
+                    self.program_legacy_timing(&header_base, true);
+                }
+                let bridge = PciPciBridge::new(header_base);
+                if self.read_only {
+                    // Skip bridges without (sane) bus numbers
+                    let secondary_bus = bridge.secondary_bus_number();
+                    if secondary_bus <= address.bus() {
+                        return None;
+                    }
+                    return Some(PciConfigSpace::PciPciBridge(bridge));
+                }
                 let primary_bus = address.bus();
                 let secondary_bus;
 
                 if let Some(parent) = self.stack.last_mut() {
-                    if parent.bridge.subordinate_bus_number() == self.bus_max {
-                        return None;
+                    match parent
+                        .subordinate
+                        .checked_add(1)
+                        .filter(|bus| *bus <= self.bus_max)
+                    {
+                        Some(bus) => secondary_bus = bus,
+                        None => {
+                            let error = Error::BusNumberExhausted {
+                                bridge: address,
+                                bus_max: self.bus_max,
+                            };
+                            emit(
+                                &mut self.events,
+                                ScanEvent::Error {
+                                    address,
+                                    error: &error,
+                                },
+                            );
+                            self.errors.push(error);
+                            return None;
+                        }
                     }
-
-                    secondary_bus = parent.bridge.subordinate_bus_number() + 1;
                 } else {
                     panic!("no parent");
                 }
-                let subordinate_bus = secondary_bus;
-                bridge.update_bus_number(|mut bus| {
-                    bus.primary = primary_bus;
-                    bus.secondary = secondary_bus;
-                    bus.subordinate = subordinate_bus;
-                    bus
-                });
+                BridgeConfigWriter::new(&bridge)
+                    .bus_number(BusNumber {
+                        primary: primary_bus,
+                        secondary: secondary_bus,
+                        // Route every bus below while its subtree is scanned,
+                        // the final number is written back when it is popped
+                        subordinate: self.bus_max,
+                    })
+                    .flush();
+                if self.acs {
+                    bridge.enable_acs();
+                }
 
                 Some(PciConfigSpace::PciPciBridge(bridge))
             }
-            pci_types::HeaderType::CardBusBridge => todo!(),
-            pci_types::HeaderType::Unknown(_) => todo!(),
-            _ => unreachable!(),
+            HeaderType::CardBusBridge => Some(PciConfigSpace::CardBusBridge(CardBusBridge::new(
+                header_base,
+            ))),
+            HeaderType::Unknown(_) => Some(PciConfigSpace::Unknown(Unknown::new(header_base))),
+        }
+    }
+
+    /// Cache Line Size and Latency Timer of the policy, for conventional PCI
+    /// functions. PCIe-to-PCI bridges get the Secondary Latency Timer of the
+    /// bus they create.
+    fn program_legacy_timing(&self, base: &PciHeaderBase, bridge: bool) {
+        let (cls, lat) = (self.policy.cache_line_size(), self.policy.latency_timer());
+        if cls.is_none() && lat.is_none() {
+            return;
+        }
+        if !base.is_pcie() {
+            base.set_cache_line_and_latency(
+                cls.unwrap_or(base.cache_line_size()),
+                lat.unwrap_or(base.latency_timer()),
+            );
+        }
+        let pcie_to_pci = base
+            .pcie_capability()
+            .is_some_and(|cap| cap.device_port_type() == DevicePortType::PcieToPciBridge);
+        if let (true, true, Some(lat)) = (bridge, pcie_to_pci, lat) {
+            base.update(0x18, |mut data| {
+                data.set_bits(24..32, lat as u32);
+                data
+            });
+        }
+    }
+
+    /// Assign BARs of `ep`, degrading to its firmware assignment when there
+    /// is no allocator or no space left. Returns the BARs assigned, reported
+    /// once the function passed probing.
+    fn assign_bars(&mut self, ep: &mut Endpoint) -> Vec<BarInfo> {
+        let address = ep.address();
+        let prefer64 = self.policy.get(address).contains(DeviceOverride::PREFER64);
+        // Only where a prefetchable 64-bit BAR would have gone above 4GiB
+        let above_4g = |bar: u64| prefer64 || bar == 0 || bar >= u32::MAX as u64;
+        let placement = match self.bridge_without_prefetch64() {
+            Some(bridge) if ep.prefetchable_mem64().any(above_4g) => {
+                emit(
+                    &mut self.events,
+                    ScanEvent::Below4GFallback { address, bridge },
+                );
+                Mem64Placement::Below4G
+            }
+            _ if prefer64 => Mem64Placement::Prefer64,
+            _ => Mem64Placement::AsFound,
+        };
+        let result = match (self.root.bar_allocator.as_mut(), self.stack.len()) {
+            (None, _) => Err(Error::NoBarAllocator { address }),
+            (Some(allocator), 1) => {
+                let mut host = HostSpace {
+                    allocator,
+                    io: self.io_space.as_mut(),
+                };
+                ep.realloc_bar(&mut host, placement)
+            }
+            (Some(_), _) => match self.stack.last_mut().and_then(|b| b.windows.as_mut()) {
+                Some(windows) => ep.realloc_bar(windows, placement),
+                // The bridge above got no windows of its own
+                None => Err(Error::NoBarSpace { address }),
+            },
+        };
+        result.unwrap_or_else(|error| {
+            // BARs stay as firmware left them
+            emit(
+                &mut self.events,
+                ScanEvent::Error {
+                    address,
+                    error: &error,
+                },
+            );
+            self.unassigned.push(address);
+            self.errors.push(error);
+            Vec::new()
+        })
+    }
+
+    fn report_bar(&mut self, address: PciAddress, bar: BarInfo) {
+        if let Some(start) = bar.assigned {
+            let range = start..start + bar.size;
+            emit(
+                &mut self.events,
+                ScanEvent::BarAssigned {
+                    address,
+                    index: bar.index,
+                    range: &range,
+                },
+            );
+            let reachable = bar.kind == BarKind::Io
+                || self.translator.as_deref().is_none_or(|t| {
+                    t.bus_to_cpu(range.start).is_some() && t.bus_to_cpu(range.end - 1).is_some()
+                });
+            if !reachable {
+                emit(
+                    &mut self.events,
+                    ScanEvent::BarNotRoutable {
+                        address,
+                        range: &range,
+                    },
+                );
+            }
         }
     }
 
+    /// Nearest bridge above the current function whose prefetchable window
+    /// can't reach above 4GiB.
+    fn bridge_without_prefetch64(&self) -> Option<PciAddress> {
+        self.stack
+            .iter()
+            .skip(1)
+            .rev()
+            .find(|b| !b.bridge.prefetchable_64bit())
+            .map(|b| b.bridge.address())
+    }
+
+    /// Devices whose BARs were left as found, see [`Self::errors`] for why.
+    pub fn unassigned(&self) -> &[PciAddress] {
+        &self.unassigned
+    }
+
     fn address(&self) -> PciAddress {
         let parent = self.stack.last().unwrap();
         let bus = parent.bridge.secondary_bus_number();
+        if parent.ari {
+            // Device number bits are the upper bits of the ARI function number
+            return PciAddress::new(self.segment, bus, self.function >> 3, self.function & 0x7);
+        }
         let device = parent.device;
 
         PciAddress::new(self.segment, bus, device, self.function)
@@ -115,6 +1457,18 @@ impl<'a> PciIterator<'a> {
 
     /// 若进位返回true
     fn is_next_function_max(&mut self) -> bool {
+        if let Some(parent) = self.stack.last_mut().filter(|b| b.ari) {
+            // Functions are chained in ascending order, anything else ends
+            // the chain
+            if self.ari_next <= self.function {
+                // The ARI device is the only one on the bus
+                parent.device = MAX_DEVICE;
+                self.function = 0;
+                return true;
+            }
+            self.function = self.ari_next;
+            return false;
+        }
         if self.is_mulitple_function {
             if self.function == MAX_FUNCTION {
                 self.function = 0;
@@ -131,39 +1485,146 @@ impl<'a> PciIterator<'a> {
 
     /// 若进位返回true
     fn next_device_not_ok(&mut self) -> bool {
-        if let Some(parent) = self.stack.last_mut() {
-            if parent.device == MAX_DEVICE {
-                if let Some(parent) = self.stack.pop() {
-                    self.is_finish = parent.bridge.subordinate_bus_number() == self.bus_max;
-
-                    // parent.header.sync_bus_number(&self.root);
-                    self.function = 0;
-                    return true;
-                } else {
-                    self.is_finish = true;
-                }
-            } else {
-                parent.device += 1;
-            }
-        } else {
+        let Some(parent) = self.stack.last_mut() else {
             self.is_finish = true;
+            return false;
+        };
+        if parent.device != MAX_DEVICE {
+            parent.device += 1;
+            return false;
+        }
+        let mut done = self.stack.pop().unwrap();
+        if !self.read_only {
+            done.sync_bus_number();
+        }
+        self.function = 0;
+        true
+    }
+
+    /// Route VGA to `ep` if it is the controller picked by [`Self::with_vga`].
+    fn route_vga(&mut self, ep: &Endpoint) {
+        let Some(policy) = self.vga.filter(|_| !self.vga_routed) else {
+            return;
+        };
+        let class = ep.revision_and_class();
+        // VGA compatible display controller
+        if class.base_class != 0x03 || class.sub_class != 0x00 {
+            return;
+        }
+        if matches!(policy, VgaPolicy::Device(address) if address != ep.address()) {
+            return;
+        }
+        // Decoding stays as BAR assignment left it, the driver enables it
+        for parent in self.stack.iter_mut().skip(1) {
+            parent.bridge.update_bridge_control(|mut control| {
+                control.insert(BridgeControl::VGA_ENABLE | BridgeControl::VGA_16BIT_DECODE);
+                control
+            });
+        }
+        self.vga_routed = true;
+    }
+
+    /// Whether the secondary bus of `bridge` is walked as one ARI device,
+    /// enabling ARI forwarding on the port if the device supports it.
+    fn ari_below(&mut self, bridge: &PciPciBridge) -> bool {
+        if self.read_only {
+            return forwards_ari(bridge);
         }
+        enable_ari(self.root, self.segment, bridge, self.quirk.as_ref())
+    }
 
-        false
+    /// Size the subtree of `bridge` and program windows of its own, taken
+    /// from the space of the bridge above. `None` if they don't fit, the
+    /// bridge then keeps the windows firmware left and nothing below it is
+    /// assigned.
+    fn open_windows(&mut self, bridge: &PciPciBridge, ari: bool) -> Option<Apertures> {
+        let address = bridge.address();
+        if self.stack.len() > 1 && self.stack.last()?.windows.is_none() {
+            return None;
+        }
+        let needs = {
+            // Functions the quirk skips don't answer the probe
+            let policy = &self.policy;
+            let skip = |address| policy.get(address).contains(DeviceOverride::SKIP);
+            let mut numbering = (bridge.secondary_bus_number(), self.bus_max);
+            bus_window_sizes(
+                self.root,
+                self.segment,
+                bridge.secondary_bus_number(),
+                ari,
+                Some(&mut numbering),
+                &skip,
+                self.quirk.as_ref(),
+            )
+        };
+        let prefetch64 = bridge.prefetchable_64bit();
+        let windows = match self.stack.len() {
+            1 => {
+                let mut host = HostSpace {
+                    allocator: self.root.bar_allocator.as_mut()?,
+                    io: self.io_space.as_mut(),
+                };
+                Apertures::carve(&mut host, &needs, prefetch64)
+            }
+            _ => Apertures::carve(self.stack.last_mut()?.windows.as_mut()?, &needs, prefetch64),
+        };
+        let Some(windows) = windows else {
+            let error = Error::NoBarSpace { address };
+            emit(
+                &mut self.events,
+                ScanEvent::Error {
+                    address,
+                    error: &error,
+                },
+            );
+            self.errors.push(error);
+            return None;
+        };
+
+        let to_u32 = |r: Range<u64>| r.start as u32..r.end as u32;
+        BridgeConfigWriter::new(bridge)
+            .io_window(windows.io.as_ref().map(|w| to_u32(w.window())))
+            .memory_window(windows.memory.as_ref().map(|w| to_u32(w.window())))
+            .prefetchable_window(windows.prefetchable.as_ref().map(|w| w.window()))
+            .flush();
+        let io = windows.io.is_some();
+        bridge.update_command(|mut cmd| {
+            cmd.insert(CommandRegister::MEMORY_ENABLE | CommandRegister::BUS_MASTER_ENABLE);
+            cmd.set(CommandRegister::IO_ENABLE, io);
+            cmd
+        });
+        Some(windows)
     }
 
     fn next(&mut self, current_bridge: Option<PciPciBridge>) {
         if let Some(bridge) = current_bridge {
+            let secondary = bridge.secondary_bus_number();
             for parent in &mut self.stack {
-                // parent.header.subordinate_bus += 1;
+                parent.subordinate = secondary;
+            }
 
-                parent.bridge.update_bus_number(|mut bus| {
-                    bus.subordinate += 1;
-                    bus
+            // Functions past 7 of an ARI device need room in the windows
+            let ari = self.ari_below(&bridge);
+            let windows = if self.allocates() {
+                self.open_windows(&bridge, ari)
+            } else {
+                None
+            };
+            let mut bridge = bridge;
+            if self.vga.is_some() && !self.read_only {
+                // Only the path to the selected controller may forward VGA
+                bridge.update_bridge_control(|mut control| {
+                    control.remove(BridgeControl::VGA_ENABLE | BridgeControl::VGA_16BIT_DECODE);
+                    control
                 });
             }
-
-            self.stack.push(Bridge { bridge, device: 0 });
+            self.stack.push(Bridge {
+                bridge,
+                device: 0,
+                subordinate: secondary,
+                windows,
+                ari,
+            });
 
             self.function = 0;
             return;
@@ -177,16 +1638,593 @@ impl<'a> PciIterator<'a> {
     }
 }
 
+/// Registers that can't be all-ones on a function that answered its Vendor
+/// ID: the ID dword, Class Code and Header Type.
+fn all_ones_fault(base: &PciHeaderBase) -> Option<DeviceFault> {
+    [0x00, 0x08, 0x0c]
+        .into_iter()
+        .find(|offset| base.read(*offset) == u32::MAX)
+        .map(|offset| DeviceFault::AllOnes { offset })
+}
+
+/// Position of a paused [`PciIterator`], for timeslicing long scans.
+pub struct ResumeToken {
+    segment: u16,
+    root_bus: u8,
+    bus_max: u8,
+    function: u8,
+    is_mulitple_function: bool,
+    is_finish: bool,
+    stack: Vec<BridgeSnapshot>,
+    errors: Vec<Error>,
+    unassigned: Vec<PciAddress>,
+    acs: bool,
+    vga: Option<VgaPolicy>,
+    vga_routed: bool,
+    policy: ResourcePolicy,
+    io_space: Option<WindowAllocator>,
+    irq_lock: Option<Arc<dyn IrqLock>>,
+    rate_limit: Option<RateLimit>,
+    read_only: bool,
+}
+
+impl core::fmt::Debug for ResumeToken {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ResumeToken")
+            .field("segment", &self.segment)
+            .field("root_bus", &self.root_bus)
+            .field("bus_max", &self.bus_max)
+            .field("function", &self.function)
+            .field("is_mulitple_function", &self.is_mulitple_function)
+            .field("is_finish", &self.is_finish)
+            .field("stack", &self.stack)
+            .field("errors", &self.errors)
+            .field("unassigned", &self.unassigned)
+            .field("acs", &self.acs)
+            .field("vga", &self.vga)
+            .field("vga_routed", &self.vga_routed)
+            .field("policy", &self.policy)
+            .field("io_space", &self.io_space)
+            .field("irq_lock", &self.irq_lock.is_some())
+            .field("rate_limit", &self.rate_limit.is_some())
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+impl Drop for ResumeToken {
+    fn drop(&mut self) {
+        if !self.read_only {
+            release_buses(self.segment, self.root_bus..=self.bus_max);
+        }
+    }
+}
+
+impl ResumeToken {
+    /// Function the resumed iterator probes first.
+    pub fn address(&self) -> Option<PciAddress> {
+        let parent = self.stack.last()?;
+        let (device, function) = if parent.ari {
+            (self.function >> 3, self.function & 0x7)
+        } else {
+            (parent.device, self.function)
+        };
+        Some(PciAddress::new(
+            self.segment,
+            parent.secondary_bus,
+            device,
+            function,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BridgeSnapshot {
+    address: Option<PciAddress>,
+    secondary_bus: u8,
+    device: u8,
+    subordinate: u8,
+    windows: Option<Apertures>,
+    ari: bool,
+}
+
 struct Bridge {
     bridge: PciPciBridge,
     device: u8,
+    /// Last bus number given out below the bridge, in config space only
+    /// once the bridge is popped
+    subordinate: u8,
+    /// Windows BARs below are assigned from, `None` when they are left as
+    /// found
+    windows: Option<Apertures>,
+    /// Secondary bus holds a single ARI device with functions 0-255
+    ari: bool,
 }
 
 impl Bridge {
-    fn root(bus_start: u8) -> Self {
+    /// Host bridge above `bus`, the first bus of the range scanned.
+    fn root(bus: u8) -> Self {
         Self {
-            bridge: PciPciBridge::root(),
-            device: bus_start,
+            bridge: PciPciBridge::root(bus),
+            device: 0,
+            subordinate: bus,
+            windows: None,
+            ari: false,
+        }
+    }
+
+    fn sync_bus_number(&mut self) {
+        let subordinate = self.subordinate;
+        self.bridge.update_bus_number(|mut bus| {
+            bus.subordinate = subordinate;
+            bus
+        });
+    }
+}
+
+/// Memory windows have 1MiB granularity
+const WINDOW_ALIGN: u64 = 0x10_0000;
+/// I/O windows have 4KiB granularity
+const IO_WINDOW_ALIGN: u64 = 0x1000;
+
+/// Host windows of the controller and the I/O window given to the
+/// iterator, for the root bus.
+struct HostSpace<'b> {
+    allocator: &'b mut SimpleBarAllocator,
+    io: Option<&'b mut WindowAllocator>,
+}
+
+impl BarSpace for HostSpace<'_> {
+    fn alloc_memory32(&mut self, size: u32, prefetchable: bool) -> Option<u32> {
+        self.allocator.alloc_memory32_with_pref(size, prefetchable)
+    }
+
+    fn alloc_memory64(&mut self, size: u64, prefetchable: bool) -> Option<u64> {
+        self.allocator.alloc_memory64_with_pref(size, prefetchable)
+    }
+
+    fn alloc_io(&mut self, size: u32) -> Option<u32> {
+        self.io.as_mut()?.alloc(size as u64).map(|v| v as u32)
+    }
+
+    fn free(&mut self, block: Range<u64>) {
+        if let Some(io) = self.io.as_mut().filter(|io| io.contains(&block)) {
+            io.free(block.start, block.end - block.start);
+        }
+    }
+}
+
+/// `space` without the ranges adopted from a previous kernel. Blocks
+/// overlapping them stay taken and are asked for again.
+struct Unreserved<'b, S> {
+    space: &'b mut S,
+    reserved: &'b [Range<u64>],
+}
+
+impl<S: BarSpace> Unreserved<'_, S> {
+    fn clear(&self, start: u64, size: u64) -> bool {
+        let end = start + size;
+        !self.reserved.iter().any(|r| r.start < end && start < r.end)
+    }
+}
+
+impl<S: BarSpace> BarSpace for Unreserved<'_, S> {
+    fn alloc_memory32(&mut self, size: u32, prefetchable: bool) -> Option<u32> {
+        loop {
+            let start = self.space.alloc_memory32(size, prefetchable)?;
+            if self.clear(start as u64, size as u64) {
+                return Some(start);
+            }
+        }
+    }
+
+    fn alloc_memory64(&mut self, size: u64, prefetchable: bool) -> Option<u64> {
+        loop {
+            let start = self.space.alloc_memory64(size, prefetchable)?;
+            if self.clear(start, size) {
+                return Some(start);
+            }
+        }
+    }
+
+    fn alloc_io(&mut self, size: u32) -> Option<u32> {
+        self.space.alloc_io(size)
+    }
+
+    fn free(&mut self, block: Range<u64>) {
+        self.space.free(block);
+    }
+}
+
+/// Windows of one bridge, programmed once when the bridge is found and
+/// sized for everything below it.
+#[derive(Debug, Clone, Default)]
+struct Apertures {
+    memory: Option<WindowAllocator>,
+    /// Below 4GiB unless the bridge and everything below decode 64 bits
+    prefetchable: Option<WindowAllocator>,
+    io: Option<WindowAllocator>,
+}
+
+impl Apertures {
+    /// Take the windows `needs` asks for from `space`. Without I/O space
+    /// the I/O window stays closed and I/O BARs below as found.
+    fn carve(space: &mut impl BarSpace, needs: &WindowNeeds, prefetch64: bool) -> Option<Self> {
+        let mut windows = Self::default();
+        if needs.sizes.memory > 0 {
+            let size = needs.sizes.memory.checked_next_power_of_two()?;
+            let start = space.alloc_memory32(u32::try_from(size).ok()?, false)?;
+            windows.memory = Some(WindowAllocator::new(start as u64..start as u64 + size));
+        }
+        if needs.sizes.prefetchable > 0 {
+            let size = needs.sizes.prefetchable.next_power_of_two();
+            let alloc32 = |space: &mut _| {
+                BarSpace::alloc_memory32(space, u32::try_from(size).ok()?, true).map(u64::from)
+            };
+            let start = if prefetch64 && !needs.prefetchable32 {
+                space.alloc_memory64(size, true).or_else(|| alloc32(space))
+            } else {
+                alloc32(space)
+            };
+            let Some(start) = start else {
+                windows.release(space);
+                return None;
+            };
+            windows.prefetchable = Some(WindowAllocator::new(start..start + size));
+        }
+        if needs.sizes.io > 0 {
+            let size = needs.sizes.io.next_power_of_two();
+            if let Some(start) = u32::try_from(size).ok().and_then(|s| space.alloc_io(s)) {
+                windows.io = Some(WindowAllocator::new(start as u64..start as u64 + size));
+            }
+        }
+        Some(windows)
+    }
+
+    /// Give the windows back to the space they were carved from.
+    fn release(self, space: &mut impl BarSpace) {
+        for window in [self.memory, self.prefetchable, self.io]
+            .into_iter()
+            .flatten()
+        {
+            space.free(window.window());
+        }
+    }
+
+    fn prefetchable_below_4g(&self) -> Option<&WindowAllocator> {
+        self.prefetchable
+            .as_ref()
+            .filter(|w| w.window().end <= MEM32_END)
+    }
+}
+
+impl BarSpace for Apertures {
+    fn alloc_memory32(&mut self, size: u32, prefetchable: bool) -> Option<u32> {
+        let pref = if prefetchable && self.prefetchable_below_4g().is_some() {
+            self.prefetchable.as_mut()?.alloc(size as u64)
+        } else {
+            None
+        };
+        pref.or_else(|| self.memory.as_mut()?.alloc(size as u64))
+            .map(|v| v as u32)
+    }
+
+    fn alloc_memory64(&mut self, size: u64, prefetchable: bool) -> Option<u64> {
+        // Non-prefetchable memory windows are 32-bit
+        let pref = match (prefetchable, self.prefetchable.as_mut()) {
+            (true, Some(window)) => window.alloc(size),
+            _ => None,
+        };
+        pref.or_else(|| self.memory.as_mut()?.alloc(size))
+    }
+
+    fn alloc_io(&mut self, size: u32) -> Option<u32> {
+        self.io.as_mut()?.alloc(size as u64).map(|v| v as u32)
+    }
+
+    fn free(&mut self, block: Range<u64>) {
+        let window = [&mut self.memory, &mut self.prefetchable, &mut self.io]
+            .into_iter()
+            .flatten()
+            .find(|w| w.contains(&block));
+        if let Some(window) = window {
+            window.free(block.start, block.end - block.start);
         }
     }
 }
+
+/// Minimal window sizes a bridge needs to forward everything beneath it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowSizes {
+    pub memory: u64,
+    pub prefetchable: u64,
+    pub io: u64,
+}
+
+/// Dry-run sizing of the windows of `bridge`, for checking feasibility
+/// against the available host ranges before any assignment.
+///
+/// Walks the buses already assigned below the bridge; BAR sizing restores
+/// the registers, nothing else is written.
+pub fn required_window_sizes(
+    controller: &mut PcieController,
+    bridge: PciAddress,
+) -> Result<WindowSizes, Error> {
+    let base = PciHeaderBase::new(controller, bridge)
+        .ok_or(Error::InvalidArgument("no device at bridge address"))?;
+    if !matches!(base.header_type(), HeaderType::PciPciBridge) {
+        return Err(Error::InvalidArgument("not a PCI-PCI bridge"));
+    }
+    let bridge = PciPciBridge::new(base);
+    let needs = bus_window_sizes(
+        controller,
+        bridge.address().segment(),
+        bridge.secondary_bus_number(),
+        forwards_ari(&bridge),
+        None,
+        &|_| false,
+        None,
+    );
+    Ok(needs.sizes)
+}
+
+/// What the windows of the bridge above a bus have to hold.
+#[derive(Debug, Default)]
+struct WindowNeeds {
+    sizes: WindowSizes,
+    /// Something below needs its prefetchable memory below 4GiB
+    prefetchable32: bool,
+}
+
+/// Sizes of the windows forwarding to `bus`, everything below rounded up to
+/// powers of two the way [`WindowAllocator`] hands them out.
+///
+/// With `numbering`, `(last bus given out, bus_max)`, bridges below are
+/// numbered from there on while their subtree is sized and cleared again
+/// after, for sizing ahead of the enumeration; functions are quiesced
+/// before their BARs are sized and ARI forwarding is enabled as
+/// [`PciIterator`] would. Otherwise the bus numbers are used as found and
+/// nothing but the BARs is touched. Functions `skip` names are left out.
+/// `ari` walks `bus` as one ARI device, see [`probe_bus`].
+fn bus_window_sizes(
+    controller: &mut PcieController,
+    segment: u16,
+    bus: u8,
+    ari: bool,
+    mut numbering: Option<&mut (u8, u8)>,
+    skip: &dyn Fn(PciAddress) -> bool,
+    quirk: Option<&SharedQuirk>,
+) -> WindowNeeds {
+    let mut memory = Vec::new();
+    let mut prefetchable = Vec::new();
+    let mut io = Vec::new();
+    let mut prefetchable32 = false;
+
+    for function in probe_bus(controller, segment, bus, ari, quirk) {
+        let address = match &function {
+            PciConfigSpace::Endpoint(ep) => ep.address(),
+            PciConfigSpace::PciPciBridge(bridge) => bridge.address(),
+            _ => continue,
+        };
+        if skip(address) {
+            continue;
+        }
+        match function {
+            PciConfigSpace::Endpoint(ep) => {
+                if numbering.is_some() {
+                    quiesce(&ep);
+                }
+                for bar in ep.bar_info() {
+                    let size = bar.size.next_power_of_two();
+                    match bar.kind {
+                        BarKind::Io => io.push(size),
+                        _ if !bar.prefetchable => memory.push(size),
+                        BarKind::Mem64 if bar.assigned.is_none_or(|a| a >= MEM32_END) => {
+                            prefetchable.push(size)
+                        }
+                        // Kept below 4GiB, see `Mem64Placement::AsFound`
+                        _ => {
+                            prefetchable32 = true;
+                            prefetchable.push(size);
+                        }
+                    }
+                }
+            }
+            PciConfigSpace::PciPciBridge(mut bridge) => {
+                let child = match numbering.as_deref_mut() {
+                    Some((last, bus_max)) => {
+                        let Some(secondary) = last.checked_add(1).filter(|b| b <= bus_max) else {
+                            continue;
+                        };
+                        *last = secondary;
+                        bridge.update_bus_number(|_| BusNumber {
+                            primary: bus,
+                            secondary,
+                            subordinate: *bus_max,
+                        });
+                        let ari = enable_ari(controller, segment, &bridge, quirk);
+                        let child = bus_window_sizes(
+                            controller,
+                            segment,
+                            secondary,
+                            ari,
+                            numbering.as_deref_mut(),
+                            skip,
+                            quirk,
+                        );
+                        bridge.update_bus_number(|_| BusNumber {
+                            primary: 0,
+                            secondary: 0,
+                            subordinate: 0,
+                        });
+                        child
+                    }
+                    // Skip unassigned or looping bus numbers
+                    None if bridge.secondary_bus_number() > bus => bus_window_sizes(
+                        controller,
+                        segment,
+                        bridge.secondary_bus_number(),
+                        forwards_ari(&bridge),
+                        None,
+                        skip,
+                        quirk,
+                    ),
+                    None => continue,
+                };
+                if child.sizes.memory > 0 {
+                    memory.push(child.sizes.memory.next_power_of_two());
+                }
+                if child.sizes.prefetchable > 0 {
+                    prefetchable.push(child.sizes.prefetchable.next_power_of_two());
+                    prefetchable32 |= child.prefetchable32 || !bridge.prefetchable_64bit();
+                }
+                if child.sizes.io > 0 {
+                    io.push(child.sizes.io.next_power_of_two());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    WindowNeeds {
+        sizes: WindowSizes {
+            memory: pack_window(memory, WINDOW_ALIGN),
+            prefetchable: pack_window(prefetchable, WINDOW_ALIGN),
+            io: pack_window(io, IO_WINDOW_ALIGN),
+        },
+        prefetchable32,
+    }
+}
+
+/// Turn off I/O and memory decode and bus mastering.
+fn quiesce(base: &PciHeaderBase) {
+    base.update_command(|mut cmd| {
+        cmd.remove(
+            CommandRegister::IO_ENABLE
+                | CommandRegister::MEMORY_ENABLE
+                | CommandRegister::BUS_MASTER_ENABLE,
+        );
+        cmd
+    });
+}
+
+/// Endpoints and bridges present on `bus`, without writing anything. With
+/// `ari` the bus holds a single ARI device, its functions are followed
+/// through the Next Function Number chain as [`PciIterator`] does.
+pub(crate) fn probe_bus(
+    controller: &mut PcieController,
+    segment: u16,
+    bus: u8,
+    ari: bool,
+    quirk: Option<&SharedQuirk>,
+) -> Vec<PciConfigSpace> {
+    let mut functions = Vec::new();
+    if ari {
+        let mut function = 0u8;
+        // Device number bits are the upper bits of the ARI function number
+        while let Some(base) = PciHeaderBase::probe(
+            controller,
+            PciAddress::new(segment, bus, function >> 3, function & 0x7),
+            false,
+            quirk.cloned(),
+        ) {
+            let next = base.ari().map_or(0, |ari| ari.next_function_number());
+            functions.extend(config_space(base));
+            // Chained in ascending order, anything else ends the chain
+            if next <= function {
+                break;
+            }
+            function = next;
+        }
+        return functions;
+    }
+    for device in 0..=MAX_DEVICE {
+        for function in 0..=MAX_FUNCTION {
+            let address = PciAddress::new(segment, bus, device, function);
+            let Some(base) = PciHeaderBase::probe(controller, address, false, quirk.cloned())
+            else {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            };
+            let multiple_functions = base.has_multiple_functions();
+            functions.extend(config_space(base));
+            if !multiple_functions {
+                break;
+            }
+        }
+    }
+    functions
+}
+
+fn config_space(base: PciHeaderBase) -> Option<PciConfigSpace> {
+    match base.header_type() {
+        HeaderType::Endpoint => Some(PciConfigSpace::Endpoint(Endpoint::new(base))),
+        HeaderType::PciPciBridge => Some(PciConfigSpace::PciPciBridge(PciPciBridge::new(base))),
+        _ => None,
+    }
+}
+
+/// Whether `bridge` forwards ARI, its secondary bus then holds one device
+/// with up to 256 functions.
+pub(crate) fn forwards_ari(bridge: &PciPciBridge) -> bool {
+    bridge
+        .pcie_capability()
+        .is_some_and(|port| port.ari_forwarding_supported() && port.ari_forwarding())
+}
+
+/// Turn ARI forwarding of `bridge` on if the device on its secondary bus
+/// supports ARI, off otherwise. Returns whether it is on.
+fn enable_ari(
+    controller: &mut PcieController,
+    segment: u16,
+    bridge: &PciPciBridge,
+    quirk: Option<&SharedQuirk>,
+) -> bool {
+    let Some(port) = bridge.pcie_capability() else {
+        return false;
+    };
+    if !port.ari_forwarding_supported() {
+        return false;
+    }
+    let function0 = PciAddress::new(segment, bridge.secondary_bus_number(), 0, 0);
+    let ari = PciHeaderBase::probe(controller, function0, false, quirk.cloned())
+        .is_some_and(|f| f.ari().is_some());
+    port.set_ari_forwarding(ari);
+    ari
+}
+
+/// Power of two blocks back to back, rounded up to the window granularity.
+fn pack_window(blocks: Vec<u64>, granularity: u64) -> u64 {
+    blocks.iter().sum::<u64>().next_multiple_of(granularity)
+}
+
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const CLASS_NETWORK: u8 = 0x02;
+const CLASS_DISPLAY: u8 = 0x03;
+
+/// Class based filters for endpoint iterators.
+pub trait EndpointIterExt: Iterator<Item = Endpoint> + Sized {
+    fn device_type(self, ty: DeviceType) -> impl Iterator<Item = Endpoint> {
+        self.filter(move |ep| ep.device_type() == ty)
+    }
+
+    fn base_class(self, class: u8) -> impl Iterator<Item = Endpoint> {
+        self.filter(move |ep| ep.revision_and_class().base_class == class)
+    }
+
+    fn storage(self) -> impl Iterator<Item = Endpoint> {
+        self.base_class(CLASS_MASS_STORAGE)
+    }
+
+    fn network(self) -> impl Iterator<Item = Endpoint> {
+        self.base_class(CLASS_NETWORK)
+    }
+
+    fn display(self) -> impl Iterator<Item = Endpoint> {
+        self.base_class(CLASS_DISPLAY)
+    }
+}
+
+impl<I: Iterator<Item = Endpoint>> EndpointIterExt for I {}
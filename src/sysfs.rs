@@ -0,0 +1,125 @@
+//! `/sys/bus/pci` like view of a scanned hierarchy, flattened to path →
+//! content so any kernel can serve it from its own pseudo-filesystem:
+//!
+//! ```text
+//! devices/pci0000:00/0000:00:01.0/0000:01:00.0/vendor   "0x1b36\n"
+//! devices/pci0000:00/0000:00:01.0/0000:01:00.0/config   raw config space
+//! bus/pci/devices/0000:01:00.0 -> ../../../devices/pci0000:00/0000:00:01.0/0000:01:00.0
+//! ```
+//!
+//! Files are a snapshot taken by [`export`], `config` included.
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+
+use crate::{Bdf, NodeKind, PciHeaderBase, PciTopology, PcieController, RootComplex};
+
+/// Linux `IORESOURCE_MEM` and `IORESOURCE_PREFETCH`, for the `resource` file
+const RESOURCE_MEM: u64 = 0x200;
+const RESOURCE_PREFETCH: u64 = 0x2000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysfsEntry {
+    File(Vec<u8>),
+    /// Symlink target, relative to the directory holding the link
+    Link(String),
+}
+
+/// Exported files and links by path, directories are implied by the paths.
+#[derive(Debug, Clone, Default)]
+pub struct SysfsTree {
+    entries: BTreeMap<String, SysfsEntry>,
+}
+
+impl SysfsTree {
+    pub fn get(&self, path: &str) -> Option<&SysfsEntry> {
+        self.entries.get(path)
+    }
+
+    /// Every entry in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SysfsEntry)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Names directly below directory `dir`, files, links and
+    /// subdirectories alike, each once.
+    pub fn list<'a>(&'a self, dir: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut last = None;
+        self.entries.keys().filter_map(move |path| {
+            let rest = path.strip_prefix(dir)?.strip_prefix('/')?;
+            let name = rest.split('/').next()?;
+            if last == Some(name) {
+                return None;
+            }
+            last = Some(name);
+            Some(name)
+        })
+    }
+
+    fn file(&mut self, dir: &str, name: &str, content: impl Into<Vec<u8>>) {
+        self.entries
+            .insert(format!("{dir}/{name}"), SysfsEntry::File(content.into()));
+    }
+}
+
+/// Export every node of `topology`, reading its config space.
+pub fn export(controller: &mut PcieController, topology: &PciTopology) -> SysfsTree {
+    let mut tree = SysfsTree::default();
+    for (_, node) in topology.nodes() {
+        let Some(base) = PciHeaderBase::new(controller, node.address) else {
+            continue;
+        };
+        let config: Vec<u8> = (0..base.config_space_size())
+            .step_by(4)
+            .flat_map(|offset| base.read(offset).to_le_bytes())
+            .collect();
+        let Some(path) = topology.path_of(node.address) else {
+            continue;
+        };
+        let dir = format!("devices/pci{:04x}:00/{path}", node.address.segment());
+        let u16_at = |offset: usize| u16::from_le_bytes([config[offset], config[offset + 1]]);
+
+        tree.file(&dir, "vendor", format!("{:#06x}\n", u16_at(0x00)));
+        tree.file(&dir, "device", format!("{:#06x}\n", u16_at(0x02)));
+        tree.file(&dir, "revision", format!("{:#04x}\n", config[0x08]));
+        let class = u32::from_le_bytes([config[0x09], config[0x0a], config[0x0b], 0]);
+        tree.file(&dir, "class", format!("{class:#08x}\n"));
+        tree.file(&dir, "irq", format!("{}\n", config[0x3c]));
+
+        if let NodeKind::Endpoint { bars } = &node.kind {
+            tree.file(&dir, "subsystem_vendor", format!("{:#06x}\n", u16_at(0x2c)));
+            tree.file(&dir, "subsystem_device", format!("{:#06x}\n", u16_at(0x2e)));
+            let mut resource = String::new();
+            for index in 0..6 {
+                let (start, end, flags) = match bars.iter().find(|b| b.index == index) {
+                    Some(bar) if bar.range.start != 0 => {
+                        let prefetch = if bar.prefetchable {
+                            RESOURCE_PREFETCH
+                        } else {
+                            0
+                        };
+                        (bar.range.start, bar.range.end - 1, RESOURCE_MEM | prefetch)
+                    }
+                    _ => (0, 0, 0),
+                };
+                resource += &format!("{start:#018x} {end:#018x} {flags:#018x}\n");
+            }
+            tree.file(&dir, "resource", resource);
+        }
+        tree.file(&dir, "config", config);
+
+        let link = format!("bus/pci/devices/{}", Bdf(node.address));
+        tree.entries
+            .insert(link, SysfsEntry::Link(format!("../../../{dir}")));
+    }
+    tree
+}
+
+impl RootComplex {
+    /// [`export`] the topology kept by the last scan, empty before any.
+    pub fn sysfs(&mut self) -> SysfsTree {
+        let Some(topology) = self.topology().cloned() else {
+            return SysfsTree::default();
+        };
+        export(self.controller(), &topology)
+    }
+}
@@ -0,0 +1,393 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use core::ops::{Range, RangeInclusive};
+
+use crate::chip::PcieController;
+use crate::root::{forwards_ari, probe_bus};
+use crate::{
+    BarKind, Bdf, BusNumber, CommandRegister, DevicePortType, Endpoint, PciAddress, PciConfigSpace,
+    PciHeaderBase, PciPciBridge,
+};
+
+/// Index of a node in a [`PciTopology`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeId(usize);
+
+/// Memory BAR as assigned at scan time
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BarResource {
+    /// BAR register, the low half for 64-bit BARs
+    pub index: u8,
+    pub range: Range<u64>,
+    pub prefetchable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum NodeKind {
+    Endpoint {
+        bars: Vec<BarResource>,
+    },
+    Bridge {
+        bus: BusNumber,
+        io: Option<Range<u32>>,
+        memory: Option<Range<u32>>,
+        prefetchable: Option<Range<u64>>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct PciNode {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub base_class: u8,
+    pub sub_class: u8,
+    /// From the PCI Express capability, `None` for conventional PCI
+    pub port_type: Option<DevicePortType>,
+    pub kind: NodeKind,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+/// Change found by a partial rescan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RescanEvent {
+    DeviceAdded(PciAddress),
+    DeviceRemoved(PciAddress),
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PciNode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("PciNode", 9)?;
+        s.serialize_field("address", &Bdf(self.address))?;
+        s.serialize_field("vendor_id", &self.vendor_id)?;
+        s.serialize_field("device_id", &self.device_id)?;
+        s.serialize_field("base_class", &self.base_class)?;
+        s.serialize_field("sub_class", &self.sub_class)?;
+        s.serialize_field("port_type", &self.port_type)?;
+        s.serialize_field("kind", &self.kind)?;
+        s.serialize_field("parent", &self.parent)?;
+        s.serialize_field("children", &self.children)?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RescanEvent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (index, variant, address) = match self {
+            Self::DeviceAdded(address) => (0, "DeviceAdded", address),
+            Self::DeviceRemoved(address) => (1, "DeviceRemoved", address),
+        };
+        serializer.serialize_newtype_variant("RescanEvent", index, variant, &Bdf(*address))
+    }
+}
+
+/// Long-lived model of a hierarchy, bridges included, as found by
+/// [`crate::RootComplex::scan`]. Nodes on the root bus have no parent.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PciTopology {
+    nodes: Vec<PciNode>,
+}
+
+impl PciTopology {
+    /// Read the hierarchy below `bus`, following the bus numbers programmed
+    /// in bridges. BAR sizes of functions in `known` with the same IDs are
+    /// taken from there and only their addresses read, other functions have
+    /// their BARs sized with decoding turned off meanwhile. Nothing else is
+    /// written. `ari` reads `bus` as one ARI device, see [`probe_bus`].
+    pub(crate) fn read(
+        controller: &mut PcieController,
+        segment: u16,
+        bus: u8,
+        ari: bool,
+        known: Option<&PciTopology>,
+    ) -> Self {
+        let mut topology = Self::default();
+        topology.read_bus(controller, segment, bus, ari, None, known);
+        topology
+    }
+
+    /// Hint for [`Self::read`] only, e.g. BAR sizes saved by a previous
+    /// kernel. Parent and child links are not set up.
+    pub(crate) fn from_nodes(nodes: Vec<PciNode>) -> Self {
+        Self { nodes }
+    }
+
+    fn read_bus(
+        &mut self,
+        controller: &mut PcieController,
+        segment: u16,
+        bus: u8,
+        ari: bool,
+        parent: Option<NodeId>,
+        known: Option<&PciTopology>,
+    ) {
+        for function in probe_bus(controller, segment, bus, ari, None) {
+            let (node, secondary) = match function {
+                PciConfigSpace::Endpoint(ep) => {
+                    let known =
+                        known.and_then(|t| t.nodes.iter().find(|n| n.address == ep.address()));
+                    (Self::endpoint_node(&ep, parent, known), None)
+                }
+                PciConfigSpace::PciPciBridge(bridge) => {
+                    let node = Self::bridge_node(&bridge, parent);
+                    let below = (bridge.secondary_bus_number(), forwards_ari(&bridge));
+                    (node, Some(below))
+                }
+                _ => continue,
+            };
+            let id = NodeId(self.nodes.len());
+            self.nodes.push(node);
+            if let Some(parent) = parent {
+                self.nodes[parent.0].children.push(id);
+            }
+            // Skip unassigned or looping bus numbers
+            if let Some((secondary, ari)) = secondary.filter(|(s, _)| *s > bus) {
+                self.read_bus(controller, segment, secondary, ari, Some(id), known);
+            }
+        }
+    }
+
+    fn endpoint_node(ep: &Endpoint, parent: Option<NodeId>, known: Option<&PciNode>) -> PciNode {
+        let known =
+            known.filter(|n| n.vendor_id == ep.vendor_id() && n.device_id == ep.device_id());
+        let bars = match known.map(|n| &n.kind) {
+            // Possibly in use, don't size again
+            Some(NodeKind::Endpoint { bars }) => bars
+                .iter()
+                .map(|bar| {
+                    let start = ep.bar_address(bar.index as usize).unwrap_or(0);
+                    BarResource {
+                        range: start..start + (bar.range.end - bar.range.start),
+                        ..bar.clone()
+                    }
+                })
+                .collect(),
+            _ => {
+                let command = ep.update_command(|mut cmd| {
+                    cmd.remove(CommandRegister::IO_ENABLE | CommandRegister::MEMORY_ENABLE);
+                    cmd
+                });
+                let bars = ep
+                    .bar_info()
+                    .filter(|bar| bar.kind != BarKind::Io)
+                    .map(|bar| {
+                        let start = bar.assigned.unwrap_or(0);
+                        BarResource {
+                            index: bar.index,
+                            range: start..start + bar.size,
+                            prefetchable: bar.prefetchable,
+                        }
+                    })
+                    .collect();
+                ep.update_command(|_| command);
+                bars
+            }
+        };
+        let class = ep.revision_and_class();
+        PciNode {
+            address: ep.address(),
+            vendor_id: ep.vendor_id(),
+            device_id: ep.device_id(),
+            base_class: class.base_class,
+            sub_class: class.sub_class,
+            port_type: port_type(ep),
+            kind: NodeKind::Endpoint { bars },
+            parent,
+            children: Vec::new(),
+        }
+    }
+
+    fn bridge_node(bridge: &PciPciBridge, parent: Option<NodeId>) -> PciNode {
+        let class = bridge.revision_and_class();
+        PciNode {
+            address: bridge.address(),
+            vendor_id: bridge.vendor_id(),
+            device_id: bridge.device_id(),
+            base_class: class.base_class,
+            sub_class: class.sub_class,
+            port_type: port_type(bridge),
+            kind: NodeKind::Bridge {
+                bus: BusNumber {
+                    primary: bridge.primary_bus_number(),
+                    secondary: bridge.secondary_bus_number(),
+                    subordinate: bridge.subordinate_bus_number(),
+                },
+                io: bridge.io_window(),
+                memory: bridge.memory_window(),
+                prefetchable: bridge.prefetchable_window(),
+            },
+            parent,
+            children: Vec::new(),
+        }
+    }
+
+    /// Bridge whose secondary bus is `bus`
+    pub fn bridge_to(&self, bus: u8) -> Option<NodeId> {
+        self.nodes()
+            .find(|(_, n)| matches!(n.kind, NodeKind::Bridge { bus: b, .. } if b.secondary == bus))
+            .map(|(id, _)| id)
+    }
+
+    /// Replace every node on `buses` with `fresh`, whose root nodes become
+    /// children of `parent`. Returns what appeared and disappeared; a
+    /// function whose IDs changed counts as both.
+    pub(crate) fn splice(
+        &mut self,
+        buses: RangeInclusive<u8>,
+        parent: Option<NodeId>,
+        fresh: PciTopology,
+    ) -> Vec<RescanEvent> {
+        let same = |a: &PciNode, b: &PciNode| {
+            a.address == b.address && a.vendor_id == b.vendor_id && a.device_id == b.device_id
+        };
+        let in_range = |n: &PciNode| buses.contains(&n.address.bus());
+
+        let mut events = Vec::new();
+        for old in self.nodes.iter().filter(|n| in_range(n)) {
+            if !fresh.nodes.iter().any(|n| same(n, old)) {
+                events.push(RescanEvent::DeviceRemoved(old.address));
+            }
+        }
+        for new in &fresh.nodes {
+            if !self.nodes.iter().any(|n| in_range(n) && same(n, new)) {
+                events.push(RescanEvent::DeviceAdded(new.address));
+            }
+        }
+
+        // Renumber the kept nodes, then append the fresh ones after them
+        let mut remap = Vec::with_capacity(self.nodes.len());
+        let mut nodes = Vec::new();
+        for node in core::mem::take(&mut self.nodes) {
+            if in_range(&node) {
+                remap.push(None);
+            } else {
+                remap.push(Some(NodeId(nodes.len())));
+                nodes.push(node);
+            }
+        }
+        let parent = parent.and_then(|p| remap[p.0]);
+        for node in &mut nodes {
+            node.parent = node.parent.and_then(|p| remap[p.0]);
+        }
+        let offset = nodes.len();
+        for mut node in fresh.nodes {
+            node.parent = match node.parent {
+                Some(p) => Some(NodeId(p.0 + offset)),
+                None => parent,
+            };
+            nodes.push(node);
+        }
+        for node in &mut nodes {
+            node.children.clear();
+        }
+        for i in 0..nodes.len() {
+            if let Some(p) = nodes[i].parent {
+                nodes[p.0].children.push(NodeId(i));
+            }
+        }
+        self.nodes = nodes;
+        events
+    }
+
+    /// Append the nodes of `other`, e.g. the hierarchy of another host
+    /// bridge. Lookups by bus number such as [`Self::bridge_to`] don't tell
+    /// segments apart.
+    pub fn merge(&mut self, other: PciTopology) {
+        let base = self.nodes.len();
+        let shift = |id: NodeId| NodeId(id.0 + base);
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.parent = node.parent.map(shift);
+            for child in &mut node.children {
+                *child = shift(*child);
+            }
+            node
+        }));
+    }
+
+    pub fn node(&self, id: NodeId) -> &PciNode {
+        &self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &PciNode)> {
+        self.nodes.iter().enumerate().map(|(i, n)| (NodeId(i), n))
+    }
+
+    /// Nodes on the root bus
+    pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes()
+            .filter(|(_, n)| n.parent.is_none())
+            .map(|(id, _)| id)
+    }
+
+    pub fn endpoints(&self) -> impl Iterator<Item = &PciNode> {
+        self.nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Endpoint { .. }))
+    }
+
+    pub fn find_by_port_type(&self, ty: DevicePortType) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes()
+            .filter(move |(_, n)| n.port_type == Some(ty))
+            .map(|(id, _)| id)
+    }
+
+    pub fn find(&self, address: PciAddress) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|n| n.address == address)
+            .map(NodeId)
+    }
+
+    /// INTx pin (1 = INTA ... 4 = INTD) seen on the root bus for `pin` of
+    /// the function at `address`, swizzled across every bridge on the way:
+    /// pin `n` of device `d` below a bridge arrives as `(n - 1 + d) % 4 + 1`.
+    pub fn intx_swizzle(&self, address: PciAddress, pin: u8) -> Option<u8> {
+        if !(1..=4).contains(&pin) {
+            return None;
+        }
+        let mut pin = pin;
+        let mut node = self.node(self.find(address)?);
+        while let Some(parent) = node.parent {
+            pin = (pin - 1 + node.address.device()) % 4 + 1;
+            node = self.node(parent);
+        }
+        Some(pin)
+    }
+
+    /// `address` with the bridges above it, e.g.
+    /// `0000:00:01.0/0000:01:00.0`, for logs and matching boot parameters.
+    pub fn path_of(&self, address: PciAddress) -> Option<String> {
+        let mut path = String::new();
+        for hop in self.upstream_path(address)? {
+            let _ = write!(path, "{}/", Bdf(hop));
+        }
+        let _ = write!(path, "{}", Bdf(address));
+        Some(path)
+    }
+
+    /// Bridges between the root bus and `address`, root side first.
+    pub fn upstream_path(&self, address: PciAddress) -> Option<Vec<PciAddress>> {
+        let mut path = Vec::new();
+        let mut parent = self.node(self.find(address)?).parent;
+        while let Some(id) = parent {
+            let node = self.node(id);
+            path.push(node.address);
+            parent = node.parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+fn port_type(base: &PciHeaderBase) -> Option<DevicePortType> {
+    base.pcie_capability().map(|cap| cap.device_port_type())
+}
@@ -0,0 +1,79 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{HostWindow, HostWindows, InboundWindow, WindowKind};
+
+/// Translation between CPU physical and PCI bus addresses.
+///
+/// BARs and bridge windows hold bus addresses, the BAR allocator hands them
+/// out in bus space too (see [`HostWindows::configure_allocator`]).
+pub trait BusAddressTranslator: Send + Sync {
+    /// Bus address a device writes to reach CPU physical `cpu`, e.g. an MSI
+    /// doorbell. Follows the inbound mapping.
+    fn cpu_to_bus(&self, cpu: u64) -> Option<u64>;
+
+    /// CPU physical address of bus address `bus`, e.g. a BAR. Follows the
+    /// outbound mapping.
+    fn bus_to_cpu(&self, bus: u64) -> Option<u64>;
+}
+
+impl<T: BusAddressTranslator + ?Sized> BusAddressTranslator for Box<T> {
+    fn cpu_to_bus(&self, cpu: u64) -> Option<u64> {
+        (**self).cpu_to_bus(cpu)
+    }
+
+    fn bus_to_cpu(&self, bus: u64) -> Option<u64> {
+        (**self).bus_to_cpu(bus)
+    }
+}
+
+/// CPU and bus addresses are the same, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdentityTranslator;
+
+impl BusAddressTranslator for IdentityTranslator {
+    fn cpu_to_bus(&self, cpu: u64) -> Option<u64> {
+        Some(cpu)
+    }
+
+    fn bus_to_cpu(&self, bus: u64) -> Option<u64> {
+        Some(bus)
+    }
+}
+
+/// Translation by the windows programmed into the host bridge. Addresses
+/// outside every window don't translate.
+#[derive(Debug, Clone, Default)]
+pub struct WindowTranslator {
+    outbound: Vec<HostWindow>,
+    inbound: Vec<InboundWindow>,
+}
+
+impl WindowTranslator {
+    /// I/O windows of `outbound` are ignored.
+    pub fn new(outbound: &HostWindows, inbound: &[InboundWindow]) -> Self {
+        Self {
+            outbound: outbound
+                .iter()
+                .filter(|w| w.kind != WindowKind::Io)
+                .copied()
+                .collect(),
+            inbound: inbound.to_vec(),
+        }
+    }
+}
+
+impl BusAddressTranslator for WindowTranslator {
+    fn cpu_to_bus(&self, cpu: u64) -> Option<u64> {
+        self.inbound
+            .iter()
+            .find(|w| cpu >= w.cpu_address && cpu - w.cpu_address < w.size)
+            .map(|w| cpu - w.cpu_address + w.pci_address)
+    }
+
+    fn bus_to_cpu(&self, bus: u64) -> Option<u64> {
+        self.outbound
+            .iter()
+            .find(|w| bus >= w.pci_address && bus - w.pci_address < w.size)
+            .map(|w| bus - w.pci_address + w.cpu_address)
+    }
+}
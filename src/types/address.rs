@@ -0,0 +1,80 @@
+//! Crate owned function address, so the public API doesn't depend on the
+//! `pci_types` version used internally. [`crate::Controller`] implementations
+//! still get the `pci_types` address of `rdif-pcie`, convert with `From`.
+
+use core::fmt::{Debug, Display};
+
+use bit_field::BitField;
+
+/// Segment, bus, device and function of a PCI function. Orders by segment,
+/// then bus, device and function.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciAddress(u32);
+
+impl PciAddress {
+    pub fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        let mut raw = 0u32;
+        raw.set_bits(0..3, function as u32);
+        raw.set_bits(3..8, device as u32);
+        raw.set_bits(8..16, bus as u32);
+        raw.set_bits(16..32, segment as u32);
+        Self(raw)
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.0.get_bits(16..32) as u16
+    }
+
+    pub fn bus(&self) -> u8 {
+        self.0.get_bits(8..16) as u8
+    }
+
+    pub fn device(&self) -> u8 {
+        self.0.get_bits(3..8) as u8
+    }
+
+    pub fn function(&self) -> u8 {
+        self.0.get_bits(0..3) as u8
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment(),
+            self.bus(),
+            self.device(),
+            self.function()
+        )
+    }
+}
+
+impl Debug for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl From<pci_types::PciAddress> for PciAddress {
+    fn from(value: pci_types::PciAddress) -> Self {
+        Self::new(
+            value.segment(),
+            value.bus(),
+            value.device(),
+            value.function(),
+        )
+    }
+}
+
+impl From<PciAddress> for pci_types::PciAddress {
+    fn from(value: PciAddress) -> Self {
+        pci_types::PciAddress::new(
+            value.segment(),
+            value.bus(),
+            value.device(),
+            value.function(),
+        )
+    }
+}
@@ -1,9 +1,12 @@
-use core::{fmt::Debug, ops::Index};
+use core::{
+    fmt::Debug,
+    ops::{Index, IndexMut},
+};
 
 use alloc::vec::Vec;
-use pci_types::{
-    Bar, BarWriteError, ConfigRegionAccess, EndpointHeader, HeaderType, PciAddress, PciHeader,
-};
+use pci_types::Bar;
+
+use super::config::HeaderAccess;
 
 #[derive(Clone)]
 pub enum BarVec {
@@ -23,6 +26,8 @@ impl Debug for BarVec {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Bar64 {
     pub address: u64,
     pub size: u64,
@@ -30,6 +35,8 @@ pub struct Bar64 {
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Bar32 {
     pub address: u32,
     pub size: u32,
@@ -37,27 +44,19 @@ pub struct Bar32 {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct BarIO {
     pub port: u32,
 }
 
 pub(crate) trait BarHeader: Sized {
-    fn read_bar<A: ConfigRegionAccess>(&self, slot: usize, access: &A) -> Option<Bar>;
-
-    fn address(&self) -> PciAddress;
-
-    fn header_type(&self) -> HeaderType;
+    fn read_bar(&self, slot: usize, access: &HeaderAccess) -> Option<Bar>;
 
-    fn parse_bar<A: ConfigRegionAccess>(&self, slot_size: usize, access: &A) -> BarVec {
+    fn parse_bar(&self, slot_size: usize, access: &HeaderAccess) -> BarVec {
         let bar0 = match self.read_bar(0, access) {
             Some(bar0) => bar0,
-            None => {
-                return BarVec::Memory32(BarVecT {
-                    data: Vec::new(),
-                    address: self.address(),
-                    header_type: self.header_type(),
-                })
-            }
+            None => return BarVec::Memory32(BarVecT { data: Vec::new() }),
         };
 
         match bar0 {
@@ -88,11 +87,7 @@ pub(crate) trait BarHeader: Sized {
                     }
                 });
 
-                BarVec::Memory32(BarVecT {
-                    data: v,
-                    address: self.address(),
-                    header_type: self.header_type(),
-                })
+                BarVec::Memory32(BarVecT { data: v })
             }
             Bar::Memory64 {
                 address,
@@ -120,11 +115,7 @@ pub(crate) trait BarHeader: Sized {
                         });
                     }
                 });
-                BarVec::Memory64(BarVecT {
-                    data: v,
-                    address: self.address(),
-                    header_type: self.header_type(),
-                })
+                BarVec::Memory64(BarVecT { data: v })
             }
             Bar::Io { port } => {
                 let mut v = alloc::vec![None; slot_size];
@@ -137,11 +128,7 @@ pub(crate) trait BarHeader: Sized {
                     }
                 });
 
-                BarVec::Io(BarVecT {
-                    data: v,
-                    address: self.address(),
-                    header_type: self.header_type(),
-                })
+                BarVec::Io(BarVecT { data: v })
             }
         }
     }
@@ -151,8 +138,8 @@ impl Debug for Bar32 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "Memory32 {{ address: {:#p}, size: {:#x}, prefetchable: {} }}",
-            self.address as *const u8, self.size, self.prefetchable
+            "Memory32 {{ address: {:#x}, size: {:#x}, prefetchable: {} }}",
+            self.address, self.size, self.prefetchable
         )
     }
 }
@@ -161,17 +148,38 @@ impl Debug for Bar64 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "Memory64 {{ address: {:#p}, size: {:#x}, prefetchable: {} }}",
-            self.address as *const u8, self.size, self.prefetchable
+            "Memory64 {{ address: {:#x}, size: {:#x}, prefetchable: {} }}",
+            self.address, self.size, self.prefetchable
         )
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BarKind {
+    Mem32,
+    Mem64,
+    Io,
+}
+
+/// One BAR of a function whatever its kind, see [`crate::Endpoint::bar_info`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BarInfo {
+    /// BAR register, the low half for 64-bit BARs
+    pub index: u8,
+    pub kind: BarKind,
+    pub size: u64,
+    pub prefetchable: bool,
+    /// Programmed address, `None` while it is 0
+    pub assigned: Option<u64>,
+}
+
 #[derive(Clone)]
 pub struct BarVecT<T> {
     data: Vec<Option<T>>,
-    address: PciAddress,
-    header_type: pci_types::HeaderType,
 }
 
 impl<T: Debug> Debug for BarVecT<T> {
@@ -185,50 +193,6 @@ impl<T: Debug> Debug for BarVecT<T> {
     }
 }
 
-impl BarVecT<Bar32> {
-    pub(crate) fn set<A: ConfigRegionAccess>(
-        &self,
-        index: usize,
-        value: u32,
-        access: &A,
-    ) -> core::result::Result<(), BarWriteError> {
-        let header = PciHeader::new(self.address);
-        match self.header_type {
-            pci_types::HeaderType::PciPciBridge => {
-                todo!()
-            }
-            pci_types::HeaderType::Endpoint => unsafe {
-                EndpointHeader::from_header(header, access)
-                    .unwrap()
-                    .write_bar(index as _, access, value as _)
-            },
-            _ => panic!("Invalid header type"),
-        }
-    }
-}
-
-impl BarVecT<Bar64> {
-    pub(crate) fn set<A: ConfigRegionAccess>(
-        &self,
-        index: usize,
-        value: u64,
-        access: &A,
-    ) -> core::result::Result<(), BarWriteError> {
-        let header = PciHeader::new(self.address);
-        match self.header_type {
-            pci_types::HeaderType::PciPciBridge => {
-                todo!()
-            }
-            pci_types::HeaderType::Endpoint => unsafe {
-                EndpointHeader::from_header(header, access)
-                    .unwrap()
-                    .write_bar((index * 2) as _, access, value as _)
-            },
-            _ => panic!("Invalid header type"),
-        }
-    }
-}
-
 impl<T> Index<usize> for BarVecT<T> {
     type Output = Option<T>;
 
@@ -237,12 +201,39 @@ impl<T> Index<usize> for BarVecT<T> {
     }
 }
 
+/// Changes only the cached entry, program the hardware with
+/// [`crate::Endpoint::set_bar`].
+impl<T> IndexMut<usize> for BarVecT<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index]
+    }
+}
+
 impl<T> BarVecT<T> {
+    /// Number of BAR slots, implemented or not
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &Option<T>> {
         self.data.iter()
     }
 
+    /// Changes only the cached entries, program the hardware with
+    /// [`crate::Endpoint::set_bar`].
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Option<T>> {
+        self.data.iter_mut()
+    }
+
     pub fn get(&self, index: usize) -> Option<&T> {
         self.data.get(index).and_then(|v| v.as_ref())
     }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.data.get_mut(index).and_then(|v| v.as_mut())
+    }
 }
@@ -0,0 +1,48 @@
+use bit_field::BitField;
+
+use crate::{err::Error, Delay, Endpoint};
+
+/// BIST register, the top byte of the dword at 0x0c
+const BIST_REG: u16 = 0x0c;
+const BIST_CAPABLE: usize = 24 + 7;
+const BIST_START: usize = 24 + 6;
+const POLL_MS: u32 = 10;
+
+impl Endpoint {
+    pub fn bist_capable(&self) -> bool {
+        self.read(BIST_REG).get_bit(BIST_CAPABLE)
+    }
+
+    /// Run the built-in self test and return its completion code, 0 means
+    /// passed. The spec gives the device 2s, `timeout_ms` bounds the wait.
+    /// The function must not be in use while the test runs.
+    pub fn run_bist(
+        &self,
+        timeout_ms: u32,
+        delay: &mut (impl Delay + ?Sized),
+    ) -> Result<u8, Error> {
+        let data = self.read(BIST_REG);
+        if !data.get_bit(BIST_CAPABLE) {
+            return Err(Error::NotSupported("BIST"));
+        }
+        if data.get_bit(BIST_START) {
+            return Err(Error::InvalidArgument("BIST already running"));
+        }
+        let mut start = data;
+        start.set_bit(BIST_START, true);
+        self.write(BIST_REG, start);
+
+        let mut waited = 0;
+        loop {
+            let data = self.read(BIST_REG);
+            if !data.get_bit(BIST_START) {
+                return Ok(data.get_bits(24..28) as u8);
+            }
+            if waited >= timeout_ms {
+                return Err(Error::Timeout);
+            }
+            delay.delay_ms(POLL_MS);
+            waited += POLL_MS;
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use bit_field::BitField;
+
+use crate::{DevicePortType, PciHeaderBase, EXT_CAP_ID_ACS};
+
+/// ACS Capability and Control share this dword
+const CAPABILITY_AND_CONTROL: u16 = 0x04;
+
+const SOURCE_VALIDATION: usize = 0;
+const TRANSLATION_BLOCKING: usize = 1;
+const P2P_REQUEST_REDIRECT: usize = 2;
+const P2P_COMPLETION_REDIRECT: usize = 3;
+const UPSTREAM_FORWARDING: usize = 4;
+const P2P_EGRESS_CONTROL: usize = 5;
+const DIRECT_TRANSLATED_P2P: usize = 6;
+
+/// Controls [`PciHeaderBase::enable_acs`] turns on when supported, the set
+/// needed to put functions below a port into IOMMU groups of their own.
+const ISOLATION: [usize; 4] = [
+    SOURCE_VALIDATION,
+    P2P_REQUEST_REDIRECT,
+    P2P_COMPLETION_REDIRECT,
+    UPSTREAM_FORWARDING,
+];
+
+/// Access Control Services extended capability
+pub struct AcsCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+impl PciHeaderBase {
+    pub fn acs(&self) -> Option<AcsCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_ACS)?;
+        Some(AcsCapability { base: self, offset })
+    }
+
+    /// Enable the ACS isolation controls of a root or downstream port.
+    /// Returns whether the port has ACS.
+    pub fn enable_acs(&self) -> bool {
+        let port = self.pcie_capability().map(|cap| cap.device_port_type());
+        if !port.is_some_and(DevicePortType::is_downstream_port) {
+            return false;
+        }
+        let Some(acs) = self.acs() else {
+            return false;
+        };
+        let caps = acs.read();
+        acs.update_control(|control| {
+            for bit in ISOLATION {
+                if caps.get_bit(bit) {
+                    control.set_bit(bit, true);
+                }
+            }
+        });
+        true
+    }
+}
+
+impl AcsCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn source_validation(&self) -> bool {
+        self.read().get_bit(SOURCE_VALIDATION)
+    }
+
+    pub fn translation_blocking(&self) -> bool {
+        self.read().get_bit(TRANSLATION_BLOCKING)
+    }
+
+    pub fn p2p_request_redirect(&self) -> bool {
+        self.read().get_bit(P2P_REQUEST_REDIRECT)
+    }
+
+    pub fn p2p_completion_redirect(&self) -> bool {
+        self.read().get_bit(P2P_COMPLETION_REDIRECT)
+    }
+
+    pub fn upstream_forwarding(&self) -> bool {
+        self.read().get_bit(UPSTREAM_FORWARDING)
+    }
+
+    pub fn p2p_egress_control(&self) -> bool {
+        self.read().get_bit(P2P_EGRESS_CONTROL)
+    }
+
+    pub fn direct_translated_p2p(&self) -> bool {
+        self.read().get_bit(DIRECT_TRANSLATED_P2P)
+    }
+
+    /// Number of bits in the Egress Control Vector, 256 when encoded as 0
+    pub fn egress_control_vector_size(&self) -> u16 {
+        match self.read().get_bits(8..16) {
+            0 => 256,
+            n => n as u16,
+        }
+    }
+
+    /// ACS Control register, same bit layout as the capability getters
+    pub fn control(&self) -> u16 {
+        self.read().get_bits(16..32) as u16
+    }
+
+    pub fn set_control(&self, control: u16) {
+        self.update_control(|c| *c = control);
+    }
+
+    fn update_control(&self, f: impl FnOnce(&mut u16)) {
+        self.base
+            .update(self.offset + CAPABILITY_AND_CONTROL, |mut data| {
+                let mut control = data.get_bits(16..32) as u16;
+                f(&mut control);
+                data.set_bits(16..32, control as u32);
+                data
+            });
+    }
+
+    fn read(&self) -> u32 {
+        self.base.read(self.offset + CAPABILITY_AND_CONTROL)
+    }
+}
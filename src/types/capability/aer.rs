@@ -0,0 +1,154 @@
+use bit_field::BitField;
+
+use super::pcie::DEVICE_CONTROL;
+use crate::{PciAddress, PciHeaderBase, EXT_CAP_ID_AER};
+
+pub(crate) const UNCORRECTABLE_STATUS: u16 = 0x04;
+pub(crate) const UNCORRECTABLE_MASK: u16 = 0x08;
+pub(crate) const UNCORRECTABLE_SEVERITY: u16 = 0x0c;
+pub(crate) const CORRECTABLE_STATUS: u16 = 0x10;
+pub(crate) const CORRECTABLE_MASK: u16 = 0x14;
+pub(crate) const CAPABILITIES_AND_CONTROL: u16 = 0x18;
+pub(crate) const HEADER_LOG: u16 = 0x1c;
+pub(crate) const ROOT_ERROR_COMMAND: u16 = 0x2c;
+pub(crate) const ROOT_ERROR_STATUS: u16 = 0x30;
+pub(crate) const ERROR_SOURCE_ID: u16 = 0x34;
+
+/// An error to raise through an [`ErrorInjector`], values are AER status bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedError {
+    Correctable(u32),
+    Uncorrectable(u32),
+}
+
+/// Error injection mechanism, implemented by vendor specific hardware
+/// injectors and, with the `mock` feature, by the mock controller.
+pub trait ErrorInjector {
+    fn inject(
+        &mut self,
+        address: PciAddress,
+        error: InjectedError,
+    ) -> Result<(), crate::err::Error>;
+}
+
+/// Advanced Error Reporting extended capability
+pub struct AerCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+impl PciHeaderBase {
+    pub fn aer(&self) -> Option<AerCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_AER)?;
+        Some(AerCapability { base: self, offset })
+    }
+}
+
+impl AerCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn uncorrectable_status(&self) -> u32 {
+        self.read(UNCORRECTABLE_STATUS)
+    }
+
+    pub fn uncorrectable_mask(&self) -> u32 {
+        self.read(UNCORRECTABLE_MASK)
+    }
+
+    pub fn set_uncorrectable_mask(&self, mask: u32) {
+        self.write(UNCORRECTABLE_MASK, mask)
+    }
+
+    /// Bits set are reported as fatal, clear as non-fatal
+    pub fn uncorrectable_severity(&self) -> u32 {
+        self.read(UNCORRECTABLE_SEVERITY)
+    }
+
+    pub fn set_uncorrectable_severity(&self, severity: u32) {
+        self.write(UNCORRECTABLE_SEVERITY, severity)
+    }
+
+    pub fn correctable_status(&self) -> u32 {
+        self.read(CORRECTABLE_STATUS)
+    }
+
+    pub fn correctable_mask(&self) -> u32 {
+        self.read(CORRECTABLE_MASK)
+    }
+
+    pub fn set_correctable_mask(&self, mask: u32) {
+        self.write(CORRECTABLE_MASK, mask)
+    }
+
+    /// Status bits are RW1C, only `bits` are cleared
+    pub fn clear_uncorrectable_status(&self, bits: u32) {
+        self.write(UNCORRECTABLE_STATUS, bits)
+    }
+
+    pub fn clear_correctable_status(&self, bits: u32) {
+        self.write(CORRECTABLE_STATUS, bits)
+    }
+
+    /// Bit position of the first uncorrectable error reported
+    pub fn first_error_pointer(&self) -> u8 {
+        self.read(CAPABILITIES_AND_CONTROL).get_bits(0..5) as u8
+    }
+
+    /// TLP header of the first uncorrectable error
+    pub fn header_log(&self) -> [u32; 4] {
+        core::array::from_fn(|i| self.read(HEADER_LOG + i as u16 * 4))
+    }
+
+    /// Correctable, Non-Fatal and Fatal Error Reporting Enable of a root
+    /// port, raising its interrupt for error messages received.
+    pub fn set_root_error_interrupts(&self, enable: bool) {
+        self.update(ROOT_ERROR_COMMAND, |mut data| {
+            data.set_bits(0..3, if enable { 0b111 } else { 0 });
+            data
+        });
+    }
+
+    /// Root Error Status of a root port or event collector
+    pub fn root_error_status(&self) -> u32 {
+        self.read(ROOT_ERROR_STATUS)
+    }
+
+    /// Status bits are RW1C, only `bits` are cleared
+    pub fn clear_root_error_status(&self, bits: u32) {
+        self.write(ROOT_ERROR_STATUS, bits)
+    }
+
+    /// Requester IDs of the first ERR_COR and the first ERR_FATAL/NONFATAL
+    /// message received, valid while their Root Error Status bit is set
+    pub fn error_source_ids(&self) -> (u16, u16) {
+        let data = self.read(ERROR_SOURCE_ID);
+        (data as u16, (data >> 16) as u16)
+    }
+
+    /// Unmask every error and enable error reporting in Device Control, so
+    /// injected errors reach the root port. Used to exercise error paths.
+    pub fn enable_all_reporting(&self) {
+        self.set_uncorrectable_mask(0);
+        self.set_correctable_mask(0);
+        if let Some(pcie) = self.base.pcie_capability() {
+            pcie.update_control(DEVICE_CONTROL, |ctrl| {
+                // Correctable, Non-Fatal, Fatal and Unsupported Request Reporting Enable
+                ctrl.set_bits(0..4, 0b1111);
+            });
+        }
+    }
+
+    fn read(&self, reg: u16) -> u32 {
+        self.base.read(self.offset + reg)
+    }
+
+    fn write(&self, reg: u16, value: u32) {
+        self.base.write(self.offset + reg, value)
+    }
+
+    fn update(&self, reg: u16, f: impl FnOnce(u32) -> u32) {
+        self.base.update(self.offset + reg, f);
+    }
+}
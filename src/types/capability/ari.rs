@@ -0,0 +1,43 @@
+use bit_field::BitField;
+
+use crate::{PciHeaderBase, EXT_CAP_ID_ARI};
+
+const CAPABILITY_AND_CONTROL: u16 = 0x04;
+
+/// Alternative Routing-ID Interpretation extended capability. Below a port
+/// with ARI forwarding enabled the device number becomes part of the
+/// function number, giving one device up to 256 functions.
+pub struct AriCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+impl PciHeaderBase {
+    pub fn ari(&self) -> Option<AriCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_ARI)?;
+        Some(AriCapability { base: self, offset })
+    }
+}
+
+impl AriCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    /// Next function of the device, 0 ends the list
+    pub fn next_function_number(&self) -> u8 {
+        self.read().get_bits(8..16) as u8
+    }
+
+    pub fn mfvc_function_groups(&self) -> bool {
+        self.read().get_bit(0)
+    }
+
+    pub fn acs_function_groups(&self) -> bool {
+        self.read().get_bit(1)
+    }
+
+    fn read(&self) -> u32 {
+        self.base.read(self.offset + CAPABILITY_AND_CONTROL)
+    }
+}
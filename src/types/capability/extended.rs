@@ -0,0 +1,153 @@
+use bit_field::BitField;
+
+use crate::PciHeaderBase;
+
+use super::*;
+
+/// Header of a PCIe extended capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ExtendedCapabilityHeader {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+/// Extended capability with its offset in config space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExtendedCapability {
+    Aer(u16),
+    VirtualChannel(u16),
+    DeviceSerialNumber(u16),
+    PowerBudgeting(u16),
+    VendorSpecific(u16),
+    Acs(u16),
+    Ari(u16),
+    SrIov(u16),
+    Multicast(u16),
+    ResizableBar(u16),
+    Tph(u16),
+    Ltr(u16),
+    SecondaryPcie(u16),
+    Dpc(u16),
+    L1PmSubstates(u16),
+    Ptm(u16),
+    Other(ExtendedCapabilityHeader),
+}
+
+impl From<ExtendedCapabilityHeader> for ExtendedCapability {
+    fn from(header: ExtendedCapabilityHeader) -> Self {
+        let offset = header.offset;
+        match header.id {
+            EXT_CAP_ID_AER => Self::Aer(offset),
+            EXT_CAP_ID_VC => Self::VirtualChannel(offset),
+            EXT_CAP_ID_DSN => Self::DeviceSerialNumber(offset),
+            EXT_CAP_ID_POWER_BUDGETING => Self::PowerBudgeting(offset),
+            EXT_CAP_ID_VSEC => Self::VendorSpecific(offset),
+            EXT_CAP_ID_ACS => Self::Acs(offset),
+            EXT_CAP_ID_ARI => Self::Ari(offset),
+            EXT_CAP_ID_SRIOV => Self::SrIov(offset),
+            EXT_CAP_ID_MULTICAST => Self::Multicast(offset),
+            EXT_CAP_ID_RESIZABLE_BAR => Self::ResizableBar(offset),
+            EXT_CAP_ID_TPH => Self::Tph(offset),
+            EXT_CAP_ID_LTR => Self::Ltr(offset),
+            EXT_CAP_ID_SECONDARY_PCIE => Self::SecondaryPcie(offset),
+            EXT_CAP_ID_DPC => Self::Dpc(offset),
+            EXT_CAP_ID_L1SS => Self::L1PmSubstates(offset),
+            EXT_CAP_ID_PTM => Self::Ptm(offset),
+            _ => Self::Other(header),
+        }
+    }
+}
+
+/// 4KiB 的寄存器空间中最多能放下的 extended capability 数
+const MAX_EXTENDED_CAPABILITIES: usize = 1024;
+
+/// Position in an extended capability list
+#[derive(Debug, Clone)]
+pub(crate) struct ExtCapCursor {
+    next: Option<u16>,
+    remaining: usize,
+}
+
+impl ExtCapCursor {
+    pub(crate) fn new(start: u16) -> Self {
+        Self {
+            next: Some(start),
+            remaining: MAX_EXTENDED_CAPABILITIES,
+        }
+    }
+
+    pub(crate) fn step(&mut self, read: impl Fn(u16) -> u32) -> Option<ExtendedCapabilityHeader> {
+        let offset = self.next.take()?;
+        if self.remaining == 0 || offset & 0x3 != 0 || offset >= 0x1000 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let data = read(offset);
+        if data == 0 || data == u32::MAX {
+            return None;
+        }
+        let next = data.get_bits(20..32) as u16;
+        if next != 0 {
+            self.next = Some(next);
+        }
+        Some(ExtendedCapabilityHeader {
+            id: data.get_bits(0..16) as u16,
+            version: data.get_bits(16..20) as u8,
+            offset,
+        })
+    }
+}
+
+/// Walks an extended capability list through `read`, which reads the dword at
+/// the given offset.
+pub(crate) struct ExtCapWalker<F> {
+    read: F,
+    cursor: ExtCapCursor,
+}
+
+impl<F: Fn(u16) -> u32> ExtCapWalker<F> {
+    pub(crate) fn new(read: F, start: u16) -> Self {
+        Self {
+            read,
+            cursor: ExtCapCursor::new(start),
+        }
+    }
+}
+
+impl<F: Fn(u16) -> u32> Iterator for ExtCapWalker<F> {
+    type Item = ExtendedCapabilityHeader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.step(&self.read)
+    }
+}
+
+/// Iterator over the extended capabilities of a function, starting at 0x100
+pub struct ExtendedCapabilityIterator<'a> {
+    base: &'a PciHeaderBase,
+    cursor: ExtCapCursor,
+}
+
+impl Iterator for ExtendedCapabilityIterator<'_> {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let base = self.base;
+        self.cursor
+            .step(|offset| base.read(offset))
+            .map(ExtendedCapability::from)
+    }
+}
+
+impl PciHeaderBase {
+    pub fn extended_capabilities(&self) -> ExtendedCapabilityIterator<'_> {
+        ExtendedCapabilityIterator {
+            base: self,
+            cursor: ExtCapCursor::new(0x100),
+        }
+    }
+}
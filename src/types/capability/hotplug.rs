@@ -0,0 +1,257 @@
+use bit_field::BitField;
+
+use super::pcie::{PcieCapability, SLOT_CAPABILITIES, SLOT_CONTROL};
+use crate::{
+    err::Error, Delay, HwEvent, Indicator, IndicatorState, PciAddress, PciHeaderBase, RootComplex,
+};
+
+// Slot Capabilities
+const ATTENTION_BUTTON_PRESENT: usize = 0;
+const POWER_CONTROLLER_PRESENT: usize = 1;
+const MRL_SENSOR_PRESENT: usize = 2;
+const HOT_PLUG_SURPRISE: usize = 5;
+const HOT_PLUG_CAPABLE: usize = 6;
+const NO_COMMAND_COMPLETED: usize = 18;
+
+// Slot Control
+const ATTENTION_BUTTON_ENABLE: usize = 0;
+const POWER_FAULT_ENABLE: usize = 1;
+const MRL_SENSOR_ENABLE: usize = 2;
+const PRESENCE_DETECT_ENABLE: usize = 3;
+const COMMAND_COMPLETED_ENABLE: usize = 4;
+const HOT_PLUG_INTERRUPT_ENABLE: usize = 5;
+const POWER_CONTROLLER_CONTROL: usize = 10;
+const DLL_STATE_CHANGED_ENABLE: usize = 12;
+
+// Slot Status, upper half of the Slot Control dword
+const ATTENTION_BUTTON_PRESSED: usize = 16;
+const POWER_FAULT_DETECTED: usize = 16 + 1;
+const MRL_SENSOR_CHANGED: usize = 16 + 2;
+const PRESENCE_DETECT_CHANGED: usize = 16 + 3;
+const COMMAND_COMPLETED: usize = 16 + 4;
+const MRL_SENSOR_STATE: usize = 16 + 5;
+const PRESENCE_DETECT_STATE: usize = 16 + 6;
+const DLL_STATE_CHANGED: usize = 16 + 8;
+
+/// RW1C event bits of Slot Status
+const SLOT_STATUS_EVENTS: u32 = 0x011f << 16;
+
+/// How long a port may take to complete a Slot Control command
+const COMMAND_TIMEOUT_MS: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugEvent {
+    AttentionButtonPressed,
+    PowerFault,
+    /// Manually-operated retention latch opened or closed
+    MrlChanged {
+        open: bool,
+    },
+    PresenceChanged {
+        present: bool,
+    },
+    LinkChanged {
+        active: bool,
+    },
+    CommandCompleted,
+}
+
+/// Hot-plug capable slot below a downstream port.
+pub struct HotplugSlot<'a> {
+    cap: PcieCapability<'a>,
+}
+
+impl PciHeaderBase {
+    /// `None` unless this is a port with a hot-plug capable slot.
+    pub fn hotplug_slot(&self) -> Option<HotplugSlot<'_>> {
+        let cap = self.pcie_capability()?;
+        if !cap.slot_implemented() || !cap.read(SLOT_CAPABILITIES).get_bit(HOT_PLUG_CAPABLE) {
+            return None;
+        }
+        Some(HotplugSlot { cap })
+    }
+}
+
+impl HotplugSlot<'_> {
+    pub fn physical_slot_number(&self) -> u16 {
+        self.cap.read(SLOT_CAPABILITIES).get_bits(19..32) as u16
+    }
+
+    pub fn has_attention_button(&self) -> bool {
+        self.cap
+            .read(SLOT_CAPABILITIES)
+            .get_bit(ATTENTION_BUTTON_PRESENT)
+    }
+
+    pub fn has_power_controller(&self) -> bool {
+        self.cap
+            .read(SLOT_CAPABILITIES)
+            .get_bit(POWER_CONTROLLER_PRESENT)
+    }
+
+    pub fn has_mrl_sensor(&self) -> bool {
+        self.cap.read(SLOT_CAPABILITIES).get_bit(MRL_SENSOR_PRESENT)
+    }
+
+    /// Adapter may be removed without prior notification
+    pub fn surprise_removal(&self) -> bool {
+        self.cap.read(SLOT_CAPABILITIES).get_bit(HOT_PLUG_SURPRISE)
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.cap.read(SLOT_CONTROL).get_bit(PRESENCE_DETECT_STATE)
+    }
+
+    pub fn is_mrl_open(&self) -> bool {
+        self.cap.read(SLOT_CONTROL).get_bit(MRL_SENSOR_STATE)
+    }
+
+    pub fn is_powered(&self) -> bool {
+        !self.has_power_controller()
+            || !self
+                .cap
+                .read(SLOT_CONTROL)
+                .get_bit(POWER_CONTROLLER_CONTROL)
+    }
+
+    /// Switch slot power, no-op without a power controller.
+    pub fn set_power(&self, on: bool, delay: &mut (impl Delay + ?Sized)) -> Result<(), Error> {
+        if !self.has_power_controller() {
+            return Ok(());
+        }
+        self.command(0, delay, |data| {
+            data.set_bit(POWER_CONTROLLER_CONTROL, !on);
+        })
+    }
+
+    /// Drive an indicator of the slot, no-op if it has none.
+    pub fn set_indicator(
+        &self,
+        indicator: Indicator,
+        state: IndicatorState,
+        delay: &mut (impl Delay + ?Sized),
+    ) -> Result<(), Error> {
+        let (present, control) = indicator.bits();
+        if !self.cap.read(SLOT_CAPABILITIES).get_bit(present) {
+            return Ok(());
+        }
+        self.command(0, delay, |data| {
+            data.set_bits(control, state.bits());
+        })
+    }
+
+    /// Enable hot-plug interrupts for every event the slot can report,
+    /// discarding events latched before.
+    pub fn enable_interrupts(&self, delay: &mut (impl Delay + ?Sized)) -> Result<(), Error> {
+        let caps = self.cap.read(SLOT_CAPABILITIES);
+        self.command((SLOT_STATUS_EVENTS >> 16) as u16, delay, |data| {
+            data.set_bit(
+                ATTENTION_BUTTON_ENABLE,
+                caps.get_bit(ATTENTION_BUTTON_PRESENT),
+            );
+            data.set_bit(POWER_FAULT_ENABLE, caps.get_bit(POWER_CONTROLLER_PRESENT));
+            data.set_bit(MRL_SENSOR_ENABLE, caps.get_bit(MRL_SENSOR_PRESENT));
+            data.set_bit(PRESENCE_DETECT_ENABLE, true);
+            data.set_bit(COMMAND_COMPLETED_ENABLE, false);
+            data.set_bit(DLL_STATE_CHANGED_ENABLE, true);
+            data.set_bit(HOT_PLUG_INTERRUPT_ENABLE, true);
+        })
+    }
+
+    pub fn disable_interrupts(&self, delay: &mut (impl Delay + ?Sized)) -> Result<(), Error> {
+        self.command(0, delay, |data| {
+            data.set_bit(HOT_PLUG_INTERRUPT_ENABLE, false);
+        })
+    }
+
+    /// Issue a Slot Control command and wait until the port completed it.
+    ///
+    /// Config access is by dword, so the write covers Slot Status too. Only
+    /// Command Completed and the events in `clear` are written as 1 there,
+    /// other events stay latched as with a 16-bit write of Slot Control.
+    fn command(
+        &self,
+        clear: u16,
+        delay: &mut (impl Delay + ?Sized),
+        f: impl FnOnce(&mut u32),
+    ) -> Result<(), Error> {
+        let clear = clear | 1 << (COMMAND_COMPLETED - 16);
+        self.cap.update_control_clear(SLOT_CONTROL, clear, f);
+        if self
+            .cap
+            .read(SLOT_CAPABILITIES)
+            .get_bit(NO_COMMAND_COMPLETED)
+        {
+            return Ok(());
+        }
+        for _ in 0..COMMAND_TIMEOUT_MS {
+            if self.cap.read(SLOT_CONTROL).get_bit(COMMAND_COMPLETED) {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Call from the port's interrupt handler: acknowledges the latched
+    /// events and passes them to `f`. Returns whether there were any.
+    pub fn handle_interrupt(&self, mut f: impl FnMut(HotplugEvent)) -> bool {
+        let data = self.cap.read(SLOT_CONTROL);
+        let events = data & SLOT_STATUS_EVENTS;
+        if events == 0 {
+            return false;
+        }
+        // Write back exactly the events seen, later ones stay latched
+        self.cap.write(SLOT_CONTROL, (data & 0xffff) | events);
+
+        if events.get_bit(PRESENCE_DETECT_CHANGED) {
+            f(HotplugEvent::PresenceChanged {
+                present: data.get_bit(PRESENCE_DETECT_STATE),
+            });
+        }
+        if events.get_bit(DLL_STATE_CHANGED) {
+            f(HotplugEvent::LinkChanged {
+                active: self.cap.link_active(),
+            });
+        }
+        if events.get_bit(ATTENTION_BUTTON_PRESSED) {
+            f(HotplugEvent::AttentionButtonPressed);
+        }
+        if events.get_bit(POWER_FAULT_DETECTED) {
+            f(HotplugEvent::PowerFault);
+        }
+        if events.get_bit(MRL_SENSOR_CHANGED) {
+            f(HotplugEvent::MrlChanged {
+                open: data.get_bit(MRL_SENSOR_STATE),
+            });
+        }
+        if events.get_bit(COMMAND_COMPLETED) {
+            f(HotplugEvent::CommandCompleted);
+        }
+        true
+    }
+}
+
+impl RootComplex {
+    /// [`HotplugSlot::handle_interrupt`] for the slot below `port`. Lost
+    /// links are reported to the limiter of [`Self::with_event_limiter`].
+    pub fn handle_hotplug_interrupt(
+        &mut self,
+        port: PciAddress,
+        mut f: impl FnMut(HotplugEvent),
+    ) -> Result<bool, Error> {
+        let limit = self.rate_limit.clone();
+        let base = self
+            .function(port)
+            .ok_or(Error::InvalidArgument("no device at port address"))?;
+        let slot = base
+            .hotplug_slot()
+            .ok_or(Error::NotSupported("no hot-plug slot"))?;
+        Ok(slot.handle_interrupt(|event| {
+            if let (HotplugEvent::LinkChanged { active: false }, Some(limit)) = (event, &limit) {
+                limit.report(HwEvent::LinkFlap, port);
+            }
+            f(event)
+        }))
+    }
+}
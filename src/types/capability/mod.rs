@@ -0,0 +1,154 @@
+use bit_field::BitField;
+
+use crate::{HeaderType, PciHeaderBase};
+
+mod acs;
+mod aer;
+mod ari;
+mod extended;
+mod hotplug;
+mod msix;
+mod multicast;
+mod pcie;
+mod power_budget;
+mod slot;
+mod sriov;
+mod standard;
+mod tph;
+mod vendor;
+
+pub use acs::AcsCapability;
+pub use aer::{AerCapability, ErrorInjector, InjectedError};
+pub use ari::AriCapability;
+pub(crate) use extended::{ExtCapCursor, ExtCapWalker};
+pub use extended::{ExtendedCapability, ExtendedCapabilityHeader, ExtendedCapabilityIterator};
+pub use hotplug::{HotplugEvent, HotplugSlot};
+pub use msix::*;
+pub use multicast::MulticastCapability;
+pub use pcie::{
+    CompletionTimeout, DevicePortType, EmergencyPowerReduction, LinkPowerState, LinkSpeed,
+    ObffMode, PcieCapability,
+};
+pub use power_budget::{PowerBudgetCapability, PowerBudgetEntry};
+pub use slot::*;
+pub use sriov::*;
+pub use standard::Capability;
+pub use tph::{StMode, StTableLocation, TphCapability};
+#[cfg(feature = "nic")]
+pub use vendor::nic;
+#[cfg(feature = "virtio")]
+pub use vendor::virtio;
+pub use vendor::{VendorCapability, Vsec};
+
+pub const CAP_ID_PM: u8 = 0x01;
+pub const CAP_ID_MSI: u8 = 0x05;
+pub const CAP_ID_VENDOR: u8 = 0x09;
+pub const CAP_ID_PCIE: u8 = 0x10;
+pub const CAP_ID_MSIX: u8 = 0x11;
+
+pub const EXT_CAP_ID_AER: u16 = 0x0001;
+pub const EXT_CAP_ID_VC: u16 = 0x0002;
+pub const EXT_CAP_ID_DSN: u16 = 0x0003;
+pub const EXT_CAP_ID_POWER_BUDGETING: u16 = 0x0004;
+pub const EXT_CAP_ID_VSEC: u16 = 0x000b;
+pub const EXT_CAP_ID_ACS: u16 = 0x000d;
+pub const EXT_CAP_ID_ARI: u16 = 0x000e;
+pub const EXT_CAP_ID_SRIOV: u16 = 0x0010;
+pub const EXT_CAP_ID_MULTICAST: u16 = 0x0012;
+pub const EXT_CAP_ID_RESIZABLE_BAR: u16 = 0x0015;
+pub const EXT_CAP_ID_TPH: u16 = 0x0017;
+pub const EXT_CAP_ID_LTR: u16 = 0x0018;
+pub const EXT_CAP_ID_SECONDARY_PCIE: u16 = 0x0019;
+pub const EXT_CAP_ID_DPC: u16 = 0x001d;
+pub const EXT_CAP_ID_L1SS: u16 = 0x001e;
+pub const EXT_CAP_ID_PTM: u16 = 0x001f;
+
+const CAP_POINTER: u16 = 0x34;
+/// CardBus bridges keep the pointer elsewhere
+const CARDBUS_CAP_POINTER: u16 = 0x14;
+
+/// `(id, offset)` of each entry in the capability list, see
+/// [`PciHeaderBase::walk_capabilities`].
+///
+/// Ends at a pointer into the header or a revisited entry, so a looping
+/// or corrupt list still terminates, and at an all-ones read from a function
+/// that went away.
+pub struct CapabilityIterator<'a> {
+    base: &'a PciHeaderBase,
+    next: u16,
+    /// Bit `n` set once the dword at `0x40 + 4n` was visited
+    visited: u64,
+}
+
+impl Iterator for CapabilityIterator<'_> {
+    type Item = (u8, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.next;
+        if !(0x40..0x100).contains(&offset) {
+            return None;
+        }
+        let slot = 1 << ((offset - 0x40) / 4);
+        if self.visited & slot != 0 {
+            warn!("capability list loops back to {offset:#x}");
+            self.next = 0;
+            return None;
+        }
+        self.visited |= slot;
+
+        let data = self.base.read(offset);
+        if data == u32::MAX {
+            self.next = 0;
+            return None;
+        }
+        // The low two bits of the pointer are reserved
+        self.next = (data.get_bits(8..16) as u16) & 0xfc;
+        Some((data.get_bits(0..8) as u8, offset))
+    }
+}
+
+impl PciHeaderBase {
+    pub fn has_capability_list(&self) -> bool {
+        self.status().has_capability_list()
+    }
+
+    /// Offset of the first capability, 0 without capability list. Read from
+    /// the right place for every header type.
+    pub fn capability_pointer(&self) -> u16 {
+        if !self.has_capability_list() {
+            return 0;
+        }
+        let register = match self.header_type() {
+            HeaderType::CardBusBridge => CARDBUS_CAP_POINTER,
+            _ => CAP_POINTER,
+        };
+        (self.read(register) as u16) & 0xfc
+    }
+
+    /// 返回 capability 链表中第一个 `id` 匹配项的偏移
+    pub fn find_capability(&self, id: u8) -> Option<u16> {
+        self.walk_capabilities()
+            .find(|(cap_id, _)| *cap_id == id)
+            .map(|(_, offset)| offset)
+    }
+
+    /// (id, offset) of each entry in the capability list
+    pub fn walk_capabilities(&self) -> CapabilityIterator<'_> {
+        CapabilityIterator {
+            base: self,
+            next: self.capability_pointer(),
+            visited: 0,
+        }
+    }
+
+    /// 返回 extended capability 链表中第一个 `id` 匹配项的偏移
+    pub fn find_extended_capability(&self, id: u16) -> Option<u16> {
+        let mut cursor = ExtCapCursor::new(0x100);
+        while let Some(cap) = cursor.step(|offset| self.read(offset)) {
+            if cap.id == id {
+                return Some(cap.offset);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,242 @@
+use core::ptr::NonNull;
+
+use bit_field::BitField;
+
+use crate::{err::Error, Endpoint, MmioMapper, PciHeaderBase, CAP_ID_MSIX};
+
+const CONTROL_ENABLE: usize = 16 + 15;
+const CONTROL_FUNCTION_MASK: usize = 16 + 14;
+
+const ENTRY_SIZE: usize = 16;
+const ENTRY_ADDRESS_LOW: usize = 0;
+const ENTRY_ADDRESS_HIGH: usize = 1;
+const ENTRY_DATA: usize = 2;
+const ENTRY_VECTOR_CONTROL: usize = 3;
+
+/// MSI-X capability of a function
+pub struct MsixCapability<'a> {
+    base: &'a Endpoint,
+    offset: u16,
+}
+
+impl Endpoint {
+    pub fn msix(&self) -> Option<MsixCapability<'_>> {
+        let offset = self.find_capability(CAP_ID_MSIX)?;
+        Some(MsixCapability { base: self, offset })
+    }
+}
+
+impl MsixCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn table_size(&self) -> u16 {
+        self.header().read(self.offset).get_bits(16..27) as u16 + 1
+    }
+
+    pub fn table_bar(&self) -> u8 {
+        self.header().read(self.offset + 4).get_bits(0..3) as u8
+    }
+
+    pub fn table_offset(&self) -> u32 {
+        self.header().read(self.offset + 4) & !0x7
+    }
+
+    pub fn pba_bar(&self) -> u8 {
+        self.header().read(self.offset + 8).get_bits(0..3) as u8
+    }
+
+    pub fn pba_offset(&self) -> u32 {
+        self.header().read(self.offset + 8) & !0x7
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.header().read(self.offset).get_bit(CONTROL_ENABLE)
+    }
+
+    pub fn set_enabled(&self, enable: bool) {
+        self.update_control(CONTROL_ENABLE, enable);
+    }
+
+    pub fn function_mask(&self) -> bool {
+        self.header()
+            .read(self.offset)
+            .get_bit(CONTROL_FUNCTION_MASK)
+    }
+
+    pub fn set_function_mask(&self, mask: bool) {
+        self.update_control(CONTROL_FUNCTION_MASK, mask);
+    }
+
+    /// Map the vector table and the pending bit array through `mapper`.
+    pub fn map_table(&self, mapper: &(impl MmioMapper + ?Sized)) -> Result<MsixTable, Error> {
+        let len = self.table_size();
+        let table_bar =
+            self.base
+                .bar_address(self.table_bar() as usize)
+                .ok_or(Error::InvalidArgument(
+                    "MSI-X table BAR is not a memory BAR",
+                ))?;
+        let pba_bar = self
+            .base
+            .bar_address(self.pba_bar() as usize)
+            .ok_or(Error::InvalidArgument("MSI-X PBA BAR is not a memory BAR"))?;
+        let table = mapper.map(
+            table_bar + self.table_offset() as u64,
+            len as usize * ENTRY_SIZE,
+        );
+        let pba = mapper.map(
+            pba_bar + self.pba_offset() as u64,
+            (len as usize).div_ceil(64) * 8,
+        );
+
+        Ok(MsixTable {
+            table: table.cast(),
+            pba: pba.cast(),
+            len,
+        })
+    }
+
+    /// Mask every vector of `table`. The function mask is held meanwhile so
+    /// no vector fires half way through.
+    pub fn mask_all(&self, table: &MsixTable) {
+        self.set_all_masked(table, true);
+    }
+
+    /// Unmask every vector of `table` at once: vector masks are cleared
+    /// under the function mask, messages pending in the PBA are sent when it
+    /// is released.
+    pub fn unmask_all(&self, table: &MsixTable) {
+        self.set_all_masked(table, false);
+    }
+
+    fn set_all_masked(&self, table: &MsixTable, masked: bool) {
+        let function_mask = self.function_mask();
+        self.set_function_mask(true);
+        for vector in 0..table.len() {
+            table.set_masked(vector, masked);
+        }
+        // Read back so the table writes complete before the function mask
+        // is released
+        if let Some(last) = table.len().checked_sub(1) {
+            table.is_masked(last);
+        }
+        self.set_function_mask(function_mask);
+    }
+
+    fn update_control(&self, bit: usize, value: bool) {
+        self.header().update(self.offset, |mut data| {
+            data.set_bit(bit, value);
+            data
+        });
+    }
+
+    pub(crate) fn header(&self) -> &PciHeaderBase {
+        self.base
+    }
+}
+
+/// Mapped MSI-X vector table.
+///
+/// The mapping returned by [`MmioMapper`] must stay valid for the lifetime
+/// of this value.
+pub struct MsixTable {
+    table: NonNull<u32>,
+    pba: NonNull<u64>,
+    len: u16,
+}
+
+unsafe impl Send for MsixTable {}
+
+impl MsixTable {
+    pub fn len(&self) -> u16 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Program the message of `vector`. The vector should be masked while
+    /// its address/data are changed.
+    pub fn set_message(&self, vector: u16, address: u64, data: u32) {
+        self.write(vector, ENTRY_ADDRESS_LOW, address as u32);
+        self.write(vector, ENTRY_ADDRESS_HIGH, (address >> 32) as u32);
+        self.write(vector, ENTRY_DATA, data);
+    }
+
+    /// [`Self::set_message`] with `vector` masked meanwhile, so no message
+    /// goes out with half of the new address/data. The mask is restored
+    /// after the writes have completed.
+    pub fn update_message(&self, vector: u16, address: u64, data: u32) {
+        let masked = self.is_masked(vector);
+        self.mask(vector);
+        self.set_message(vector, address, data);
+        // Read back so the writes complete before the vector is unmasked
+        self.read(vector, ENTRY_DATA);
+        if !masked {
+            self.unmask(vector);
+        }
+    }
+
+    pub fn message(&self, vector: u16) -> (u64, u32) {
+        let address = (self.read(vector, ENTRY_ADDRESS_HIGH) as u64) << 32
+            | self.read(vector, ENTRY_ADDRESS_LOW) as u64;
+        (address, self.read(vector, ENTRY_DATA))
+    }
+
+    pub fn mask(&self, vector: u16) {
+        self.set_masked(vector, true);
+    }
+
+    pub fn unmask(&self, vector: u16) {
+        self.set_masked(vector, false);
+    }
+
+    pub fn is_masked(&self, vector: u16) -> bool {
+        self.read(vector, ENTRY_VECTOR_CONTROL).get_bit(0)
+    }
+
+    /// Steering tag of `vector`, when the TPH table lives in the MSI-X table
+    /// (see [`crate::TphCapability::st_table_location`]).
+    pub fn steering_tag(&self, vector: u16) -> u16 {
+        self.read(vector, ENTRY_VECTOR_CONTROL).get_bits(16..32) as u16
+    }
+
+    pub fn set_steering_tag(&self, vector: u16, tag: u16) {
+        let mut ctrl = self.read(vector, ENTRY_VECTOR_CONTROL);
+        ctrl.set_bits(16..32, tag as u32);
+        self.write(vector, ENTRY_VECTOR_CONTROL, ctrl);
+    }
+
+    /// Pending bit of `vector`, set while a message is held back by a mask.
+    pub fn pending(&self, vector: u16) -> bool {
+        assert!(vector < self.len, "MSI-X vector out of range");
+        let qword = unsafe { self.pba.add(vector as usize / 64).as_ptr().read_volatile() };
+        u64::from_le(qword).get_bit(vector as usize % 64)
+    }
+
+    fn set_masked(&self, vector: u16, masked: bool) {
+        let mut ctrl = self.read(vector, ENTRY_VECTOR_CONTROL);
+        ctrl.set_bit(0, masked);
+        self.write(vector, ENTRY_VECTOR_CONTROL, ctrl);
+    }
+
+    fn entry(&self, vector: u16, dword: usize) -> NonNull<u32> {
+        assert!(vector < self.len, "MSI-X vector out of range");
+        unsafe { self.table.add(vector as usize * ENTRY_SIZE / 4 + dword) }
+    }
+
+    fn read(&self, vector: u16, dword: usize) -> u32 {
+        u32::from_le(unsafe { self.entry(vector, dword).as_ptr().read_volatile() })
+    }
+
+    fn write(&self, vector: u16, dword: usize, value: u32) {
+        unsafe {
+            self.entry(vector, dword)
+                .as_ptr()
+                .write_volatile(value.to_le())
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use bit_field::BitField;
+
+use crate::{err::Error, PciHeaderBase, EXT_CAP_ID_MULTICAST};
+
+const CAPABILITY_AND_CONTROL: u16 = 0x04;
+const BASE_ADDRESS: u16 = 0x08;
+const RECEIVE: u16 = 0x10;
+const BLOCK_ALL: u16 = 0x18;
+const BLOCK_UNTRANSLATED: u16 = 0x20;
+const OVERLAY_BAR: u16 = 0x28;
+
+const CONTROL_ENABLE: usize = 31;
+
+/// Multicast extended capability. Posted writes into
+/// `base + group << index_position` are delivered to every port and
+/// endpoint with `group` set in its receive vector.
+pub struct MulticastCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+impl PciHeaderBase {
+    pub fn multicast(&self) -> Option<MulticastCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_MULTICAST)?;
+        Some(MulticastCapability { base: self, offset })
+    }
+}
+
+impl MulticastCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    /// Number of groups supported, 1 to 64
+    pub fn max_groups(&self) -> u8 {
+        self.read(CAPABILITY_AND_CONTROL).get_bits(0..6) as u8 + 1
+    }
+
+    /// log2 of the window an endpoint asks for per group, 0 on ports
+    pub fn window_size_requested(&self) -> u8 {
+        self.read(CAPABILITY_AND_CONTROL).get_bits(8..14) as u8
+    }
+
+    pub fn ecrc_regeneration_supported(&self) -> bool {
+        self.read(CAPABILITY_AND_CONTROL).get_bit(15)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.read(CAPABILITY_AND_CONTROL).get_bit(CONTROL_ENABLE)
+    }
+
+    /// Number of groups configured
+    pub fn num_groups(&self) -> u8 {
+        self.read(CAPABILITY_AND_CONTROL).get_bits(16..22) as u8 + 1
+    }
+
+    pub fn base_address(&self) -> u64 {
+        self.read64(BASE_ADDRESS) & !0xfff
+    }
+
+    /// log2 of the address stride between groups
+    pub fn index_position(&self) -> u8 {
+        self.read(BASE_ADDRESS).get_bits(0..6) as u8
+    }
+
+    /// Program `num_groups` groups at `base`, `1 << index_position` bytes
+    /// apart. Multicast must be disabled, `base` aligned to the whole range.
+    pub fn configure(&self, base: u64, index_position: u8, num_groups: u8) -> Result<(), Error> {
+        if self.is_enabled() {
+            return Err(Error::InvalidArgument("multicast enabled"));
+        }
+        if num_groups == 0 || num_groups > self.max_groups() {
+            return Err(Error::InvalidArgument("num_groups out of range"));
+        }
+        if !(12..=63).contains(&index_position) {
+            return Err(Error::InvalidArgument("index_position"));
+        }
+        let size = (num_groups as u64)
+            .next_power_of_two()
+            .checked_shl(index_position as u32)
+            .filter(|size| *size != 0)
+            .ok_or(Error::InvalidArgument("index_position"))?;
+        if !base.is_multiple_of(size) {
+            return Err(Error::InvalidArgument("base not aligned to the groups"));
+        }
+
+        self.write64(BASE_ADDRESS, base | index_position as u64);
+        self.update(CAPABILITY_AND_CONTROL, |mut data| {
+            data.set_bits(16..22, num_groups as u32 - 1);
+            data
+        });
+        Ok(())
+    }
+
+    pub fn set_enabled(&self, enable: bool) {
+        self.update(CAPABILITY_AND_CONTROL, |mut data| {
+            data.set_bit(CONTROL_ENABLE, enable);
+            data
+        });
+    }
+
+    /// Bit `n` set receives group `n`
+    pub fn receive(&self) -> u64 {
+        self.read64(RECEIVE)
+    }
+
+    pub fn set_receive(&self, groups: u64) {
+        self.write64(RECEIVE, groups);
+    }
+
+    /// Groups not forwarded out of this port
+    pub fn block_all(&self) -> u64 {
+        self.read64(BLOCK_ALL)
+    }
+
+    pub fn set_block_all(&self, groups: u64) {
+        self.write64(BLOCK_ALL, groups);
+    }
+
+    /// Groups not forwarded when the TLP is untranslated
+    pub fn block_untranslated(&self) -> u64 {
+        self.read64(BLOCK_UNTRANSLATED)
+    }
+
+    pub fn set_block_untranslated(&self, groups: u64) {
+        self.write64(BLOCK_UNTRANSLATED, groups);
+    }
+
+    /// `(address, log2 size)` multicast TLPs leaving a switch or root port
+    /// are redirected to, `None` while the overlay is off. Endpoints don't
+    /// implement it.
+    pub fn overlay(&self) -> Option<(u64, u8)> {
+        let data = self.read64(OVERLAY_BAR);
+        let size = data.get_bits(0..6) as u8;
+        (size >= 6).then_some((data & !0x3f, size))
+    }
+
+    /// `size` 0 turns the overlay off, otherwise it is log2 of the overlay
+    /// window, at least 6, and `address` must be aligned to it.
+    pub fn set_overlay(&self, address: u64, size: u8) -> Result<(), Error> {
+        if size == 0 {
+            self.write64(OVERLAY_BAR, 0);
+            return Ok(());
+        }
+        if !(6..64).contains(&size) || !address.is_multiple_of(1 << size) {
+            return Err(Error::InvalidArgument("overlay size"));
+        }
+        self.write64(OVERLAY_BAR, address | size as u64);
+        Ok(())
+    }
+
+    fn read(&self, reg: u16) -> u32 {
+        self.base.read(self.offset + reg)
+    }
+
+    fn write(&self, reg: u16, value: u32) {
+        self.base.write(self.offset + reg, value)
+    }
+
+    fn update(&self, reg: u16, f: impl FnOnce(u32) -> u32) {
+        self.base.update(self.offset + reg, f);
+    }
+
+    fn read64(&self, reg: u16) -> u64 {
+        self.read(reg) as u64 | (self.read(reg + 4) as u64) << 32
+    }
+
+    fn write64(&self, reg: u16, value: u64) {
+        self.write(reg, value as u32);
+        self.write(reg + 4, (value >> 32) as u32);
+    }
+}
@@ -0,0 +1,483 @@
+use bit_field::BitField;
+
+use crate::{err::Error, PciHeaderBase, CAP_ID_PCIE, EXT_CAP_ID_L1SS};
+
+pub(crate) const PCIE_CAPABILITIES: u16 = 0x00;
+pub(crate) const DEVICE_CAPABILITIES: u16 = 0x04;
+pub(crate) const DEVICE_CONTROL: u16 = 0x08;
+pub(crate) const LINK_CAPABILITIES: u16 = 0x0c;
+pub(crate) const LINK_CONTROL: u16 = 0x10;
+pub(crate) const SLOT_CAPABILITIES: u16 = 0x14;
+pub(crate) const SLOT_CONTROL: u16 = 0x18;
+pub(crate) const DEVICE_CAPABILITIES_2: u16 = 0x24;
+pub(crate) const DEVICE_CONTROL_2: u16 = 0x28;
+
+const ARI_FORWARDING: usize = 5;
+const COMPLETION_TIMEOUT_DISABLE: usize = 4;
+
+/// Device/Port Type of the PCI Express capability
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DevicePortType {
+    Endpoint,
+    LegacyEndpoint,
+    RootPort,
+    UpstreamPort,
+    DownstreamPort,
+    PcieToPciBridge,
+    PciToPcieBridge,
+    RootComplexIntegratedEndpoint,
+    RootComplexEventCollector,
+    Unknown(u8),
+}
+
+impl From<u8> for DevicePortType {
+    fn from(value: u8) -> Self {
+        match value {
+            0b0000 => Self::Endpoint,
+            0b0001 => Self::LegacyEndpoint,
+            0b0100 => Self::RootPort,
+            0b0101 => Self::UpstreamPort,
+            0b0110 => Self::DownstreamPort,
+            0b0111 => Self::PcieToPciBridge,
+            0b1000 => Self::PciToPcieBridge,
+            0b1001 => Self::RootComplexIntegratedEndpoint,
+            0b1010 => Self::RootComplexEventCollector,
+            v => Self::Unknown(v),
+        }
+    }
+}
+
+impl DevicePortType {
+    /// Root ports and switch downstream ports, the ones with a link below
+    pub fn is_downstream_port(self) -> bool {
+        matches!(self, Self::RootPort | Self::DownstreamPort)
+    }
+
+    /// Endpoints attached to the root complex without a link
+    pub fn is_integrated(self) -> bool {
+        matches!(
+            self,
+            Self::RootComplexIntegratedEndpoint | Self::RootComplexEventCollector
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum LinkSpeed {
+    /// 2.5 GT/s
+    Gen1,
+    /// 5 GT/s
+    Gen2,
+    /// 8 GT/s
+    Gen3,
+    /// 16 GT/s
+    Gen4,
+    /// 32 GT/s
+    Gen5,
+    /// 64 GT/s
+    Gen6,
+    Unknown(u8),
+}
+
+impl From<u8> for LinkSpeed {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Gen1,
+            2 => Self::Gen2,
+            3 => Self::Gen3,
+            4 => Self::Gen4,
+            5 => Self::Gen5,
+            6 => Self::Gen6,
+            v => Self::Unknown(v),
+        }
+    }
+}
+
+/// Link power state, see [`PcieCapability::deepest_link_state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum LinkPowerState {
+    Down,
+    L0,
+    L0s,
+    L1,
+    L1Substate1,
+    L1Substate2,
+}
+
+/// Completion Timeout Value of Device Control 2, named by the range the
+/// timeout falls in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum CompletionTimeout {
+    /// 50us to 50ms, always supported
+    Default,
+    Us50To100,
+    Ms1To10,
+    Ms16To55,
+    Ms65To210,
+    Ms260To900,
+    Ms1000To3500,
+    S4To13,
+    S17To64,
+}
+
+impl CompletionTimeout {
+    pub const ALL: [Self; 9] = [
+        Self::Default,
+        Self::Us50To100,
+        Self::Ms1To10,
+        Self::Ms16To55,
+        Self::Ms65To210,
+        Self::Ms260To900,
+        Self::Ms1000To3500,
+        Self::S4To13,
+        Self::S17To64,
+    ];
+
+    /// Bounds of the timeout in microseconds
+    pub fn range_us(self) -> core::ops::RangeInclusive<u64> {
+        match self {
+            Self::Default => 50..=50_000,
+            Self::Us50To100 => 50..=100,
+            Self::Ms1To10 => 1_000..=10_000,
+            Self::Ms16To55 => 16_000..=55_000,
+            Self::Ms65To210 => 65_000..=210_000,
+            Self::Ms260To900 => 260_000..=900_000,
+            Self::Ms1000To3500 => 1_000_000..=3_500_000,
+            Self::S4To13 => 4_000_000..=13_000_000,
+            Self::S17To64 => 17_000_000..=64_000_000,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Self::Default => 0b0000,
+            Self::Us50To100 => 0b0001,
+            Self::Ms1To10 => 0b0010,
+            Self::Ms16To55 => 0b0101,
+            Self::Ms65To210 => 0b0110,
+            Self::Ms260To900 => 0b1001,
+            Self::Ms1000To3500 => 0b1010,
+            Self::S4To13 => 0b1101,
+            Self::S17To64 => 0b1110,
+        }
+    }
+
+    /// Range A to D of Completion Timeout Ranges Supported, `None` for the
+    /// default
+    fn supported_bit(self) -> Option<usize> {
+        (self != Self::Default).then_some((self.bits() >> 2) as usize)
+    }
+}
+
+/// Optimized Buffer Flush/Fill signaling, how the platform tells devices
+/// when DMA is cheap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ObffMode {
+    Disabled,
+    /// OBFF messages, variation A
+    MessageA,
+    /// OBFF messages, variation B
+    MessageB,
+    /// WAKE# signaling
+    Wake,
+}
+
+/// Emergency Power Reduction Supported of Device Capabilities 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum EmergencyPowerReduction {
+    NotSupported,
+    /// Only through the Device Control 2 request bit
+    DeviceSpecific,
+    /// Also through the form factor's power reduction signal
+    FormFactor,
+}
+
+/// PCI Express capability structure
+pub struct PcieCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+impl PciHeaderBase {
+    pub fn pcie_capability(&self) -> Option<PcieCapability<'_>> {
+        let offset = self.find_capability(CAP_ID_PCIE)?;
+        Some(PcieCapability { base: self, offset })
+    }
+}
+
+impl PcieCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(PCIE_CAPABILITIES).get_bits(16..20) as u8
+    }
+
+    pub fn device_port_type(&self) -> DevicePortType {
+        DevicePortType::from(self.read(PCIE_CAPABILITIES).get_bits(20..24) as u8)
+    }
+
+    pub fn slot_implemented(&self) -> bool {
+        self.read(PCIE_CAPABILITIES).get_bit(24)
+    }
+
+    /// Max Payload Size Supported in bytes
+    pub fn max_payload_size_supported(&self) -> u16 {
+        decode_size(self.read(DEVICE_CAPABILITIES).get_bits(0..3))
+    }
+
+    /// Max Payload Size in bytes
+    pub fn max_payload_size(&self) -> u16 {
+        decode_size(self.read(DEVICE_CONTROL).get_bits(5..8))
+    }
+
+    pub fn set_max_payload_size(&self, size: u16) -> Result<(), Error> {
+        if size > self.max_payload_size_supported() {
+            return Err(Error::InvalidArgument("max payload size not supported"));
+        }
+        let bits = encode_size(size)?;
+        self.update_control(DEVICE_CONTROL, |ctrl| {
+            ctrl.set_bits(5..8, bits);
+        });
+        Ok(())
+    }
+
+    /// Max Read Request Size in bytes
+    pub fn max_read_request_size(&self) -> u16 {
+        decode_size(self.read(DEVICE_CONTROL).get_bits(12..15))
+    }
+
+    pub fn set_max_read_request_size(&self, size: u16) -> Result<(), Error> {
+        let bits = encode_size(size)?;
+        self.update_control(DEVICE_CONTROL, |ctrl| {
+            ctrl.set_bits(12..15, bits);
+        });
+        Ok(())
+    }
+
+    pub fn max_link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from(self.read(LINK_CAPABILITIES).get_bits(0..4) as u8)
+    }
+
+    pub fn max_link_width(&self) -> u8 {
+        self.read(LINK_CAPABILITIES).get_bits(4..10) as u8
+    }
+
+    /// Current link speed
+    pub fn link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from(self.read(LINK_CONTROL).get_bits(16..20) as u8)
+    }
+
+    /// Negotiated link width
+    pub fn link_width(&self) -> u8 {
+        self.read(LINK_CONTROL).get_bits(20..26) as u8
+    }
+
+    pub fn link_training(&self) -> bool {
+        self.read(LINK_CONTROL).get_bit(16 + 11)
+    }
+
+    /// Data Link Layer Link Active
+    pub fn link_active(&self) -> bool {
+        self.read(LINK_CONTROL).get_bit(16 + 13)
+    }
+
+    /// Deepest state ASPM and L1 PM Substates allow the link to enter, or
+    /// [`LinkPowerState::Down`] if the data link layer is inactive. Config
+    /// space doesn't report the state the link is in at the moment.
+    pub fn deepest_link_state(&self) -> LinkPowerState {
+        // Data Link Layer Link Active Reporting Capable
+        if self.read(LINK_CAPABILITIES).get_bit(20) && !self.link_active() {
+            return LinkPowerState::Down;
+        }
+
+        let aspm = self.read(LINK_CONTROL).get_bits(0..2);
+        if aspm.get_bit(1) {
+            if let Some(l1ss) = self.base.find_extended_capability(EXT_CAP_ID_L1SS) {
+                let ctrl = self.base.read(l1ss + 0x08);
+                // ASPM L1.2 / L1.1 Enable
+                if ctrl.get_bit(2) {
+                    return LinkPowerState::L1Substate2;
+                }
+                if ctrl.get_bit(3) {
+                    return LinkPowerState::L1Substate1;
+                }
+            }
+            return LinkPowerState::L1;
+        }
+        if aspm.get_bit(0) {
+            return LinkPowerState::L0s;
+        }
+        LinkPowerState::L0
+    }
+
+    /// Port can forward ARI function numbers above 7 downstream
+    pub fn ari_forwarding_supported(&self) -> bool {
+        self.read(DEVICE_CAPABILITIES_2).get_bit(ARI_FORWARDING)
+    }
+
+    pub fn ari_forwarding(&self) -> bool {
+        self.read(DEVICE_CONTROL_2).get_bit(ARI_FORWARDING)
+    }
+
+    pub fn set_ari_forwarding(&self, enable: bool) {
+        self.update_control(DEVICE_CONTROL_2, |data| {
+            data.set_bit(ARI_FORWARDING, enable);
+        });
+    }
+
+    /// Completion timeouts the function can be set to, the default first.
+    pub fn supported_completion_timeouts(&self) -> impl Iterator<Item = CompletionTimeout> {
+        let ranges = self.read(DEVICE_CAPABILITIES_2).get_bits(0..4);
+        CompletionTimeout::ALL
+            .into_iter()
+            .filter(move |t| t.supported_bit().is_none_or(|bit| ranges.get_bit(bit)))
+    }
+
+    /// `None` for reserved encodings
+    pub fn completion_timeout(&self) -> Option<CompletionTimeout> {
+        let bits = self.read(DEVICE_CONTROL_2).get_bits(0..4);
+        CompletionTimeout::ALL
+            .into_iter()
+            .find(|t| t.bits() == bits)
+    }
+
+    pub fn set_completion_timeout(&self, timeout: CompletionTimeout) -> Result<(), Error> {
+        if !self.supported_completion_timeouts().any(|t| t == timeout) {
+            return Err(Error::NotSupported("completion timeout range"));
+        }
+        self.update_control(DEVICE_CONTROL_2, |data| {
+            data.set_bits(0..4, timeout.bits());
+        });
+        Ok(())
+    }
+
+    pub fn completion_timeout_disable_supported(&self) -> bool {
+        self.read(DEVICE_CAPABILITIES_2)
+            .get_bit(COMPLETION_TIMEOUT_DISABLE)
+    }
+
+    pub fn completion_timeout_disabled(&self) -> bool {
+        self.read(DEVICE_CONTROL_2)
+            .get_bit(COMPLETION_TIMEOUT_DISABLE)
+    }
+
+    /// Requests never time out while disabled, a lost completion hangs the
+    /// requester.
+    pub fn set_completion_timeout_disabled(&self, disable: bool) -> Result<(), Error> {
+        if disable && !self.completion_timeout_disable_supported() {
+            return Err(Error::NotSupported("completion timeout disable"));
+        }
+        self.update_control(DEVICE_CONTROL_2, |data| {
+            data.set_bit(COMPLETION_TIMEOUT_DISABLE, disable);
+        });
+        Ok(())
+    }
+
+    pub fn supports_obff(&self, mode: ObffMode) -> bool {
+        let supported = self.read(DEVICE_CAPABILITIES_2).get_bits(18..20);
+        match mode {
+            ObffMode::Disabled => true,
+            ObffMode::MessageA | ObffMode::MessageB => supported.get_bit(0),
+            ObffMode::Wake => supported.get_bit(1),
+        }
+    }
+
+    pub fn obff(&self) -> ObffMode {
+        match self.read(DEVICE_CONTROL_2).get_bits(13..15) {
+            0b00 => ObffMode::Disabled,
+            0b01 => ObffMode::MessageA,
+            0b10 => ObffMode::MessageB,
+            _ => ObffMode::Wake,
+        }
+    }
+
+    pub fn set_obff(&self, mode: ObffMode) -> Result<(), Error> {
+        if !self.supports_obff(mode) {
+            return Err(Error::NotSupported("OBFF mode"));
+        }
+        let bits = match mode {
+            ObffMode::Disabled => 0b00,
+            ObffMode::MessageA => 0b01,
+            ObffMode::MessageB => 0b10,
+            ObffMode::Wake => 0b11,
+        };
+        self.update_control(DEVICE_CONTROL_2, |data| {
+            data.set_bits(13..15, bits);
+        });
+        Ok(())
+    }
+
+    pub fn emergency_power_reduction(&self) -> EmergencyPowerReduction {
+        match self.read(DEVICE_CAPABILITIES_2).get_bits(24..26) {
+            0b01 => EmergencyPowerReduction::DeviceSpecific,
+            0b10 => EmergencyPowerReduction::FormFactor,
+            _ => EmergencyPowerReduction::NotSupported,
+        }
+    }
+
+    /// The function needs its power reduction state initialized by
+    /// software before use
+    pub fn emergency_power_reduction_init_required(&self) -> bool {
+        self.read(DEVICE_CAPABILITIES_2).get_bit(26)
+    }
+
+    pub fn emergency_power_reduction_requested(&self) -> bool {
+        self.read(DEVICE_CONTROL_2).get_bit(11)
+    }
+
+    /// Ask the function to enter (or leave) its emergency power reduction
+    /// state.
+    pub fn request_emergency_power_reduction(&self, request: bool) -> Result<(), Error> {
+        if self.emergency_power_reduction() == EmergencyPowerReduction::NotSupported {
+            return Err(Error::NotSupported("emergency power reduction"));
+        }
+        self.update_control(DEVICE_CONTROL_2, |data| {
+            data.set_bit(11, request);
+        });
+        Ok(())
+    }
+
+    pub(crate) fn read(&self, reg: u16) -> u32 {
+        self.base.read(self.offset + reg)
+    }
+
+    pub(crate) fn write(&self, reg: u16, value: u32) {
+        self.base.write(self.offset + reg, value)
+    }
+
+    /// Update the control half of a control/status dword, the status half is
+    /// written as 0 so RW1C bits are left alone.
+    pub(crate) fn update_control<F: FnOnce(&mut u32)>(&self, reg: u16, f: F) {
+        self.update_control_clear(reg, 0, f);
+    }
+
+    /// [`Self::update_control`] clearing the RW1C status bits in `clear`.
+    pub(crate) fn update_control_clear<F: FnOnce(&mut u32)>(&self, reg: u16, clear: u16, f: F) {
+        self.base.update(self.offset + reg, |data| {
+            let mut data = data & 0xffff;
+            f(&mut data);
+            data & 0xffff | (clear as u32) << 16
+        });
+    }
+}
+
+fn decode_size(bits: u32) -> u16 {
+    128 << bits.min(5)
+}
+
+fn encode_size(size: u16) -> Result<u32, Error> {
+    if !size.is_power_of_two() || !(128..=4096).contains(&size) {
+        return Err(Error::InvalidArgument(
+            "size must be a power of two in 128..=4096",
+        ));
+    }
+    Ok(size.trailing_zeros() - 7)
+}
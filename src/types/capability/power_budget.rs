@@ -0,0 +1,127 @@
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+use super::pcie::SLOT_CAPABILITIES;
+use crate::{PciHeaderBase, EXT_CAP_ID_POWER_BUDGETING};
+
+const DATA_SELECT: u16 = 0x04;
+const DATA: u16 = 0x08;
+const CAPABILITY: u16 = 0x0c;
+
+const RAIL_THERMAL: u8 = 7;
+
+/// Power Budgeting extended capability, a table of power figures per power
+/// state read one entry at a time through Data Select.
+pub struct PowerBudgetCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+/// One Power Budgeting entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerBudgetEntry {
+    pub power_mw: u32,
+    /// 0 = D0 ... 3 = D3
+    pub pm_state: u8,
+    pub pm_sub_state: u8,
+    /// 0 = PME Aux, 1 = Auxiliary, 2 = Idle, 3 = Sustained, 7 = Maximum
+    pub kind: u8,
+    /// 0 = 12V, 1 = 3.3V, 2 = 1.5V or 1.8V, 7 = thermal
+    pub rail: u8,
+}
+
+/// Power in mW from an 8-bit value and 2-bit scale, as used by Slot Power
+/// Limit and Power Budgeting. Values above 0xef with scale 1.0x are 250W,
+/// 275W and 300W, larger ones are reserved.
+fn scaled_power_mw(value: u32, scale: u32) -> Option<u32> {
+    if scale == 0 && value > 0xef {
+        return match value {
+            0xf0..=0xf2 => Some(250_000 + (value - 0xf0) * 25_000),
+            _ => None,
+        };
+    }
+    Some(value * 1000 / 10u32.pow(scale))
+}
+
+impl PciHeaderBase {
+    pub fn power_budget(&self) -> Option<PowerBudgetCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_POWER_BUDGETING)?;
+        Some(PowerBudgetCapability { base: self, offset })
+    }
+
+    /// Slot Power Limit of the slot below this downstream port in mW, what a
+    /// card may draw from it. `None` without slot or for reserved values.
+    pub fn slot_power_limit_mw(&self) -> Option<u32> {
+        let cap = self.pcie_capability()?;
+        if !cap.slot_implemented() {
+            return None;
+        }
+        let data = cap.read(SLOT_CAPABILITIES);
+        scaled_power_mw(data.get_bits(7..15), data.get_bits(15..17))
+    }
+}
+
+impl PowerBudgetCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    /// Whether the power is already part of the system budget, e.g. for
+    /// soldered down devices
+    pub fn system_allocated(&self) -> bool {
+        self.base.read(self.offset + CAPABILITY).get_bit(0)
+    }
+
+    /// Entry `index`, `None` past the end of the table or for a reserved
+    /// power value. Selects the entry, not safe against concurrent readers.
+    pub fn entry(&self, index: u8) -> Option<PowerBudgetEntry> {
+        decode_entry(self.read_entry(index)?)
+    }
+
+    /// Data of entry `index`, `None` past the end of the table.
+    fn read_entry(&self, index: u8) -> Option<u32> {
+        self.base.update(self.offset + DATA_SELECT, |mut select| {
+            select.set_bits(0..8, index as u32);
+            select
+        });
+        let data = self.base.read(self.offset + DATA);
+        (data != 0).then_some(data)
+    }
+
+    /// Every entry, the table ends at the first all-zero one. Entries with
+    /// reserved power values are skipped.
+    pub fn entries(&self) -> impl Iterator<Item = PowerBudgetEntry> + '_ {
+        (0..=u8::MAX)
+            .map_while(|i| self.read_entry(i))
+            .filter_map(decode_entry)
+    }
+
+    /// Highest power drawn in D0, to compare against
+    /// [`PciHeaderBase::slot_power_limit_mw`] before powering the slot. The
+    /// rails of one sub state and type add up, thermal entries are heat, not
+    /// drawn from the slot.
+    pub fn max_power_mw(&self) -> Option<u32> {
+        let mut totals: Vec<((u8, u8), u32)> = Vec::new();
+        for e in self
+            .entries()
+            .filter(|e| e.pm_state == 0 && e.rail != RAIL_THERMAL)
+        {
+            let key = (e.pm_sub_state, e.kind);
+            match totals.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, total)) => *total += e.power_mw,
+                None => totals.push((key, e.power_mw)),
+            }
+        }
+        totals.into_iter().map(|(_, total)| total).max()
+    }
+}
+
+fn decode_entry(data: u32) -> Option<PowerBudgetEntry> {
+    Some(PowerBudgetEntry {
+        power_mw: scaled_power_mw(data.get_bits(0..8), data.get_bits(8..10))?,
+        pm_sub_state: data.get_bits(10..13) as u8,
+        pm_state: data.get_bits(13..15) as u8,
+        kind: data.get_bits(15..18) as u8,
+        rail: data.get_bits(18..21) as u8,
+    })
+}
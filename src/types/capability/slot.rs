@@ -0,0 +1,60 @@
+use bit_field::BitField;
+
+use super::pcie::{SLOT_CAPABILITIES, SLOT_CONTROL};
+use crate::PciHeaderBase;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indicator {
+    Attention,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndicatorState {
+    On,
+    Blink,
+    Off,
+}
+
+impl IndicatorState {
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            Self::On => 0b01,
+            Self::Blink => 0b10,
+            Self::Off => 0b11,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0b01 => Some(Self::On),
+            0b10 => Some(Self::Blink),
+            0b11 => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+impl Indicator {
+    /// (present bit in Slot Capabilities, control bits in Slot Control)
+    pub(crate) fn bits(self) -> (usize, core::ops::Range<usize>) {
+        match self {
+            Self::Attention => (3, 6..8),
+            Self::Power => (4, 8..10),
+        }
+    }
+}
+
+impl PciHeaderBase {
+    /// State of an indicator of the slot below this downstream port. Use
+    /// [`HotplugSlot::set_indicator`](crate::HotplugSlot::set_indicator) to
+    /// drive it, Slot Control writes have to wait for Command Completed.
+    pub fn indicator(&self, indicator: Indicator) -> Option<IndicatorState> {
+        let cap = self.pcie_capability()?;
+        let (present, control) = indicator.bits();
+        if !cap.read(SLOT_CAPABILITIES).get_bit(present) {
+            return None;
+        }
+        IndicatorState::from_bits(cap.read(SLOT_CONTROL).get_bits(control))
+    }
+}
@@ -0,0 +1,320 @@
+use core::ops::Range;
+
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+use crate::{
+    err::Error, requester_id, BarAllocatorExt, BarKind, BarSpace, Endpoint, PciAddress,
+    PciHeaderBase, SimpleBarAllocator, EXT_CAP_ID_SRIOV,
+};
+
+const CONTROL: u16 = 0x08;
+const INITIAL_VFS: u16 = 0x0c;
+const NUM_VFS: u16 = 0x10;
+const VF_OFFSET: u16 = 0x14;
+const VF_DEVICE_ID: u16 = 0x18;
+const SUPPORTED_PAGE_SIZES: u16 = 0x1c;
+const SYSTEM_PAGE_SIZE: u16 = 0x20;
+const VF_BAR0: u16 = 0x24;
+
+const CONTROL_VF_ENABLE: usize = 0;
+const CONTROL_VF_MSE: usize = 3;
+
+/// SR-IOV extended capability of a physical function
+pub struct SriovCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+/// One VF BAR, VF `n` owns `base + n * stride .. base + (n + 1) * stride`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfBar {
+    pub base: u64,
+    pub stride: u64,
+    pub prefetchable: bool,
+    pub is_64bit: bool,
+}
+
+/// VF resources assigned by [`SriovCapability::enable`]
+#[derive(Debug, Clone)]
+pub struct SriovVfs {
+    pub num_vfs: u16,
+    pub bars: [Option<VfBar>; 6],
+}
+
+impl SriovVfs {
+    pub fn vf_bar(&self, vf: u16, index: usize) -> Option<Range<u64>> {
+        if vf >= self.num_vfs {
+            return None;
+        }
+        let bar = self.bars.get(index)?.as_ref()?;
+        let start = bar.base + vf as u64 * bar.stride;
+        Some(start..start + bar.stride)
+    }
+}
+
+/// Group functions linked through the SR-IOV Function Dependency Link.
+///
+/// Functions in the same group must be reset and power managed together.
+/// Functions without SR-IOV form groups of their own.
+pub fn function_dependency_groups<'a, I>(endpoints: I) -> Vec<Vec<PciAddress>>
+where
+    I: IntoIterator<Item = &'a Endpoint>,
+{
+    let mut groups: Vec<Vec<PciAddress>> = Vec::new();
+    for ep in endpoints {
+        let address = ep.address();
+        let target = ep
+            .sriov()
+            .map(|sriov| sriov.dependency_target())
+            .unwrap_or(address);
+
+        let a = groups.iter().position(|g| g.contains(&address));
+        let b = groups.iter().position(|g| g.contains(&target));
+        match (a, b) {
+            (Some(i), Some(j)) if i != j => {
+                let merged = groups.remove(i.max(j));
+                groups[i.min(j)].extend(merged);
+            }
+            (Some(_), Some(_)) => {}
+            (Some(i), None) => groups[i].push(target),
+            (None, Some(j)) => groups[j].push(address),
+            (None, None) if address == target => groups.push(vec![address]),
+            (None, None) => groups.push(vec![address, target]),
+        }
+    }
+    groups
+}
+
+impl Endpoint {
+    pub fn sriov(&self) -> Option<SriovCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_SRIOV)?;
+        Some(SriovCapability { base: self, offset })
+    }
+}
+
+impl SriovCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn initial_vfs(&self) -> u16 {
+        self.read(INITIAL_VFS).get_bits(0..16) as u16
+    }
+
+    pub fn total_vfs(&self) -> u16 {
+        self.read(INITIAL_VFS).get_bits(16..32) as u16
+    }
+
+    pub fn num_vfs(&self) -> u16 {
+        self.read(NUM_VFS).get_bits(0..16) as u16
+    }
+
+    pub fn vf_offset(&self) -> u16 {
+        self.read(VF_OFFSET).get_bits(0..16) as u16
+    }
+
+    pub fn vf_stride(&self) -> u16 {
+        self.read(VF_OFFSET).get_bits(16..32) as u16
+    }
+
+    /// Function Number of the PF this PF depends on, its own number if none
+    pub fn function_dependency_link(&self) -> u8 {
+        self.read(NUM_VFS).get_bits(16..24) as u8
+    }
+
+    /// Address of the PF named by [`Self::function_dependency_link`]
+    pub fn dependency_target(&self) -> PciAddress {
+        let pf = self.base.address();
+        let fdl = self.function_dependency_link();
+        // With ARI the device number is 0 and FDL is the 8-bit function number
+        let (device, function) = if pf.device() == 0 {
+            (fdl >> 3, fdl & 0x7)
+        } else {
+            (pf.device(), fdl & 0x7)
+        };
+        PciAddress::new(pf.segment(), pf.bus(), device, function)
+    }
+
+    pub fn vf_device_id(&self) -> u16 {
+        self.read(VF_DEVICE_ID).get_bits(16..32) as u16
+    }
+
+    /// Bit `n` set means a page size of `2^(n + 12)` is supported
+    pub fn supported_page_sizes(&self) -> u32 {
+        self.read(SUPPORTED_PAGE_SIZES)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.read(CONTROL).get_bit(CONTROL_VF_ENABLE)
+    }
+
+    /// Routing ID of VF `vf` (0 based)
+    pub fn vf_address(&self, vf: u16) -> PciAddress {
+        let pf = self.base.address();
+        let rid = requester_id(pf)
+            .wrapping_add(self.vf_offset())
+            .wrapping_add(self.vf_stride().wrapping_mul(vf));
+        PciAddress::new(
+            pf.segment(),
+            (rid >> 8) as u8,
+            ((rid >> 3) & 0x1f) as u8,
+            (rid & 0x7) as u8,
+        )
+    }
+
+    /// Enable `num_vfs` VFs with every VF BAR aligned to `page_size`.
+    ///
+    /// Each VF BAR aperture is allocated contiguously with
+    /// [`BarAllocatorExt::alloc_contiguous`], `num_vfs` strides of the VF BAR
+    /// size rounded up to `page_size`, so every VF gets its own pages and can
+    /// be mapped into a guest on its own. VFs must not be
+    /// accessed until 100ms after this returns.
+    pub fn enable(
+        &self,
+        num_vfs: u16,
+        page_size: usize,
+        allocator: &mut SimpleBarAllocator,
+    ) -> Result<SriovVfs, Error> {
+        if self.is_enabled() {
+            return Err(Error::InvalidArgument("SR-IOV already enabled"));
+        }
+        if num_vfs == 0 || num_vfs > self.total_vfs() {
+            return Err(Error::InvalidArgument("num_vfs out of range"));
+        }
+        if !page_size.is_power_of_two() || page_size < 0x1000 {
+            return Err(Error::InvalidArgument("page_size"));
+        }
+        let page_bit = page_size.trailing_zeros() - 12;
+        if page_bit >= 32 || !self.supported_page_sizes().get_bit(page_bit as usize) {
+            return Err(Error::InvalidArgument("page_size not supported by device"));
+        }
+
+        // VF BAR sizes depend on System Page Size and NumVFs, set them first.
+        self.write(SYSTEM_PAGE_SIZE, 1 << page_bit);
+        self.update(NUM_VFS, |mut data| {
+            data.set_bits(0..16, num_vfs as u32);
+            data
+        });
+
+        let mut vfs = SriovVfs {
+            num_vfs,
+            bars: [None; 6],
+        };
+        let mut index = 0;
+        while index < 6 {
+            let offset = VF_BAR0 + index as u16 * 4;
+            let low = self.read(offset);
+            let is_64bit = low.get_bits(1..3) == 0b10;
+            let prefetchable = low.get_bit(3);
+
+            let size = self.size_bar(offset, is_64bit);
+            if size == 0 {
+                index += if is_64bit { 2 } else { 1 };
+                continue;
+            }
+            // BAR sizes are powers of two, so is the stride
+            let stride = size.max(page_size as u64);
+            let kind = if is_64bit {
+                BarKind::Mem64
+            } else {
+                BarKind::Mem32
+            };
+            let Some(base) =
+                allocator.alloc_contiguous(stride, num_vfs as u64, stride, kind, prefetchable)
+            else {
+                self.release(&vfs, allocator);
+                return Err(Error::NoBarSpace {
+                    address: self.base.address(),
+                });
+            };
+
+            self.write(offset, base as u32);
+            if is_64bit {
+                self.write(offset + 4, (base >> 32) as u32);
+            }
+            vfs.bars[index] = Some(VfBar {
+                base,
+                stride,
+                prefetchable,
+                is_64bit,
+            });
+            index += if is_64bit { 2 } else { 1 };
+        }
+
+        self.update(CONTROL, |mut control| {
+            control.set_bit(CONTROL_VF_ENABLE, true);
+            control.set_bit(CONTROL_VF_MSE, true);
+            control
+        });
+
+        Ok(vfs)
+    }
+
+    /// Undo a failed [`Self::enable`]: clear NumVFs and the VF BARs set so
+    /// far and give their blocks back as far as `allocator` can take them,
+    /// see [`BarSpace::free`].
+    fn release(&self, vfs: &SriovVfs, allocator: &mut SimpleBarAllocator) {
+        for (index, bar) in vfs.bars.iter().enumerate() {
+            let Some(bar) = bar else {
+                continue;
+            };
+            BarSpace::free(
+                allocator,
+                bar.base..bar.base + bar.stride * vfs.num_vfs as u64,
+            );
+            let offset = VF_BAR0 + index as u16 * 4;
+            self.write(offset, 0);
+            if bar.is_64bit {
+                self.write(offset + 4, 0);
+            }
+        }
+        self.update(NUM_VFS, |mut data| {
+            data.set_bits(0..16, 0);
+            data
+        });
+    }
+
+    pub fn disable(&self) {
+        self.update(CONTROL, |mut control| {
+            control.set_bit(CONTROL_VF_ENABLE, false);
+            control.set_bit(CONTROL_VF_MSE, false);
+            control
+        });
+    }
+
+    /// Size of one VF's BAR, 0 if unimplemented
+    fn size_bar(&self, offset: u16, is_64bit: bool) -> u64 {
+        let low = self.read(offset);
+        self.write(offset, u32::MAX);
+        let mut mask = (self.read(offset) & !0xf) as u64;
+        self.write(offset, low);
+
+        if is_64bit {
+            let high = self.read(offset + 4);
+            self.write(offset + 4, u32::MAX);
+            mask |= (self.read(offset + 4) as u64) << 32;
+            self.write(offset + 4, high);
+        } else if mask != 0 {
+            mask |= 0xffff_ffff_0000_0000;
+        }
+
+        if mask == 0 {
+            0
+        } else {
+            (!mask).wrapping_add(1)
+        }
+    }
+
+    fn read(&self, reg: u16) -> u32 {
+        self.base.read(self.offset + reg)
+    }
+
+    fn write(&self, reg: u16, value: u32) {
+        self.base.write(self.offset + reg, value)
+    }
+
+    fn update(&self, reg: u16, f: impl FnOnce(u32) -> u32) {
+        self.base.update(self.offset + reg, f);
+    }
+}
@@ -0,0 +1,70 @@
+use alloc::vec::Vec;
+
+use crate::PciHeaderBase;
+
+use super::*;
+
+/// Capability with its offset in config space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Capability {
+    PowerManagement(u16),
+    Msi(u16),
+    VendorSpecific(u16),
+    PciExpress(u16),
+    MsiX(u16),
+    /// Unrecognized capability, `len` runs up to the next capability or the
+    /// end of the 256 byte config space so it can still be dumped.
+    Other {
+        id: u8,
+        offset: u16,
+        len: u16,
+    },
+}
+
+impl Capability {
+    pub fn offset(&self) -> u16 {
+        match *self {
+            Self::PowerManagement(offset)
+            | Self::Msi(offset)
+            | Self::VendorSpecific(offset)
+            | Self::PciExpress(offset)
+            | Self::MsiX(offset) => offset,
+            Self::Other { offset, .. } => offset,
+        }
+    }
+}
+
+impl PciHeaderBase {
+    /// [`Self::capability_list`]
+    pub fn capabilities(&self) -> Vec<Capability> {
+        self.capability_list()
+    }
+
+    /// All entries of the capability list, unknown IDs included.
+    pub fn capability_list(&self) -> Vec<Capability> {
+        let caps: Vec<_> = self.walk_capabilities().collect();
+        caps.iter()
+            .map(|&(id, offset)| match id {
+                CAP_ID_PM => Capability::PowerManagement(offset),
+                CAP_ID_MSI => Capability::Msi(offset),
+                CAP_ID_VENDOR => Capability::VendorSpecific(offset),
+                CAP_ID_PCIE => Capability::PciExpress(offset),
+                CAP_ID_MSIX => Capability::MsiX(offset),
+                _ => {
+                    let end = caps
+                        .iter()
+                        .map(|&(_, o)| o)
+                        .filter(|&o| o > offset)
+                        .min()
+                        .unwrap_or(0x100);
+                    Capability::Other {
+                        id,
+                        offset,
+                        len: end - offset,
+                    }
+                }
+            })
+            .collect()
+    }
+}
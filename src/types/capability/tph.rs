@@ -0,0 +1,164 @@
+use bit_field::BitField;
+
+use crate::{err::Error, PciHeaderBase, EXT_CAP_ID_TPH};
+
+const CAPABILITY: u16 = 0x04;
+const CONTROL: u16 = 0x08;
+const ST_TABLE: u16 = 0x0c;
+
+/// TPH Requester extended capability. Steering tags tell the completer
+/// which cache or CPU a DMA write is meant for.
+pub struct TphCapability<'a> {
+    base: &'a PciHeaderBase,
+    offset: u16,
+}
+
+/// Steering Tag Mode Select
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StMode {
+    /// Tag 0 on every request
+    NoSt,
+    /// Tag of the MSI-X vector the request belongs to
+    InterruptVector,
+    DeviceSpecific,
+}
+
+/// Where the steering tag table lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StTableLocation {
+    None,
+    /// In the capability, see [`TphCapability::set_steering_tag`]
+    Capability,
+    /// In the upper half of MSI-X Vector Control, see
+    /// [`crate::MsixTable::set_steering_tag`]
+    MsixTable,
+}
+
+impl StMode {
+    fn bits(self) -> u32 {
+        match self {
+            Self::NoSt => 0,
+            Self::InterruptVector => 1,
+            Self::DeviceSpecific => 2,
+        }
+    }
+}
+
+impl PciHeaderBase {
+    pub fn tph(&self) -> Option<TphCapability<'_>> {
+        let offset = self.find_extended_capability(EXT_CAP_ID_TPH)?;
+        Some(TphCapability { base: self, offset })
+    }
+}
+
+impl TphCapability<'_> {
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    pub fn supports_mode(&self, mode: StMode) -> bool {
+        self.read(CAPABILITY).get_bit(mode.bits() as usize)
+    }
+
+    /// 16-bit tags besides the 8-bit ones
+    pub fn extended_supported(&self) -> bool {
+        self.read(CAPABILITY).get_bit(8)
+    }
+
+    pub fn st_table_location(&self) -> StTableLocation {
+        match self.read(CAPABILITY).get_bits(9..11) {
+            1 => StTableLocation::Capability,
+            2 => StTableLocation::MsixTable,
+            _ => StTableLocation::None,
+        }
+    }
+
+    /// Entries in the steering tag table, 0 without table
+    pub fn st_table_size(&self) -> u16 {
+        match self.st_table_location() {
+            StTableLocation::None => 0,
+            _ => self.read(CAPABILITY).get_bits(16..27) as u16 + 1,
+        }
+    }
+
+    pub fn mode(&self) -> Option<StMode> {
+        match self.read(CONTROL).get_bits(0..3) {
+            0 => Some(StMode::NoSt),
+            1 => Some(StMode::InterruptVector),
+            2 => Some(StMode::DeviceSpecific),
+            _ => None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.read(CONTROL).get_bits(8..10) != 0
+    }
+
+    /// Select `mode` and let the function issue TPH, with 16-bit tags if
+    /// `extended`. Program the tags first.
+    pub fn enable(&self, mode: StMode, extended: bool) -> Result<(), Error> {
+        if !self.supports_mode(mode) {
+            return Err(Error::NotSupported("steering tag mode"));
+        }
+        if extended && !self.extended_supported() {
+            return Err(Error::NotSupported("extended TPH"));
+        }
+        self.update(CONTROL, |mut data| {
+            data.set_bits(0..3, mode.bits());
+            data.set_bits(8..10, if extended { 0b11 } else { 0b01 });
+            data
+        });
+        Ok(())
+    }
+
+    pub fn disable(&self) {
+        self.update(CONTROL, |mut data| {
+            data.set_bits(8..10, 0);
+            data
+        });
+    }
+
+    /// Tag `index` of the table held in the capability
+    pub fn steering_tag(&self, index: u16) -> Option<u16> {
+        let reg = self.st_register(index).ok()?;
+        let shift = (index as usize % 2) * 16;
+        Some(self.read(reg).get_bits(shift..shift + 16) as u16)
+    }
+
+    /// Program tag `index` of the table held in the capability. Tables in
+    /// the MSI-X table are programmed through [`crate::MsixTable::set_steering_tag`].
+    pub fn set_steering_tag(&self, index: u16, tag: u16) -> Result<(), Error> {
+        let reg = self.st_register(index)?;
+        if tag > 0xff && !self.extended_supported() {
+            return Err(Error::InvalidArgument("16-bit tag without extended TPH"));
+        }
+        let shift = (index as usize % 2) * 16;
+        self.update(reg, |mut data| {
+            data.set_bits(shift..shift + 16, tag as u32);
+            data
+        });
+        Ok(())
+    }
+
+    fn st_register(&self, index: u16) -> Result<u16, Error> {
+        match self.st_table_location() {
+            StTableLocation::Capability => {}
+            StTableLocation::MsixTable => {
+                return Err(Error::NotSupported("steering tags are in the MSI-X table"))
+            }
+            StTableLocation::None => return Err(Error::NotSupported("no steering tag table")),
+        }
+        if index >= self.st_table_size() {
+            return Err(Error::InvalidArgument("steering tag index out of range"));
+        }
+        Ok(ST_TABLE + index / 2 * 4)
+    }
+
+    fn read(&self, reg: u16) -> u32 {
+        self.base.read(self.offset + reg)
+    }
+
+    fn update(&self, reg: u16, f: impl FnOnce(u32) -> u32) {
+        self.base.update(self.offset + reg, f);
+    }
+}
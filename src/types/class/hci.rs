@@ -0,0 +1,42 @@
+use crate::{err::Error, BarInfo, BarKind, Endpoint};
+
+const XHCI_CLASS: (u8, u8, u8) = (0x0c, 0x03, 0x30);
+const XHCI_BAR: u8 = 0;
+/// Capability, operational and runtime registers of the smallest controllers
+const XHCI_MIN_SIZE: u64 = 0x1000;
+
+const AHCI_CLASS: (u8, u8, u8) = (0x01, 0x06, 0x01);
+/// ABAR
+const AHCI_BAR: u8 = 5;
+/// Generic host control and the registers of one port
+const AHCI_MIN_SIZE: u64 = 0x180;
+
+impl Endpoint {
+    /// BAR0 of an xHCI controller, which must be a 64-bit memory BAR of at
+    /// least 4KiB. Sizes the BARs, see [`Self::bar_info`].
+    pub fn xhci_bar(&self) -> Result<BarInfo, Error> {
+        let (base, sub, interface) = XHCI_CLASS;
+        if !self.is_class(base, sub, interface) {
+            return Err(Error::NotSupported("not an xHCI controller"));
+        }
+        let bar = self.class_bar(XHCI_BAR, XHCI_MIN_SIZE, "xHCI BAR0")?;
+        if bar.kind != BarKind::Mem64 {
+            return Err(Error::InvalidArgument("xHCI BAR0 is not 64-bit"));
+        }
+        Ok(bar)
+    }
+
+    /// ABAR (BAR5) of an AHCI controller, a 32-bit memory BAR holding at
+    /// least one port. Sizes the BARs, see [`Self::bar_info`].
+    pub fn ahci_bar(&self) -> Result<BarInfo, Error> {
+        let (base, sub, interface) = AHCI_CLASS;
+        if !self.is_class(base, sub, interface) {
+            return Err(Error::NotSupported("not an AHCI controller"));
+        }
+        let bar = self.class_bar(AHCI_BAR, AHCI_MIN_SIZE, "AHCI ABAR")?;
+        if bar.kind != BarKind::Mem32 {
+            return Err(Error::InvalidArgument("AHCI ABAR is not 32-bit"));
+        }
+        Ok(bar)
+    }
+}
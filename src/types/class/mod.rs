@@ -0,0 +1,30 @@
+//! Helpers for device classes common enough to be worth it, matched by class
+//! code rather than vendor.
+
+use crate::{err::Error, BarInfo, BarKind, Endpoint};
+
+mod hci;
+mod nvme;
+
+pub use nvme::NvmeRegisters;
+
+impl Endpoint {
+    fn is_class(&self, base: u8, sub: u8, interface: u8) -> bool {
+        let class = self.revision_and_class();
+        (class.base_class, class.sub_class, class.interface) == (base, sub, interface)
+    }
+
+    /// Memory BAR `index`, assigned and at least `min_size` bytes. Sizes the
+    /// BARs, see [`Self::bar_info`].
+    fn class_bar(&self, index: u8, min_size: u64, what: &'static str) -> Result<BarInfo, Error> {
+        let bar = self
+            .bar_info()
+            .find(|b| b.index == index)
+            .filter(|b| b.kind != BarKind::Io)
+            .ok_or(Error::InvalidArgument(what))?;
+        if bar.assigned.is_none() || bar.size < min_size {
+            return Err(Error::InvalidArgument(what));
+        }
+        Ok(bar)
+    }
+}
@@ -0,0 +1,148 @@
+use core::ptr::NonNull;
+
+use bit_field::BitField;
+
+use crate::{err::Error, Endpoint, MmioMapper};
+
+const CLASS_STORAGE: u8 = 0x01;
+const SUBCLASS_NVM: u8 = 0x08;
+const INTERFACE_NVME: u8 = 0x02;
+
+const REG_CAP: usize = 0x00;
+const REG_VS: usize = 0x08;
+/// Doorbells start here, [`NvmeRegisters::doorbell_stride`] apart
+const DOORBELL_BASE: usize = 0x1000;
+
+/// Mapped controller registers of an NVMe function (BAR0/1), with the
+/// Controller Capabilities read when mapping.
+///
+/// The mapping returned by [`MmioMapper`] must stay valid for the lifetime
+/// of this value.
+pub struct NvmeRegisters {
+    base: NonNull<u8>,
+    len: usize,
+    cap: u64,
+}
+
+unsafe impl Send for NvmeRegisters {}
+
+impl Endpoint {
+    /// Map BAR0 of an NVMe controller and check it holds the registers and
+    /// the admin queue doorbells. Sizes the BARs, so call it before memory
+    /// decode is enabled.
+    pub fn nvme_registers(
+        &self,
+        mapper: &(impl MmioMapper + ?Sized),
+    ) -> Result<NvmeRegisters, Error> {
+        if !self.is_class(CLASS_STORAGE, SUBCLASS_NVM, INTERFACE_NVME) {
+            return Err(Error::NotSupported("not an NVMe controller"));
+        }
+        let bar = self.class_bar(0, DOORBELL_BASE as u64, "NVMe BAR0")?;
+        let len = usize::try_from(bar.size)
+            .map_err(|_| Error::InvalidArgument("NVMe BAR0 not addressable"))?;
+        let base = mapper.map(bar.assigned.unwrap_or_default(), len);
+        let mut regs = NvmeRegisters { base, len, cap: 0 };
+        regs.cap = regs.read64(REG_CAP);
+        // Submission and completion doorbell of the admin queue
+        if regs.doorbell_offset(1) + 4 > len {
+            return Err(Error::InvalidArgument(
+                "NVMe BAR0 smaller than the admin doorbells",
+            ));
+        }
+        Ok(regs)
+    }
+}
+
+impl NvmeRegisters {
+    pub fn base(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Controller Capabilities as read when mapping
+    pub fn capabilities(&self) -> u64 {
+        self.cap
+    }
+
+    /// Maximum Queue Entries Supported, per queue
+    pub fn max_queue_entries(&self) -> u32 {
+        self.cap.get_bits(0..16) as u32 + 1
+    }
+
+    /// Bytes between two doorbells
+    pub fn doorbell_stride(&self) -> usize {
+        4 << self.cap.get_bits(32..36)
+    }
+
+    /// Worst case time to become ready after enable or disable
+    pub fn timeout_ms(&self) -> u32 {
+        self.cap.get_bits(24..32) as u32 * 500
+    }
+
+    /// `(major, minor, tertiary)`
+    pub fn version(&self) -> (u16, u8, u8) {
+        let vs = self.read32(REG_VS);
+        (
+            vs.get_bits(16..32) as u16,
+            vs.get_bits(8..16) as u8,
+            vs.get_bits(0..8) as u8,
+        )
+    }
+
+    /// Submission Queue `queue` Tail Doorbell, `None` past the BAR
+    pub fn sq_doorbell(&self, queue: u16) -> Option<NonNull<u32>> {
+        self.doorbell(2 * queue as usize)
+    }
+
+    /// Completion Queue `queue` Head Doorbell, `None` past the BAR
+    pub fn cq_doorbell(&self, queue: u16) -> Option<NonNull<u32>> {
+        self.doorbell(2 * queue as usize + 1)
+    }
+
+    /// Number of I/O queue pairs whose doorbells fit in the BAR
+    pub fn max_io_queues(&self) -> usize {
+        ((self.len - DOORBELL_BASE) / (2 * self.doorbell_stride())).saturating_sub(1)
+    }
+
+    pub fn read32(&self, offset: usize) -> u32 {
+        u32::from_le(unsafe { self.reg(offset).read_volatile() })
+    }
+
+    pub fn write32(&self, offset: usize, value: u32) {
+        unsafe { self.reg(offset).write_volatile(value.to_le()) }
+    }
+
+    /// 64-bit registers as two dword accesses, low half first
+    pub fn read64(&self, offset: usize) -> u64 {
+        self.read32(offset) as u64 | (self.read32(offset + 4) as u64) << 32
+    }
+
+    pub fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+
+    fn doorbell_offset(&self, index: usize) -> usize {
+        DOORBELL_BASE + index * self.doorbell_stride()
+    }
+
+    fn doorbell(&self, index: usize) -> Option<NonNull<u32>> {
+        let offset = self.doorbell_offset(index);
+        (offset + 4 <= self.len).then(|| unsafe { self.base.add(offset).cast() })
+    }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        assert!(
+            offset.is_multiple_of(4) && offset + 4 <= self.len,
+            "NVMe register out of range"
+        );
+        unsafe { self.base.add(offset).cast::<u32>().as_ptr() }
+    }
+}
@@ -0,0 +1,138 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
+
+use pci_types::{ConfigRegionAccess, PciAddress};
+use rdif_pcie::ConfigAccess;
+
+use crate::{
+    chip::SharedQuirk,
+    irq_safe::{IrqLock, IrqLockGuard},
+    AccessPolicy, Bdf,
+};
+
+const HEADER_DWORDS: usize = 16;
+
+/// Dwords hardware changes on its own: Status (0x04) and, of bridges,
+/// Secondary Status (0x1c). They are never served from a snapshot.
+const VOLATILE: u16 = 1 << 1 | 1 << 7;
+
+/// Dwords probing reads: class, header type, the BARs or bus numbers and
+/// windows, the capability pointer and interrupt pin. Subsystem IDs, ROM
+/// and the upper halves of bridge windows are left for when they are asked.
+const PROBED: u16 = 0x3ff << 2 | 1 << 13 | 1 << 15;
+
+/// Config access of one function, optionally serving reads of the first 64
+/// bytes from a snapshot taken in one pass.
+///
+/// A write drops the dword it hits from the snapshot, unless it writes back
+/// the snapshot value, as BAR sizing and read-modify-write cycles do.
+pub(crate) struct HeaderAccess {
+    inner: ConfigAccess,
+    address: PciAddress,
+    dwords: [AtomicU32; HEADER_DWORDS],
+    valid: AtomicU16,
+    active: AtomicBool,
+    /// Held across read-modify-write cycles, see
+    /// [`crate::RootComplex::with_irq_lock`]
+    irq_lock: Option<Arc<dyn IrqLock>>,
+    quirk: Option<SharedQuirk>,
+}
+
+impl HeaderAccess {
+    pub fn new(inner: ConfigAccess, address: PciAddress, quirk: Option<SharedQuirk>) -> Self {
+        Self {
+            inner,
+            address,
+            dwords: core::array::from_fn(|_| AtomicU32::new(0)),
+            valid: AtomicU16::new(0),
+            active: AtomicBool::new(false),
+            irq_lock: None,
+            quirk,
+        }
+    }
+
+    pub fn set_irq_lock(&mut self, lock: Option<Arc<dyn IrqLock>>) {
+        self.irq_lock = lock;
+    }
+
+    /// Keep interrupt handlers out of a read-modify-write cycle.
+    pub fn irq_guard(&self) -> Option<IrqLockGuard<'_>> {
+        self.irq_lock.as_deref().map(IrqLockGuard::new)
+    }
+
+    /// Read the header in one pass, `id` is the dword at 0x00 already read.
+    pub fn snapshot(&self, id: u32) {
+        self.dwords[0].store(id, Ordering::Relaxed);
+        let mut valid = 1;
+        for i in 1..HEADER_DWORDS {
+            if (PROBED & !VOLATILE) & (1 << i) == 0 {
+                continue;
+            }
+            let value = unsafe { self.read_through(self.address, (i * 4) as u16) };
+            self.dwords[i].store(value, Ordering::Relaxed);
+            valid |= 1 << i;
+        }
+        self.valid.store(valid, Ordering::Relaxed);
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Read everything from the function again.
+    pub fn forget(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.valid.store(0, Ordering::Relaxed);
+    }
+
+    fn blocked(&self, address: PciAddress, offset: u16) -> bool {
+        self.quirk
+            .as_ref()
+            .is_some_and(|q| q.quirk(address.into(), offset & !0x3) == AccessPolicy::Skip)
+    }
+
+    /// Read from the function unless the quirk forbids it
+    unsafe fn read_through(&self, address: PciAddress, offset: u16) -> u32 {
+        if self.blocked(address, offset) {
+            return u32::MAX;
+        }
+        unsafe { self.inner.read(address, offset) }
+    }
+
+    fn slot(&self, address: PciAddress, offset: u16) -> Option<usize> {
+        let i = (offset / 4) as usize;
+        (address == self.address && i < HEADER_DWORDS).then_some(i)
+    }
+}
+
+impl ConfigRegionAccess for HeaderAccess {
+    unsafe fn read(&self, address: PciAddress, offset: u16) -> u32 {
+        if let Some(i) = self.slot(address, offset) {
+            if self.valid.load(Ordering::Relaxed) & (1 << i) != 0 {
+                return self.dwords[i].load(Ordering::Relaxed) >> ((offset % 4) * 8);
+            }
+        }
+        unsafe { self.read_through(address, offset) }
+    }
+
+    unsafe fn write(&self, address: PciAddress, offset: u16, value: u32) {
+        if self.blocked(address, offset) {
+            debug!(
+                "{}: quirk drops config write {offset:#x} <- {value:#x}",
+                Bdf(address.into())
+            );
+            return;
+        }
+        if let Some(i) = self.slot(address, offset) {
+            // Only the previously snapshotted value is known to read back
+            // unchanged, anything else may be masked or start something
+            let restores = offset.is_multiple_of(4)
+                && self.active.load(Ordering::Relaxed)
+                && VOLATILE & (1 << i) == 0
+                && self.dwords[i].load(Ordering::Relaxed) == value;
+            if restores {
+                self.valid.fetch_or(1 << i, Ordering::Relaxed);
+            } else {
+                self.valid.fetch_and(!(1 << i), Ordering::Relaxed);
+            }
+        }
+        unsafe { self.inner.write(address, offset, value) }
+    }
+}
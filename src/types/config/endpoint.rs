@@ -4,13 +4,23 @@ use core::{
 };
 
 use alloc::vec::Vec;
-use pci_types::{
-    capability::PciCapability, device_type::DeviceType, Bar, CommandRegister, ConfigRegionAccess,
-    EndpointHeader, PciAddress,
+use bit_field::BitField;
+use pci_types::{Bar, EndpointHeader};
+
+use crate::{
+    err::Error, BarHeader, BarInfo, BarKind, BarSpace, BarVec, Bdf, CommandRegister, DeviceType,
 };
-use rdif_pcie::ConfigAccess;
 
-use crate::{BarHeader, BarVec, SimpleBarAllocator};
+/// Where [`Endpoint::realloc_bar`] puts 64-bit BARs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mem64Placement {
+    /// On the side of 4GiB firmware put them
+    AsFound,
+    /// Above 4GiB when there is space there
+    Prefer64,
+    /// Below 4GiB, e.g. behind a bridge with a 32-bit prefetchable window
+    Below4G,
+}
 
 pub struct Endpoint {
     base: super::PciHeaderBase,
@@ -18,17 +28,10 @@ pub struct Endpoint {
 }
 
 impl Endpoint {
-    pub(crate) fn new(
-        base: super::PciHeaderBase,
-        bar_allocator: Option<&mut SimpleBarAllocator>,
-    ) -> Self {
+    pub(crate) fn new(base: super::PciHeaderBase) -> Self {
         let header = EndpointHeader::from_header(base.header(), &base.root)
             .expect("EndpointHeader::from_header failed");
-        let mut s = Self { base, header };
-        if let Some(alloc) = bar_allocator {
-            s.realloc_bar(alloc).unwrap();
-        }
-        s
+        Self { base, header }
     }
 
     pub fn device_type(&self) -> DeviceType {
@@ -36,33 +39,184 @@ impl Endpoint {
         DeviceType::from((class_info.base_class, class_info.sub_class))
     }
 
+    /// BAR range as CPU addresses, `None` if it is not addressable by `usize`
+    /// (64-bit BARs above 4GiB on 32-bit targets).
     pub fn bar(&self, index: usize) -> Option<Range<usize>> {
-        assert!(index < 6, "BAR index out of range");
-        let bars = self.bars();
-        let r = match &bars {
-            BarVec::Memory32(bar_vec) => {
-                let b = bar_vec.get(index)?;
-                b.address as usize..(b.address as usize + b.size as usize)
+        let r = self.bar_range(index)?;
+        match (usize::try_from(r.start), usize::try_from(r.end)) {
+            (Ok(start), Ok(end)) => Some(start..end),
+            _ => {
+                warn!("BAR{index} {r:#x?} is not addressable by usize");
+                None
             }
-            BarVec::Memory64(bar_vec) => {
-                let b = bar_vec.get(index)?;
-                b.address as usize..(b.address + b.size) as usize
+        }
+    }
+
+    /// Memory BAR range by register `index`, `None` for I/O BARs, the upper
+    /// half of a 64-bit BAR and indexes past the last BAR.
+    pub fn bar_range(&self, index: usize) -> Option<Range<u64>> {
+        let info = self.bar_info().find(|b| b.index as usize == index)?;
+        if info.kind == BarKind::Io {
+            return None;
+        }
+        let address = info.assigned.unwrap_or(0);
+        Some(address..address + info.size)
+    }
+
+    /// Every implemented BAR in register order, mixed kinds included. Sizes
+    /// the BARs, so like [`Self::bars`] only call it while the device is
+    /// quiesced.
+    pub fn bar_info(&self) -> impl Iterator<Item = BarInfo> + '_ {
+        let mut slot = 0;
+        core::iter::from_fn(move || {
+            while slot < 6 {
+                let index = slot;
+                slot += 1;
+                let info = match self.header.bar(index, self.access()) {
+                    Some(Bar::Memory32 {
+                        address,
+                        size,
+                        prefetchable,
+                    }) => BarInfo {
+                        index,
+                        kind: BarKind::Mem32,
+                        size: size as u64,
+                        prefetchable,
+                        assigned: (address != 0).then_some(address as u64),
+                    },
+                    Some(Bar::Memory64 {
+                        address,
+                        size,
+                        prefetchable,
+                    }) => {
+                        // The upper half is not a BAR of its own
+                        slot += 1;
+                        BarInfo {
+                            index,
+                            kind: BarKind::Mem64,
+                            size,
+                            prefetchable,
+                            assigned: (address != 0).then_some(address),
+                        }
+                    }
+                    Some(Bar::Io { port }) => BarInfo {
+                        index,
+                        kind: BarKind::Io,
+                        size: self.io_bar_size(index),
+                        prefetchable: false,
+                        assigned: (port != 0).then_some(port as u64),
+                    },
+                    None => continue,
+                };
+                return Some(info);
             }
-            BarVec::Io(_) => unimplemented!(), // IO BAR size is typically 4 bytes
-        };
-        Some(r)
+            None
+        })
+    }
+
+    /// `pci_types` doesn't size I/O BARs
+    fn io_bar_size(&self, index: u8) -> u64 {
+        let offset = 0x10 + index as u16 * 4;
+        let original = self.base.read(offset);
+        let command = self.base.update_command(|mut cmd| {
+            cmd.remove(CommandRegister::IO_ENABLE);
+            cmd
+        });
+        self.base.write(offset, u32::MAX);
+        let mut mask = self.base.read(offset) & !0x3;
+        self.base.write(offset, original);
+        self.base.update_command(|_| command);
+        if mask == 0 {
+            return 0;
+        }
+        // Only 16 address bits decoded
+        if mask & 0xffff_0000 == 0 {
+            mask |= 0xffff_0000;
+        }
+        (!mask).wrapping_add(1) as u64
+    }
+
+    /// Address programmed in BAR register `index`, `None` for I/O BARs.
+    /// Only reads the BAR registers, no sizing, so it is safe to call while
+    /// the device is in use. For a 64-bit BAR pass the index of its low half.
+    #[inline]
+    pub fn bar_address(&self, index: usize) -> Option<u64> {
+        if index >= 6 {
+            return None;
+        }
+        let offset = 0x10 + index as u16 * 4;
+        let low = self.base.read(offset);
+        if low.get_bit(0) {
+            return None;
+        }
+        let mut address = (low & !0xf) as u64;
+        if low.get_bits(1..3) == 0b10 {
+            if index == 5 {
+                return None;
+            }
+            address |= (self.base.read(offset + 4) as u64) << 32;
+        }
+        Some(address)
+    }
+
+    /// Addresses programmed in the prefetchable 64-bit BARs, 0 if none.
+    /// Reads the BAR registers like [`Self::bar_address`], without sizing.
+    pub(crate) fn prefetchable_mem64(&self) -> impl Iterator<Item = u64> + '_ {
+        let mut index = 0;
+        core::iter::from_fn(move || {
+            while index < 5 {
+                let low = self.base.read(0x10 + index as u16 * 4);
+                let mem64 = !low.get_bit(0) && low.get_bits(1..3) == 0b10;
+                let address = mem64.then(|| self.bar_address(index)).flatten();
+                index += if mem64 { 2 } else { 1 };
+                if let (Some(address), true) = (address, low.get_bit(3)) {
+                    return Some(address);
+                }
+            }
+            None
+        })
     }
 
     pub fn bars(&self) -> BarVec {
         self.header.parse_bar(6, &self.base.root)
     }
 
-    pub fn capabilities_pointer(&self) -> u16 {
-        self.header.capability_pointer(self.access())
+    /// Program memory BAR `index` with `value`, e.g. to move it above 4GiB
+    /// late in boot. For a 64-bit BAR pass the index of its low half, I/O BARs
+    /// are not supported. `value` must be aligned to the BAR size. Read
+    /// the BARs again with [`Self::bars`] afterwards.
+    ///
+    /// # Safety
+    ///
+    /// Memory decoding of the function must be off while the BAR moves, and
+    /// `value` must lie in the windows of every bridge above it. Mappings
+    /// of the old address become stale.
+    pub unsafe fn set_bar(&self, index: usize, value: u64) -> Result<(), Error> {
+        // Kinds may be mixed, look the BAR up on its own
+        let bar = self
+            .bar_info()
+            .find(|b| b.index as usize == index)
+            .ok_or(Error::InvalidArgument("no such BAR"))?;
+        match bar.kind {
+            BarKind::Io => return Err(Error::NotSupported("I/O BARs")),
+            BarKind::Mem32 if value > u32::MAX as u64 => {
+                return Err(Error::InvalidArgument("address beyond a 32-bit BAR"));
+            }
+            _ => {}
+        }
+        if !value.is_multiple_of(bar.size) {
+            return Err(Error::InvalidArgument("BAR value not aligned to its size"));
+        }
+        let offset = 0x10 + index as u16 * 4;
+        self.base.write(offset, value as u32);
+        if bar.kind == BarKind::Mem64 {
+            self.base.write(offset + 4, (value >> 32) as u32);
+        }
+        Ok(())
     }
 
-    pub fn capabilities(&self) -> Vec<PciCapability> {
-        self.header.capabilities(self.access()).collect()
+    pub fn capabilities_pointer(&self) -> u16 {
+        self.capability_pointer()
     }
 
     pub fn interrupt_pin(&self) -> u8 {
@@ -91,88 +245,94 @@ impl Endpoint {
             .update_interrupt(&self.base.root, |(pin, _)| (pin, line));
     }
 
-    fn access(&self) -> &ConfigAccess {
+    fn access(&self) -> &super::access::HeaderAccess {
         &self.base.root
     }
 
-    fn realloc_bar(
+    /// Assign all BARs from `space`, I/O BARs only if it has I/O space, and
+    /// return the assigned ones. Nothing is written if any memory BAR doesn't
+    /// fit, the firmware assignment stays and the blocks taken so far are
+    /// given back as far as `space` can take them, see [`BarSpace::free`].
+    pub(crate) fn realloc_bar(
         &mut self,
-        allocator: &mut SimpleBarAllocator,
-    ) -> Result<(), pci_types::BarWriteError> {
+        space: &mut impl BarSpace,
+        placement: Mem64Placement,
+    ) -> Result<Vec<BarInfo>, Error> {
         // Disable IO/MEM before reprogramming BARs
-        self.base.update_command(|mut cmd| {
+        let command = self.base.update_command(|mut cmd| {
             cmd.remove(CommandRegister::IO_ENABLE);
             cmd.remove(CommandRegister::MEMORY_ENABLE);
             cmd
         });
-        let bar = self.bars();
-
-        match &bar {
-            crate::BarVec::Memory32(bar_vec) => {
-                // Compute new values with mutable allocator, then write using immutable access
-                let new_vals = {
-                    bar_vec
-                        .iter()
-                        .map(|old| {
-                            old.clone().map(|ref b| {
-                                allocator
-                                    .alloc_memory32_with_pref(b.size, b.prefetchable)
-                                    .unwrap()
-                            })
-                        })
-                        .collect::<alloc::vec::Vec<_>>()
-                };
-                for (i, v) in new_vals.into_iter().enumerate() {
-                    if let Some(value) = v {
-                        bar_vec.set(i, value, &self.base.root).unwrap();
+        let no_space = Error::NoBarSpace {
+            address: self.address(),
+        };
+
+        let mut new_vals = Vec::new();
+        for bar in self.bar_info().collect::<Vec<_>>() {
+            let value = match bar.kind {
+                BarKind::Mem32 => u32::try_from(bar.size)
+                    .ok()
+                    .and_then(|size| space.alloc_memory32(size, bar.prefetchable))
+                    .map(u64::from),
+                BarKind::Mem64 => {
+                    let below_4g = bar.assigned.is_some_and(|a| a < u32::MAX as u64);
+                    let alloc32 = |space: &mut _| {
+                        BarSpace::alloc_memory32(
+                            space,
+                            u32::try_from(bar.size).ok()?,
+                            bar.prefetchable,
+                        )
+                        .map(u64::from)
+                    };
+                    match placement {
+                        Mem64Placement::Below4G => alloc32(space),
+                        Mem64Placement::Prefer64 => space
+                            .alloc_memory64(bar.size, bar.prefetchable)
+                            .or_else(|| alloc32(space)),
+                        Mem64Placement::AsFound if below_4g => alloc32(space),
+                        Mem64Placement::AsFound => space.alloc_memory64(bar.size, bar.prefetchable),
                     }
                 }
-                self.base.update_command(|mut cmd| {
-                    cmd.insert(CommandRegister::MEMORY_ENABLE);
-                    cmd
-                });
-            }
-            crate::BarVec::Memory64(bar_vec) => {
-                let new_vals = {
-                    bar_vec
-                        .iter()
-                        .map(|old| {
-                            old.clone().map(|ref b| {
-                                if b.address > 0 && b.address < u32::MAX as u64 {
-                                    allocator
-                                        .alloc_memory32_with_pref(b.size as u32, b.prefetchable)
-                                        .unwrap() as u64
-                                } else {
-                                    allocator
-                                        .alloc_memory64_with_pref(b.size, b.prefetchable)
-                                        .unwrap()
-                                }
-                            })
-                        })
-                        .collect::<alloc::vec::Vec<_>>()
-                };
-                for (i, v) in new_vals.into_iter().enumerate() {
-                    if let Some(value) = v {
-                        bar_vec
-                            .set(i, value, &self.base.root)
-                            .inspect_err(|e| error!("{e:?}"))
-                            .unwrap();
+                // Left as found without I/O space
+                BarKind::Io => match u32::try_from(bar.size).ok() {
+                    Some(size) => space.alloc_io(size).map(u64::from),
+                    None => None,
+                },
+            };
+            match value {
+                Some(value) => new_vals.push((bar, value)),
+                None if bar.kind == BarKind::Io => {}
+                None => {
+                    for (bar, value) in new_vals {
+                        space.free(value..value + bar.size);
                     }
+                    self.base.update_command(|_| command);
+                    return Err(no_space);
                 }
-                self.base.update_command(|mut cmd| {
-                    cmd.insert(CommandRegister::MEMORY_ENABLE);
-                    cmd
-                });
             }
-            crate::BarVec::Io(_bar_vec_t) => {
-                self.base.update_command(|mut cmd| {
-                    cmd.insert(CommandRegister::IO_ENABLE);
-                    cmd
-                });
+        }
+
+        let mut decode = command;
+        let mut assigned = Vec::new();
+        for (mut bar, value) in new_vals {
+            // Decoding is off, the allocator keeps to the windows
+            let offset = 0x10 + bar.index as u16 * 4;
+            self.base.write(offset, value as u32);
+            match bar.kind {
+                BarKind::Mem64 => self.base.write(offset + 4, (value >> 32) as u32),
+                BarKind::Mem32 => {}
+                BarKind::Io => decode.insert(CommandRegister::IO_ENABLE),
+            }
+            if bar.kind != BarKind::Io {
+                decode.insert(CommandRegister::MEMORY_ENABLE);
             }
+            bar.assigned = Some(value);
+            assigned.push(bar);
         }
+        self.base.update_command(|_| decode);
 
-        Ok(())
+        Ok(assigned)
     }
 }
 
@@ -191,17 +351,9 @@ impl DerefMut for Endpoint {
 }
 
 impl BarHeader for EndpointHeader {
-    fn read_bar<A: ConfigRegionAccess>(&self, slot: usize, access: &A) -> Option<Bar> {
+    fn read_bar(&self, slot: usize, access: &super::access::HeaderAccess) -> Option<Bar> {
         self.bar(slot as u8, access)
     }
-
-    fn address(&self) -> PciAddress {
-        self.header().address()
-    }
-
-    fn header_type(&self) -> pci_types::HeaderType {
-        pci_types::HeaderType::Endpoint
-    }
 }
 
 impl Debug for Endpoint {
@@ -222,16 +374,19 @@ impl Display for Endpoint {
 
         write!(
             f,
-            "{:04x}:{:02x}:{:02x}.{} {:<24} {:04x}:{:04x} (rev {:02x}, prog-if {:02x})",
-            address.segment(),
-            address.bus(),
-            address.device(),
-            address.function(),
+            "{} {:<24} {:04x}:{:04x} (rev {:02x}, prog-if {:02x})",
+            Bdf(address),
             class_name,
             self.base.vendor_id(),
             self.base.device_id(),
             class_info.revision_id,
             class_info.interface,
-        )
+        )?;
+        // `{:#}` adds the lspci -v style register lines
+        if f.alternate() {
+            write!(f, "\n\tControl: {}", self.command_flags())?;
+            write!(f, "\n\tStatus: {}", self.status_flags())?;
+        }
+        Ok(())
     }
 }
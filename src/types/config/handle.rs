@@ -0,0 +1,55 @@
+use alloc::sync::Arc;
+
+use super::Endpoint;
+use crate::{
+    lock::{SpinLock, SpinLockGuard},
+    PciAddress,
+};
+
+/// Cloneable, shared handle to an [`Endpoint`], so e.g. a driver's IRQ path
+/// and task path can both hold their device.
+///
+/// All access goes through a lock. Interrupt handlers should use
+/// [`Self::try_lock`], spinning there on a lock held by the interrupted
+/// context never ends.
+#[derive(Clone)]
+pub struct EndpointHandle {
+    address: PciAddress,
+    inner: Arc<SpinLock<Endpoint>>,
+}
+
+pub type EndpointGuard<'a> = SpinLockGuard<'a, Endpoint>;
+
+impl EndpointHandle {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            address: endpoint.address(),
+            inner: Arc::new(SpinLock::new(endpoint)),
+        }
+    }
+
+    /// Address of the function, no locking needed.
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    pub fn lock(&self) -> EndpointGuard<'_> {
+        self.inner.lock()
+    }
+
+    pub fn try_lock(&self) -> Option<EndpointGuard<'_>> {
+        self.inner.try_lock()
+    }
+}
+
+impl From<Endpoint> for EndpointHandle {
+    fn from(endpoint: Endpoint) -> Self {
+        Self::new(endpoint)
+    }
+}
+
+impl Endpoint {
+    pub fn into_shared(self) -> EndpointHandle {
+        EndpointHandle::new(self)
+    }
+}
@@ -1,21 +1,27 @@
 use core::fmt::Debug;
 
+mod access;
 mod card_bridge;
 mod endpoint;
+mod handle;
 mod pci_bridge;
 mod unknown;
 
 pub use card_bridge::*;
 pub use endpoint::Endpoint;
+pub(crate) use endpoint::Mem64Placement;
+pub use handle::{EndpointGuard, EndpointHandle};
 pub use pci_bridge::*;
-use rdif_pcie::ConfigAccess;
 pub use unknown::*;
 
-use pci_types::{
-    CommandRegister, ConfigRegionAccess, HeaderType, PciAddress, PciHeader, StatusRegister,
-};
+use alloc::sync::Arc;
 
-use crate::chip::PcieController;
+use pci_types::{ConfigRegionAccess, PciHeader};
+
+pub(crate) use self::access::HeaderAccess;
+use crate::chip::{PcieController, SharedQuirk};
+use crate::irq_safe::IrqLock;
+use crate::{Bdf, CommandRegister, HeaderType, PciAddress, StatusRegister, CAP_ID_PCIE};
 
 #[derive(Debug)]
 pub enum PciConfigSpace {
@@ -28,56 +34,132 @@ pub enum PciConfigSpace {
 pub struct PciHeaderBase {
     vid: u16,
     did: u16,
-    root: ConfigAccess,
+    root: HeaderAccess,
     header: PciHeader,
+    /// Config space beyond 256 bytes, only PCIe functions have it
+    extended: bool,
 }
 
 impl PciHeaderBase {
     pub(crate) fn new(root: &mut PcieController, address: PciAddress) -> Option<Self> {
-        let root = root.config_access(address);
-        let header = PciHeader::new(address);
+        Self::probe(root, address, false, None)
+    }
+
+    /// As [`Self::new`], with every access of the function checked against
+    /// `quirk`. With `snapshot` the header is read in one pass and reads of
+    /// it are served from that snapshot until [`Self::forget_snapshot`].
+    pub(crate) fn probe(
+        root: &mut PcieController,
+        address: PciAddress,
+        snapshot: bool,
+        quirk: Option<SharedQuirk>,
+    ) -> Option<Self> {
+        let root = HeaderAccess::new(root.config_access(address.into()), address.into(), quirk);
+        let header = PciHeader::new(address.into());
         let (vid, did) = header.id(&root);
         if vid == 0xffff {
             return None;
         }
+        if snapshot {
+            root.snapshot((did as u32) << 16 | vid as u32);
+        }
 
-        Some(Self {
+        let mut base = Self {
             vid,
             did,
             root,
             header,
-        })
+            extended: false,
+        };
+        // Conventional PCI functions, e.g. behind a PCIe-to-PCI bridge, may
+        // alias or float the upper config space
+        base.extended = base.find_capability(CAP_ID_PCIE).is_some();
+        Some(base)
+    }
+
+    /// Take `lock` for read-modify-write cycles, so they don't interleave
+    /// with interrupt handlers using [`crate::IrqConfigHandle`].
+    pub(crate) fn set_irq_lock(&mut self, lock: Option<Arc<dyn IrqLock>>) {
+        self.root.set_irq_lock(lock);
+    }
+
+    /// Read the header from the function again, e.g. once the scan that took
+    /// the snapshot hands the function out.
+    pub(crate) fn forget_snapshot(&self) {
+        self.root.forget();
+    }
+
+    /// Whether the function has a PCI Express capability.
+    pub fn is_pcie(&self) -> bool {
+        self.extended
+    }
+
+    /// 256 bytes for conventional PCI functions, 4KiB for PCIe ones.
+    pub fn config_space_size(&self) -> u16 {
+        if self.extended {
+            0x1000
+        } else {
+            0x100
+        }
     }
 
-    pub fn header(&self) -> PciHeader {
-        PciHeader::new(self.address())
+    pub(crate) fn header(&self) -> PciHeader {
+        PciHeader::new(self.address().into())
     }
 
     pub fn address(&self) -> PciAddress {
-        self.header.address()
+        self.header.address().into()
     }
 
     pub fn header_type(&self) -> HeaderType {
-        self.header.header_type(&self.root)
+        HeaderType::from((self.read(0x0c) >> 16) as u8)
+    }
+
+    /// Cache Line Size in bytes
+    pub fn cache_line_size(&self) -> u16 {
+        (self.read(0x0c) & 0xff) as u16 * 4
+    }
+
+    pub fn latency_timer(&self) -> u8 {
+        (self.read(0x0c) >> 8) as u8
+    }
+
+    /// Program Cache Line Size (`bytes`, a multiple of 4 up to 1020) and
+    /// Latency Timer. Only conventional PCI functions use them.
+    pub fn set_cache_line_and_latency(&self, bytes: u16, latency_timer: u8) {
+        self.update(0x0c, |data| {
+            data & 0x00ff_0000 | (latency_timer as u32) << 8 | (bytes / 4).min(0xff) as u32
+        });
     }
 
     pub fn has_multiple_functions(&self) -> bool {
         self.header.has_multiple_functions(&self.root)
     }
 
-    pub fn update_command<F>(&mut self, f: F)
+    /// Read-modify-write of the Command register, returns the value before.
+    /// The Status half is written as 0 so no RW1C bit is cleared by the way.
+    pub fn update_command<F>(&self, f: F) -> CommandRegister
     where
         F: FnOnce(CommandRegister) -> CommandRegister,
     {
-        self.header.update_command(&self.root, f);
+        let old = self.update(0x04, |data| {
+            f(CommandRegister::from_bits_retain(data as u16)).bits() as u32
+        });
+        CommandRegister::from_bits_retain(old as u16)
     }
 
     pub fn status(&self) -> StatusRegister {
-        self.header.status(&self.root)
+        StatusRegister::new((self.read(0x04) >> 16) as u16)
+    }
+
+    /// Clear the RW1C Status bits set in `bits`, e.g. `status().bits()` for
+    /// all of them. The Command half is written back unchanged.
+    pub fn update_status_clear(&self, bits: u16) {
+        self.update(0x04, |data| data & 0xffff | (bits as u32) << 16);
     }
 
     pub fn command(&self) -> CommandRegister {
-        self.header.command(&self.root)
+        CommandRegister::from_bits_retain(self.read(0x04) as u16)
     }
 
     pub fn revision_and_class(&self) -> RevisionAndClass {
@@ -99,12 +181,34 @@ impl PciHeaderBase {
         self.did
     }
 
+    /// Offsets beyond [`Self::config_space_size`] read as all ones.
     pub fn read(&self, offset: u16) -> u32 {
-        unsafe { self.root.read(self.address(), offset) }
+        if offset >= self.config_space_size() {
+            return u32::MAX;
+        }
+        unsafe { self.root.read(self.address().into(), offset) }
     }
 
+    /// Offsets beyond [`Self::config_space_size`] are dropped.
     pub fn write(&self, offset: u16, value: u32) {
-        unsafe { self.root.write(self.address(), offset, value) }
+        if offset >= self.config_space_size() {
+            debug!(
+                "{}: write to {offset:#x} beyond config space",
+                Bdf(self.address())
+            );
+            return;
+        }
+        unsafe { self.root.write(self.address().into(), offset, value) }
+    }
+
+    /// Read-modify-write of the dword at `offset` under the IRQ lock of the
+    /// [`crate::RootComplex`] the function was found through, if any.
+    /// Returns the value before.
+    pub(crate) fn update(&self, offset: u16, f: impl FnOnce(u32) -> u32) -> u32 {
+        let _guard = self.root.irq_guard();
+        let old = self.read(offset);
+        self.write(offset, f(old));
+        old
     }
 }
 
@@ -119,7 +223,7 @@ pub struct RevisionAndClass {
 impl Debug for PciHeaderBase {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("PciHeaderBase")
-            .field("address", &self.address())
+            .field("address", &Bdf(self.address()))
             .field("vid", &format_args!("{:#06x}", self.vid))
             .field("did", &format_args!("{:#06x}", self.did))
             .field("command", &self.command())
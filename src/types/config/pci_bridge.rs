@@ -1,23 +1,30 @@
-use core::{fmt::Debug, ops::Deref};
+use core::{
+    fmt::Debug,
+    ops::{Deref, DerefMut, Range},
+};
 
 use bit_field::BitField;
-use pci_types::{ConfigRegionAccess, PciPciBridgeHeader};
-use rdif_pcie::ConfigAccess;
+use pci_types::PciPciBridgeHeader;
 
-use super::PciHeaderBase;
+use super::{access::HeaderAccess, PciHeaderBase};
+use crate::{BridgeControl, BusNumber, SecondaryStatus};
 
 pub struct PciPciBridge {
     base: Option<PciHeaderBase>,
     header: Option<PciPciBridgeHeader>,
     is_root: bool,
+    /// First bus of the host bridge, its secondary and subordinate bus
+    root_bus: u8,
 }
 
 impl PciPciBridge {
-    pub(crate) fn root() -> Self {
+    /// Host bridge whose root bus is `bus`.
+    pub(crate) fn root(bus: u8) -> Self {
         Self {
             base: None,
             header: None,
             is_root: true,
+            root_bus: bus,
         }
     }
 
@@ -29,6 +36,7 @@ impl PciPciBridge {
             base: Some(base),
             header: Some(header),
             is_root: false,
+            root_bus: 0,
         }
     }
 
@@ -36,7 +44,7 @@ impl PciPciBridge {
         self.header.as_ref().expect("Not a root bridge")
     }
 
-    fn access(&self) -> &ConfigAccess {
+    fn access(&self) -> &HeaderAccess {
         &self.base.as_ref().expect("Not a root bridge").root
     }
 
@@ -49,18 +57,106 @@ impl PciPciBridge {
 
     pub fn secondary_bus_number(&self) -> u8 {
         if self.is_root {
-            return 0;
+            return self.root_bus;
         }
         self.header().secondary_bus_number(self.access())
     }
 
     pub fn subordinate_bus_number(&self) -> u8 {
         if self.is_root {
-            return 0;
+            return self.root_bus;
         }
         self.header().subordinate_bus_number(self.access())
     }
 
+    /// I/O window as `[start, end)`, `None` if disabled.
+    pub fn io_window(&self) -> Option<Range<u32>> {
+        if self.is_root {
+            return None;
+        }
+        let data = self.read(0x1c);
+        let upper = self.read(0x30);
+        let mut start = data.get_bits(4..8) << 12;
+        let mut end = data.get_bits(12..16) << 12 | 0xfff;
+        // 32-bit I/O addressing
+        if data.get_bits(0..4) == 0x1 {
+            start |= upper.get_bits(0..16) << 16;
+            end |= upper.get_bits(16..32) << 16;
+        }
+        (start <= end).then(|| start..end.saturating_add(1))
+    }
+
+    /// Memory window as `[start, end)`, `None` if disabled.
+    pub fn memory_window(&self) -> Option<Range<u32>> {
+        if self.is_root {
+            return None;
+        }
+        let data = self.read(0x20);
+        let start = (data & 0xfff0) << 16;
+        let end = (data & 0xfff0_0000) | 0xf_ffff;
+        (start <= end).then(|| start..end.saturating_add(1))
+    }
+
+    /// Whether the prefetchable window decodes 64-bit addresses. The host
+    /// bridge counts as 64-bit.
+    pub fn prefetchable_64bit(&self) -> bool {
+        self.is_root || self.read(0x24).get_bits(0..4) == 0x1
+    }
+
+    /// Prefetchable window as `[start, end)`, `None` if disabled.
+    pub fn prefetchable_window(&self) -> Option<Range<u64>> {
+        if self.is_root {
+            return None;
+        }
+        let data = self.read(0x24);
+        let mut start = ((data & 0xfff0) as u64) << 16;
+        let mut end = (data & 0xfff0_0000) as u64 | 0xf_ffff;
+        // 64-bit prefetchable memory
+        if data.get_bits(0..4) == 0x1 {
+            start |= (self.read(0x28) as u64) << 32;
+            end |= (self.read(0x2c) as u64) << 32;
+        }
+        (start <= end).then(|| start..end.saturating_add(1))
+    }
+
+    pub fn secondary_status(&self) -> SecondaryStatus {
+        if self.is_root {
+            return SecondaryStatus::default();
+        }
+        SecondaryStatus::new((self.read(0x1c) >> 16) as u16)
+    }
+
+    /// Clear the RW1C Secondary Status bits set in `bits`, the I/O window
+    /// sharing the dword is kept.
+    pub fn clear_secondary_status(&self, bits: u16) {
+        if self.is_root {
+            return;
+        }
+        let io = self.read(0x1c) & 0xffff;
+        self.write(0x1c, io | (bits as u32) << 16);
+    }
+
+    pub fn bridge_control(&self) -> BridgeControl {
+        if self.is_root {
+            return BridgeControl::empty();
+        }
+        BridgeControl::from_bits_retain((self.read(0x3c) >> 16) as u16)
+    }
+
+    pub fn update_bridge_control<F>(&mut self, f: F)
+    where
+        F: FnOnce(BridgeControl) -> BridgeControl,
+    {
+        if self.is_root {
+            return;
+        }
+        self.update(0x3c, |mut data| {
+            let control = f(BridgeControl::from_bits_retain((data >> 16) as u16));
+            data.set_bits(16..32, control.bits().into());
+            data
+        });
+    }
+
     pub fn update_bus_number<F>(&mut self, f: F)
     where
         F: FnOnce(BusNumber) -> BusNumber,
@@ -68,26 +164,150 @@ impl PciPciBridge {
         if self.is_root {
             return;
         }
-        let address = self.base.as_ref().unwrap().address();
-        let mut data = unsafe { self.access().read(address, 0x18) };
-        let new_bus = f(BusNumber {
-            primary: data.get_bits(0..8) as u8,
-            secondary: data.get_bits(8..16) as u8,
-            subordinate: data.get_bits(16..24) as u8,
+        self.update(0x18, |mut data| {
+            let new_bus = f(BusNumber {
+                primary: data.get_bits(0..8) as u8,
+                secondary: data.get_bits(8..16) as u8,
+                subordinate: data.get_bits(16..24) as u8,
+            });
+            data.set_bits(16..24, new_bus.subordinate.into());
+            data.set_bits(8..16, new_bus.secondary.into());
+            data.set_bits(0..8, new_bus.primary.into());
+            data
         });
-        data.set_bits(16..24, new_bus.subordinate.into());
-        data.set_bits(8..16, new_bus.secondary.into());
-        data.set_bits(0..8, new_bus.primary.into());
-        unsafe {
-            self.access().write(address, 0x18, data);
-        }
     }
 }
 
-pub struct BusNumber {
-    pub primary: u8,
-    pub secondary: u8,
-    pub subordinate: u8,
+/// Stages bridge bus number, window and control registers and writes them
+/// with one dword write per register in a fixed order on [`Self::flush`].
+///
+/// Windows are `[start, end)` ranges, `None` disables the window. I/O windows
+/// are 4KiB granular, memory windows 1MiB granular.
+pub struct BridgeConfigWriter<'a> {
+    bridge: &'a PciPciBridge,
+    bus: Option<BusNumber>,
+    io: Option<Option<Range<u32>>>,
+    memory: Option<Option<Range<u32>>>,
+    prefetchable: Option<Option<Range<u64>>>,
+    control: Option<u16>,
+}
+
+impl<'a> BridgeConfigWriter<'a> {
+    pub fn new(bridge: &'a PciPciBridge) -> Self {
+        Self {
+            bridge,
+            bus: None,
+            io: None,
+            memory: None,
+            prefetchable: None,
+            control: None,
+        }
+    }
+
+    pub fn bus_number(mut self, bus: BusNumber) -> Self {
+        self.bus = Some(bus);
+        self
+    }
+
+    pub fn io_window(mut self, window: Option<Range<u32>>) -> Self {
+        self.io = Some(window);
+        self
+    }
+
+    pub fn memory_window(mut self, window: Option<Range<u32>>) -> Self {
+        self.memory = Some(window);
+        self
+    }
+
+    pub fn prefetchable_window(mut self, window: Option<Range<u64>>) -> Self {
+        self.prefetchable = Some(window);
+        self
+    }
+
+    pub fn bridge_control(mut self, control: u16) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Write order: bus numbers, I/O, memory, prefetchable, bridge control.
+    /// Each window is disabled while its upper half is inconsistent.
+    pub fn flush(self) {
+        if self.bridge.is_root {
+            return;
+        }
+        let b = self.bridge;
+
+        if let Some(bus) = self.bus {
+            // Keep the secondary latency timer
+            b.update(0x18, |mut data| {
+                data.set_bits(0..8, bus.primary.into());
+                data.set_bits(8..16, bus.secondary.into());
+                data.set_bits(16..24, bus.subordinate.into());
+                data
+            });
+        }
+
+        if let Some(io) = self.io {
+            // Upper base above upper limit disables the window meanwhile,
+            // the upper half of 0x1c is the RW1C secondary status.
+            b.write(0x30, 0x0000_ffff);
+            match io {
+                Some(r) => {
+                    debug_assert!(r.start.is_multiple_of(0x1000) && r.end.is_multiple_of(0x1000));
+                    let limit = r.end - 1;
+                    b.write(0x1c, (limit & 0xf000) | ((r.start >> 8) & 0xf0));
+                    b.write(0x30, (limit & 0xffff_0000) | (r.start >> 16));
+                }
+                None => {
+                    b.write(0x1c, 0x0000_00f0);
+                    b.write(0x30, 0);
+                }
+            }
+        }
+
+        if let Some(memory) = self.memory {
+            let data = match memory {
+                Some(r) => {
+                    debug_assert!(
+                        r.start.is_multiple_of(0x10_0000) && r.end.is_multiple_of(0x10_0000)
+                    );
+                    ((r.end - 1) & 0xfff0_0000) | (r.start >> 16)
+                }
+                None => 0x0000_fff0,
+            };
+            b.write(0x20, data);
+        }
+
+        if let Some(prefetchable) = self.prefetchable {
+            // Clearing the upper limit first disables a 64-bit window meanwhile
+            b.write(0x2c, 0);
+            match prefetchable {
+                Some(r) => {
+                    debug_assert!(
+                        r.start.is_multiple_of(0x10_0000) && r.end.is_multiple_of(0x10_0000)
+                    );
+                    let limit = r.end - 1;
+                    b.write(
+                        0x24,
+                        ((limit as u32) & 0xfff0_0000) | (((r.start as u32) >> 16) & 0xfff0),
+                    );
+                    b.write(0x28, (r.start >> 32) as u32);
+                    b.write(0x2c, (limit >> 32) as u32);
+                }
+                None => {
+                    b.write(0x24, 0x0000_fff0);
+                    b.write(0x28, 0);
+                }
+            }
+        }
+
+        if let Some(control) = self.control {
+            b.update(0x3c, |mut data| {
+                data.set_bits(16..32, control.into());
+                data
+            });
+        }
+    }
 }
 
 impl Deref for PciPciBridge {
@@ -98,6 +318,12 @@ impl Deref for PciPciBridge {
     }
 }
 
+impl DerefMut for PciPciBridge {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.base.as_mut().expect("Not a root bridge")
+    }
+}
+
 impl Debug for PciPciBridge {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("PciPciBridge")
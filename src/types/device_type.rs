@@ -0,0 +1,215 @@
+/// Kind of function by base and sub class, see the PCI Code and ID
+/// Assignment Specification. Owned by this crate like [`crate::PciAddress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DeviceType {
+    Unknown,
+    // Unclassified
+    LegacyNotVgaCompatible,
+    LegacyVgaCompatible,
+    // Mass storage
+    ScsiBusController,
+    IdeController,
+    FloppyController,
+    IpiBusController,
+    RaidController,
+    AtaController,
+    SataController,
+    SasController,
+    NvmeController,
+    UfsController,
+    OtherMassStorageController,
+    // Network
+    EthernetController,
+    TokenRingController,
+    FddiController,
+    AtmController,
+    IsdnController,
+    PicmgController,
+    InfinibandController,
+    FabricController,
+    OtherNetworkController,
+    // Display
+    VgaCompatibleController,
+    XgaController,
+    ThreeDController,
+    OtherDisplayController,
+    // Multimedia
+    VideoController,
+    AudioController,
+    TelephonyController,
+    HdAudioController,
+    OtherMultimediaController,
+    // Memory
+    RamController,
+    FlashController,
+    OtherMemoryController,
+    // Bridges
+    HostBridge,
+    IsaBridge,
+    EisaBridge,
+    McaBridge,
+    PciPciBridge,
+    PcmciaBridge,
+    NuBusBridge,
+    CardBusBridge,
+    RacewayBridge,
+    SemiTransparentPciPciBridge,
+    InfinibandPciHostBridge,
+    OtherBridgeDevice,
+    // Communication
+    SerialController,
+    ParallelPort,
+    MultiportSerialController,
+    Modem,
+    GpibController,
+    SmartCard,
+    OtherCommunicationController,
+    // System peripherals
+    InterruptController,
+    DmaController,
+    Timer,
+    RtcController,
+    PciHotPlugController,
+    SdHostController,
+    IommuController,
+    OtherSystemPeripheral,
+    // Input
+    KeyboardController,
+    Digitizer,
+    MouseController,
+    ScannerController,
+    GameportController,
+    OtherInputController,
+    DockingStation,
+    Processor,
+    // Serial bus
+    FirewireController,
+    AccessBusController,
+    SsaController,
+    UsbController,
+    FibreChannelController,
+    SmBusController,
+    IpmiController,
+    CanBusController,
+    OtherSerialBusController,
+    // Wireless
+    IrdaController,
+    BluetoothController,
+    WirelessLanController,
+    OtherWirelessController,
+    EncryptionController,
+    SignalProcessingController,
+    ProcessingAccelerator,
+    Coprocessor,
+}
+
+impl From<(u8, u8)> for DeviceType {
+    /// `(base_class, sub_class)`
+    fn from((base, sub): (u8, u8)) -> Self {
+        match (base, sub) {
+            (0x00, 0x00) => Self::LegacyNotVgaCompatible,
+            (0x00, 0x01) => Self::LegacyVgaCompatible,
+
+            (0x01, 0x00) => Self::ScsiBusController,
+            (0x01, 0x01) => Self::IdeController,
+            (0x01, 0x02) => Self::FloppyController,
+            (0x01, 0x03) => Self::IpiBusController,
+            (0x01, 0x04) => Self::RaidController,
+            (0x01, 0x05) => Self::AtaController,
+            (0x01, 0x06) => Self::SataController,
+            (0x01, 0x07) => Self::SasController,
+            (0x01, 0x08) => Self::NvmeController,
+            (0x01, 0x09) => Self::UfsController,
+            (0x01, 0x80) => Self::OtherMassStorageController,
+
+            (0x02, 0x00) => Self::EthernetController,
+            (0x02, 0x01) => Self::TokenRingController,
+            (0x02, 0x02) => Self::FddiController,
+            (0x02, 0x03) => Self::AtmController,
+            (0x02, 0x04) => Self::IsdnController,
+            (0x02, 0x06) => Self::PicmgController,
+            (0x02, 0x07) => Self::InfinibandController,
+            (0x02, 0x08) => Self::FabricController,
+            (0x02, 0x80) => Self::OtherNetworkController,
+
+            (0x03, 0x00) => Self::VgaCompatibleController,
+            (0x03, 0x01) => Self::XgaController,
+            (0x03, 0x02) => Self::ThreeDController,
+            (0x03, 0x80) => Self::OtherDisplayController,
+
+            (0x04, 0x00) => Self::VideoController,
+            (0x04, 0x01) => Self::AudioController,
+            (0x04, 0x02) => Self::TelephonyController,
+            (0x04, 0x03) => Self::HdAudioController,
+            (0x04, 0x80) => Self::OtherMultimediaController,
+
+            (0x05, 0x00) => Self::RamController,
+            (0x05, 0x01) => Self::FlashController,
+            (0x05, 0x80) => Self::OtherMemoryController,
+
+            (0x06, 0x00) => Self::HostBridge,
+            (0x06, 0x01) => Self::IsaBridge,
+            (0x06, 0x02) => Self::EisaBridge,
+            (0x06, 0x03) => Self::McaBridge,
+            (0x06, 0x04) => Self::PciPciBridge,
+            (0x06, 0x05) => Self::PcmciaBridge,
+            (0x06, 0x06) => Self::NuBusBridge,
+            (0x06, 0x07) => Self::CardBusBridge,
+            (0x06, 0x08) => Self::RacewayBridge,
+            (0x06, 0x09) => Self::SemiTransparentPciPciBridge,
+            (0x06, 0x0a) => Self::InfinibandPciHostBridge,
+            (0x06, 0x80) => Self::OtherBridgeDevice,
+
+            (0x07, 0x00) => Self::SerialController,
+            (0x07, 0x01) => Self::ParallelPort,
+            (0x07, 0x02) => Self::MultiportSerialController,
+            (0x07, 0x03) => Self::Modem,
+            (0x07, 0x04) => Self::GpibController,
+            (0x07, 0x05) => Self::SmartCard,
+            (0x07, 0x80) => Self::OtherCommunicationController,
+
+            (0x08, 0x00) => Self::InterruptController,
+            (0x08, 0x01) => Self::DmaController,
+            (0x08, 0x02) => Self::Timer,
+            (0x08, 0x03) => Self::RtcController,
+            (0x08, 0x04) => Self::PciHotPlugController,
+            (0x08, 0x05) => Self::SdHostController,
+            (0x08, 0x06) => Self::IommuController,
+            (0x08, 0x80) => Self::OtherSystemPeripheral,
+
+            (0x09, 0x00) => Self::KeyboardController,
+            (0x09, 0x01) => Self::Digitizer,
+            (0x09, 0x02) => Self::MouseController,
+            (0x09, 0x03) => Self::ScannerController,
+            (0x09, 0x04) => Self::GameportController,
+            (0x09, 0x80) => Self::OtherInputController,
+
+            (0x0a, _) => Self::DockingStation,
+            (0x0b, 0x40) => Self::Coprocessor,
+            (0x0b, _) => Self::Processor,
+
+            (0x0c, 0x00) => Self::FirewireController,
+            (0x0c, 0x01) => Self::AccessBusController,
+            (0x0c, 0x02) => Self::SsaController,
+            (0x0c, 0x03) => Self::UsbController,
+            (0x0c, 0x04) => Self::FibreChannelController,
+            (0x0c, 0x05) => Self::SmBusController,
+            (0x0c, 0x06) => Self::InfinibandController,
+            (0x0c, 0x07) => Self::IpmiController,
+            (0x0c, 0x09) => Self::CanBusController,
+            (0x0c, 0x80) => Self::OtherSerialBusController,
+
+            (0x0d, 0x00) => Self::IrdaController,
+            (0x0d, 0x11) => Self::BluetoothController,
+            (0x0d, 0x20 | 0x21) => Self::WirelessLanController,
+            (0x0d, 0x80) => Self::OtherWirelessController,
+
+            (0x10, _) => Self::EncryptionController,
+            (0x11, _) => Self::SignalProcessingController,
+            (0x12, _) => Self::ProcessingAccelerator,
+            (0x40, _) => Self::Coprocessor,
+            _ => Self::Unknown,
+        }
+    }
+}
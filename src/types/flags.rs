@@ -0,0 +1,140 @@
+//! Compact `MEM+ IO- BM+` renderings of the Command and Status registers,
+//! parseable back for golden files.
+
+use core::{fmt::Display, str::FromStr};
+
+use alloc::string::ToString;
+
+use crate::{err::Error, CommandRegister, PciHeaderBase};
+
+const COMMAND_BITS: [(u16, &str); 11] = [
+    (0, "IO"),
+    (1, "MEM"),
+    (2, "BM"),
+    (3, "SpecCycle"),
+    (4, "MemWINV"),
+    (5, "VGASnoop"),
+    (6, "ParErr"),
+    (7, "Stepping"),
+    (8, "SERR"),
+    (9, "FastB2B"),
+    (10, "DisINTx"),
+];
+
+const STATUS_BITS: [(u16, &str); 11] = [
+    (3, "INTx"),
+    (4, "Cap"),
+    (5, "66MHz"),
+    (6, "UDF"),
+    (7, "FastB2B"),
+    (8, "ParErr"),
+    (11, ">TAbort"),
+    (12, "<TAbort"),
+    (13, "<MAbort"),
+    (14, ">SERR"),
+    (15, "<PERR"),
+];
+
+const STATUS_DEVSEL_SHIFT: u16 = 9;
+const DEVSEL: [&str; 3] = ["fast", "medium", "slow"];
+
+/// Command register, rendered as `IO- MEM+ BM+ ... DisINTx-`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandFlags(pub u16);
+
+/// Status register, rendered as `INTx- Cap+ ... DEVSEL=fast ... <PERR-`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusFlags(pub u16);
+
+impl From<CommandRegister> for CommandFlags {
+    fn from(value: CommandRegister) -> Self {
+        Self(value.bits())
+    }
+}
+
+impl PciHeaderBase {
+    pub fn command_flags(&self) -> CommandFlags {
+        CommandFlags(self.command().bits())
+    }
+
+    pub fn status_flags(&self) -> StatusFlags {
+        StatusFlags(self.status().bits())
+    }
+}
+
+fn write_bits(
+    f: &mut core::fmt::Formatter<'_>,
+    value: u16,
+    bits: &[(u16, &str)],
+) -> core::fmt::Result {
+    for (i, (bit, name)) in bits.iter().enumerate() {
+        if i > 0 {
+            f.write_str(" ")?;
+        }
+        let sign = if value & (1 << bit) != 0 { '+' } else { '-' };
+        write!(f, "{name}{sign}")?;
+    }
+    Ok(())
+}
+
+/// Parse `NAME+`/`NAME-` tokens in any order, unmentioned bits stay clear.
+fn parse_token(token: &str, bits: &[(u16, &str)]) -> Result<u16, Error> {
+    let fail = || Error::ParseFail(token.to_string());
+    let (name, set) = match token.split_at_checked(token.len().saturating_sub(1)) {
+        Some((name, "+")) => (name, true),
+        Some((name, "-")) => (name, false),
+        _ => return Err(fail()),
+    };
+    let (bit, _) = bits.iter().find(|(_, n)| *n == name).ok_or_else(fail)?;
+    Ok(if set { 1 << bit } else { 0 })
+}
+
+impl Display for CommandFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_bits(f, self.0, &COMMAND_BITS)
+    }
+}
+
+impl FromStr for CommandFlags {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .try_fold(0, |value, token| {
+                Ok(value | parse_token(token, &COMMAND_BITS)?)
+            })
+            .map(Self)
+    }
+}
+
+impl Display for StatusFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (before, after) = STATUS_BITS.split_at(6);
+        write_bits(f, self.0, before)?;
+        let devsel = (self.0 >> STATUS_DEVSEL_SHIFT) & 0x3;
+        match DEVSEL.get(devsel as usize) {
+            Some(speed) => write!(f, " DEVSEL={speed} ")?,
+            None => f.write_str(" DEVSEL=?? ")?,
+        }
+        write_bits(f, self.0, after)
+    }
+}
+
+impl FromStr for StatusFlags {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .try_fold(0, |value, token| match token.strip_prefix("DEVSEL=") {
+                Some(speed) => {
+                    let devsel = DEVSEL
+                        .iter()
+                        .position(|s| *s == speed)
+                        .ok_or_else(|| Error::ParseFail(token.to_string()))?;
+                    Ok(value | (devsel as u16) << STATUS_DEVSEL_SHIFT)
+                }
+                None => Ok(value | parse_token(token, &STATUS_BITS)?),
+            })
+            .map(Self)
+    }
+}
@@ -0,0 +1,185 @@
+use bit_field::BitField;
+
+use crate::{CommandRegister, Endpoint, PciAddress, CAP_ID_MSI, CAP_ID_MSIX};
+
+/// 设备支持的中断机制汇总
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InterruptSummary {
+    /// INTx 引脚，1 = INTA ... 4 = INTD
+    pub intx_pin: Option<u8>,
+    pub msi: Option<MsiInfo>,
+    pub msix: Option<MsixInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MsiInfo {
+    pub offset: u16,
+    pub vectors: u8,
+    pub is_64bit: bool,
+    pub per_vector_mask: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MsixInfo {
+    pub offset: u16,
+    pub table_size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+impl Endpoint {
+    pub fn interrupt_summary(&self) -> InterruptSummary {
+        let pin = self.interrupt_pin();
+        InterruptSummary {
+            intx_pin: (1..=4).contains(&pin).then_some(pin),
+            msi: self.msi_info(),
+            msix: self.msix_info(),
+        }
+    }
+
+    pub fn msi_info(&self) -> Option<MsiInfo> {
+        let offset = self.find_capability(CAP_ID_MSI)?;
+        let control = self.read(offset).get_bits(16..32);
+        Some(MsiInfo {
+            offset,
+            vectors: 1 << control.get_bits(1..4).min(5),
+            is_64bit: control.get_bit(7),
+            per_vector_mask: control.get_bit(8),
+        })
+    }
+
+    pub fn msix_info(&self) -> Option<MsixInfo> {
+        let offset = self.find_capability(CAP_ID_MSIX)?;
+        let control = self.read(offset).get_bits(16..32);
+        let table = self.read(offset + 4);
+        let pba = self.read(offset + 8);
+        Some(MsixInfo {
+            offset,
+            table_size: control.get_bits(0..11) as u16 + 1,
+            table_bar: table.get_bits(0..3) as u8,
+            table_offset: table & !0x7,
+            pba_bar: pba.get_bits(0..3) as u8,
+            pba_offset: pba & !0x7,
+        })
+    }
+}
+
+/// Which interrupt mechanisms `configure_interrupts` may pick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptPolicy {
+    pub allow_msix: bool,
+    pub allow_msi: bool,
+    pub allow_intx: bool,
+    /// Upper bound of vectors enabled for MSI/MSI-X
+    pub max_vectors: u16,
+}
+
+impl Default for InterruptPolicy {
+    fn default() -> Self {
+        Self {
+            allow_msix: true,
+            allow_msi: true,
+            allow_intx: true,
+            max_vectors: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptMode {
+    MsiX { vectors: u16 },
+    Msi { vectors: u8 },
+    Intx { pin: u8 },
+    None,
+}
+
+/// Pick MSI-X > MSI > INTx according to `policy` and disable the other
+/// mechanisms. The chosen MSI or MSI-X capability is left disabled, the
+/// driver enables it once its messages are programmed, see
+/// [`Endpoint::msix`]; for MSI the Multiple Message Enable field is already
+/// set. Bus mastering is left to the driver as well.
+pub fn configure_interrupts(ep: &mut Endpoint, policy: &InterruptPolicy) -> InterruptMode {
+    let summary = ep.interrupt_summary();
+    let max_vectors = policy.max_vectors.max(1);
+
+    let mode = match (summary.msix, summary.msi, summary.intx_pin) {
+        (Some(msix), _, _) if policy.allow_msix => InterruptMode::MsiX {
+            vectors: msix.table_size.min(max_vectors),
+        },
+        (_, Some(msi), _) if policy.allow_msi => {
+            // MSI vector count must be a power of two
+            let limit = (msi.vectors as u16).min(max_vectors);
+            InterruptMode::Msi {
+                vectors: 1 << (15 - limit.leading_zeros()),
+            }
+        }
+        (_, _, Some(pin)) if policy.allow_intx => InterruptMode::Intx { pin },
+        _ => InterruptMode::None,
+    };
+
+    // Without messages programmed the device would write to address 0
+    if let Some(msix) = ep.msix() {
+        msix.set_enabled(false);
+    }
+    if let Some(msi) = summary.msi {
+        let vectors = match mode {
+            InterruptMode::Msi { vectors } => vectors,
+            _ => 0,
+        };
+        ep.set_msi_vectors(msi.offset, vectors);
+    }
+
+    ep.update_command(|mut cmd| {
+        cmd.set(
+            CommandRegister::INTERRUPT_DISABLE,
+            !matches!(mode, InterruptMode::Intx { .. }),
+        );
+        cmd
+    });
+
+    mode
+}
+
+impl Endpoint {
+    /// Turn off MSI and MSI-X, e.g. left on by firmware for a function kept
+    /// to INTx.
+    pub(crate) fn disable_msi(&self) {
+        if let Some(msi) = self.msi_info() {
+            self.set_msi_vectors(msi.offset, 0);
+        }
+        if let Some(msix) = self.msix() {
+            msix.set_enabled(false);
+        }
+    }
+
+    /// Disable MSI and set Multiple Message Enable to `vectors`, if any.
+    fn set_msi_vectors(&self, offset: u16, vectors: u8) {
+        self.update(offset, |mut data| {
+            data.set_bit(16, false);
+            if vectors > 0 {
+                data.set_bits(20..23, vectors.trailing_zeros());
+            }
+            data
+        });
+    }
+}
+
+/// Address/data pair of an MSI or MSI-X message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+/// Hands out host interrupt messages, implemented by the interrupt
+/// controller driver (GIC ITS, IOAPIC/LAPIC, ...).
+pub trait MsiAllocator {
+    fn alloc(&mut self, device: PciAddress) -> Option<MsiMessage>;
+
+    fn free(&mut self, device: PciAddress, message: MsiMessage);
+}
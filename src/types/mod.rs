@@ -1,15 +1,132 @@
+use alloc::string::ToString;
+use core::fmt::{Debug, Display};
+use core::str::FromStr;
+
+use crate::err::Error;
+
+mod address;
 mod bar;
+mod bist;
+mod capability;
+mod class;
 mod config;
+mod device_type;
+mod flags;
+mod interrupt;
+mod msix_pool;
+mod register;
+mod rom;
 
+pub use address::PciAddress;
 pub use bar::*;
+pub use capability::*;
+pub use class::*;
 pub use config::*;
-pub use pci_types::{
-    capability::PciCapability, device_type::DeviceType, CommandRegister, PciAddress, StatusRegister,
+pub use device_type::DeviceType;
+pub use flags::{CommandFlags, StatusFlags};
+pub use interrupt::*;
+pub use msix_pool::{IrqHandle, MsixVectorPool};
+pub use register::{
+    BridgeControl, CommandRegister, DevselTiming, HeaderType, SecondaryStatus, StatusRegister,
 };
+pub use rom::{RomCodeType, RomEfiInfo, RomImage, RomImages};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct BusNumber {
     pub primary: u8,
     pub secondary: u8,
     pub subordinate: u8,
 }
+
+/// `ssss:bb:dd.f` formatting of a [`PciAddress`], domain included. Parses
+/// back from the same form, or `bb:dd.f` for domain 0. The ARI form
+/// `[ssss:]bb:ff` with an 8-bit function is accepted too, function 0x21 is
+/// the same as `04.1`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bdf(pub PciAddress);
+
+impl From<PciAddress> for Bdf {
+    fn from(value: PciAddress) -> Self {
+        Self(value)
+    }
+}
+
+impl Display for Bdf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.0.segment(),
+            self.0.bus(),
+            self.0.device(),
+            self.0.function()
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Bdf {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{=u16:04x}:{=u8:02x}:{=u8:02x}.{=u8}",
+            self.0.segment(),
+            self.0.bus(),
+            self.0.device(),
+            self.0.function()
+        )
+    }
+}
+
+/// Serialized as its `ssss:bb:dd.f` string
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bdf {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Bdf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fail = || Error::ParseFail(s.to_string());
+        let hex = |field: &str, max: u16| {
+            u16::from_str_radix(field, 16)
+                .ok()
+                .filter(|v| *v <= max)
+                .ok_or_else(fail)
+        };
+        let (rest, function) = match s.rsplit_once('.') {
+            Some((rest, function)) => (rest, Some(function)),
+            None => (s, None),
+        };
+        let mut parts = rest.rsplitn(3, ':');
+        let last = parts.next().ok_or_else(fail)?;
+        let bus = parts.next().ok_or_else(fail)?;
+        let segment = parts.next().unwrap_or("0");
+
+        let (device, function) = match function {
+            Some(function) => (hex(last, 0x1f)? as u8, hex(function, 0x7)? as u8),
+            // ARI, device and function share the 8 bits
+            None => {
+                let function = hex(last, 0xff)? as u8;
+                (function >> 3, function & 0x7)
+            }
+        };
+        Ok(Self(PciAddress::new(
+            hex(segment, u16::MAX)?,
+            hex(bus, 0xff)? as u8,
+            device,
+            function,
+        )))
+    }
+}
+
+impl Debug for Bdf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,163 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{err::Error, BusAddressTranslator, MsiAllocator, MsiMessage, MsixTable, PciAddress};
+
+/// One vector handed out by [`MsixVectorPool::alloc`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandle {
+    vector: u16,
+    message: MsiMessage,
+}
+
+impl IrqHandle {
+    /// Index in the MSI-X table, what the device uses to raise it
+    pub fn vector(&self) -> u16 {
+        self.vector
+    }
+
+    /// Host message, as handed out by the [`MsiAllocator`] or given to
+    /// [`MsixVectorPool::migrate`]
+    pub fn message(&self) -> MsiMessage {
+        self.message
+    }
+}
+
+/// MSI-X vectors of one function, allocated and freed one at a time while
+/// the driver runs, e.g. a queue per CPU coming and going.
+///
+/// Entries not handed out stay masked. Entries are only ever rewritten
+/// masked, see [`MsixTable::update_message`].
+pub struct MsixVectorPool {
+    device: PciAddress,
+    table: MsixTable,
+    slots: Vec<Option<MsiMessage>>,
+    translator: Option<Arc<dyn BusAddressTranslator>>,
+}
+
+impl MsixVectorPool {
+    /// Masks every entry of `table`, the mapped table of `device`.
+    pub fn new(device: PciAddress, table: MsixTable) -> Self {
+        let len = table.len();
+        for vector in 0..len {
+            table.mask(vector);
+        }
+        Self {
+            device,
+            table,
+            slots: vec![None; len as usize],
+            translator: None,
+        }
+    }
+
+    /// Program doorbells as the bus addresses given by `translator`
+    /// instead of as is.
+    pub fn with_translator(mut self, translator: Arc<dyn BusAddressTranslator>) -> Self {
+        self.translator = Some(translator);
+        self
+    }
+
+    pub fn table(&self) -> &MsixTable {
+        &self.table
+    }
+
+    /// Vectors handed out
+    pub fn handles(&self) -> impl Iterator<Item = IrqHandle> + '_ {
+        self.slots.iter().enumerate().filter_map(|(vector, slot)| {
+            slot.map(|message| IrqHandle {
+                vector: vector as u16,
+                message,
+            })
+        })
+    }
+
+    pub fn free_vectors(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_none()).count()
+    }
+
+    /// Lowest free vector with a message from `allocator`, left masked.
+    pub fn alloc(
+        &mut self,
+        allocator: &mut (impl MsiAllocator + ?Sized),
+    ) -> Result<IrqHandle, Error> {
+        let device = self.device;
+        let no_space = || Error::NoVectorSpace { address: device };
+        let vector = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(no_space)? as u16;
+        let message = allocator.alloc(device).ok_or_else(no_space)?;
+        if let Err(e) = self.program(vector, message) {
+            allocator.free(self.device, message);
+            return Err(e);
+        }
+        self.slots[vector as usize] = Some(message);
+        Ok(IrqHandle { vector, message })
+    }
+
+    /// Mask the vector and give its message back to `allocator`.
+    pub fn free(
+        &mut self,
+        handle: IrqHandle,
+        allocator: &mut (impl MsiAllocator + ?Sized),
+    ) -> Result<(), Error> {
+        self.check(&handle)?;
+        self.table.mask(handle.vector);
+        self.slots[handle.vector as usize] = None;
+        allocator.free(self.device, handle.message);
+        Ok(())
+    }
+
+    /// Point `handle` at `message`, e.g. to move it to another CPU. The
+    /// entry is masked while it is rewritten, an interrupt raised meanwhile
+    /// is held in the PBA and sent to the new target.
+    ///
+    /// Returns the old message, which is the caller's to free now.
+    pub fn migrate(
+        &mut self,
+        handle: &mut IrqHandle,
+        message: MsiMessage,
+    ) -> Result<MsiMessage, Error> {
+        self.check(handle)?;
+        self.program(handle.vector, message)?;
+        self.slots[handle.vector as usize] = Some(message);
+        Ok(core::mem::replace(&mut handle.message, message))
+    }
+
+    pub fn mask(&self, handle: &IrqHandle) {
+        self.table.mask(handle.vector);
+    }
+
+    pub fn unmask(&self, handle: &IrqHandle) {
+        self.table.unmask(handle.vector);
+    }
+
+    /// Mask every vector and give all messages back.
+    pub fn release(&mut self, allocator: &mut (impl MsiAllocator + ?Sized)) {
+        for (vector, slot) in self.slots.iter_mut().enumerate() {
+            self.table.mask(vector as u16);
+            if let Some(message) = slot.take() {
+                allocator.free(self.device, message);
+            }
+        }
+    }
+
+    fn program(&self, vector: u16, message: MsiMessage) -> Result<(), Error> {
+        let doorbell = match &self.translator {
+            Some(t) => t.cpu_to_bus(message.address),
+            None => Some(message.address),
+        }
+        .ok_or(Error::NotTranslatable {
+            address: message.address,
+        })?;
+        self.table.update_message(vector, doorbell, message.data);
+        Ok(())
+    }
+
+    fn check(&self, handle: &IrqHandle) -> Result<(), Error> {
+        match self.slots.get(handle.vector as usize) {
+            Some(Some(message)) if *message == handle.message => Ok(()),
+            _ => Err(Error::InvalidArgument("stale IRQ handle")),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Crate owned config header registers, so the public API doesn't depend on
+//! the `pci_types` version used internally.
+
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+
+use bit_field::BitField;
+
+/// Command register, same flag names as `pci_types`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CommandRegister(u16);
+
+impl CommandRegister {
+    pub const IO_ENABLE: Self = Self(1 << 0);
+    pub const MEMORY_ENABLE: Self = Self(1 << 1);
+    pub const BUS_MASTER_ENABLE: Self = Self(1 << 2);
+    pub const SPECIAL_CYCLE_ENABLE: Self = Self(1 << 3);
+    pub const MEMORY_WRITE_AND_INVALIDATE: Self = Self(1 << 4);
+    pub const VGA_PALETTE_SNOOP: Self = Self(1 << 5);
+    pub const PARITY_ERROR_RESPONSE: Self = Self(1 << 6);
+    pub const IDSEL_STEP_WAIT_CYCLE_CONTROL: Self = Self(1 << 7);
+    pub const SERR_ENABLE: Self = Self(1 << 8);
+    pub const FAST_BACK_TO_BACK_ENABLE: Self = Self(1 << 9);
+    pub const INTERRUPT_DISABLE: Self = Self(1 << 10);
+
+    const ALL: u16 = 0x07ff;
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub const fn from_bits_retain(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    /// Reserved bits are dropped
+    pub const fn from_bits_truncate(bits: u16) -> Self {
+        Self(bits & Self::ALL)
+    }
+
+    pub const fn all() -> Self {
+        Self(Self::ALL)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(&self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+}
+
+impl BitOr for CommandRegister {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for CommandRegister {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for CommandRegister {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for CommandRegister {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+
+/// Complement within the defined flags, like `bitflags`
+impl Not for CommandRegister {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0 & Self::ALL)
+    }
+}
+
+impl From<pci_types::CommandRegister> for CommandRegister {
+    fn from(value: pci_types::CommandRegister) -> Self {
+        Self(value.bits())
+    }
+}
+
+impl From<CommandRegister> for pci_types::CommandRegister {
+    fn from(value: CommandRegister) -> Self {
+        pci_types::CommandRegister::from_bits_retain(value.0)
+    }
+}
+
+/// Speed of DEVSEL# assertion on conventional PCI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevselTiming {
+    Fast,
+    Medium,
+    Slow,
+    Reserved,
+}
+
+/// Status register. The error bits are RW1C, clear them with
+/// [`crate::PciHeaderBase::update_status_clear`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StatusRegister(u16);
+
+impl StatusRegister {
+    pub const fn new(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn interrupt_status(&self) -> bool {
+        self.0.get_bit(3)
+    }
+
+    pub fn has_capability_list(&self) -> bool {
+        self.0.get_bit(4)
+    }
+
+    pub fn capable_66mhz(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn fast_back_to_back_capable(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn master_data_parity_error(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn devsel_timing(&self) -> DevselTiming {
+        match self.0.get_bits(9..11) {
+            0 => DevselTiming::Fast,
+            1 => DevselTiming::Medium,
+            2 => DevselTiming::Slow,
+            _ => DevselTiming::Reserved,
+        }
+    }
+
+    pub fn signaled_target_abort(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn received_target_abort(&self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    pub fn received_master_abort(&self) -> bool {
+        self.0.get_bit(13)
+    }
+
+    pub fn signaled_system_error(&self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    pub fn detected_parity_error(&self) -> bool {
+        self.0.get_bit(15)
+    }
+}
+
+/// Secondary Status of a PCI-PCI bridge, errors seen on its secondary bus.
+/// RW1C like the Status register, clear with
+/// [`crate::PciPciBridge::clear_secondary_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SecondaryStatus(u16);
+
+impl SecondaryStatus {
+    pub const fn new(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn capable_66mhz(&self) -> bool {
+        self.0.get_bit(5)
+    }
+
+    pub fn fast_back_to_back_capable(&self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    pub fn master_data_parity_error(&self) -> bool {
+        self.0.get_bit(8)
+    }
+
+    pub fn devsel_timing(&self) -> DevselTiming {
+        StatusRegister(self.0).devsel_timing()
+    }
+
+    pub fn signaled_target_abort(&self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    pub fn received_target_abort(&self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    pub fn received_master_abort(&self) -> bool {
+        self.0.get_bit(13)
+    }
+
+    /// SERR# asserted on the secondary bus, where the primary Status has
+    /// Signaled System Error
+    pub fn received_system_error(&self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    pub fn detected_parity_error(&self) -> bool {
+        self.0.get_bit(15)
+    }
+}
+
+/// Bridge Control register of a PCI-PCI bridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BridgeControl(u16);
+
+impl BridgeControl {
+    pub const PARITY_ERROR_RESPONSE: Self = Self(1 << 0);
+    /// Forward SERR# from the secondary bus
+    pub const SERR_ENABLE: Self = Self(1 << 1);
+    /// Don't forward the upper 768 bytes of each 1KiB of the I/O window
+    pub const ISA_ENABLE: Self = Self(1 << 2);
+    /// Forward legacy VGA memory and I/O ranges
+    pub const VGA_ENABLE: Self = Self(1 << 3);
+    pub const VGA_16BIT_DECODE: Self = Self(1 << 4);
+    pub const MASTER_ABORT_MODE: Self = Self(1 << 5);
+    pub const SECONDARY_BUS_RESET: Self = Self(1 << 6);
+    pub const FAST_BACK_TO_BACK_ENABLE: Self = Self(1 << 7);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub const fn from_bits_retain(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+}
+
+impl BitOr for BridgeControl {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for BridgeControl {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Layout of the config header, from the Header Type register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderType {
+    Endpoint,
+    PciPciBridge,
+    CardBusBridge,
+    Unknown(u8),
+}
+
+impl From<u8> for HeaderType {
+    /// The multi-function bit is ignored
+    fn from(value: u8) -> Self {
+        match value & 0x7f {
+            0x00 => Self::Endpoint,
+            0x01 => Self::PciPciBridge,
+            0x02 => Self::CardBusBridge,
+            v => Self::Unknown(v),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Expansion ROM contents: images with their PCI Data Structures.
+
+use alloc::vec::Vec;
+
+use crate::{err::Error, CommandRegister, Endpoint, MmioMapper};
+
+const ROM_BAR: u16 = 0x30;
+const ROM_ADDRESS_MASK: u32 = 0xffff_f800;
+const ROM_ENABLE: u32 = 1;
+const ROM_SIGNATURE: u16 = 0xaa55;
+const EFI_SIGNATURE: u32 = 0x0ef1;
+/// Image Length and the EFI header are counted in these
+const ROM_BLOCK: usize = 512;
+
+/// Code Type of a PCI Data Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomCodeType {
+    /// Intel x86, PC-AT compatible
+    X86,
+    OpenFirmware,
+    PaRisc,
+    Efi,
+    Unknown(u8),
+}
+
+impl From<u8> for RomCodeType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => Self::X86,
+            0x01 => Self::OpenFirmware,
+            0x02 => Self::PaRisc,
+            0x03 => Self::Efi,
+            v => Self::Unknown(v),
+        }
+    }
+}
+
+/// Header fields of an EFI image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomEfiInfo {
+    /// PE subsystem, 0x0b boot service driver, 0x0c runtime driver
+    pub subsystem: u16,
+    /// PE machine type, e.g. 0x8664 x64, 0xaa64 AArch64
+    pub machine_type: u16,
+    pub compressed: bool,
+}
+
+/// One image of an expansion ROM
+#[derive(Debug, Clone)]
+pub struct RomImage {
+    /// Offset in the ROM
+    pub offset: usize,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    /// `[base_class, sub_class, interface]`
+    pub class: [u8; 3],
+    pub code_revision: u16,
+    pub code_type: RomCodeType,
+    pub efi: Option<RomEfiInfo>,
+    /// The whole image, Image Length bytes from `offset`
+    pub data: Vec<u8>,
+}
+
+/// Images of an expansion ROM in ROM order, see [`Endpoint::read_rom`].
+#[derive(Debug, Clone, Default)]
+pub struct RomImages {
+    images: Vec<RomImage>,
+}
+
+impl RomImages {
+    /// Walk the images of a ROM copy up to the one marked last. Fails if
+    /// the first image has no valid header, stops quietly at a broken later
+    /// one.
+    pub fn parse(rom: &[u8]) -> Result<Self, Error> {
+        let mut images = Vec::new();
+        let mut offset = 0;
+        while let Some((image, last)) = parse_image(rom, offset) {
+            offset += image.data.len();
+            images.push(image);
+            if last {
+                break;
+            }
+        }
+        if images.is_empty() {
+            return Err(Error::InvalidArgument("no valid expansion ROM image"));
+        }
+        Ok(Self { images })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RomImage> {
+        self.images.iter()
+    }
+
+    /// First image of `code_type`
+    pub fn find(&self, code_type: RomCodeType) -> Option<&RomImage> {
+        self.images.iter().find(|i| i.code_type == code_type)
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+}
+
+fn u16_at(rom: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        rom.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(rom: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        rom.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Image at `offset` and whether it is the last one
+fn parse_image(rom: &[u8], offset: usize) -> Option<(RomImage, bool)> {
+    if u16_at(rom, offset)? != ROM_SIGNATURE {
+        return None;
+    }
+    let pcir = offset + u16_at(rom, offset + 0x18)? as usize;
+    if rom.get(pcir..pcir + 4)? != b"PCIR" {
+        return None;
+    }
+    let len = u16_at(rom, pcir + 0x10)? as usize * ROM_BLOCK;
+    let data = rom.get(offset..offset + len).filter(|d| !d.is_empty())?;
+    let code_type = RomCodeType::from(*rom.get(pcir + 0x14)?);
+    let efi = match code_type {
+        RomCodeType::Efi if u32_at(rom, offset + 0x04)? == EFI_SIGNATURE => Some(RomEfiInfo {
+            subsystem: u16_at(rom, offset + 0x08)?,
+            machine_type: u16_at(rom, offset + 0x0a)?,
+            compressed: u16_at(rom, offset + 0x0c)? != 0,
+        }),
+        _ => None,
+    };
+    let class = rom.get(pcir + 0x0d..pcir + 0x10)?;
+    let image = RomImage {
+        offset,
+        vendor_id: u16_at(rom, pcir + 0x04)?,
+        device_id: u16_at(rom, pcir + 0x06)?,
+        class: [class[2], class[1], class[0]],
+        code_revision: u16_at(rom, pcir + 0x12)?,
+        code_type,
+        efi,
+        data: data.to_vec(),
+    };
+    // Last Image indicator
+    let last = rom.get(pcir + 0x15)? & 0x80 != 0;
+    Some((image, last))
+}
+
+impl Endpoint {
+    /// Size of the Expansion ROM BAR, `None` without ROM. Writes the BAR,
+    /// call it before memory decode is enabled.
+    pub fn rom_size(&self) -> Option<u32> {
+        let old = self.read(ROM_BAR);
+        self.write(ROM_BAR, ROM_ADDRESS_MASK);
+        let mask = self.read(ROM_BAR) & ROM_ADDRESS_MASK;
+        self.write(ROM_BAR, old);
+        (mask != 0).then(|| !mask + 1)
+    }
+
+    /// Copy the expansion ROM through `mapper` and parse its images. The
+    /// ROM BAR must be assigned; ROM decode, and memory decode if it was
+    /// off, are enabled only for the copy.
+    ///
+    /// Devices may share a decoder between the ROM and other BARs, keep the
+    /// driver away from the device meanwhile.
+    pub fn read_rom(&self, mapper: &(impl MmioMapper + ?Sized)) -> Result<RomImages, Error> {
+        let size = self
+            .rom_size()
+            .ok_or(Error::NotSupported("no expansion ROM"))?;
+        let old = self.read(ROM_BAR);
+        let address = old & ROM_ADDRESS_MASK;
+        if address == 0 {
+            return Err(Error::InvalidArgument("expansion ROM not assigned"));
+        }
+        let command = self.update_command(|mut cmd| {
+            cmd.insert(CommandRegister::MEMORY_ENABLE);
+            cmd
+        });
+        self.write(ROM_BAR, address | ROM_ENABLE);
+
+        let base = mapper.map(address as u64, size as usize).cast::<u32>();
+        let mut rom = Vec::with_capacity(size as usize);
+        for i in 0..size as usize / 4 {
+            let dword = unsafe { base.add(i).read_volatile() };
+            // In memory order, the image is a byte stream
+            rom.extend_from_slice(&dword.to_ne_bytes());
+        }
+
+        self.write(ROM_BAR, old & !ROM_ENABLE);
+        self.update_command(|_| command);
+        RomImages::parse(&rom)
+    }
+}
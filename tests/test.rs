@@ -15,7 +15,12 @@ mod tests {
     };
     use log::info;
     use pcie::{
-        enumerate_by_controller, CommandRegister, PciMem32, PciMem64, PcieController, PcieGeneric,
+        enumerate_by_controller,
+        fixture::{assert_golden, nvme, Fixture},
+        passthrough::ShadowConfig,
+        BarKind, Bdf, CommandRegister, Controller, DeviceOverride, MockController, MockFunction,
+        PciAddress, PciMem32, PciMem64, PcieController, PcieGeneric, PortControl, QemuVirt,
+        ResourcePolicy, RootComplex,
     };
 
     #[test]
@@ -99,4 +104,2424 @@ mod tests {
 
         println!("test passed!");
     }
+
+    #[test]
+    fn test_mock_bar64_above_4g() {
+        let chip = MockController::new().with_function(0, 0, 0, nvme().with_bar64(0, 0x4000, true));
+        let mut drv = PcieController::new(chip);
+        drv.set_mem64(
+            PciMem64 {
+                address: 0x80_0000_0000,
+                size: 0x10_0000_0000,
+            },
+            true,
+        );
+
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let bar = ep.bar_range(0).unwrap();
+        assert!(bar.start >= 0x80_0000_0000);
+        assert_eq!(bar.end - bar.start, 0x4000);
+        // Must not be truncated to the low 32 bits on any target
+        match usize::try_from(bar.end) {
+            Ok(end) => assert_eq!(ep.bar(0), Some(bar.start as usize..end)),
+            Err(_) => assert!(ep.bar(0).is_none()),
+        }
+        let debug = alloc::format!("{:?}", ep.bars());
+        assert!(debug.contains(&alloc::format!("address: {:#x},", bar.start)));
+    }
+
+    #[test]
+    fn test_nvme_registers() {
+        use core::ptr::NonNull;
+
+        let chip = MockController::new().with_function(0, 0, 0, nvme().with_bar64(0, 0x4000, true));
+        let mut drv = PcieController::new(chip);
+        drv.set_mem64(
+            PciMem64 {
+                address: 0x80_0000_0000,
+                size: 0x10_0000_0000,
+            },
+            true,
+        );
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+
+        // CAP: MQES 1023, TO 20 (10s), DSTRD 1 (8 byte doorbells); VS 1.4.0
+        let mut regs = alloc::vec![0u32; 0x4000 / 4];
+        regs[0] = 0x1400_03ff;
+        regs[1] = 0x1;
+        regs[2] = 0x0001_0400;
+        let window = NonNull::new(regs.as_mut_ptr().cast::<u8>()).unwrap();
+        let nvme = ep.nvme_registers(&|_, _| window).unwrap();
+        assert_eq!(nvme.max_queue_entries(), 1024);
+        assert_eq!(nvme.timeout_ms(), 10_000);
+        assert_eq!(nvme.doorbell_stride(), 8);
+        assert_eq!(nvme.version(), (1, 4, 0));
+        assert_eq!(
+            nvme.cq_doorbell(1).unwrap().as_ptr() as usize - window.as_ptr() as usize,
+            0x1018
+        );
+        assert!(nvme.sq_doorbell(0x1000).is_none());
+    }
+
+    #[test]
+    fn test_class_bars() {
+        let ahci =
+            MockFunction::endpoint(0x8086, 0x2922, [0x01, 0x06, 0x01]).with_bar32(5, 0x1000, false);
+        // xHCI with a 32-bit BAR0, which the spec doesn't allow
+        let xhci =
+            MockFunction::endpoint(0x1b36, 0x000d, [0x0c, 0x03, 0x30]).with_bar32(0, 0x4000, false);
+        let chip = MockController::new()
+            .with_function(0, 1, 0, ahci)
+            .with_function(0, 2, 0, xhci);
+        let mut drv = PcieController::new(chip);
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let eps: alloc::vec::Vec<_> = enumerate_by_controller(&mut drv, None).collect();
+
+        let abar = eps[0].ahci_bar().unwrap();
+        assert_eq!((abar.index, abar.size), (5, 0x1000));
+        assert!(eps[0].xhci_bar().is_err());
+        assert!(eps[1].xhci_bar().is_err());
+    }
+
+    /// INTA, 4 MSI vectors with 64-bit addresses and 4 MSI-X vectors, both
+    /// left enabled by firmware
+    fn interrupt_nic() -> MockFunction {
+        MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_register(0x3c, 0x0100, 0xff)
+            .with_capability(pcie::CAP_ID_MSI, &[(1 | 2 << 1 | 1 << 7) << 16, 0, 0, 0])
+            .with_capability(pcie::CAP_ID_MSIX, &[(1 << 15 | 3) << 16, 0x2000, 0x3001])
+    }
+
+    #[test]
+    fn test_interrupt_summary() {
+        use pcie::{MsiInfo, MsixInfo};
+
+        let chip = MockController::new().with_function(0, 0, 0, interrupt_nic());
+        let mut drv = PcieController::new(chip);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let summary = ep.interrupt_summary();
+        assert_eq!(summary.intx_pin, Some(1));
+        assert_eq!(
+            summary.msi,
+            Some(MsiInfo {
+                offset: 0x40,
+                vectors: 4,
+                is_64bit: true,
+                per_vector_mask: false,
+            })
+        );
+        assert_eq!(
+            summary.msix,
+            Some(MsixInfo {
+                offset: 0x50,
+                table_size: 4,
+                table_bar: 0,
+                table_offset: 0x2000,
+                pba_bar: 1,
+                pba_offset: 0x3000,
+            })
+        );
+    }
+
+    #[test]
+    fn test_configure_interrupts() {
+        use pcie::{configure_interrupts, InterruptMode, InterruptPolicy};
+
+        let chip = MockController::new().with_function(0, 0, 0, interrupt_nic());
+        let mut drv = PcieController::new(chip);
+        let mut ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let msi_control = |ep: &pcie::Endpoint| ep.read(0x40) >> 16;
+        let msix_enabled = |ep: &pcie::Endpoint| ep.read(0x50) & 1 << 31 != 0;
+
+        let mode = configure_interrupts(&mut ep, &InterruptPolicy::default());
+        assert_eq!(mode, InterruptMode::MsiX { vectors: 1 });
+        // Selected, but enabled by the driver once the table is programmed
+        assert!(!msix_enabled(&ep));
+        assert_eq!(msi_control(&ep) & 1, 0);
+        assert!(ep.command().contains(CommandRegister::INTERRUPT_DISABLE));
+        assert!(!ep.command().contains(CommandRegister::BUS_MASTER_ENABLE));
+
+        let policy = InterruptPolicy {
+            allow_msix: false,
+            max_vectors: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            configure_interrupts(&mut ep, &policy),
+            InterruptMode::Msi { vectors: 2 }
+        );
+        // Multiple Message Enable 2 vectors, MSI itself still off
+        assert_eq!(msi_control(&ep) & 0x71, 1 << 4);
+
+        let policy = InterruptPolicy {
+            allow_msix: false,
+            allow_msi: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            configure_interrupts(&mut ep, &policy),
+            InterruptMode::Intx { pin: 1 }
+        );
+        assert!(!ep.command().contains(CommandRegister::INTERRUPT_DISABLE));
+        assert!(!ep.command().contains(CommandRegister::BUS_MASTER_ENABLE));
+    }
+
+    #[test]
+    fn test_capability_list_loop() {
+        // PM at 0x40 and MSI at 0x48, whose next pointer leads back to 0x40
+        let f = nvme()
+            .with_capability(0x01, &[0, 0])
+            .with_capability(0x05, &[0, 0])
+            .with_register(0x48, 0x4005, 0);
+        let mut drv = PcieController::new(MockController::new().with_function(0, 0, 0, f));
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let caps: alloc::vec::Vec<_> = ep.walk_capabilities().collect();
+        assert_eq!(caps, [(0x01, 0x40), (0x05, 0x48)]);
+        assert_eq!(ep.find_capability(0x10), None);
+    }
+
+    #[test]
+    fn test_qemu_virt_highmem_windows() {
+        let qemu = QemuVirt { highmem: true };
+        assert_eq!(qemu.ecam(), (0x40_1000_0000, 0x1000_0000));
+        assert_eq!(qemu.buses(), 0..=255);
+
+        let chip = MockController::new()
+            .with_function(0, 1, 0, nvme().with_bar32(0, 0x4000, false))
+            .with_function(0, 2, 0, nvme().with_bar64(0, 0x10_0000, false));
+        let mut root = RootComplex::new(PcieController::new(chip)).with_windows(&qemu.windows());
+
+        let eps: alloc::vec::Vec<_> = root.enumerate(None).collect();
+        assert_eq!(eps.len(), 2);
+        let low = eps[0].bar_range(0).unwrap();
+        assert!(low.start >= 0x1000_0000 && low.end <= 0x3eff_0000);
+        let high = eps[1].bar_range(0).unwrap();
+        assert!(high.start >= 0x80_0000_0000);
+        assert_eq!(high.end - high.start, 0x10_0000);
+    }
+
+    #[test]
+    fn test_mock_legacy_function_config_size() {
+        let nic = || MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00]);
+        let chip = MockController::new()
+            .with_function(0, 0, 0, nic().with_aer())
+            .with_function(0, 1, 0, nic().with_pcie(0).with_aer());
+        let mut drv = PcieController::new(chip);
+
+        let eps: alloc::vec::Vec<_> = enumerate_by_controller(&mut drv, None).collect();
+        assert_eq!(eps[0].config_space_size(), 0x100);
+        assert_eq!(eps[0].extended_capabilities().count(), 0);
+        assert_eq!(eps[0].read(0x100), u32::MAX);
+        assert_eq!(eps[1].config_space_size(), 0x1000);
+        assert_eq!(eps[1].extended_capabilities().count(), 1);
+    }
+
+    #[test]
+    fn test_mock_bar_info_mixed_kinds() {
+        let chip = MockController::new().with_function(
+            0,
+            0,
+            0,
+            MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00])
+                .with_bar32(0, 0x2_0000, false)
+                .with_bar64(1, 0x10_0000, true)
+                .with_io_bar(3, 0x40),
+        );
+        let mut drv = PcieController::new(chip);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+
+        let bars: alloc::vec::Vec<_> = ep
+            .bar_info()
+            .map(|b| (b.index, b.kind, b.size, b.prefetchable))
+            .collect();
+        assert_eq!(
+            bars,
+            [
+                (0, BarKind::Mem32, 0x2_0000, false),
+                (1, BarKind::Mem64, 0x10_0000, true),
+                (3, BarKind::Io, 0x40, false),
+            ]
+        );
+
+        let mem64 = ep.bar_range(1).unwrap();
+        assert_eq!(mem64.end - mem64.start, 0x10_0000);
+        for index in [2, 3, 4, 6] {
+            assert_eq!(ep.bar_range(index), None);
+            assert_eq!(ep.bar(index), None);
+        }
+    }
+
+    #[test]
+    fn test_set_bar() {
+        // Kinds mixed: 32-bit, 64-bit over BAR1-2, I/O, 32-bit
+        let chip = MockController::new().with_function(
+            0,
+            0,
+            0,
+            MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00])
+                .with_bar32(0, 0x2_0000, false)
+                .with_bar64(1, 0x10_0000, true)
+                .with_io_bar(3, 0x40)
+                .with_bar32(4, 0x1000, false),
+        );
+        let mut drv = PcieController::new(chip);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        ep.update_command(|mut cmd| {
+            cmd.remove(CommandRegister::MEMORY_ENABLE | CommandRegister::IO_ENABLE);
+            cmd
+        });
+        let io = ep.read(0x1c);
+
+        unsafe { ep.set_bar(0, 0x4000_0000) }.unwrap();
+        assert_eq!(ep.bar_range(0), Some(0x4000_0000..0x4002_0000));
+        assert!(unsafe { ep.set_bar(0, 0x4000_1000) }.is_err());
+        assert!(unsafe { ep.set_bar(0, 0x1_0000_0000) }.is_err());
+
+        // The 64-bit BAR moves above 4GiB while BAR0 is 32-bit
+        unsafe { ep.set_bar(1, 0x10_0000_0000) }.unwrap();
+        assert_eq!(ep.bar_address(1), Some(0x10_0000_0000));
+        unsafe { ep.set_bar(4, 0x5000_0000) }.unwrap();
+        assert_eq!(ep.bar_address(4), Some(0x5000_0000));
+        assert_eq!(ep.bar_address(1), Some(0x10_0000_0000));
+        assert_eq!(ep.read(0x1c), io);
+
+        // Upper half, I/O, unimplemented and out of range
+        for index in [2, 3, 5, 6] {
+            assert!(unsafe { ep.set_bar(index, 0x6000_0000) }.is_err());
+        }
+        assert_eq!(ep.read(0x1c), io);
+    }
+
+    #[test]
+    fn test_sriov_vf_bars_page_aligned() {
+        use pcie::EXT_CAP_ID_SRIOV;
+
+        // 4 VFs at routing ID offset 0x80 with stride 2, 4KiB to 4MiB pages
+        let mut regs = [0; 15];
+        regs[2] = 4 << 16 | 4;
+        regs[4] = 2 << 16 | 0x80;
+        regs[6] = 0x553;
+        regs[7] = 1;
+        let pf = MockFunction::endpoint(0x8086, 0x10fb, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_extended_capability(EXT_CAP_ID_SRIOV, 1, &regs);
+        let sriov = pf.extended_capability(EXT_CAP_ID_SRIOV).unwrap();
+        // VF BAR0 16KiB, VF BAR2 128KiB 64-bit prefetchable
+        let pf = pf
+            .with_register(sriov + 0x24, 0, 0xffff_c000)
+            .with_register(sriov + 0x2c, 0xc, 0xfffe_0000)
+            .with_register(sriov + 0x30, 0, u32::MAX);
+        let mut drv = PcieController::new(Fixture::new().endpoint(0, 0, pf).controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        drv.set_mem64(
+            PciMem64 {
+                address: 0x8_0000_0000,
+                size: 0x1_0000_0000,
+            },
+            true,
+        );
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let allocator = drv.bar_allocator.as_mut().unwrap();
+        let cap = ep.sriov().unwrap();
+
+        assert!(cap.enable(5, 0x1_0000, allocator).is_err());
+        assert!(cap.enable(3, 0x4000, allocator).is_err());
+        let vfs = cap.enable(3, 0x1_0000, allocator).unwrap();
+        assert!(cap.is_enabled());
+        assert_eq!(ep.read(sriov + 0x20), 1 << 4);
+
+        // The 16KiB BAR takes a 64KiB page per VF, the 128KiB one its size
+        let bar0 = vfs.bars[0].unwrap();
+        assert_eq!(bar0.stride, 0x1_0000);
+        assert_eq!(bar0.base % 0x1_0000, 0);
+        assert_eq!(ep.read(sriov + 0x24), bar0.base as u32);
+        let bar2 = vfs.bars[2].unwrap();
+        assert_eq!(bar2.stride, 0x2_0000);
+        assert!(bar2.is_64bit && bar2.prefetchable);
+        assert!(bar2.base >= 0x8_0000_0000);
+        assert!(vfs.bars[1].is_none() && vfs.bars[3].is_none());
+
+        assert_eq!(
+            vfs.vf_bar(1, 0),
+            Some(bar0.base + 0x1_0000..bar0.base + 0x2_0000)
+        );
+        assert_eq!(vfs.vf_bar(3, 0), None);
+        assert_eq!(cap.vf_address(1), PciAddress::new(0, 0, 0x10, 2));
+    }
+
+    #[test]
+    fn test_sriov_enable_failure_cleans_up() {
+        use pcie::EXT_CAP_ID_SRIOV;
+
+        let mut regs = [0; 15];
+        regs[2] = 4 << 16 | 4;
+        regs[4] = 2 << 16 | 0x80;
+        regs[6] = 0x553;
+        regs[7] = 1;
+        let pf = MockFunction::endpoint(0x8086, 0x10fb, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_extended_capability(EXT_CAP_ID_SRIOV, 1, &regs);
+        let sriov = pf.extended_capability(EXT_CAP_ID_SRIOV).unwrap();
+        // VF BAR0 fits the 32-bit window, VF BAR2 finds no space
+        let pf = pf
+            .with_register(sriov + 0x24, 0, 0xffff_c000)
+            .with_register(sriov + 0x2c, 0xc, 0xfffe_0000)
+            .with_register(sriov + 0x30, 0, u32::MAX);
+        let mut drv = PcieController::new(Fixture::new().endpoint(0, 0, pf).controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x4_0000,
+            },
+            false,
+        );
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let allocator = drv.bar_allocator.as_mut().unwrap();
+        let cap = ep.sriov().unwrap();
+
+        assert!(matches!(
+            cap.enable(3, 0x1_0000, allocator),
+            Err(pcie::err::Error::NoBarSpace { .. })
+        ));
+        assert!(!cap.is_enabled());
+        assert_eq!(ep.read(sriov + 0x10) & 0xffff, 0);
+        assert_eq!(ep.read(sriov + 0x24), 0);
+        assert_eq!(ep.read(sriov + 0x2c) & !0xf, 0);
+        assert_eq!(ep.read(sriov + 0x30), 0);
+    }
+
+    #[test]
+    fn test_alloc_contiguous() {
+        use pcie::BarAllocatorExt;
+
+        let mut drv = PcieController::new(MockController::new());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x100_0000,
+            },
+            false,
+        );
+        let allocator = drv.bar_allocator.as_mut().unwrap();
+
+        // Three 16KiB blocks, rounded up to one aligned 64KiB block
+        let mut alloc = || allocator.alloc_contiguous(0x4000, 3, 0x1_0000, BarKind::Mem32, false);
+        let first = alloc().unwrap();
+        let second = alloc().unwrap();
+        assert_eq!(first % 0x1_0000, 0);
+        assert_eq!(second % 0x1_0000, 0);
+        assert!(second >= first + 0x1_0000 || second + 0x1_0000 <= first);
+
+        assert_eq!(
+            allocator.alloc_contiguous(0x1000, 2, 0x1000, BarKind::Io, false),
+            None
+        );
+        assert_eq!(
+            allocator.alloc_contiguous(0x1000, 0, 0x1000, BarKind::Mem32, false),
+            None
+        );
+        assert_eq!(
+            allocator.alloc_contiguous(0x1000, 2, 0x3000, BarKind::Mem32, false),
+            None
+        );
+        // More than the window
+        assert_eq!(
+            allocator.alloc_contiguous(0x100_0000, 2, 0x1000, BarKind::Mem32, false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_mock_nested_bridge_bus_numbers() {
+        // 00:00.0 -> bus 1: 01:00.0 -> bus 2: endpoint
+        // 00:01.0 -> bus 3: endpoint
+        let chip = MockController::new()
+            .with_function(0, 0, 0, MockFunction::bridge(0x1b36, 0x000c))
+            .with_function(0, 1, 0, MockFunction::bridge(0x1b36, 0x000c))
+            .with_function(1, 0, 0, MockFunction::bridge(0x1b36, 0x000c))
+            .with_function(2, 0, 0, nvme())
+            .with_function(3, 0, 0, nvme());
+        let mut root = RootComplex::new(PcieController::new(chip));
+        assert_eq!(root.enumerate(None).count(), 2);
+
+        let mut bus_number = |bus, device| {
+            let data = root.read_config(PciAddress::new(0, bus, device, 0), 0x18);
+            ((data >> 8) as u8, (data >> 16) as u8)
+        };
+        assert_eq!(bus_number(0, 0), (1, 2));
+        assert_eq!(bus_number(1, 0), (2, 2));
+        assert_eq!(bus_number(0, 1), (3, 3));
+    }
+
+    #[test]
+    fn test_fixture_golden() {
+        let fixture = Fixture::new()
+            .bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, nvme()).endpoint(0, 1, nvme()),
+            )
+            .endpoint(2, 0, nvme());
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()));
+        let topology = root.scan();
+        assert_golden(
+            &topology,
+            "
+            0000:00:00.0 1b36:000c bridge 01-01
+            0000:01:00.0 1b36:0010
+            0000:01:00.1 1b36:0010
+            0000:00:02.0 1b36:0010
+            ",
+        );
+    }
+
+    #[test]
+    fn test_reset_config_state() {
+        // Expansion ROMs decoding at 0xfe00_0000
+        let endpoint = || nvme().with_register(0x30, 0xfe00_0001, 0xffff_f801);
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c).with_register(0x38, 0xfe00_0001, 0xffff_f801),
+            Fixture::new().bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, endpoint()),
+            ),
+        );
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()));
+        root.scan();
+        let port = PciAddress::new(0, 0, 0, 0);
+        root.write_config_u16(port, 0x04, 0x0007);
+
+        assert_eq!(root.reset_config_state(), 3);
+        assert!(root.topology().is_none());
+        assert_eq!(root.read_config(port, 0x18) & 0xff_ffff, 0);
+        assert_eq!(root.read_config_u16(port, 0x04) & 0x7, 0);
+        assert_eq!(root.read_config(port, 0x38), 0);
+        assert_eq!(root.read_config(PciAddress::new(0, 2, 0, 0), 0x30), 0);
+
+        let topology = root.scan();
+        assert_golden(
+            &topology,
+            "
+            0000:00:00.0 1b36:000c bridge 01-02
+            0000:01:00.0 1b36:000c bridge 02-02
+            0000:02:00.0 1b36:0010
+            ",
+        );
+    }
+
+    #[test]
+    fn test_reset_config_state_bus_range() {
+        let nvme = nvme().with_register(0x10, 0x1000_0000, 0xffff_c000);
+        let mock = MockController::new().with_function(0x40, 0, 0, nvme);
+        let mut root = RootComplex::new(PcieController::new(mock)).with_bus_range(0x40..0x80);
+
+        assert_eq!(root.reset_config_state(), 1);
+        assert_eq!(root.read_config(PciAddress::new(0, 0x40, 0, 0), 0x10), 0);
+    }
+
+    #[test]
+    fn test_root_complex_new_dyn() {
+        let nvme = nvme();
+        let chips: [alloc::boxed::Box<dyn Controller>; 2] = [
+            alloc::boxed::Box::new(Fixture::new().endpoint(0, 0, nvme.clone()).controller()),
+            alloc::boxed::Box::new(Fixture::new().endpoint(3, 0, nvme).controller()),
+        ];
+        for (chip, device) in chips.into_iter().zip([0, 3]) {
+            let mut root = RootComplex::new_dyn(chip);
+            let found: alloc::vec::Vec<_> = root.enumerate(None).map(|ep| ep.address()).collect();
+            assert_eq!(found, [PciAddress::new(0, 0, device, 0)]);
+        }
+    }
+
+    #[test]
+    fn test_pcie_generic_try_new() {
+        use core::ptr::NonNull;
+        use pcie::err::Error;
+
+        // Never accessed, only the address is checked
+        let at = |address: usize| NonNull::new(address as *mut u8).unwrap();
+        assert!(matches!(
+            PcieGeneric::try_new(at(0x4010_1000), 16 << 20),
+            Err(Error::InvalidEcamWindow { .. })
+        ));
+        assert!(matches!(
+            PcieGeneric::try_new(at(0x4010_0000), 0x8_0000),
+            Err(Error::InvalidEcamWindow { .. })
+        ));
+        assert!(PcieGeneric::try_new(at(0x4010_0000), 16 << 20).is_ok());
+        assert!(RootComplex::new_generic(at(0x4010_0001), 256 << 20).is_err());
+    }
+
+    #[cfg(feature = "virtio")]
+    #[test]
+    fn test_virtio_layout() {
+        use pcie::{virtio, CAP_ID_VENDOR};
+
+        // cap_len and cfg_type above the header, then bar, offset, length
+        let cap = |cfg_type: u32, offset: u32, length: u32| {
+            [16 << 16 | cfg_type << 24, 4, offset, length]
+        };
+        let notify = [20 << 16 | 2 << 24, 4, 0x3000, 0x1000, 4];
+        let net = MockFunction::endpoint(0x1af4, 0x1041, [0x02, 0x00, 0x00])
+            .with_bar64(4, 0x4000, true)
+            .with_capability(CAP_ID_VENDOR, &cap(1, 0x0000, 0x1000))
+            .with_capability(CAP_ID_VENDOR, &cap(3, 0x1000, 0x1000))
+            .with_capability(CAP_ID_VENDOR, &cap(4, 0x2000, 0x1000))
+            .with_capability(CAP_ID_VENDOR, &notify);
+        let mut drv = PcieController::new(MockController::new().with_function(0, 0, 0, net));
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+
+        assert_eq!(virtio::device_type(&ep), Some(1));
+        let layout = virtio::layout(&ep).unwrap();
+        assert_eq!(layout.common.bar, 4);
+        assert_eq!(layout.isr.offset, 0x1000);
+        assert_eq!(layout.device.unwrap().offset, 0x2000);
+        assert_eq!(layout.notify.offset, 0x3000);
+        assert_eq!(layout.notify_off_multiplier, 4);
+    }
+
+    #[test]
+    fn test_absent_retry() {
+        use core::cell::Cell;
+
+        let retries = |bridge: MockFunction, below: Fixture| {
+            let fixture = Fixture::new().bridge(0, 0, bridge, below);
+            let mut drv = PcieController::new(fixture.controller());
+            let delays = Cell::new(0);
+            let found = enumerate_by_controller(&mut drv, None)
+                .with_absent_retry(3, 10, |_| delays.set(delays.get() + 1))
+                .count();
+            (found, delays.get())
+        };
+        let root_port = || MockFunction::bridge(0x1b36, 0x000c).with_pcie(0x4);
+
+        // The mock bridges never report a master abort. Below a root port
+        // only device 0 is looked for, none on the root bus
+        let below = Fixture::new().endpoint(0, 0, nvme());
+        assert_eq!(retries(root_port(), below), (1, 0));
+        assert_eq!(retries(root_port(), Fixture::new()), (0, 2));
+        // Any of the 31 empty slots of a conventional bus is read 3 times
+        let below = Fixture::new().endpoint(0, 0, nvme());
+        assert_eq!(
+            retries(MockFunction::bridge(0x1b36, 0x000c), below),
+            (1, 31 * 2)
+        );
+    }
+
+    #[test]
+    fn test_below_4g_fallback() {
+        use core::cell::RefCell;
+        use pcie::ScanEvent;
+
+        let gpu = || MockFunction::endpoint(0x1234, 0x1111, [0x03, 0x00, 0x00]);
+        // Behind a bridge with a 32-bit prefetchable window: one 64-bit BAR
+        // firmware put below 4GiB, one unassigned, one 32-bit
+        let below = Fixture::new()
+            .endpoint(0, 0, gpu().with_bar64(0, 0x10_0000, true))
+            .endpoint(1, 0, gpu().with_bar64(0, 0x10_0000, true))
+            .endpoint(2, 0, gpu().with_bar32(0, 0x10_0000, true));
+        let fixture = Fixture::new().bridge(0, 0, MockFunction::bridge(0x1b36, 0x000c), below);
+        let mut mock = fixture.controller();
+        mock = mock.with_function(
+            1,
+            0,
+            0,
+            gpu()
+                .with_bar64(0, 0x10_0000, true)
+                .with_register(0x10, 0x1080_000c, 0xfff0_0000),
+        );
+        let mut drv = PcieController::new(mock);
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            true,
+        );
+        drv.set_mem64(
+            PciMem64 {
+                address: 0x8_0000_0000,
+                size: 0x1_0000_0000,
+            },
+            true,
+        );
+        let fallbacks = RefCell::new(alloc::vec::Vec::new());
+        let found = enumerate_by_controller(&mut drv, None)
+            .with_events(|e| {
+                if let ScanEvent::Below4GFallback { address, bridge } = e {
+                    fallbacks.borrow_mut().push((address, bridge));
+                }
+            })
+            .count();
+
+        assert_eq!(found, 3);
+        assert_eq!(
+            fallbacks.into_inner(),
+            [(PciAddress::new(0, 1, 1, 0), PciAddress::new(0, 0, 0, 0))]
+        );
+        let mut root = RootComplex::new(drv);
+        for device in 0..3 {
+            let address = PciAddress::new(0, 1, device, 0);
+            assert!(root.read_config(address, 0x10) < 0x2000_0000);
+            assert_eq!(root.read_config(address, 0x14), 0);
+        }
+    }
+
+    #[test]
+    fn test_probe_budget_slow_endpoint() {
+        use core::cell::{Cell, RefCell};
+        use pcie::ScanEvent;
+
+        let nic =
+            || MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00]).with_io_bar(0, 0x100);
+        let fixture = Fixture::new()
+            .endpoint(0, 0, nic().with_bar32(1, 0x4000, false))
+            .endpoint(1, 0, nic());
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        // Only probing 00:00.0 takes 100ms
+        let calls = Cell::new(0);
+        let now_ms = || {
+            calls.set(calls.get() + 1);
+            if calls.get() == 1 {
+                0
+            } else {
+                100
+            }
+        };
+        let events = RefCell::new(alloc::vec::Vec::new());
+        let found: alloc::vec::Vec<_> = enumerate_by_controller(&mut drv, None)
+            .with_io_window(0x1000..0x1_0000)
+            .with_probe_budget(50, now_ms)
+            .with_events(|e| {
+                let found = matches!(e, ScanEvent::DeviceFound { .. });
+                events.borrow_mut().push((e.address(), found));
+            })
+            .map(|ep| ep.address())
+            .collect();
+
+        let slow = PciAddress::new(0, 0, 0, 0);
+        let fast = PciAddress::new(0, 0, 1, 0);
+        assert_eq!(found, [fast]);
+        let events = events.into_inner();
+        assert!(!events.contains(&(slow, true)));
+        assert!(events.contains(&(slow, false)));
+        assert!(events.contains(&(fast, true)));
+        let mut root = RootComplex::new(drv);
+        assert_eq!(root.read_config(slow, 0x04) & 0x7, 0);
+        // The I/O block of the slow one went to the next function
+        assert_eq!(root.read_config(slow, 0x10), 0x1001);
+        assert_eq!(root.read_config(fast, 0x10), 0x1001);
+    }
+
+    #[test]
+    fn test_event_limiter() {
+        use pcie::{EventLimiter, HwEvent};
+
+        let limiter = EventLimiter::new(2, 10);
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 0), Some(0));
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 1), Some(0));
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 2), None);
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 3), None);
+        assert_eq!(limiter.suppressed(HwEvent::CrsRetry), 2);
+        // Every kind has a budget of its own
+        assert_eq!(limiter.record(HwEvent::LinkFlap, 3), Some(0));
+
+        // The first report of the next window carries the count dropped
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 10), Some(2));
+        assert_eq!(limiter.suppressed(HwEvent::CrsRetry), 0);
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 11), Some(0));
+        assert_eq!(limiter.record(HwEvent::CrsRetry, 12), None);
+    }
+
+    #[test]
+    fn test_bus_number_exhausted() {
+        use core::cell::RefCell;
+        use pcie::{err::Error, ScanEvent};
+
+        let below = || Fixture::new().endpoint(0, 0, nvme());
+        let fixture = Fixture::new()
+            .bridge(0, 0, MockFunction::bridge(0x1b36, 0x000c), below())
+            .bridge(1, 0, MockFunction::bridge(0x1b36, 0x000c), below());
+        let mut drv = PcieController::new(fixture.controller());
+        let exhausted = RefCell::new(alloc::vec::Vec::new());
+        // Buses 0-1 leave no secondary bus for the second bridge
+        let mut iter = enumerate_by_controller(&mut drv, Some(0..2)).with_events(|e| {
+            if let ScanEvent::Error {
+                address,
+                error: Error::BusNumberExhausted { .. },
+            } = e
+            {
+                exhausted.borrow_mut().push(address);
+            }
+        });
+        let found: alloc::vec::Vec<_> = iter.by_ref().map(|ep| ep.address()).collect();
+
+        let second = PciAddress::new(0, 0, 1, 0);
+        assert_eq!(found, [PciAddress::new(0, 1, 0, 0)]);
+        assert!(iter.errors().iter().any(|e| matches!(
+            e,
+            Error::BusNumberExhausted { bridge, bus_max: 1 } if *bridge == second
+        )));
+        drop(iter);
+        assert_eq!(exhausted.into_inner(), [second]);
+        let mut root = RootComplex::new(drv);
+        assert_eq!(root.read_config(second, 0x18) & 0xff_ffff, 0);
+    }
+
+    #[test]
+    fn test_scan_parallel_merges_segments() {
+        use pcie::{scan_parallel, SequentialExecutor};
+
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().endpoint(0, 0, nvme()),
+        );
+        let mut roots = [
+            RootComplex::new(PcieController::new(fixture.controller())),
+            RootComplex::new(PcieController::new(fixture.controller())).with_segment(1),
+        ];
+        let topology = scan_parallel(&mut roots, &SequentialExecutor);
+
+        let endpoints: alloc::vec::Vec<_> = topology.endpoints().map(|n| n.address).collect();
+        assert_eq!(
+            endpoints,
+            [PciAddress::new(0, 1, 0, 0), PciAddress::new(1, 1, 0, 0)]
+        );
+        let second = topology.find(PciAddress::new(1, 1, 0, 0)).unwrap();
+        let parent = topology.node(second).parent.unwrap();
+        assert_eq!(topology.node(parent).address, PciAddress::new(1, 0, 0, 0));
+        // Each root keeps its own part
+        assert_eq!(roots[1].topology().unwrap().nodes().count(), 2);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        use alloc::vec::Vec;
+        use pcie::PciIterator;
+
+        let endpoint = || nvme().with_bar32(0, 0x4000, false);
+        let bridge = || MockFunction::bridge(0x1b36, 0x000c);
+        // The bridge at 01:04.0 and the one beside the first get their bus
+        // numbers after the pause
+        let nested = Fixture::new()
+            .endpoint(0, 0, endpoint())
+            .endpoint(1, 0, endpoint());
+        let below = (0..4)
+            .fold(Fixture::new(), |f, device| {
+                f.endpoint(device, 0, endpoint())
+            })
+            .bridge(4, 0, bridge(), nested);
+        let fixture = Fixture::new()
+            .bridge(0, 0, bridge(), below)
+            .endpoint(1, 0, endpoint())
+            .bridge(2, 0, bridge(), Fixture::new().endpoint(0, 0, endpoint()));
+        let controller = || {
+            let mut drv = PcieController::new(fixture.controller());
+            drv.set_mem32(
+                PciMem32 {
+                    address: 0x1000_0000,
+                    size: 0x1000_0000,
+                },
+                false,
+            );
+            drv
+        };
+        let found = |iter: &mut PciIterator<'_>, n: usize| -> Vec<_> {
+            iter.take(n)
+                .map(|ep| (ep.address(), ep.bar_address(0)))
+                .collect()
+        };
+
+        let mut full_drv = controller();
+        let full = found(
+            &mut enumerate_by_controller(&mut full_drv, None),
+            usize::MAX,
+        );
+        assert_eq!(full.len(), 8);
+
+        let mut drv = controller();
+        let mut iter = enumerate_by_controller(&mut drv, None);
+        let mut paused = found(&mut iter, 2);
+        let token = iter.pause();
+        assert_eq!(token.address().map(|a| a.bus()), Some(1));
+        let mut iter = PciIterator::resume(&mut drv, token).unwrap();
+        paused.extend(found(&mut iter, usize::MAX));
+        drop(iter);
+        assert_eq!(paused, full);
+
+        let mut full_root = RootComplex::new(full_drv);
+        let mut root = RootComplex::new(drv);
+        for bridge in [
+            PciAddress::new(0, 0, 0, 0),
+            PciAddress::new(0, 1, 4, 0),
+            PciAddress::new(0, 0, 2, 0),
+        ] {
+            assert_eq!(
+                root.read_config(bridge, 0x18),
+                full_root.read_config(bridge, 0x18),
+                "{bridge:?}"
+            );
+        }
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 1, 4, 0), 0x18) & 0xffff,
+            0x0201
+        );
+    }
+
+    #[test]
+    fn test_class_filters() {
+        use alloc::vec::Vec;
+        use pcie::EndpointIterExt;
+
+        let ahci = MockFunction::endpoint(0x8086, 0x2922, [0x01, 0x06, 0x01]);
+        let nic = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00]);
+        let vga = MockFunction::endpoint(0x1234, 0x1111, [0x03, 0x00, 0x00]);
+        let below = Fixture::new().endpoint(0, 0, ahci).endpoint(1, 0, vga);
+        let fixture = Fixture::new()
+            .endpoint(0, 0, nvme())
+            .endpoint(1, 0, nic)
+            .bridge(2, 0, MockFunction::bridge(0x1b36, 0x000c), below);
+        let mut drv = PcieController::new(fixture.controller());
+        let storage: Vec<_> = enumerate_by_controller(&mut drv, None)
+            .storage()
+            .map(|ep| ep.address())
+            .collect();
+        assert_eq!(
+            storage,
+            [PciAddress::new(0, 0, 0, 0), PciAddress::new(0, 1, 0, 0)]
+        );
+        let network: Vec<_> = enumerate_by_controller(&mut drv, None)
+            .network()
+            .map(|ep| ep.address())
+            .collect();
+        assert_eq!(network, [PciAddress::new(0, 0, 1, 0)]);
+        let display: Vec<_> = enumerate_by_controller(&mut drv, None)
+            .display()
+            .map(|ep| ep.address())
+            .collect();
+        assert_eq!(display, [PciAddress::new(0, 1, 1, 0)]);
+    }
+
+    #[test]
+    fn test_intx_swizzle() {
+        let bridge = || MockFunction::bridge(0x1b36, 0x000c);
+        let nested = Fixture::new().endpoint(1, 0, nvme());
+        let below = Fixture::new()
+            .endpoint(2, 0, nvme())
+            .bridge(3, 0, bridge(), nested);
+        let fixture = Fixture::new()
+            .bridge(1, 0, bridge(), below)
+            .endpoint(5, 0, nvme());
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()));
+        let topology = root.scan();
+
+        // INTA of device 2 arrives as INTC at the bridge on the root bus
+        assert_eq!(
+            topology.intx_swizzle(PciAddress::new(0, 1, 2, 0), 1),
+            Some(3)
+        );
+        // INTB of device 1: INTC at 01:03.0, then INTB at 00:01.0
+        assert_eq!(
+            topology.intx_swizzle(PciAddress::new(0, 2, 1, 0), 2),
+            Some(2)
+        );
+        assert_eq!(
+            topology.intx_swizzle(PciAddress::new(0, 2, 1, 0), 4),
+            Some(4)
+        );
+        // Not swizzled on the root bus
+        assert_eq!(
+            topology.intx_swizzle(PciAddress::new(0, 0, 5, 0), 4),
+            Some(4)
+        );
+        assert_eq!(topology.intx_swizzle(PciAddress::new(0, 1, 2, 0), 0), None);
+        assert_eq!(topology.intx_swizzle(PciAddress::new(0, 3, 0, 0), 1), None);
+    }
+
+    #[test]
+    fn test_required_window_sizes() {
+        use pcie::{required_window_sizes, WindowSizes};
+
+        let bridge = || MockFunction::bridge(0x1b36, 0x000c);
+        let nested = Fixture::new().endpoint(
+            0,
+            0,
+            nvme().with_bar32(0, 0x10_0000, true).with_io_bar(1, 0x20),
+        );
+        let below = Fixture::new()
+            .endpoint(
+                0,
+                0,
+                nvme()
+                    .with_bar32(0, 0x4000, false)
+                    .with_bar64(1, 0x20_0000, true)
+                    .with_io_bar(3, 0x100),
+            )
+            .endpoint(1, 0, nvme().with_bar32(0, 0x10_0000, false))
+            .bridge(2, 0, bridge(), nested);
+        let fixture = Fixture::new().bridge(0, 0, bridge(), below);
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        // Bus numbers first
+        assert_eq!(enumerate_by_controller(&mut drv, None).count(), 3);
+
+        // 16KiB + 1MiB, the 2MiB BAR plus the nested bridge's 1MiB window,
+        // 256 bytes plus the nested bridge's 4KiB I/O window
+        assert_eq!(
+            required_window_sizes(&mut drv, PciAddress::new(0, 0, 0, 0)).unwrap(),
+            WindowSizes {
+                memory: 0x20_0000,
+                prefetchable: 0x30_0000,
+                io: 0x2000,
+            }
+        );
+        assert_eq!(
+            required_window_sizes(&mut drv, PciAddress::new(0, 1, 2, 0)).unwrap(),
+            WindowSizes {
+                memory: 0,
+                prefetchable: 0x10_0000,
+                io: 0x1000,
+            }
+        );
+        assert!(required_window_sizes(&mut drv, PciAddress::new(0, 1, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_policy_cache_line_and_latency() {
+        // Cache Line Size and Latency Timer writable
+        let timing = |f: MockFunction| f.with_register(0x0c, 0, 0xffff);
+        let conventional = || timing(MockFunction::endpoint(0x8086, 0x1229, [0x02, 0x00, 0x00]));
+        let pcie_to_pci = timing(MockFunction::bridge(0x1b36, 0x000c))
+            .with_pcie(0x7)
+            .with_register(0x18, 0, u32::MAX);
+        let fixture = Fixture::new()
+            .endpoint(1, 0, conventional())
+            .endpoint(2, 0, timing(nvme().with_pcie(0)))
+            .bridge(
+                3,
+                0,
+                pcie_to_pci,
+                Fixture::new().endpoint(0, 0, conventional()),
+            );
+        let policy = ResourcePolicy::new()
+            .with_cache_line_size(64)
+            .unwrap()
+            .with_latency_timer(0x40);
+        let mut root =
+            RootComplex::new(PcieController::new(fixture.controller())).with_policy(policy);
+        root.scan();
+
+        // 64 bytes as 16 dwords at 0x0c, 0x40 clocks at 0x0d
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 0, 1, 0), 0x0c) & 0xffff,
+            0x4010
+        );
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 1, 0, 0), 0x0c) & 0xffff,
+            0x4010
+        );
+        // PCIe functions are left alone, the bridge gets the Secondary
+        // Latency Timer of its PCI bus
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 0, 2, 0), 0x0c) & 0xffff,
+            0
+        );
+        let bridge = PciAddress::new(0, 0, 3, 0);
+        assert_eq!(root.read_config(bridge, 0x0c) & 0xffff, 0);
+        assert_eq!(root.read_config(bridge, 0x18) >> 24, 0x40);
+    }
+
+    #[test]
+    fn test_header_snapshot_cost() {
+        use pcie::fixture::{mixed, scan_cost, ScanCost};
+
+        let fixture = mixed();
+        let baseline = scan_cost(&fixture, false);
+        let snapshot = scan_cost(&fixture, true);
+        // Pinned, see benches/scan.rs
+        assert_eq!(
+            baseline,
+            ScanCost {
+                functions: 10,
+                reads: 571,
+                absent_reads: 78,
+                writes: 274
+            }
+        );
+        assert_eq!(
+            snapshot,
+            ScanCost {
+                functions: 10,
+                reads: 542,
+                absent_reads: 78,
+                writes: 274
+            }
+        );
+        assert!(snapshot.reads_per_function() < baseline.reads_per_function());
+    }
+
+    #[test]
+    fn test_config_cache() {
+        let mock = MockController::new().with_function(0, 1, 0, nvme());
+        let count = mock.access_count();
+        let mut root = RootComplex::new(PcieController::new(mock)).with_config_cache();
+        let address = PciAddress::new(0, 0, 1, 0);
+
+        let id = root.identity(address).unwrap();
+        assert_eq!((id.vendor_id, id.device_id), (0x1b36, 0x0010));
+        assert_eq!(
+            (id.base_class, id.sub_class, id.interface),
+            (0x01, 0x08, 0x02)
+        );
+        let reads = count.reads();
+        assert_eq!(root.identity(address), Some(id));
+        assert_eq!(count.reads(), reads);
+        assert!(root.identity(PciAddress::new(0, 0, 2, 0)).is_none());
+
+        root.rescan_bus(0);
+        assert!(root.config_cache().unwrap().get(address).is_none());
+        assert_eq!(root.identity(address), Some(id));
+        assert_eq!(root.config_cache().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_irq_safe_access() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use pcie::{IrqLock, CAP_ID_MSI};
+
+        struct TestLock {
+            held: AtomicBool,
+            taken: Arc<AtomicUsize>,
+        }
+
+        impl IrqLock for TestLock {
+            fn lock(&self) -> usize {
+                assert!(!self.held.swap(true, Ordering::Acquire));
+                self.taken.fetch_add(1, Ordering::Relaxed);
+                0x80
+            }
+
+            fn unlock(&self, saved: usize) {
+                assert_eq!(saved, 0x80);
+                self.held.store(false, Ordering::Release);
+            }
+        }
+
+        // 4 vectors, per-vector masking, 32-bit address
+        let msi = [0x0104_0000, 0, 0, 0, 0];
+        let mock =
+            MockController::new().with_function(0, 1, 0, nvme().with_capability(CAP_ID_MSI, &msi));
+        let address = PciAddress::new(0, 0, 1, 0);
+        let mut root = RootComplex::new(PcieController::new(mock));
+        assert!(root.with_irq_safe_access(|_| ()).is_err());
+
+        let taken = Arc::new(AtomicUsize::new(0));
+        let mut root = root.with_irq_lock(TestLock {
+            held: AtomicBool::new(false),
+            taken: taken.clone(),
+        });
+        let cap = root.read_config_u8(address, 0x34) as u16;
+        root.with_irq_safe_access(|cfg| {
+            cfg.set_msi_masked(address, 2, true).unwrap();
+            assert!(cfg.set_msi_masked(address, 4, true).is_err());
+            assert!(cfg.set_msix_function_masked(address, true).is_err());
+        })
+        .unwrap();
+        assert_eq!(root.read_config(address, cap + 0x0c), 1 << 2);
+
+        root.write_config_u16(address, 0x04, 0x0006);
+        assert_eq!(root.read_config_u16(address, 0x04), 0x0006);
+
+        // Interrupt handlers keep a clone and only need `&self`
+        let handle = root.irq_handle(address).unwrap();
+        let irq = handle.clone();
+        irq.set_msi_masked(2, false).unwrap();
+        assert_eq!(handle.read(cap + 0x0c), 0);
+
+        // Read-modify-writes of functions found through the root complex
+        // take the same lock
+        let ep = root.find_by_id(0x1b36, 0x0010).next().unwrap();
+        let before = taken.load(Ordering::Relaxed);
+        ep.update_command(|cmd| cmd | CommandRegister::BUS_MASTER_ENABLE);
+        assert_eq!(taken.load(Ordering::Relaxed), before + 1);
+        assert_eq!(root.read_config_u16(address, 0x04), 0x0006 | 1 << 2);
+    }
+
+    #[test]
+    fn test_sub_dword_writes_keep_rw1c_status() {
+        use pcie::{CAP_ID_PCIE, EXT_CAP_ID_AER};
+
+        let f = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_aer();
+        let cap = f.capability(CAP_ID_PCIE).unwrap();
+        let aer = f.extended_capability(EXT_CAP_ID_AER).unwrap();
+        // Correctable Error Detected and Link Bandwidth Management Status
+        // latched, Receiver Error and Bad TLP latched in AER
+        let f = f
+            .with_register(cap + 0x08, 1 << 16, 0xffff)
+            .with_rw1c(cap + 0x08, 0xf << 16)
+            .with_register(cap + 0x10, 1 << 30, 0xffff)
+            .with_rw1c(cap + 0x10, 0xc000 << 16)
+            .with_register(aer + 0x10, 1 << 6 | 1, 0)
+            .with_rw1c(aer + 0x10, u32::MAX);
+        let mock = MockController::new().with_function(0, 1, 0, f);
+        let address = PciAddress::new(0, 0, 1, 0);
+        let mut root = RootComplex::new(PcieController::new(mock));
+
+        root.write_config_u16(address, cap + 0x08, 0x000f);
+        assert_eq!(root.read_config(address, cap + 0x08), 1 << 16 | 0xf);
+        root.write_config_u8(address, cap + 0x10, 0x20);
+        assert_eq!(root.read_config(address, cap + 0x10), 1 << 30 | 0x20);
+
+        // Only the byte written clears status bits
+        root.write_config_u8(address, aer + 0x10, 0x01);
+        assert_eq!(root.read_config(address, aer + 0x10), 1 << 6);
+    }
+
+    #[test]
+    fn test_read_rom() {
+        use core::ptr::NonNull;
+        use pcie::RomCodeType;
+
+        let mut rom = alloc::vec![0u32; 0x800 / 4];
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(rom.as_mut_ptr().cast::<u8>(), 0x800) };
+        for (offset, code_type, last) in [(0x000, 0x00, 0x00), (0x400, 0x03, 0x80)] {
+            let image = &mut bytes[offset..offset + 0x400];
+            image[0..2].copy_from_slice(&0xaa55u16.to_le_bytes());
+            image[0x18..0x1a].copy_from_slice(&0x1cu16.to_le_bytes());
+            let pcir = &mut image[0x1c..0x34];
+            pcir[0..4].copy_from_slice(b"PCIR");
+            pcir[4..8].copy_from_slice(&[0x86, 0x80, 0x0e, 0x10]);
+            pcir[0x0d..0x10].copy_from_slice(&[0x00, 0x00, 0x02]);
+            pcir[0x10..0x12].copy_from_slice(&2u16.to_le_bytes());
+            pcir[0x14] = code_type;
+            pcir[0x15] = last;
+        }
+        bytes[0x404..0x408].copy_from_slice(&0x0ef1u32.to_le_bytes());
+        bytes[0x408..0x40c].copy_from_slice(&[0x0b, 0x00, 0x64, 0xaa]);
+
+        let chip = MockController::new().with_function(
+            0,
+            0,
+            0,
+            MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00]).with_register(
+                0x30,
+                0x1000_0000,
+                0xffff_f801,
+            ),
+        );
+        let mut drv = PcieController::new(chip);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        assert_eq!(ep.rom_size(), Some(0x800));
+
+        let base = NonNull::new(rom.as_mut_ptr().cast::<u8>()).unwrap();
+        let images = ep
+            .read_rom(&|address: u64, size: usize| {
+                assert_eq!((address, size), (0x1000_0000, 0x800));
+                base
+            })
+            .unwrap();
+        assert_eq!(images.len(), 2);
+        let x86 = images.find(RomCodeType::X86).unwrap();
+        assert_eq!((x86.vendor_id, x86.device_id), (0x8086, 0x100e));
+        assert_eq!(x86.class, [0x02, 0x00, 0x00]);
+        assert_eq!(x86.data.len(), 0x400);
+        let efi = images.find(RomCodeType::Efi).unwrap().efi.unwrap();
+        assert_eq!((efi.subsystem, efi.machine_type), (0x0b, 0xaa64));
+        // Decode is off again
+        assert_eq!(ep.read(0x30), 0x1000_0000);
+    }
+
+    #[test]
+    fn test_export_import_state() {
+        let endpoint = || nvme().with_bar32(0, 0x4000, false);
+        // Slot 01:01.0 stays empty until its ID register is written
+        let hotplug = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_bar32(0, 0x4000, false)
+            .with_register(0x00, u32::MAX, u32::MAX);
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new()
+                .endpoint(0, 0, endpoint())
+                .endpoint(1, 0, hotplug),
+        );
+        let root_complex = || {
+            let mut drv = PcieController::new(fixture.controller());
+            drv.set_mem32(
+                PciMem32 {
+                    address: 0x1000_0000,
+                    size: 0x1000_0000,
+                },
+                false,
+            );
+            RootComplex::new(drv)
+        };
+        let mut root = root_complex();
+        assert!(root.export_state().is_err());
+        root.scan();
+        let blob = root.export_state().unwrap();
+
+        // Firmware of the next stage left nothing programmed
+        let mut fresh = root_complex();
+        assert!(fresh.import_state(&blob).is_err());
+        assert!(fresh.topology().is_none());
+
+        // Second stage on hardware the first one left configured
+        let bridge = PciAddress::new(0, 0, 0, 0);
+        let ep = PciAddress::new(0, 1, 0, 0);
+        for address in [bridge, ep] {
+            for offset in (0x04..0x40).step_by(4) {
+                fresh.write_config(address, offset, root.read_config(address, offset));
+            }
+        }
+        assert_eq!(fresh.import_state(&blob).unwrap(), 2);
+        let adopted = fresh.topology().unwrap();
+        assert_eq!(adopted.nodes().count(), 2);
+        assert_eq!(fresh.read_config(ep, 0x10), root.read_config(ep, 0x10));
+        let bar0 = fresh.read_config(ep, 0x10) as u64;
+        let window = fresh.read_config(bridge, 0x20);
+        let window = ((window & 0xfff0) as u64) << 16..(((window >> 16) as u64) << 16) + 0x10_0000;
+
+        // A function appears below the bridge, its BAR must stay clear of
+        // the adopted ones
+        let new = PciAddress::new(0, 1, 1, 0);
+        fresh.write_config(new, 0x00, 0x10d3_8086);
+        assert!(fresh.import_state(&blob).is_err());
+        assert_eq!(fresh.rescan_bus(1), [pcie::RescanEvent::DeviceAdded(new)]);
+        let start = (fresh.read_config(new, 0x10) & !0xf) as u64;
+        assert_ne!(start, 0);
+        let end = start + 0x4000;
+        assert!(end <= bar0 || bar0 + 0x4000 <= start);
+        assert!(end <= window.start || window.end <= start);
+
+        // Same hardware, still configured
+        assert_eq!(root.import_state(&blob).unwrap(), 2);
+        assert_eq!(root.topology().unwrap().nodes().count(), 2);
+        let bar0 = root.read_config(ep, 0x10);
+        root.write_config(ep, 0x10, bar0 + 0x4000);
+        assert!(root.import_state(&blob).is_err());
+        assert!(root.import_state(&blob[..blob.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_rescan_unknown_bus_covers_bridges_below() {
+        use pcie::RescanEvent;
+
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, nvme()),
+            ),
+        );
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()));
+        // Bus numbers assigned, but no topology stored
+        root.enumerate(None).for_each(drop);
+
+        let switch = PciAddress::new(0, 1, 0, 0);
+        let ep = PciAddress::new(0, 2, 0, 0);
+        assert_eq!(
+            root.rescan_bus(1),
+            [
+                RescanEvent::DeviceAdded(switch),
+                RescanEvent::DeviceAdded(ep)
+            ]
+        );
+        assert!(root.rescan_bus(1).is_empty());
+        assert_eq!(root.topology().unwrap().nodes().count(), 2);
+    }
+
+    #[test]
+    fn test_rescan_replaced_function() {
+        use pcie::RescanEvent;
+
+        // Another card in the slot shows up with other IDs
+        let nvme = nvme()
+            .with_bar32(0, 0x4000, false)
+            .with_register(0x00, 0x0010_1b36, u32::MAX);
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().endpoint(0, 0, nvme),
+        );
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let mut root = RootComplex::new(drv);
+        root.scan();
+
+        let ep = PciAddress::new(0, 1, 0, 0);
+        root.write_config(ep, 0x00, 0x10d3_8086);
+        root.write_config(ep, 0x10, 0);
+        assert_eq!(
+            root.rescan_bus(1),
+            [RescanEvent::DeviceRemoved(ep), RescanEvent::DeviceAdded(ep)]
+        );
+        assert_ne!(root.read_config(ep, 0x10), 0);
+    }
+
+    #[test]
+    fn test_enumerate_while_buses_held() {
+        let fixture = Fixture::new().endpoint(0, 0, nvme().with_bar32(0, 0x4000, false));
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let mut root = RootComplex::new(drv).with_segment(7);
+        let ep = PciAddress::new(7, 0, 0, 0);
+
+        let mut other = PcieController::new(fixture.controller());
+        let holder = enumerate_by_controller(&mut other, None).with_segment(7);
+        root.enumerate(None).for_each(drop);
+        assert_eq!(root.read_config(ep, 0x10), 0);
+        drop(holder);
+
+        // The read-only fallback was not the first enumeration
+        root.enumerate(None).for_each(drop);
+        assert_eq!(root.read_config(ep, 0x10), 0x1000_0000);
+    }
+
+    #[test]
+    fn test_aer_root_error_dispatch() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU32, Ordering};
+        use pcie::{AerSeverity, ErrorInjector, InjectedError};
+
+        // ERR_COR received from 01:00.0, AER of the root port sits at 0x100
+        let port = MockFunction::bridge(0x1b36, 0x000c)
+            .with_pcie(0x4)
+            .with_aer()
+            .with_register(0x130, 0b1, 0)
+            .with_rw1c(0x130, 0x7f)
+            .with_register(0x134, 0x0100, 0);
+        let nic = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_aer();
+        let fixture = Fixture::new().bridge(0, 0, port, Fixture::new().endpoint(0, 0, nic));
+        let mut mock = fixture.controller();
+        mock.inject(
+            PciAddress::new(0, 1, 0, 0),
+            InjectedError::Correctable(1 << 6),
+        )
+        .unwrap();
+
+        let mut root = RootComplex::new(PcieController::new(mock));
+        root.scan();
+        let seen = Arc::new(AtomicU32::new(0));
+        let handler_seen = seen.clone();
+        root.on_aer_error(PciAddress::new(0, 1, 0, 0), move |event| {
+            assert_eq!(event.severity, AerSeverity::Correctable);
+            handler_seen.store(event.status, Ordering::Relaxed);
+        });
+
+        let port = PciAddress::new(0, 0, 0, 0);
+        let events = root.handle_root_error(port).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].source, PciAddress::new(0, 1, 0, 0));
+        assert!(events[0].node.is_some());
+        assert_eq!(seen.load(Ordering::Relaxed), 1 << 6);
+        // Source and root status are cleared
+        assert_eq!(root.read_config(PciAddress::new(0, 1, 0, 0), 0x110), 0);
+        assert_eq!(root.read_config(port, 0x130), 0);
+        assert!(root.handle_root_error(port).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_aer_without_handler_rate_limited() {
+        use pcie::{EventLimiter, HwEvent};
+
+        // Nothing passes, every report is counted as suppressed
+        static LIMITER: EventLimiter = EventLimiter::new(0, 100);
+
+        // ERR_COR and ERR_UNCOR received, both from 01:00.0
+        let port = MockFunction::bridge(0x1b36, 0x000c)
+            .with_pcie(0x4)
+            .with_aer()
+            .with_register(0x130, 0b101, 0)
+            .with_rw1c(0x130, 0x7f)
+            .with_register(0x134, 0x0100_0100, 0);
+        let nic = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_aer();
+        let fixture = Fixture::new().bridge(0, 0, port, Fixture::new().endpoint(0, 0, nic));
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()))
+            .with_event_limiter(&LIMITER, || 0);
+        root.scan();
+
+        let events = root.handle_root_error(PciAddress::new(0, 0, 0, 0)).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(LIMITER.suppressed(HwEvent::CorrectableError), 1);
+        assert_eq!(LIMITER.suppressed(HwEvent::UncorrectableError), 1);
+    }
+
+    #[test]
+    fn test_dwc_error_injection() {
+        use core::ptr::NonNull;
+        use pcie::{err::Error, DwPcieHost, ErrorInjector, InjectedError};
+
+        // RAS DES VSEC at 0x100 of DBI
+        let mut dbi = alloc::vec![0u32; 0x1000 / 4];
+        dbi[0x100 / 4] = 0x0001_000b;
+        dbi[0x104 / 4] = 0x1004_0002;
+        let mut cfg = alloc::vec![0u32; 0x1000 / 4];
+        let mut host = DwPcieHost::new(
+            NonNull::new(dbi.as_mut_ptr().cast::<u8>()).unwrap(),
+            NonNull::new(cfg.as_mut_ptr().cast::<u8>()).unwrap(),
+            0x4000_0000,
+            0x1000,
+            |_| {},
+        );
+        let port = PciAddress::new(0, 0, 0, 0);
+
+        host.inject(port, InjectedError::Uncorrectable(1 << 19))
+            .unwrap();
+        // EINJ0: tx ECRC, count 1; group 0 enabled
+        assert_eq!(dbi[0x134 / 4], 3 << 8 | 1);
+        assert_eq!(dbi[0x130 / 4], 1);
+
+        host.inject(port, InjectedError::Correctable(1 << 6))
+            .unwrap();
+        assert_eq!(dbi[0x134 / 4], 1);
+
+        assert!(matches!(
+            host.inject(port, InjectedError::Correctable(1 << 12)),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(matches!(
+            host.inject(
+                PciAddress::new(0, 1, 0, 0),
+                InjectedError::Correctable(1 << 6)
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_dwc_outbound_atu() {
+        use core::ptr::NonNull;
+        use pcie::{DwPcieHost, HostWindow, OutboundAtu, WindowKind};
+
+        let mut dbi = alloc::vec![0u32; 0x1000 / 4];
+        let mut cfg = alloc::vec![0u32; 0x1000 / 4];
+        let mut host = DwPcieHost::new(
+            NonNull::new(dbi.as_mut_ptr().cast::<u8>()).unwrap(),
+            NonNull::new(cfg.as_mut_ptr().cast::<u8>()).unwrap(),
+            0x4000_0000,
+            0x1000,
+            |_| panic!("the region reads back enabled at once"),
+        )
+        .with_regions(4, 2);
+        // 4GiB crossing a 4GiB boundary needs the upper limit
+        host.program_outbound(
+            2,
+            &HostWindow {
+                kind: WindowKind::Memory64,
+                cpu_address: 0x8000_0000,
+                pci_address: 0x80_0000_0000,
+                size: 0x1_0000_0000,
+                prefetchable: true,
+            },
+        )
+        .unwrap();
+        // Through the viewport at 0x900
+        assert_eq!(dbi[0x900 / 4], 2);
+        assert_eq!(dbi[0x904 / 4], 1 << 13);
+        assert_eq!(dbi[0x908 / 4], 1 << 31);
+        assert_eq!(dbi[0x90c / 4], 0x8000_0000);
+        assert_eq!(dbi[0x914 / 4], 0x7fff_ffff);
+        assert_eq!(dbi[0x91c / 4], 0x80);
+        assert_eq!(dbi[0x924 / 4], 1);
+    }
+
+    #[test]
+    fn test_rcrb_out_of_range() {
+        use core::ptr::NonNull;
+        use pcie::{Rcrb, EXT_CAP_ID_AER};
+
+        // One dword past the block to catch stray writes
+        let mut regs = alloc::vec![0u32; Rcrb::SIZE / 4 + 1];
+        regs[0] = 0x100 << 20 | 1 << 16 | 0x000b;
+        regs[0x100 / 4] = 1 << 16 | EXT_CAP_ID_AER as u32;
+        let rcrb = Rcrb::new(NonNull::new(regs.as_mut_ptr().cast()).unwrap());
+
+        assert_eq!(rcrb.read(Rcrb::SIZE as u16), u32::MAX);
+        rcrb.write(Rcrb::SIZE as u16, 1);
+        let aer = rcrb.capability(EXT_CAP_ID_AER).unwrap();
+        assert_eq!(aer.header().offset, 0x100);
+        assert_eq!(aer.read(0xf00), u32::MAX);
+        // Wraps past u16::MAX
+        assert_eq!(aer.read(0xff00), u32::MAX);
+        aer.write(0xff00, 1);
+        aer.write(0x04, 0x10);
+        assert_eq!(aer.read(0x04), 0x10);
+        assert_eq!(regs[Rcrb::SIZE / 4], 0);
+        assert_eq!(regs[0], 0x100 << 20 | 1 << 16 | 0x000b);
+    }
+
+    #[test]
+    fn test_bdf_parse_and_path() {
+        let bdf: Bdf = "0000:01:00.1".parse().unwrap();
+        assert_eq!(bdf.0, PciAddress::new(0, 1, 0, 1));
+        assert_eq!(
+            "1f:1f.7".parse::<Bdf>().unwrap().0,
+            PciAddress::new(0, 0x1f, 0x1f, 7)
+        );
+        assert!("00:20.0".parse::<Bdf>().is_err());
+        assert!("00:00.8".parse::<Bdf>().is_err());
+        // ARI function number, printed back as device and function
+        let ari: Bdf = "0001:02:21".parse().unwrap();
+        assert_eq!(ari.0, PciAddress::new(1, 2, 4, 1));
+        assert_eq!(alloc::format!("{ari}").parse::<Bdf>().unwrap(), ari);
+        assert!("02:100".parse::<Bdf>().is_err());
+
+        let fixture = Fixture::new().bridge(
+            1,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().endpoint(0, 0, nvme()),
+        );
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()));
+        let topology = root.scan();
+        assert_eq!(
+            topology.path_of(PciAddress::new(0, 1, 0, 0)).as_deref(),
+            Some("0000:00:01.0/0000:01:00.0")
+        );
+    }
+
+    #[test]
+    fn test_owned_types() {
+        use pcie::DeviceType;
+
+        let cmd = CommandRegister::MEMORY_ENABLE | CommandRegister::BUS_MASTER_ENABLE;
+        assert!(cmd.intersects(CommandRegister::MEMORY_ENABLE | CommandRegister::IO_ENABLE));
+        assert!(!cmd.intersects(CommandRegister::IO_ENABLE));
+        assert_eq!(
+            cmd & !CommandRegister::MEMORY_ENABLE,
+            CommandRegister::BUS_MASTER_ENABLE
+        );
+        assert!((cmd & CommandRegister::IO_ENABLE).is_empty());
+        assert_eq!((!CommandRegister::empty()).bits(), 0x07ff);
+        let mut masked = cmd;
+        masked &= CommandRegister::BUS_MASTER_ENABLE;
+        assert_eq!(masked, CommandRegister::BUS_MASTER_ENABLE);
+
+        let address = PciAddress::new(1, 0x3a, 0x1f, 7);
+        let raw = pci_types::PciAddress::from(address);
+        assert_eq!(
+            (raw.segment(), raw.bus(), raw.device(), raw.function()),
+            (1, 0x3a, 0x1f, 7)
+        );
+        assert_eq!(PciAddress::from(raw), address);
+        assert_eq!(alloc::format!("{address}"), "0001:3a:1f.7");
+        assert!(PciAddress::new(0, 1, 0, 0) < PciAddress::new(0, 1, 0, 1));
+        assert!(PciAddress::new(0, 0xff, 0, 0) < PciAddress::new(1, 0, 0, 0));
+
+        assert_eq!(DeviceType::from((0x01, 0x08)), DeviceType::NvmeController);
+        assert_eq!(DeviceType::from((0x06, 0x04)), DeviceType::PciPciBridge);
+        assert_eq!(DeviceType::from((0xff, 0x00)), DeviceType::Unknown);
+    }
+
+    #[test]
+    fn test_quirk_every_access() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use pcie::AccessPolicy;
+        use rdif_pcie::{DriverGeneric, KError};
+
+        /// Counts accesses reaching the chip for `target`
+        struct Watch {
+            inner: MockController,
+            target: PciAddress,
+            touched: Arc<AtomicUsize>,
+        }
+
+        impl DriverGeneric for Watch {
+            fn open(&mut self) -> Result<(), KError> {
+                Ok(())
+            }
+
+            fn close(&mut self) -> Result<(), KError> {
+                Ok(())
+            }
+        }
+
+        impl Controller for Watch {
+            fn read(&mut self, address: pci_types::PciAddress, offset: u16) -> u32 {
+                if PciAddress::from(address) == self.target {
+                    self.touched.fetch_add(1, Ordering::Relaxed);
+                }
+                self.inner.read(address, offset)
+            }
+
+            fn write(&mut self, address: pci_types::PciAddress, offset: u16, value: u32) {
+                if PciAddress::from(address) == self.target {
+                    self.touched.fetch_add(1, Ordering::Relaxed);
+                }
+                self.inner.write(address, offset, value)
+            }
+        }
+
+        let nic = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_bar32(0, 0x2_0000, false)
+            .with_capability(0x05, &[0, 0, 0]);
+        let hangs = MockFunction::endpoint(0x1234, 0x5678, [0xff, 0x00, 0x00]);
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().endpoint(0, 0, nic).endpoint(1, 0, hangs),
+        );
+        let touched = Arc::new(AtomicUsize::new(0));
+        let mut drv = PcieController::new(Watch {
+            inner: fixture.controller(),
+            target: PciAddress::new(0, 1, 1, 0),
+            touched: touched.clone(),
+        });
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        // 01:01.0 must never be touched, 01:00.0 not above its header
+        let quirk = |address: PciAddress, offset: u16| match (address.device(), offset) {
+            _ if address.bus() != 1 => AccessPolicy::Allow,
+            (1, _) => AccessPolicy::Skip,
+            (0, 0x40..=0xff) => AccessPolicy::Skip,
+            _ => AccessPolicy::Allow,
+        };
+        let eps: alloc::vec::Vec<_> = enumerate_by_controller(&mut drv, None)
+            .with_quirk(quirk)
+            .collect();
+        assert_eq!(eps.len(), 1);
+        assert_eq!(touched.load(Ordering::Relaxed), 0);
+
+        let ep = &eps[0];
+        assert!(ep.bar_address(0).is_some_and(|a| a != 0));
+        assert_eq!(ep.read(0x40), u32::MAX);
+        ep.write(0x44, 0x1234);
+        let mut root = RootComplex::new(drv);
+        assert_eq!(root.read_config(ep.address(), 0x40) & 0xff, 0x05);
+        assert_eq!(root.read_config(ep.address(), 0x44), 0);
+    }
+
+    #[test]
+    fn test_port_control_reset() {
+        use alloc::sync::Arc;
+        use core::{
+            cell::Cell,
+            sync::atomic::{AtomicU32, Ordering},
+        };
+
+        /// Calls as decimal digits, 1 = assert, 2 = deassert, 3 = REFCLK
+        #[derive(Clone, Default)]
+        struct Board(Arc<AtomicU32>);
+
+        impl Board {
+            fn record(&self, call: u32) {
+                let calls = self.0.load(Ordering::Relaxed);
+                self.0.store(calls * 10 + call, Ordering::Relaxed);
+            }
+        }
+
+        impl PortControl for Board {
+            fn assert_perst(&mut self, _port: PciAddress) {
+                self.record(1);
+            }
+
+            fn deassert_perst(&mut self, _port: PciAddress) {
+                self.record(2);
+            }
+
+            fn enable_refclk(&mut self, _port: PciAddress) {
+                self.record(3);
+            }
+        }
+
+        let fixture = Fixture::new()
+            .bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c).with_pcie(0x4),
+                Fixture::new().endpoint(0, 0, nvme()),
+            )
+            .bridge(
+                1,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, nvme()),
+            );
+        let board = Board::default();
+        let mut root = RootComplex::new(PcieController::new(fixture.controller()))
+            .with_port_control(board.clone());
+        root.scan();
+
+        let waited = Cell::new(0);
+        let mut delay = |ms: u32| waited.set(waited.get() + ms);
+        let port = PciAddress::new(0, 0, 0, 0);
+        let events = root.reset_port(port, &mut delay).unwrap();
+        assert!(events.is_empty());
+        assert_eq!(board.0.load(Ordering::Relaxed), 132);
+        assert!(waited.get() >= 200);
+        assert!(root
+            .reset_port(PciAddress::new(0, 1, 0, 0), &mut delay)
+            .is_err());
+
+        // Not a root port, PERST# isn't wired to it
+        waited.set(0);
+        let bridge = PciAddress::new(0, 0, 1, 0);
+        assert!(root.reset_port(bridge, &mut delay).unwrap().is_empty());
+        assert_eq!(board.0.load(Ordering::Relaxed), 132);
+        assert!(waited.get() >= 100 && waited.get() < 200);
+        assert_eq!(root.read_config(bridge, 0x3c) & 1 << 22, 0);
+    }
+
+    #[test]
+    fn test_resource_policy_skip() {
+        let policy: ResourcePolicy = "0000:00:01.0=skip; 00:02.0=prefer64,no-msi"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy.get(PciAddress::new(0, 0, 2, 0)),
+            DeviceOverride::PREFER64 | DeviceOverride::NO_MSI
+        );
+        assert!("00:02.0=fast".parse::<ResourcePolicy>().is_err());
+        let timing: ResourcePolicy = "cls=64;lat=32".parse().unwrap();
+        assert_eq!(timing.cache_line_size(), Some(64));
+        assert_eq!(timing.latency_timer(), Some(32));
+
+        // MSI left enabled by firmware
+        let endpoint = || nvme().with_capability(pcie::CAP_ID_MSI, &[0x0001_0000, 0, 0]);
+        let fixture = Fixture::new()
+            .endpoint(0, 0, endpoint())
+            .endpoint(1, 0, endpoint())
+            .endpoint(2, 0, endpoint());
+        let mut root =
+            RootComplex::new(PcieController::new(fixture.controller())).with_policy(policy);
+        let found: alloc::vec::Vec<_> = root.enumerate(None).map(|ep| ep.address()).collect();
+        assert_eq!(
+            found,
+            [PciAddress::new(0, 0, 0, 0), PciAddress::new(0, 0, 2, 0)]
+        );
+        let msi_enabled = |root: &mut RootComplex, device| {
+            root.read_config(PciAddress::new(0, 0, device, 0), 0x40) & 1 << 16 != 0
+        };
+        assert!(msi_enabled(&mut root, 0));
+        assert!(!msi_enabled(&mut root, 2));
+    }
+
+    #[test]
+    fn test_shadow_config_bar_sizing() {
+        let chip = MockController::new().with_function(
+            0,
+            0,
+            0,
+            MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00])
+                .with_bar32(0, 0x2_0000, false),
+        );
+        let mut drv = PcieController::new(chip);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let mut shadow = ShadowConfig::capture_endpoint(&ep);
+        assert_eq!(shadow.len(), 0x100);
+
+        // The guest sizes BAR0, the device is untouched
+        shadow.write(0x10, u32::MAX);
+        assert_eq!(shadow.read(0x10), !(0x2_0000 - 1));
+        assert_eq!(shadow.read(0x00), 0x100e_8086);
+        shadow.write(0x00, 0);
+        assert_eq!(shadow.read(0x00), 0x100e_8086);
+    }
+
+    #[test]
+    fn test_virtual_msix() {
+        use core::ptr::NonNull;
+        use pcie::{err::Error, passthrough::VirtualMsix, MsiAllocator, MsiMessage};
+
+        struct OneMessage(Option<MsiMessage>);
+
+        impl MsiAllocator for OneMessage {
+            fn alloc(&mut self, _device: PciAddress) -> Option<MsiMessage> {
+                self.0.take()
+            }
+
+            fn free(&mut self, _device: PciAddress, message: MsiMessage) {
+                self.0 = Some(message);
+            }
+        }
+
+        // 4 vectors, table at 0x2000 and PBA at 0x3000 of BAR0
+        let chip = MockController::new().with_function(
+            0,
+            0,
+            0,
+            MockFunction::endpoint(0x1af4, 0x1041, [0x02, 0x00, 0x00])
+                .with_bar64(0, 0x4000, false)
+                .with_capability(0x11, &[3 << 16, 0x2000, 0x3000]),
+        );
+        let mut drv = PcieController::new(chip);
+        drv.set_mem64(
+            PciMem64 {
+                address: 0x80_0000_0000,
+                size: 0x10_0000_0000,
+            },
+            true,
+        );
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let bar = ep.bar_address(0).unwrap();
+        let mut regs = alloc::vec![0u32; 0x4000 / 4];
+        let window = regs.as_mut_ptr().cast::<u8>();
+        let cap = ep.msix().unwrap();
+        let table = cap
+            .map_table(&|address: u64, _| {
+                NonNull::new(unsafe { window.add((address - bar) as usize) }).unwrap()
+            })
+            .unwrap();
+        let mut msix = VirtualMsix::new(ep.address(), &cap, table);
+
+        let host = MsiMessage {
+            address: 0x0800_0040,
+            data: 0x21,
+        };
+        let mut allocator = OneMessage(Some(host));
+        assert_eq!(
+            msix.write(0, 0x2000, 0xfee0_0000, &mut allocator).unwrap(),
+            None
+        );
+        assert_eq!(msix.write(0, 0x2008, 0x41, &mut allocator).unwrap(), None);
+        let remap = msix.write(0, 0x200c, 0, &mut allocator).unwrap().unwrap();
+        assert_eq!(remap.host, host);
+        assert_eq!(remap.guest.address, 0xfee0_0000);
+        // The hardware entry holds the host message, unmasked
+        assert_eq!(regs[0x2000 / 4], 0x0800_0040);
+        assert_eq!(regs[0x2008 / 4], 0x21);
+        assert_eq!(regs[0x200c / 4] & 1, 0);
+
+        // Nothing left for vector 1, which stays masked
+        assert!(matches!(
+            msix.write(0, 0x201c, 0, &mut allocator),
+            Err(Error::NoVectorSpace { .. })
+        ));
+        assert_eq!(regs[0x201c / 4] & 1, 1);
+
+        // The guest moves BAR0, the trapped pages follow it
+        assert_eq!(
+            msix.bar_registers().collect::<alloc::vec::Vec<_>>(),
+            [0x10, 0x14]
+        );
+        assert!(msix.write_bar_register(0x10, 0xc000_000c));
+        assert!(msix.write_bar_register(0x14, 0x1));
+        assert!(!msix.write_bar_register(0x18, 0));
+        assert_eq!(msix.locate_guest(0x1_c000_2010), Some((0, 0x2010)));
+        assert_eq!(msix.locate_guest(0x1_c000_3000), Some((0, 0x3000)));
+        assert_eq!(msix.locate_guest(0x1_c000_1000), None);
+        assert_eq!(msix.locate_guest(bar + 0x2000), None);
+    }
+
+    #[test]
+    fn test_emergency_power_reduction() {
+        use pcie::EmergencyPowerReduction;
+
+        // PCIe capability at 0x40: Device Capabilities 2 at 0x64 with EPR
+        // supported through PWRBRK (10b) and initialization required, Device
+        // Control 2 at 0x68 with End-End TLP Prefix Blocking set
+        let f = MockFunction::endpoint(0x8086, 0x10d3, [0x02, 0x00, 0x00])
+            .with_pcie(0)
+            .with_register(0x64, 0b110 << 24, 0)
+            .with_register(0x68, 1 << 15, 0xffff);
+        let mut drv = PcieController::new(MockController::new().with_function(0, 0, 0, f));
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let cap = ep.pcie_capability().unwrap();
+
+        assert_eq!(
+            cap.emergency_power_reduction(),
+            EmergencyPowerReduction::FormFactor
+        );
+        assert!(cap.emergency_power_reduction_init_required());
+        assert!(!cap.emergency_power_reduction_requested());
+        cap.request_emergency_power_reduction(true).unwrap();
+        assert!(cap.emergency_power_reduction_requested());
+        assert_eq!(ep.read(0x68) & 0xffff, 1 << 15 | 1 << 11);
+        cap.request_emergency_power_reduction(false).unwrap();
+        assert_eq!(ep.read(0x68) & 0xffff, 1 << 15);
+    }
+
+    #[test]
+    fn test_route_vga() {
+        use pcie::VgaPolicy;
+
+        let vga = MockFunction::endpoint(0x1234, 0x1111, [0x03, 0x00, 0x00]);
+        // Firmware left VGA forwarding on the bridge without the controller
+        let stale = MockFunction::bridge(0x1b36, 0x000c).with_register(0x3c, 1 << 19, 0xffff_00ff);
+        let fixture = Fixture::new()
+            .bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, vga),
+            )
+            .bridge(1, 0, stale, Fixture::new());
+        let mut drv = PcieController::new(fixture.controller());
+        let found = enumerate_by_controller(&mut drv, None)
+            .with_vga(VgaPolicy::First)
+            .count();
+        assert_eq!(found, 1);
+
+        let mut root = RootComplex::new(drv);
+        // VGA Enable and VGA 16-bit Decode
+        let routed = 0b11 << 19;
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 0, 0, 0), 0x3c) & routed,
+            routed
+        );
+        assert_eq!(
+            root.read_config(PciAddress::new(0, 0, 1, 0), 0x3c) & routed,
+            0
+        );
+        // Without BARs the controller doesn't decode anything
+        assert_eq!(root.read_config(PciAddress::new(0, 1, 0, 0), 0x04) & 0x3, 0);
+    }
+
+    #[cfg(feature = "audit")]
+    #[test]
+    fn test_config_region_classify() {
+        use pcie::audit::ConfigRegion::{self, *};
+
+        let endpoint = [
+            (0x04, Command),
+            (0x10, Bar),
+            (0x24, Bar),
+            (0x30, Bar),
+            (0x34, Header),
+        ];
+        let bridge = [
+            (0x14, Bar),
+            (0x18, BusNumber),
+            (0x1c, BridgeWindow),
+            (0x30, BridgeWindow),
+            (0x34, Header),
+            (0x38, Bar),
+            (0x3e, Header),
+        ];
+        let cardbus = [
+            (0x10, Bar),
+            (0x14, Header),
+            (0x18, BusNumber),
+            (0x1c, BridgeWindow),
+            (0x38, BridgeWindow),
+            (0x40, Header),
+            (0x44, Header),
+            (0x48, Capability),
+        ];
+        for (header_type, regions) in [(0x00, &endpoint[..]), (0x81, &bridge), (0x02, &cardbus)] {
+            for &(offset, region) in regions {
+                assert_eq!(
+                    ConfigRegion::classify(header_type, offset),
+                    region,
+                    "type {header_type:#x} offset {offset:#x}"
+                );
+            }
+            assert_eq!(
+                ConfigRegion::classify(header_type, 0x100),
+                ExtendedCapability
+            );
+        }
+    }
+
+    #[test]
+    fn test_hotplug_commands() {
+        use pcie::{err::Error, Indicator, IndicatorState};
+
+        // Only the slot registers matter, the header type is not checked
+        let slot = |completes: bool| {
+            let f = MockFunction::endpoint(0x1b36, 0x000c, [0x06, 0x04, 0x00]).with_pcie(0x6);
+            let cap = f.capability(0x10).unwrap();
+            let header = f.read(cap);
+            // Power controller, power indicator, hot-plug capable; presence
+            // detected with Presence Detect Changed latched
+            let f = f
+                .with_register(cap, header | 1 << 24, 0)
+                .with_register(cap + 0x14, 0b101_0010, 0)
+                .with_register(cap + 0x18, 1 << 22 | 1 << 19, 0xffff)
+                .with_rw1c(cap + 0x18, 0x011f << 16);
+            let f = if completes {
+                f.with_write_latch(cap + 0x18, 1 << 20)
+            } else {
+                f
+            };
+            let chip = MockController::new().with_function(0, 0, 0, f);
+            (PcieController::new(chip), cap)
+        };
+
+        let (mut drv, cap) = slot(true);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let hotplug = ep.hotplug_slot().unwrap();
+        let mut delay = |_: u32| unreachable!("the port completes commands at once");
+        hotplug.set_power(false, &mut delay).unwrap();
+        hotplug
+            .set_indicator(Indicator::Power, IndicatorState::Off, &mut delay)
+            .unwrap();
+        let data = ep.read(cap + 0x18);
+        // Power off, indicator off, the presence event still latched and the
+        // last command completed
+        assert_eq!(data & 0xffff, 1 << 10 | 0b11 << 8);
+        assert_eq!(data >> 16 & 0x1f, 1 << 3 | 1 << 4);
+
+        let mut events = alloc::vec::Vec::new();
+        assert!(hotplug.handle_interrupt(|e| events.push(e)));
+        assert!(events.contains(&pcie::HotplugEvent::PresenceChanged { present: true }));
+
+        hotplug.enable_interrupts(&mut delay).unwrap();
+        // Earlier events discarded, only the completion of this command left
+        assert_eq!(ep.read(cap + 0x18) >> 16 & 0x1f, 1 << 4);
+
+        // A port that never completes the command
+        let (mut drv, _) = slot(false);
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let mut waited = 0;
+        assert!(matches!(
+            ep.hotplug_slot()
+                .unwrap()
+                .set_power(true, &mut |ms: u32| waited += ms),
+            Err(Error::Timeout)
+        ));
+        assert_eq!(waited, 1000);
+    }
+
+    #[test]
+    fn test_power_budget() {
+        use pcie::EXT_CAP_ID_POWER_BUDGETING;
+
+        // Base Power, scale 1.0x, D0 unless said otherwise
+        let entry = |watts: u32, kind: u32, rail: u32| watts | kind << 15 | rail << 18;
+        const MAXIMUM: u32 = 7;
+        const SUSTAINED: u32 = 3;
+        let table = [
+            entry(25, MAXIMUM, 0),
+            entry(10, MAXIMUM, 1),
+            // Reserved power value
+            entry(0xf5, SUSTAINED, 0),
+            entry(20, SUSTAINED, 0),
+            // Thermal
+            entry(40, MAXIMUM, 7),
+            // D3
+            entry(5, MAXIMUM, 0) | 3 << 13,
+        ];
+        // Power Budgeting at 0x100: Data Select at 0x104, Data at 0x108
+        let f = MockFunction::endpoint(0x10de, 0x2204, [0x03, 0x00, 0x00])
+            .with_pcie(0)
+            .with_extended_capability(EXT_CAP_ID_POWER_BUDGETING, 1, &[0, 0, 0])
+            .with_table(0x104, 0x108, &table);
+        let mut drv = PcieController::new(MockController::new().with_function(0, 0, 0, f));
+        let ep = enumerate_by_controller(&mut drv, None).next().unwrap();
+        let budget = ep.power_budget().unwrap();
+
+        assert!(budget.entry(2).is_none());
+        assert_eq!(budget.entry(3).unwrap().power_mw, 20_000);
+        assert!(budget.entry(6).is_none());
+        // The reserved entry doesn't end the table
+        assert_eq!(budget.entries().count(), 5);
+        // 12V and 3.3V add up, thermal and D3 don't count
+        assert_eq!(budget.max_power_mw(), Some(35_000));
+    }
+
+    #[test]
+    fn test_ari_function_chain() {
+        use pcie::{NodeKind, CAP_ID_PCIE, EXT_CAP_ID_ARI};
+
+        let function = |next: u8, size: u32| {
+            nvme()
+                .with_bar32(0, size, false)
+                .with_pcie(0)
+                .with_extended_capability(EXT_CAP_ID_ARI, 1, &[(next as u32) << 8])
+        };
+        // Downstream port supporting ARI forwarding
+        let port = MockFunction::bridge(0x1b36, 0x000c).with_pcie(0x6);
+        let cap = port.capability(CAP_ID_PCIE).unwrap();
+        let port = port.with_register(cap + 0x24, 1 << 5, 0);
+        // Functions 0x00 -> 0x08 -> 0x21, 0x30 is off the chain. The window
+        // only fits 0x21 if it is sized through the chain as well.
+        let below = Fixture::new()
+            .endpoint(0, 0, function(0x08, 0x10_0000))
+            .endpoint(1, 0, function(0x21, 0x10_0000))
+            .endpoint(4, 1, function(0, 0x40_0000))
+            .endpoint(6, 0, function(0, 0x10_0000));
+        let fixture = Fixture::new().bridge(0, 0, port, below);
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let mut root = RootComplex::new(drv);
+
+        let topology = root.scan();
+        let found: alloc::vec::Vec<_> = topology.endpoints().map(|n| n.address).collect();
+        assert_eq!(
+            found,
+            [
+                PciAddress::new(0, 1, 0, 0),
+                PciAddress::new(0, 1, 1, 0),
+                PciAddress::new(0, 1, 4, 1),
+            ]
+        );
+        for node in topology.endpoints() {
+            let NodeKind::Endpoint { bars } = &node.kind else {
+                unreachable!()
+            };
+            assert!(bars[0].range.start >= 0x1000_0000, "{node:?}");
+        }
+        let control2 = root.read_config(PciAddress::new(0, 0, 0, 0), cap + 0x28);
+        assert_ne!(control2 & 1 << 5, 0);
+
+        // The port and the functions on the chain
+        assert_eq!(root.reset_config_state(), 4);
+    }
+
+    #[test]
+    fn test_bridge_windows_disjoint() {
+        use pcie::NodeKind;
+
+        let endpoint = |size| {
+            nvme()
+                .with_bar64(0, size, false)
+                .with_bar64(2, 0x10_0000, true)
+        };
+        let fixture = Fixture::new()
+            .bridge(
+                0,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new().endpoint(0, 0, endpoint(0x4000)),
+            )
+            .bridge(
+                1,
+                0,
+                MockFunction::bridge(0x1b36, 0x000c),
+                Fixture::new()
+                    .endpoint(0, 0, endpoint(0x1000))
+                    .endpoint(1, 0, endpoint(0x20_0000)),
+            );
+        let mut drv = PcieController::new(fixture.controller());
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let mut root = RootComplex::new(drv);
+        let topology = root.scan();
+
+        let mut windows = alloc::vec::Vec::new();
+        for (_, node) in topology.nodes() {
+            if let NodeKind::Bridge {
+                memory,
+                prefetchable,
+                ..
+            } = &node.kind
+            {
+                let memory = memory.clone().unwrap();
+                let prefetchable = prefetchable.clone().unwrap();
+                windows.push(memory.start as u64..memory.end as u64);
+                windows.push(prefetchable.clone());
+                // Every BAR below inside the windows of its bridge
+                for child in &node.children {
+                    let NodeKind::Endpoint { bars } = &topology.node(*child).kind else {
+                        panic!("endpoint expected");
+                    };
+                    assert_eq!(bars.len(), 2);
+                    for bar in bars {
+                        let window = if bar.prefetchable {
+                            prefetchable.clone()
+                        } else {
+                            memory.start as u64..memory.end as u64
+                        };
+                        assert!(
+                            window.start <= bar.range.start && bar.range.end <= window.end,
+                            "{bar:x?} outside {window:x?}"
+                        );
+                    }
+                }
+            }
+        }
+        assert_eq!(windows.len(), 4);
+        for (i, a) in windows.iter().enumerate() {
+            for b in &windows[i + 1..] {
+                assert!(
+                    a.end <= b.start || b.end <= a.start,
+                    "{a:x?} overlaps {b:x?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bridge_io_window_round_trip() {
+        use pcie::{HostWindow, HostWindows, NodeKind, WindowKind};
+
+        let nic = MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00]).with_io_bar(0, 0x100);
+        let fixture = Fixture::new().bridge(
+            0,
+            0,
+            MockFunction::bridge(0x1b36, 0x000c),
+            Fixture::new().endpoint(0, 0, nic),
+        );
+        let mut windows = HostWindows::new();
+        windows.push(HostWindow {
+            kind: WindowKind::Memory32,
+            cpu_address: 0x1000_0000,
+            pci_address: 0x1000_0000,
+            size: 0x1000_0000,
+            prefetchable: false,
+        });
+        windows.push(HostWindow {
+            kind: WindowKind::Io,
+            cpu_address: 0x3eff_0000,
+            pci_address: 0x1000,
+            size: 0xf000,
+            prefetchable: false,
+        });
+        let mut root =
+            RootComplex::new(PcieController::new(fixture.controller())).with_windows(&windows);
+        let topology = root.scan();
+
+        // I/O base 0x1000 in bits 4..8, limit 0x1fff in bits 12..16
+        let bridge = PciAddress::new(0, 0, 0, 0);
+        assert_eq!(root.read_config(bridge, 0x1c) & 0xffff, 0x1010);
+        let (_, node) = topology.nodes().next().unwrap();
+        let NodeKind::Bridge { io, .. } = &node.kind else {
+            panic!("bridge expected");
+        };
+        assert_eq!(io.clone(), Some(0x1000..0x2000));
+        assert_eq!(root.read_config(PciAddress::new(0, 1, 0, 0), 0x10), 0x1001);
+    }
+
+    #[test]
+    fn test_failed_assignment_gives_space_back() {
+        use pcie::{HostWindow, HostWindows, WindowKind};
+
+        // BAR1 doesn't fit the memory window, the I/O taken for BAR0 must
+        // go to the next function
+        let big = MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00])
+            .with_io_bar(0, 0x100)
+            .with_bar32(1, 0x2000_0000, false);
+        let nic = MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00]).with_io_bar(0, 0x100);
+        let fixture = Fixture::new().endpoint(0, 0, big).endpoint(1, 0, nic);
+        let mut windows = HostWindows::new();
+        windows.push(HostWindow {
+            kind: WindowKind::Memory32,
+            cpu_address: 0x1000_0000,
+            pci_address: 0x1000_0000,
+            size: 0x1000_0000,
+            prefetchable: false,
+        });
+        windows.push(HostWindow {
+            kind: WindowKind::Io,
+            cpu_address: 0x3eff_0000,
+            pci_address: 0x1000,
+            size: 0xf000,
+            prefetchable: false,
+        });
+        let mut root =
+            RootComplex::new(PcieController::new(fixture.controller())).with_windows(&windows);
+        root.scan();
+
+        assert_eq!(root.read_config(PciAddress::new(0, 0, 0, 0), 0x10), 0x1);
+        assert_eq!(root.read_config(PciAddress::new(0, 0, 1, 0), 0x10), 0x1001);
+    }
+
+    #[test]
+    fn test_topology_bars_by_register() {
+        use pcie::NodeKind;
+
+        let f = MockFunction::endpoint(0x8086, 0x100e, [0x02, 0x00, 0x00])
+            .with_bar32(0, 0x2_0000, false)
+            .with_bar64(2, 0x10_0000, true);
+        let chip = MockController::new().with_function(0x10, 0, 0, f);
+        let count = chip.access_count();
+        let mut drv = PcieController::new(chip);
+        drv.set_mem32(
+            PciMem32 {
+                address: 0x1000_0000,
+                size: 0x1000_0000,
+            },
+            false,
+        );
+        let mut root = RootComplex::new(drv).with_bus_range(0x10..0x20);
+        let topology = root.scan();
+        let (_, node) = topology.nodes().next().unwrap();
+        assert_eq!(node.address, PciAddress::new(0, 0x10, 0, 0));
+        let NodeKind::Endpoint { bars } = &node.kind else {
+            panic!("endpoint expected");
+        };
+        let layout: alloc::vec::Vec<_> = bars
+            .iter()
+            .map(|b| (b.index, b.range.end - b.range.start, b.prefetchable))
+            .collect();
+        assert_eq!(layout, [(0, 0x2_0000, false), (2, 0x10_0000, true)]);
+        // Assigned on bus 0x10 from the window, not left at reset values
+        let bar0 = &bars[0].range;
+        assert!(bar0.start >= 0x1000_0000 && bar0.end <= 0x2000_0000);
+        let value = root.read_config(PciAddress::new(0, 0x10, 0, 0), 0x10);
+        assert_eq!(value as u64 & !0xf, bar0.start);
+
+        // Known functions are not sized again
+        let writes = count.writes();
+        assert_eq!(root.scan().nodes().next().unwrap().1.kind, node.kind);
+        assert_eq!(count.writes(), writes);
+    }
 }